@@ -19,12 +19,104 @@
 //! so we need to maintain the count of write and read reference.
 //! When page fault occurs, if the read reference count is 0 and the write reference count is 1，
 //! The copy process should be skipped and the entry is mark as writable directly.
+//!
+//! To share frames between different page tables (e.g. on fork),
+//! the reference count must be global, so it is maintained by the `FrameAllocator`.
+//! `share_page()`, `map_shared_page()` and `copy_on_write()` implement the same process
+//! for `MemoryHandler`s based on it.
 
+use super::memory_set::handler::FrameAllocator;
+use super::memory_set::MemoryAttr;
 use super::paging::*;
 use super::*;
 use alloc::collections::BTreeMap;
 use core::ops::{Deref, DerefMut};
 
+/*
+ **  @brief  share the present page of `addr` by copy-on-write
+ **          the page becomes readonly, and will be copied on the first write
+ **  @param  pt: &mut PageTable    the page table containing the page
+ **  @param  addr: VirtAddr        the virtual address of the page
+ **  @param  allocator: &impl FrameAllocator
+ **                                the allocator owning the frame
+ **  @retval Option<PhysAddr>      the shared frame, or None if it can not be shared
+ */
+pub fn share_page(
+    pt: &mut PageTable,
+    addr: VirtAddr,
+    allocator: &impl FrameAllocator,
+) -> Option<PhysAddr> {
+    let entry = pt.get_entry(addr)?;
+    if !entry.present() {
+        return None;
+    }
+    let writable = entry.writable() || entry.writable_shared();
+    entry.set_shared(writable);
+    if !entry.readonly_shared() && !entry.writable_shared() {
+        // shared bits are not supported by the page table
+        return None;
+    }
+    if !allocator.share(entry.target()) {
+        entry.clear_shared();
+        return None;
+    }
+    entry.set_writable(false);
+    entry.update();
+    Some(entry.target())
+}
+
+/*
+ **  @brief  map the virtual address to the frame shared by `share_page()`
+ **  @param  pt: &mut PageTable    the page table to map
+ **  @param  addr: VirtAddr        the virtual address to map
+ **  @param  target: PhysAddr      the shared frame
+ **  @param  attr: &MemoryAttr     the attributes of the page
+ **  @retval none
+ */
+pub fn map_shared_page(pt: &mut PageTable, addr: VirtAddr, target: PhysAddr, attr: &MemoryAttr) {
+    let entry = pt.map(addr, target);
+    attr.apply(entry);
+    let writable = entry.writable();
+    entry.set_writable(false);
+    entry.set_shared(writable);
+    entry.update();
+}
+
+/*
+ **  @brief  execute the COW process for the page fault on `addr`
+ **          the page table must be active
+ **  @param  pt: &mut PageTable    the page table containing the page
+ **  @param  addr: VirtAddr        the virtual address of the page fault
+ **  @param  allocator: &impl FrameAllocator
+ **                                the allocator owning the frame
+ **  @retval bool                  whether copy-on-write happens
+ */
+pub fn copy_on_write(pt: &mut PageTable, addr: VirtAddr, allocator: &impl FrameAllocator) -> bool {
+    let target = match pt.get_entry(addr) {
+        Some(entry) if entry.present() && entry.writable_shared() => entry.target(),
+        _ => return false,
+    };
+    if allocator.ref_count(target) > 1 {
+        let frame = allocator.alloc().expect("failed to alloc frame");
+        let mut temp_data = [0u8; PAGE_SIZE];
+        temp_data.copy_from_slice(pt.get_page_slice_mut(addr));
+        pt.get_entry(addr).unwrap().set_target(frame);
+        allocator.dealloc(target);
+        let entry = pt.get_entry(addr).unwrap();
+        entry.clear_shared();
+        entry.set_writable(true);
+        entry.update();
+        pt.get_page_slice_mut(addr).copy_from_slice(&temp_data);
+    } else {
+        // the last reference, no need to copy
+        let entry = pt.get_entry(addr).unwrap();
+        entry.clear_shared();
+        entry.set_writable(true);
+        entry.update();
+    }
+    true
+}
+
 /// Wrapper for page table, supporting shared map & copy-on-write
 pub struct CowExt<T: PageTable> {
     page_table: T,
@@ -210,6 +302,77 @@ impl FrameRcMap {
 
 pub mod test {
     use super::*;
+    #[test]
+    fn share_frame() {
+        use alloc::rc::Rc;
+        use core::cell::RefCell;
+
+        /// Frame allocator with reference count
+        #[derive(Debug, Clone)]
+        struct RcFrameAlloc(Rc<RefCell<(usize, BTreeMap<PhysAddr, usize>)>>);
+
+        impl FrameAllocator for RcFrameAlloc {
+            fn alloc(&self) -> Option<PhysAddr> {
+                let mut inner = self.0.borrow_mut();
+                let pa = inner.0 * PAGE_SIZE;
+                inner.0 += 1;
+                inner.1.insert(pa, 1);
+                Some(pa)
+            }
+            fn dealloc(&self, target: PhysAddr) {
+                let mut inner = self.0.borrow_mut();
+                *inner.1.get_mut(&target).unwrap() -= 1;
+                if inner.1[&target] == 0 {
+                    inner.1.remove(&target);
+                }
+            }
+            fn share(&self, target: PhysAddr) -> bool {
+                *self.0.borrow_mut().1.get_mut(&target).unwrap() += 1;
+                true
+            }
+            fn ref_count(&self, target: PhysAddr) -> usize {
+                self.0.borrow().1.get(&target).cloned().unwrap_or(0)
+            }
+        }
+
+        let alloc = RcFrameAlloc(Rc::new(RefCell::new((4, BTreeMap::new()))));
+        let mut pt = MockPageTable::new();
+        let pt0 = unsafe { &mut *(&mut pt as *mut MockPageTable) };
+        let alloc0 = alloc.clone();
+        pt.set_handler(Box::new(move |_, addr: VirtAddr| {
+            assert!(copy_on_write(pt0, addr, &alloc0));
+        }));
+
+        // 0x1000 is the source page, 0x2000 is the forked one
+        let target = alloc.alloc().unwrap();
+        pt.map(0x1000, target);
+        pt.write(0x1000, 1);
+        assert_eq!(share_page(&mut pt, 0x1000, &alloc), Some(target));
+        pt.map(0x2000, target).set_writable(false);
+        pt.get_entry(0x2000).unwrap().set_shared(true);
+        assert_eq!(alloc.ref_count(target), 2);
+        assert!(!pt.get_entry(0x1000).unwrap().writable());
+        assert_eq!(pt.read(0x2000), 1);
+
+        // the first write copies the frame
+        pt.write(0x2000, 2);
+        assert_ne!(pt.get_entry(0x2000).unwrap().target(), target);
+        assert_eq!(alloc.ref_count(target), 1);
+        assert_eq!(pt.read(0x1000), 1);
+        assert_eq!(pt.read(0x2000), 2);
+
+        // the last reference is made writable directly
+        pt.write(0x1000, 3);
+        assert_eq!(pt.get_entry(0x1000).unwrap().target(), target);
+        assert_eq!(alloc.ref_count(target), 1);
+        assert_eq!(pt.read(0x1000), 3);
+        assert_eq!(pt.read(0x2000), 2);
+
+        // readonly shared page is not copied
+        assert_eq!(share_page(&mut pt, 0x1000, &alloc), Some(target));
+        pt.get_entry(0x1000).unwrap().set_shared(false);
+        assert!(!copy_on_write(&mut pt, 0x1000, &alloc));
+    }
 
     #[test]
     fn test() {
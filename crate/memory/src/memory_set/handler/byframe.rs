@@ -1,4 +1,5 @@
 use super::*;
+use crate::cow::{copy_on_write, share_page};
 
 #[derive(Debug, Clone)]
 pub struct ByFrame<T: FrameAllocator> {
@@ -22,8 +23,12 @@ impl<T: FrameAllocator> MemoryHandler for ByFrame<T> {
         pt.unmap(addr);
     }
 
-    fn handle_page_fault(&self, _pt: &mut PageTable, _addr: VirtAddr) -> bool {
-        false
+    fn handle_page_fault(&self, pt: &mut PageTable, addr: VirtAddr) -> bool {
+        copy_on_write(pt, addr, &self.allocator)
+    }
+
+    fn share(&self, pt: &mut PageTable, addr: VirtAddr) -> Option<PhysAddr> {
+        share_page(pt, addr, &self.allocator)
     }
 }
 
@@ -1,4 +1,5 @@
 use super::*;
+use crate::cow::{copy_on_write, share_page};
 
 #[derive(Debug, Clone)]
 pub struct Delay<T: FrameAllocator> {
@@ -37,8 +38,8 @@ impl<T: FrameAllocator> MemoryHandler for Delay<T> {
     fn handle_page_fault(&self, pt: &mut PageTable, addr: VirtAddr) -> bool {
         let entry = pt.get_entry(addr).expect("failed to get entry");
         if entry.present() {
-            // not a delay case
-            return false;
+            // not a delay case, try copy-on-write
+            return copy_on_write(pt, addr, &self.allocator);
         }
         let frame = self.allocator.alloc().expect("failed to alloc frame");
         entry.set_target(frame);
@@ -46,6 +47,10 @@ impl<T: FrameAllocator> MemoryHandler for Delay<T> {
         entry.update();
         true
     }
+
+    fn share(&self, pt: &mut PageTable, addr: VirtAddr) -> Option<PhysAddr> {
+        share_page(pt, addr, &self.allocator)
+    }
 }
 
 impl<T: FrameAllocator> Delay<T> {
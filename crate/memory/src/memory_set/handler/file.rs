@@ -0,0 +1,213 @@
+use super::shared::SharedFrames;
+use super::*;
+use crate::cow::{copy_on_write, share_page};
+use alloc::sync::Arc;
+use spin::Mutex;
+
+/// The file to be mapped, implemented by OS
+pub trait MappedFile: Debug + Clone + 'static {
+    /// Read from `offset` of the file to `buf`, return the length read
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize;
+    /// Write `buf` to `offset` of the file, return the length written
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize;
+}
+
+/// Memory mapped from [`file_start`, `file_end`) of a file, paged in on demand.
+///
+/// Private mappings are shared by copy-on-write on fork,
+/// and changes are never written to the file.
+/// Shared mappings own their frames together with the forked ones,
+/// and dirty pages are written back to the file by `MemorySet::sync`.
+/// Independent shared mappings of a file, e.g. by two `mmap`s, have frames of
+/// their own, so one sees the changes of another only after they are written back
+/// and its pages are read again.
+#[derive(Debug, Clone)]
+pub struct File<F: MappedFile, T: FrameAllocator> {
+    file: F,
+    /// the start address of the mapping, mapped to `file_start`
+    mem_start: VirtAddr,
+    file_start: usize,
+    file_end: usize,
+    /// frames of a shared mapping, `None` if it is private
+    shared: Option<Arc<Mutex<SharedFrames<T>>>>,
+    allocator: T,
+}
+
+impl<F: MappedFile, T: FrameAllocator> MemoryHandler for File<F, T> {
+    fn box_clone(&self) -> Box<MemoryHandler> {
+        Box::new(self.clone())
+    }
+
+    fn map(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        match self.shared {
+            Some(ref shared) => shared.lock().map(pt, addr, self.page_index(addr), attr),
+            None => {
+                let entry = pt.map(addr, 0);
+                entry.set_present(false);
+                attr.apply(entry);
+            }
+        }
+    }
+
+    fn map_eager(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        if self.shared.is_some() {
+            return self.map(pt, addr, attr);
+        }
+        let target = self.allocator.alloc().expect("failed to alloc frame");
+        let entry = pt.map(addr, target);
+        attr.apply(entry);
+    }
+
+    fn unmap(&self, pt: &mut PageTable, addr: VirtAddr) {
+        if self.shared.is_some() {
+            return SharedFrames::<T>::unmap(pt, addr);
+        }
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        if entry.present() {
+            self.allocator.dealloc(entry.target());
+        }
+        // PageTable::unmap requires page to be present
+        entry.set_present(true);
+        pt.unmap(addr);
+    }
+
+    fn handle_page_fault(&self, pt: &mut PageTable, addr: VirtAddr) -> bool {
+        let addr = addr & !(PAGE_SIZE - 1);
+        if let Some(ref shared) = self.shared {
+            return shared
+                .lock()
+                .handle_page_fault(pt, addr, self.page_index(addr), |data| {
+                    self.fill_data(addr, data)
+                });
+        }
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        if entry.present() {
+            // not a delay case, try copy-on-write
+            return copy_on_write(pt, addr, &self.allocator);
+        }
+        let target = self.allocator.alloc().expect("failed to alloc frame");
+        let writable = entry.writable();
+        entry.set_target(target);
+        entry.set_present(true);
+        entry.set_writable(true);
+        entry.update();
+        self.fill_data(addr, pt.get_page_slice_mut(addr));
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        entry.set_writable(writable);
+        entry.update();
+        true
+    }
+
+    fn is_major_fault(&self, pt: &mut PageTable, addr: VirtAddr) -> bool {
+        let addr = addr & !(PAGE_SIZE - 1);
+        // a present page is copied on write
+        if pt.get_entry(addr).map_or(true, |entry| entry.present()) {
+            return false;
+        }
+        match self.shared {
+            // the page may have been read by another process
+            Some(ref shared) => !shared.lock().contains(self.page_index(addr)),
+            None => true,
+        }
+    }
+
+    fn share(&self, pt: &mut PageTable, addr: VirtAddr) -> Option<PhysAddr> {
+        match self.shared {
+            // the frame is owned by the mapping, map it directly
+            Some(_) => pt.get_entry(addr).map(|entry| entry.target()),
+            None => share_page(pt, addr, &self.allocator),
+        }
+    }
+
+    fn map_shared(&self, pt: &mut PageTable, addr: VirtAddr, target: PhysAddr, attr: &MemoryAttr) {
+        match self.shared {
+            Some(_) => {
+                let entry = pt.map(addr, target);
+                attr.apply(entry);
+            }
+            None => crate::cow::map_shared_page(pt, addr, target, attr),
+        }
+    }
+
+    fn is_shared(&self) -> bool {
+        self.shared.is_some()
+    }
+
+    fn clear_dirty(&self, pt: &mut PageTable, addr: VirtAddr) -> bool {
+        if self.shared.is_none() {
+            return false;
+        }
+        match pt.get_entry(addr) {
+            Some(entry) if entry.present() && entry.dirty() => {
+                entry.clear_dirty();
+                entry.update();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn write_back(&self, addr: VirtAddr, data: &[u8]) {
+        let offset = self.file_offset(addr);
+        if offset < self.file_end {
+            let len = PAGE_SIZE.min(self.file_end - offset);
+            self.file.write_at(offset, &data[..len]);
+        }
+    }
+}
+
+impl<F: MappedFile, T: FrameAllocator> File<F, T> {
+    /// Create a private mapping at `mem_start` of [`file_start`, `file_end`) of `file`
+    pub fn new(
+        file: F,
+        mem_start: VirtAddr,
+        file_start: usize,
+        file_end: usize,
+        allocator: T,
+    ) -> Self {
+        File {
+            file,
+            mem_start,
+            file_start,
+            file_end,
+            shared: None,
+            allocator,
+        }
+    }
+
+    /// Create a shared mapping at `mem_start` of [`file_start`, `file_end`) of `file`
+    pub fn new_shared(
+        file: F,
+        mem_start: VirtAddr,
+        file_start: usize,
+        file_end: usize,
+        allocator: T,
+    ) -> Self {
+        File {
+            shared: Some(SharedFrames::new(allocator.clone())),
+            ..Self::new(file, mem_start, file_start, file_end, allocator)
+        }
+    }
+
+    fn page_index(&self, addr: VirtAddr) -> usize {
+        (addr - self.mem_start) / PAGE_SIZE
+    }
+
+    /// Offset in the file of `addr`, which is beyond `file_end` if it overflows
+    fn file_offset(&self, addr: VirtAddr) -> usize {
+        (addr - self.mem_start)
+            .checked_add(self.file_start)
+            .unwrap_or(usize::max_value())
+    }
+
+    /// Fill the page `addr` with file data, and zero the rest beyond `file_end`
+    fn fill_data(&self, addr: VirtAddr, data: &mut [u8]) {
+        let offset = self.file_offset(addr);
+        let mut read_len = 0;
+        if offset < self.file_end {
+            let len = PAGE_SIZE.min(self.file_end - offset);
+            read_len = self.file.read_at(offset, &mut data[..len]);
+        }
+        data[read_len..].iter_mut().for_each(|x| *x = 0);
+    }
+}
@@ -23,6 +23,15 @@ impl MemoryHandler for Linear {
     fn handle_page_fault(&self, _pt: &mut PageTable, _addr: VirtAddr) -> bool {
         false
     }
+
+    fn share(&self, pt: &mut PageTable, addr: VirtAddr) -> Option<PhysAddr> {
+        // the memory is not owned by us, map the same target directly
+        pt.get_entry(addr).map(|entry| entry.target())
+    }
+
+    fn map_shared(&self, pt: &mut PageTable, addr: VirtAddr, _target: PhysAddr, attr: &MemoryAttr) {
+        self.map(pt, addr, attr);
+    }
 }
 
 impl Linear {
@@ -21,6 +21,37 @@ pub trait MemoryHandler: Debug + 'static {
     /// Handle page fault on `addr`
     /// Return true if success, false if error
     fn handle_page_fault(&self, pt: &mut PageTable, addr: VirtAddr) -> bool;
+
+    /// Whether handling the page fault on `addr` would read a file, i.e. it is a major fault
+    fn is_major_fault(&self, _pt: &mut PageTable, _addr: VirtAddr) -> bool {
+        false
+    }
+
+    /// Share the present page `addr` with a forked page table, called with the source one.
+    /// Return the frame to be mapped by `map_shared`,
+    /// or `None` if the page can not be shared and should be copied.
+    fn share(&self, _pt: &mut PageTable, _addr: VirtAddr) -> Option<PhysAddr> {
+        None
+    }
+
+    /// Map `addr` to the frame `target` returned by `share`, called with the forked page table.
+    fn map_shared(&self, pt: &mut PageTable, addr: VirtAddr, target: PhysAddr, attr: &MemoryAttr) {
+        crate::cow::map_shared_page(pt, addr, target, attr);
+    }
+
+    /// Clear the dirty bit of page `addr`.
+    /// Return true if the page should be written back to its file by `write_back`.
+    fn clear_dirty(&self, _pt: &mut PageTable, _addr: VirtAddr) -> bool {
+        false
+    }
+
+    /// Write back the content `data` of page `addr` to its file
+    fn write_back(&self, _addr: VirtAddr, _data: &[u8]) {}
+
+    /// Whether the frames are shared with other processes instead of copied on fork
+    fn is_shared(&self) -> bool {
+        false
+    }
 }
 
 impl Clone for Box<MemoryHandler> {
@@ -31,14 +62,30 @@ impl Clone for Box<MemoryHandler> {
 
 pub trait FrameAllocator: Debug + Clone + 'static {
     fn alloc(&self) -> Option<PhysAddr>;
+    /// Release a reference of the frame, free it if it is the last one
     fn dealloc(&self, target: PhysAddr);
+
+    /// Add a reference to the frame `target` to share it by copy-on-write.
+    /// Each reference is released by `dealloc`.
+    /// Return false if sharing is not supported.
+    fn share(&self, _target: PhysAddr) -> bool {
+        false
+    }
+    /// Get the number of references to the frame `target`
+    fn ref_count(&self, _target: PhysAddr) -> usize {
+        1
+    }
 }
 
 mod byframe;
 mod delay;
+mod file;
 mod linear;
+mod shared;
 //mod swap;
 
 pub use self::byframe::ByFrame;
 pub use self::delay::Delay;
+pub use self::file::{File, MappedFile};
 pub use self::linear::Linear;
+pub use self::shared::Shared;
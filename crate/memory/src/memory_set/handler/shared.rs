@@ -0,0 +1,147 @@
+use super::*;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use spin::Mutex;
+
+/// Frames of a shared mapping, indexed by page number from the start of the mapping
+///
+/// Owned by all the areas forked or split from the mapping,
+/// the frames are freed when the last one is dropped.
+#[derive(Debug)]
+pub struct SharedFrames<T: FrameAllocator> {
+    frames: BTreeMap<usize, PhysAddr>,
+    allocator: T,
+}
+
+impl<T: FrameAllocator> SharedFrames<T> {
+    pub fn new(allocator: T) -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(SharedFrames {
+            frames: BTreeMap::new(),
+            allocator,
+        }))
+    }
+
+    /// Map page `addr` to the frame of page `index` if it exists, otherwise map it absent.
+    pub fn map(&self, pt: &mut PageTable, addr: VirtAddr, index: usize, attr: &MemoryAttr) {
+        match self.frames.get(&index) {
+            Some(&target) => {
+                let entry = pt.map(addr, target);
+                attr.apply(entry);
+            }
+            None => {
+                let entry = pt.map(addr, 0);
+                entry.set_present(false);
+                attr.apply(entry);
+            }
+        }
+    }
+
+    /// Whether the frame of page `index` exists
+    pub fn contains(&self, index: usize) -> bool {
+        self.frames.contains_key(&index)
+    }
+
+    /// Unmap page `addr`, the frame is still owned by the mapping
+    pub fn unmap(pt: &mut PageTable, addr: VirtAddr) {
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        // PageTable::unmap requires page to be present
+        entry.set_present(true);
+        pt.unmap(addr);
+    }
+
+    /// Handle page fault on the absent page `addr` by mapping the frame of page `index`.
+    /// A new frame is filled by `fill` before it becomes visible.
+    pub fn handle_page_fault(
+        &mut self,
+        pt: &mut PageTable,
+        addr: VirtAddr,
+        index: usize,
+        fill: impl FnOnce(&mut [u8]),
+    ) -> bool {
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        if entry.present() {
+            // writes to a shared page never copy
+            return false;
+        }
+        if let Some(&target) = self.frames.get(&index) {
+            entry.set_target(target);
+            entry.set_present(true);
+            entry.update();
+            return true;
+        }
+        let target = self.allocator.alloc().expect("failed to alloc frame");
+        let writable = entry.writable();
+        entry.set_target(target);
+        entry.set_present(true);
+        entry.set_writable(true);
+        entry.update();
+        fill(pt.get_page_slice_mut(addr));
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        entry.set_writable(writable);
+        entry.update();
+        self.frames.insert(index, target);
+        true
+    }
+}
+
+impl<T: FrameAllocator> Drop for SharedFrames<T> {
+    fn drop(&mut self) {
+        for &target in self.frames.values() {
+            self.allocator.dealloc(target);
+        }
+    }
+}
+
+/// Anonymous memory shared with forked processes (`MAP_SHARED | MAP_ANONYMOUS`)
+#[derive(Debug, Clone)]
+pub struct Shared<T: FrameAllocator> {
+    /// the start address of the mapping, to index the frames
+    mem_start: VirtAddr,
+    frames: Arc<Mutex<SharedFrames<T>>>,
+}
+
+impl<T: FrameAllocator> MemoryHandler for Shared<T> {
+    fn box_clone(&self) -> Box<MemoryHandler> {
+        Box::new(self.clone())
+    }
+
+    fn map(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        let index = (addr - self.mem_start) / PAGE_SIZE;
+        self.frames.lock().map(pt, addr, index, attr);
+    }
+
+    fn unmap(&self, pt: &mut PageTable, addr: VirtAddr) {
+        SharedFrames::<T>::unmap(pt, addr);
+    }
+
+    fn handle_page_fault(&self, pt: &mut PageTable, addr: VirtAddr) -> bool {
+        let index = (addr - self.mem_start) / PAGE_SIZE;
+        self.frames
+            .lock()
+            .handle_page_fault(pt, addr, index, |data| data.iter_mut().for_each(|x| *x = 0))
+    }
+
+    fn share(&self, pt: &mut PageTable, addr: VirtAddr) -> Option<PhysAddr> {
+        // the frame is owned by the mapping, map it directly
+        pt.get_entry(addr).map(|entry| entry.target())
+    }
+
+    fn map_shared(&self, pt: &mut PageTable, addr: VirtAddr, target: PhysAddr, attr: &MemoryAttr) {
+        let entry = pt.map(addr, target);
+        attr.apply(entry);
+    }
+
+    fn is_shared(&self) -> bool {
+        true
+    }
+}
+
+impl<T: FrameAllocator> Shared<T> {
+    /// Create a shared mapping starting at `mem_start`
+    pub fn new(mem_start: VirtAddr, allocator: T) -> Self {
+        Shared {
+            mem_start,
+            frames: SharedFrames::new(allocator),
+        }
+    }
+}
@@ -54,9 +54,32 @@ impl MemoryArea {
     pub fn contains(&self, addr: VirtAddr) -> bool {
         addr >= self.start_addr && addr < self.end_addr
     }
+    /// Get the start address of the memory area
+    pub fn start_addr(&self) -> VirtAddr {
+        self.start_addr
+    }
+    /// Get the end address of the memory area
+    pub fn end_addr(&self) -> VirtAddr {
+        self.end_addr
+    }
+    /// Get the attributes of the memory area
+    pub fn attr(&self) -> MemoryAttr {
+        self.attr
+    }
+    /// Get the name of the memory area
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+    /// Whether the memory is shared with other processes, e.g. `MAP_SHARED`
+    pub fn is_shared(&self) -> bool {
+        self.handler.is_shared()
+    }
     /// Check the array is within the readable memory
     fn check_read_array<S>(&self, ptr: *const S, count: usize) -> bool {
-        ptr as usize >= self.start_addr && unsafe { ptr.add(count) as usize } <= self.end_addr
+        // kernel-only (e.g. PROT_NONE) areas are not accessible by user pointers
+        self.attr.user
+            && ptr as usize >= self.start_addr
+            && unsafe { ptr.add(count) as usize } <= self.end_addr
     }
     /// Check the array is within the writable memory
     fn check_write_array<S>(&self, ptr: *mut S, count: usize) -> bool {
@@ -67,7 +90,7 @@ impl MemoryArea {
     ///
     /// Unsafe: the page table must be active.
     pub unsafe fn check_and_clone_cstr(&self, ptr: *const u8) -> Option<String> {
-        if ptr as usize >= self.end_addr {
+        if !self.attr.user || ptr as usize >= self.end_addr {
             return None;
         }
         let max_len = self.end_addr - ptr as usize;
@@ -76,8 +99,12 @@ impl MemoryArea {
             .and_then(|len| core::str::from_utf8(core::slice::from_raw_parts(ptr, len)).ok())
             .map(|s| String::from(s))
     }
-    /// Test whether this area is (page) overlap with area [`start_addr`, `end_addr`]
+    /// Test whether this area is (page) overlap with area [`start_addr`, `end_addr`)
+    /// An empty area, e.g. the heap before `brk`, overlaps nothing.
     pub fn is_overlap_with(&self, start_addr: VirtAddr, end_addr: VirtAddr) -> bool {
+        if self.start_addr >= self.end_addr || start_addr >= end_addr {
+            return false;
+        }
         let p0 = Page::of_addr(self.start_addr);
         let p1 = Page::of_addr(self.end_addr - 1) + 1;
         let p2 = Page::of_addr(start_addr);
@@ -87,35 +114,52 @@ impl MemoryArea {
     /*
      **  @brief  map the memory area to the physice address in a page table
      **  @param  pt: &mut T::Active   the page table to use
-     **  @retval none
+     **  @retval usize                the number of pages present after mapping
      */
-    fn map(&self, pt: &mut PageTable) {
+    fn map(&self, pt: &mut PageTable) -> usize {
+        let mut resident = 0;
         for page in Page::range_of(self.start_addr, self.end_addr) {
-            self.handler.map(pt, page.start_address(), &self.attr);
+            let addr = page.start_address();
+            self.handler.map(pt, addr, &self.attr);
+            resident += is_present(pt, addr) as usize;
         }
+        resident
     }
     /*
      **  @brief  map the memory area to the physice address in a page table eagerly
      **  @param  pt: &mut T::Active   the page table to use
-     **  @retval none
+     **  @retval usize                the number of pages present after mapping
      */
-    fn map_eager(&self, pt: &mut PageTable) {
+    fn map_eager(&self, pt: &mut PageTable) -> usize {
+        let mut resident = 0;
         for page in Page::range_of(self.start_addr, self.end_addr) {
-            self.handler.map_eager(pt, page.start_address(), &self.attr);
+            let addr = page.start_address();
+            self.handler.map_eager(pt, addr, &self.attr);
+            resident += is_present(pt, addr) as usize;
         }
+        resident
     }
     /*
      **  @brief  unmap the memory area from the physice address in a page table
      **  @param  pt: &mut T::Active   the page table to use
-     **  @retval none
+     **  @retval usize                the number of pages present before unmapping
      */
-    fn unmap(&self, pt: &mut PageTable) {
+    fn unmap(&self, pt: &mut PageTable) -> usize {
+        let mut resident = 0;
         for page in Page::range_of(self.start_addr, self.end_addr) {
-            self.handler.unmap(pt, page.start_address());
+            let addr = page.start_address();
+            resident += is_present(pt, addr) as usize;
+            self.handler.unmap(pt, addr);
         }
+        resident
     }
 }
 
+/// Whether the page of `addr` is present in `pt`
+fn is_present(pt: &mut PageTable, addr: VirtAddr) -> bool {
+    pt.get_entry(addr).map_or(false, |entry| entry.present())
+}
+
 /// The attributes of the memory
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
 pub struct MemoryAttr {
@@ -123,6 +167,8 @@ pub struct MemoryAttr {
     readonly: bool,
     execute: bool,
     mmio: u8,
+    /// the attributes can not be changed by `protect`
+    fixed: bool,
 }
 
 impl MemoryAttr {
@@ -166,11 +212,41 @@ impl MemoryAttr {
         self.mmio = value;
         self
     }
+    /// Forbid changing the attributes by `MemorySet::protect`,
+    /// e.g. of kernel pages mapped to user space
+    pub fn fixed(mut self) -> Self {
+        self.fixed = true;
+        self
+    }
+    /// Whether the attributes can not be changed
+    pub fn is_fixed(&self) -> bool {
+        self.fixed
+    }
+    /// Whether the memory is accessible by user
+    pub fn is_user(&self) -> bool {
+        self.user
+    }
+    /// Whether the memory is readonly
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+    /// Whether the memory is executable
+    pub fn is_execute(&self) -> bool {
+        self.execute
+    }
     /// Apply the attributes to page table entry, then update it.
     /// NOTE: You may need to set present manually.
+    ///
+    /// A page shared by copy-on-write is kept readonly,
+    /// only the writable bit to restore on write is changed.
     pub fn apply(&self, entry: &mut Entry) {
         entry.set_user(self.user);
-        entry.set_writable(!self.readonly);
+        if entry.writable_shared() || entry.readonly_shared() {
+            entry.set_shared(!self.readonly);
+            entry.set_writable(false);
+        } else {
+            entry.set_writable(!self.readonly);
+        }
         entry.set_execute(self.execute);
         entry.set_mmio(self.mmio);
         entry.update();
@@ -182,6 +258,9 @@ impl MemoryAttr {
 pub struct MemorySet<T: InactivePageTable> {
     areas: Vec<MemoryArea>,
     page_table: T,
+    /// number of pages present in the areas, counted as they are mapped,
+    /// unmapped and faulted in
+    resident: usize,
 }
 
 impl<T: InactivePageTable> MemorySet<T> {
@@ -193,12 +272,14 @@ impl<T: InactivePageTable> MemorySet<T> {
         MemorySet {
             areas: Vec::new(),
             page_table: T::new(),
+            resident: 0,
         }
     }
     pub fn new_bare() -> Self {
         MemorySet {
             areas: Vec::new(),
             page_table: T::new_bare(),
+            resident: 0,
         }
     }
     /// Check the pointer is within the readable memory
@@ -236,17 +317,31 @@ impl<T: InactivePageTable> MemorySet<T> {
             .next()
             .ok_or(VMError::InvalidPtr)
     }
+    /// Total size of the memory areas, i.e. the virtual memory used
+    pub fn size(&self) -> usize {
+        self.areas
+            .iter()
+            .map(|area| area.end_addr - area.start_addr)
+            .sum()
+    }
+    /// Total size of the pages present in the memory areas, i.e. the resident set size
+    pub fn resident_size(&self) -> usize {
+        self.resident * PAGE_SIZE
+    }
     /// Find a free area with hint address `addr_hint` and length `len`.
-    /// Return the start address of found free area.
+    /// Return the start address of found free area, or `None` if there is none.
     /// Used for mmap.
-    pub fn find_free_area(&self, addr_hint: usize, len: usize) -> VirtAddr {
+    pub fn find_free_area(&self, addr_hint: usize, len: usize) -> Option<VirtAddr> {
         // brute force:
         // try each area's end address as the start
         core::iter::once(addr_hint)
             .chain(self.areas.iter().map(|area| area.end_addr))
-            .map(|addr| (addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)) // round up a page
-            .find(|&addr| self.test_free_area(addr, addr + len))
-            .expect("failed to find free area ???")
+            .filter_map(|addr| addr.checked_add(PAGE_SIZE - 1))
+            .map(|addr| addr & !(PAGE_SIZE - 1)) // round up a page
+            .find(|&addr| match addr.checked_add(len) {
+                Some(end) => self.test_free_area(addr, end),
+                None => false,
+            })
     }
     /// Test if [`start_addr`, `end_addr`) is a free area
     fn test_free_area(&self, start_addr: usize, end_addr: usize) -> bool {
@@ -280,10 +375,57 @@ impl<T: InactivePageTable> MemorySet<T> {
             handler: Box::new(handler),
             name,
         };
-        self.page_table.edit(|pt| area.map(pt));
+        self.resident += self.page_table.edit(|pt| area.map(pt));
         self.areas.push(area);
     }
 
+    /*
+     **  @brief  move the end address of the memory area starting at start_addr,
+     **          and map or unmap the pages accordingly
+     **  @param  start_addr: VirtAddr  the start address of the memory area
+     **  @param  end_addr: VirtAddr    the new end address of the memory area
+     **  @retval VMResult<()>          Err if the area is not found or overlaps others
+     */
+    pub fn resize(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) -> VMResult<()> {
+        assert!(start_addr <= end_addr, "invalid memory area");
+        let i = self
+            .areas
+            .iter()
+            .position(|area| area.start_addr == start_addr)
+            .ok_or(VMError::InvalidPtr)?;
+        let old_end = self.areas[i].end_addr;
+        // the first page not belonging to the old/new area
+        let old_end_page = Page::of_addr(old_end + PAGE_SIZE - 1).start_address();
+        let new_end_page = Page::of_addr(end_addr + PAGE_SIZE - 1).start_address();
+        if new_end_page > old_end_page {
+            if !self.test_free_area(old_end_page, new_end_page) {
+                return Err(VMError::InvalidPtr);
+            }
+            let area = &self.areas[i];
+            let resident = &mut self.resident;
+            self.page_table.edit(|pt| {
+                for page in Page::range_of(old_end_page, new_end_page) {
+                    let addr = page.start_address();
+                    area.handler.map(pt, addr, &area.attr);
+                    *resident += is_present(pt, addr) as usize;
+                }
+            });
+        } else if new_end_page < old_end_page {
+            self.sync(new_end_page, old_end_page);
+            let area = &self.areas[i];
+            let resident = &mut self.resident;
+            self.page_table.edit(|pt| {
+                for page in Page::range_of(new_end_page, old_end_page) {
+                    let addr = page.start_address();
+                    *resident -= is_present(pt, addr) as usize;
+                    area.handler.unmap(pt, addr);
+                }
+            });
+        }
+        self.areas[i].end_addr = end_addr;
+        Ok(())
+    }
+
     /*
      **  @brief  remove the memory area from the memory set
      **  @param  area: MemoryArea     the memory area to remove
@@ -291,10 +433,11 @@ impl<T: InactivePageTable> MemorySet<T> {
      */
     pub fn pop(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) {
         assert!(start_addr <= end_addr, "invalid memory area");
+        self.sync(start_addr, end_addr);
         for i in 0..self.areas.len() {
             if self.areas[i].start_addr == start_addr && self.areas[i].end_addr == end_addr {
                 let area = self.areas.remove(i);
-                self.page_table.edit(|pt| area.unmap(pt));
+                self.resident -= self.page_table.edit(|pt| area.unmap(pt));
                 return;
             }
         }
@@ -308,13 +451,14 @@ impl<T: InactivePageTable> MemorySet<T> {
      */
     pub fn pop_with_split(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) {
         assert!(start_addr <= end_addr, "invalid memory area");
+        self.sync(start_addr, end_addr);
         let mut i = 0;
         while i < self.areas.len() {
             if self.areas[i].is_overlap_with(start_addr, end_addr) {
                 if self.areas[i].start_addr >= start_addr && self.areas[i].end_addr <= end_addr {
                     // subset
                     let area = self.areas.remove(i);
-                    self.page_table.edit(|pt| area.unmap(pt));
+                    self.resident -= self.page_table.edit(|pt| area.unmap(pt));
                     i -= 1;
                 } else if self.areas[i].start_addr >= start_addr
                     && self.areas[i].start_addr < end_addr
@@ -328,7 +472,7 @@ impl<T: InactivePageTable> MemorySet<T> {
                         handler: area.handler.box_clone(),
                         name: area.name,
                     };
-                    self.page_table.edit(|pt| dead_area.unmap(pt));
+                    self.resident -= self.page_table.edit(|pt| dead_area.unmap(pt));
                     let new_area = MemoryArea {
                         start_addr: end_addr,
                         end_addr: area.end_addr,
@@ -348,7 +492,7 @@ impl<T: InactivePageTable> MemorySet<T> {
                         handler: area.handler.box_clone(),
                         name: area.name,
                     };
-                    self.page_table.edit(|pt| dead_area.unmap(pt));
+                    self.resident -= self.page_table.edit(|pt| dead_area.unmap(pt));
                     let new_area = MemoryArea {
                         start_addr: area.start_addr,
                         end_addr: start_addr,
@@ -367,7 +511,7 @@ impl<T: InactivePageTable> MemorySet<T> {
                         handler: area.handler.box_clone(),
                         name: area.name,
                     };
-                    self.page_table.edit(|pt| dead_area.unmap(pt));
+                    self.resident -= self.page_table.edit(|pt| dead_area.unmap(pt));
                     let new_area_left = MemoryArea {
                         start_addr: area.start_addr,
                         end_addr: start_addr,
@@ -391,6 +535,120 @@ impl<T: InactivePageTable> MemorySet<T> {
         }
     }
 
+    /*
+     **  @brief  write back dirty pages of file mappings in [start_addr, end_addr)
+     **  @param  start_addr: VirtAddr  the start address of the memory
+     **  @param  end_addr: VirtAddr    the end address of the memory
+     **  @retval none
+     */
+    pub fn sync(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) {
+        if start_addr >= end_addr {
+            return;
+        }
+        let mut data = [0u8; PAGE_SIZE];
+        for area in self.areas.iter() {
+            if !area.is_overlap_with(start_addr, end_addr) {
+                continue;
+            }
+            let start = start_addr.max(area.start_addr);
+            let end = end_addr.min(area.end_addr);
+            let dirty_pages: Vec<_> = self.page_table.edit(|pt| {
+                Page::range_of(start, end)
+                    .map(|page| page.start_address())
+                    .filter(|&addr| area.handler.clear_dirty(pt, addr))
+                    .collect()
+            });
+            // the page table may be inactive, copy the pages through a buffer
+            for addr in dirty_pages {
+                let page = addr as *const [u8; PAGE_SIZE];
+                unsafe {
+                    self.page_table.with(|| data.copy_from_slice(&*page));
+                }
+                area.handler.write_back(addr, &data);
+            }
+        }
+    }
+
+    /*
+     **  @brief  change the attribute of the memory in [start_addr, end_addr),
+     **          and split existed areas when necessary
+     **  @param  start_addr: VirtAddr  the start address of the memory
+     **  @param  end_addr: VirtAddr    the end address of the memory
+     **  @param  attr: MemoryAttr      the new attribute
+     **  @retval VMResult<()>          Err if some pages in the range are not mapped,
+     **                                or belong to an area with fixed attributes
+     */
+    pub fn protect(
+        &mut self,
+        start_addr: VirtAddr,
+        end_addr: VirtAddr,
+        attr: MemoryAttr,
+    ) -> VMResult<()> {
+        assert!(start_addr <= end_addr, "invalid memory area");
+        if start_addr == end_addr {
+            return Ok(());
+        }
+        let start_addr = Page::of_addr(start_addr).start_address();
+        let end_addr = (Page::of_addr(end_addr - 1) + 1).start_address();
+        // the whole range must be mapped, otherwise nothing is changed
+        // areas, e.g. ELF segments, may start or end in the middle of a page
+        let mapped = Page::range_of(start_addr, end_addr).all(|page| {
+            let addr = page.start_address();
+            self.areas
+                .iter()
+                .any(|area| area.is_overlap_with(addr, addr + PAGE_SIZE))
+        });
+        let fixed = self
+            .areas
+            .iter()
+            .any(|area| area.attr.fixed && area.is_overlap_with(start_addr, end_addr));
+        if !mapped || fixed {
+            return Err(VMError::InvalidPtr);
+        }
+        self.split_at(start_addr);
+        self.split_at(end_addr);
+        let Self {
+            ref mut page_table,
+            ref mut areas,
+            ..
+        } = self;
+        for area in areas.iter_mut() {
+            if area.start_addr >= start_addr && area.end_addr <= end_addr {
+                area.attr = attr;
+                page_table.edit(|pt| {
+                    for page in Page::range_of(area.start_addr, area.end_addr) {
+                        if let Some(entry) = pt.get_entry(page.start_address()) {
+                            attr.apply(entry);
+                        }
+                    }
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Split the area which strictly contains `addr` into two at `addr`
+    fn split_at(&mut self, addr: VirtAddr) {
+        let i = match self
+            .areas
+            .iter()
+            .position(|area| area.start_addr < addr && addr < area.end_addr)
+        {
+            Some(i) => i,
+            None => return,
+        };
+        let area = &mut self.areas[i];
+        let right = MemoryArea {
+            start_addr: addr,
+            end_addr: area.end_addr,
+            attr: area.attr,
+            handler: area.handler.box_clone(),
+            name: area.name,
+        };
+        area.end_addr = addr;
+        self.areas.insert(i + 1, right);
+    }
+
     /*
      **  @brief  get iterator of the memory area
      **  @retval impl Iterator<Item=&MemoryArea>
@@ -429,6 +687,7 @@ impl<T: InactivePageTable> MemorySet<T> {
      **  @retval none
      */
     pub fn clear(&mut self) {
+        self.sync(0, usize::max_value());
         let Self {
             ref mut page_table,
             ref mut areas,
@@ -440,6 +699,7 @@ impl<T: InactivePageTable> MemorySet<T> {
             }
         });
         areas.clear();
+        self.resident = 0;
     }
 
     /// Get physical address of the page of given virtual `addr`
@@ -455,6 +715,11 @@ impl<T: InactivePageTable> MemorySet<T> {
         })
     }
 
+    /// Whether the page of `addr` is present, i.e. accessible without a page fault
+    pub fn is_present(&mut self, addr: VirtAddr) -> bool {
+        self.page_table.edit(|pt| is_present(pt, addr))
+    }
+
     /*
      **  @brief  get the mutable reference for the inactive page table
      **  @retval: &mut T                 the mutable reference of the inactive page table
@@ -463,12 +728,81 @@ impl<T: InactivePageTable> MemorySet<T> {
         &mut self.page_table
     }
 
+    /*
+     **  @brief  clone the memory set for fork
+     **          present pages are shared by copy-on-write if the handler supports,
+     **          otherwise they are copied eagerly
+     **          Unsafe: the page table must be active
+     **  @retval MemorySet<T>         the new memory set
+     */
+    pub unsafe fn clone_cow(&mut self) -> Self {
+        let mut page_table = T::new();
+        let mut resident = 0;
+        let mut copied_pages = Vec::new();
+        for area in self.areas.iter() {
+            let shared: Vec<_> = self.page_table.edit(|pt| {
+                Page::range_of(area.start_addr, area.end_addr)
+                    .map(|page| {
+                        let addr = page.start_address();
+                        if is_present(pt, addr) {
+                            (addr, Some(area.handler.share(pt, addr)))
+                        } else {
+                            (addr, None)
+                        }
+                    })
+                    .collect()
+            });
+            page_table.edit(|pt| {
+                for &(addr, target) in shared.iter() {
+                    match target {
+                        Some(Some(target)) => area.handler.map_shared(pt, addr, target, &area.attr),
+                        Some(None) => {
+                            area.handler.map_eager(pt, addr, &area.attr);
+                            copied_pages.push(addr);
+                        }
+                        None => area.handler.map(pt, addr, &area.attr),
+                    }
+                    resident += is_present(pt, addr) as usize;
+                }
+            });
+        }
+        // copy the pages not shared through a buffer
+        let mut data = [0u8; PAGE_SIZE];
+        for addr in copied_pages {
+            let page = addr as *mut [u8; PAGE_SIZE];
+            data.copy_from_slice(&*page);
+            page_table.with(|| (*page).copy_from_slice(&data));
+        }
+        MemorySet {
+            areas: self.areas.clone(),
+            page_table,
+            resident,
+        }
+    }
+
     pub fn handle_page_fault(&mut self, addr: VirtAddr) -> bool {
+        let area = self.areas.iter().find(|area| area.contains(addr));
+        let resident = &mut self.resident;
+        match area {
+            Some(area) => self.page_table.edit(|pt| {
+                let present = is_present(pt, addr);
+                let handled = area.handler.handle_page_fault(pt, addr);
+                if !present && is_present(pt, addr) {
+                    *resident += 1;
+                }
+                handled
+            }),
+            None => false,
+        }
+    }
+
+    /// Whether the page fault on `addr` would read a file, i.e. it is a major fault
+    pub fn is_major_fault(&mut self, addr: VirtAddr) -> bool {
         let area = self.areas.iter().find(|area| area.contains(addr));
         match area {
             Some(area) => self
                 .page_table
-                .edit(|pt| area.handler.handle_page_fault(pt, addr)),
+                .edit(|pt| area.handler.is_major_fault(pt, addr)),
             None => false,
         }
     }
@@ -477,15 +811,14 @@ impl<T: InactivePageTable> MemorySet<T> {
 impl<T: InactivePageTable> Clone for MemorySet<T> {
     fn clone(&self) -> Self {
         let mut page_table = T::new();
-        page_table.edit(|pt| {
+        let resident = page_table.edit(|pt| {
             // without CoW, we should allocate the pages eagerly
-            for area in self.areas.iter() {
-                area.map_eager(pt);
-            }
+            self.areas.iter().map(|area| area.map_eager(pt)).sum()
         });
         MemorySet {
             areas: self.areas.clone(),
             page_table,
+            resident,
         }
     }
 }
@@ -501,3 +834,180 @@ impl<T: InactivePageTable> Debug for MemorySet<T> {
         f.debug_list().entries(self.areas.iter()).finish()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::handler::Linear;
+    use super::*;
+
+    type MockMemorySet = MemorySet<MockInactivePageTable>;
+
+    fn attr() -> MemoryAttr {
+        MemoryAttr::default().user()
+    }
+
+    /// A memory set with an area of 4 pages at 0x4000
+    fn memory_set() -> MockMemorySet {
+        let mut ms = MockMemorySet::new();
+        ms.push(0x4000, 0x8000, attr(), Linear::new(0), "test");
+        ms
+    }
+
+    /// Ranges and whether readonly of the areas
+    fn areas(ms: &MockMemorySet) -> Vec<(VirtAddr, VirtAddr, bool)> {
+        ms.iter()
+            .map(|area| {
+                (
+                    area.start_addr(),
+                    area.end_addr(),
+                    area.attr().is_readonly(),
+                )
+            })
+            .collect()
+    }
+
+    fn writable(ms: &mut MockMemorySet, addr: VirtAddr) -> bool {
+        let mut writable = false;
+        ms.edit(|pt| writable = pt.get_entry(addr).unwrap().writable());
+        writable
+    }
+
+    #[test]
+    fn protect_split_in_middle() {
+        let mut ms = memory_set();
+        assert!(ms.protect(0x5000, 0x6000, attr().readonly()).is_ok());
+        assert_eq!(
+            areas(&ms),
+            [
+                (0x4000, 0x5000, false),
+                (0x5000, 0x6000, true),
+                (0x6000, 0x8000, false)
+            ]
+        );
+        assert!(writable(&mut ms, 0x4000));
+        assert!(!writable(&mut ms, 0x5000));
+        assert!(writable(&mut ms, 0x6000));
+    }
+
+    #[test]
+    fn protect_split_at_edge() {
+        let mut ms = memory_set();
+        // unaligned addresses are rounded to pages
+        assert!(ms.protect(0x4800, 0x4900, attr().readonly()).is_ok());
+        assert_eq!(
+            areas(&ms),
+            [(0x4000, 0x5000, true), (0x5000, 0x8000, false)]
+        );
+        assert!(ms.protect(0x7000, 0x8000, attr().readonly()).is_ok());
+        assert_eq!(
+            areas(&ms),
+            [
+                (0x4000, 0x5000, true),
+                (0x5000, 0x7000, false),
+                (0x7000, 0x8000, true)
+            ]
+        );
+        // the whole area is not split
+        let mut ms = memory_set();
+        assert!(ms.protect(0x4000, 0x8000, attr().readonly()).is_ok());
+        assert_eq!(areas(&ms), [(0x4000, 0x8000, true)]);
+    }
+
+    #[test]
+    fn protect_unaligned_area() {
+        let mut ms = MockMemorySet::new();
+        ms.push(0x4e10, 0x6000, attr(), Linear::new(0), "test");
+        assert!(ms.protect(0x4e10, 0x6000, attr().readonly()).is_ok());
+        assert_eq!(areas(&ms), [(0x4e10, 0x6000, true)]);
+        assert!(!writable(&mut ms, 0x4000));
+        assert!(ms.protect(0x5000, 0x5800, attr()).is_ok());
+        assert_eq!(
+            areas(&ms),
+            [(0x4e10, 0x5000, true), (0x5000, 0x6000, false)]
+        );
+        assert!(writable(&mut ms, 0x5000));
+    }
+
+    #[test]
+    fn protect_fixed() {
+        let mut ms = MockMemorySet::new();
+        let fixed = attr().readonly().fixed();
+        ms.push(0x4000, 0x6000, fixed, Linear::new(0), "test");
+        assert!(ms.protect(0x5000, 0x6000, attr()).is_err());
+        assert_eq!(areas(&ms), [(0x4000, 0x6000, true)]);
+        assert!(!writable(&mut ms, 0x5000));
+    }
+
+    #[test]
+    fn resize_grow() {
+        let mut ms = MockMemorySet::new();
+        ms.push(0x4000, 0x4000, attr(), Linear::new(0), "heap");
+        assert!(ms.resize(0x4000, 0x5800).is_ok());
+        assert_eq!(areas(&ms), [(0x4000, 0x5800, false)]);
+        assert!(ms.is_present(0x4000));
+        assert!(ms.is_present(0x5000));
+        assert!(!ms.is_present(0x6000));
+    }
+
+    #[test]
+    fn resize_shrink_to_zero() {
+        let mut ms = memory_set();
+        assert!(ms.resize(0x4000, 0x4000).is_ok());
+        assert_eq!(areas(&ms), [(0x4000, 0x4000, false)]);
+        assert!((0x4000..0x8000)
+            .step_by(0x1000)
+            .all(|addr| !ms.is_present(addr)));
+        // an empty area takes no page
+        ms.push(0x3000, 0x5000, attr(), Linear::new(0), "test");
+        assert!(ms.is_present(0x4000));
+        assert!(ms.resize(0x4000, 0x6000).is_err());
+    }
+
+    #[test]
+    fn resize_overlap() {
+        let mut ms = memory_set();
+        ms.push(0x9000, 0xa000, attr(), Linear::new(0), "test");
+        assert!(ms.resize(0x4000, 0x9800).is_err());
+        assert_eq!(areas(&ms)[0], (0x4000, 0x8000, false));
+        assert!(!ms.is_present(0x8000));
+        assert!(ms.resize(0x4000, 0x9000).is_ok());
+        assert!(ms.is_present(0x8000));
+        assert!(ms.resize(0x3000, 0x4000).is_err());
+    }
+
+    #[test]
+    fn overlap() {
+        let ms = memory_set();
+        let area = ms.iter().next().unwrap();
+        assert!(area.is_overlap_with(0x3000, 0x4001));
+        assert!(area.is_overlap_with(0x7fff, 0x9000));
+        assert!(!area.is_overlap_with(0x3000, 0x4000));
+        assert!(!area.is_overlap_with(0x8000, 0x9000));
+        assert!(!area.is_overlap_with(0x5000, 0x5000));
+        let mut ms = MockMemorySet::new();
+        ms.push(0x4000, 0x4000, attr(), Linear::new(0), "heap");
+        let empty = ms.iter().next().unwrap();
+        assert!(!empty.is_overlap_with(0x3000, 0x5000));
+    }
+
+    #[test]
+    fn resident_size() {
+        let mut ms = memory_set();
+        assert_eq!(ms.resident_size(), 4 * PAGE_SIZE);
+        ms.pop_with_split(0x5000, 0x6000);
+        assert_eq!(ms.resident_size(), 3 * PAGE_SIZE);
+        assert!(ms.resize(0x6000, 0x7000).is_ok());
+        assert_eq!(ms.resident_size(), 2 * PAGE_SIZE);
+        assert_eq!(ms.clone().resident_size(), 2 * PAGE_SIZE);
+        ms.clear();
+        assert_eq!(ms.resident_size(), 0);
+    }
+
+    #[test]
+    fn protect_unmapped() {
+        let mut ms = memory_set();
+        assert!(ms.protect(0x7000, 0x9000, attr().readonly()).is_err());
+        assert_eq!(areas(&ms), [(0x4000, 0x8000, false)]);
+        assert!(writable(&mut ms, 0x7000));
+    }
+}
@@ -4,7 +4,7 @@
 
 pub use self::ext::*;
 #[cfg(test)]
-pub use self::mock_page_table::MockPageTable;
+pub use self::mock_page_table::{MockInactivePageTable, MockPageTable};
 use super::*;
 
 mod ext;
@@ -14,9 +14,11 @@ fn main() {
     match arch.as_str() {
         "x86_64" => {
             gen_vector_asm().unwrap();
+            gen_vdso_asm().unwrap();
+        }
+        "riscv32" | "riscv64" => {
+            gen_vdso_asm().unwrap();
         }
-        "riscv32" => {}
-        "riscv64" => {}
         "mipsel" => {
             gen_dtb_asm(&arch, &board).unwrap();
         }
@@ -77,3 +79,33 @@ _dtb_end:
 
     Ok(())
 }
+
+fn gen_vdso_asm() -> Result<()> {
+    let vdso = std::env::var("VDSO").unwrap();
+
+    if !Path::new(&vdso).is_file() {
+        panic!("vDSO `{}` not found", vdso)
+    }
+
+    let mut f = File::create("src/vdso/vdso.gen.s").unwrap();
+
+    println!("cargo:rerun-if-changed={}", vdso);
+    println!("cargo:rerun-if-env-changed=VDSO");
+
+    writeln!(f, "# generated by build.rs - do not edit")?;
+    write!(
+        f,
+        r#"
+    .section .rodata
+    .p2align 12
+    .global _vdso_start, _vdso_end
+_vdso_start:
+    .incbin "{}"
+    .p2align 12
+_vdso_end:
+    "#,
+        vdso
+    )?;
+
+    Ok(())
+}
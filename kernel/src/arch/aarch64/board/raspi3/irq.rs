@@ -6,11 +6,11 @@ pub use bcm2837::interrupt::Interrupt;
 
 static IRQ_HANDLERS: &'static [Option<fn()>; 64] = &[None; 64];
 
-pub fn handle_irq(_tf: &mut TrapFrame) {
+pub fn handle_irq(tf: &mut TrapFrame) {
     let controller = bcm2837::timer::Timer::new();
     if controller.is_pending() {
         super::timer::set_next();
-        crate::trap::timer();
+        crate::trap::timer(tf);
     }
 
     for int in Controller::new().pending_interrupts() {
@@ -40,11 +40,57 @@ impl TrapFrame {
         tf.spsr = 0b1101_00_0000; // To EL 0, enable IRQ
         tf
     }
+    /// Whether the trap comes from EL0
     pub fn is_user(&self) -> bool {
-        unimplemented!()
+        self.spsr & 0b1111 == 0
+    }
+    /// Get user stack pointer
+    pub fn get_sp(&self) -> usize {
+        self.sp
+    }
+    /// Rewind to the `svc` instruction, to restart the syscall with `args`,
+    /// whose first one is overwritten by the return value
+    pub fn restart_syscall(&mut self, _id: usize, args: &[usize; 6]) {
+        self.elr -= 4;
+        self.x0 = args[0];
+    }
+    /// Redirect to the signal handler at `handler` with user stack `sp`.
+    ///
+    /// `ret` is set as the link register, `args` are passed in x0, x1, x2.
+    pub fn enter_signal_handler(
+        &mut self,
+        handler: usize,
+        sp: usize,
+        ret: usize,
+        args: [usize; 3],
+    ) {
+        self.elr = handler;
+        self.sp = sp;
+        self.x30 = ret;
+        self.x0 = args[0];
+        self.x1to29[0] = args[1];
+        self.x1to29[1] = args[2];
+    }
+    /// Restore user registers saved in a signal frame.
+    ///
+    /// Only the condition flags of SPSR can be changed.
+    pub fn restore_from_signal(&mut self, saved: &TrapFrame) {
+        const NZCV: usize = 0xf000_0000;
+        self.elr = saved.elr;
+        self.spsr = (self.spsr & !NZCV) | (saved.spsr & NZCV);
+        self.sp = saved.sp;
+        self.x1to29 = saved.x1to29;
+        self.x30 = saved.x30;
+        self.x0 = saved.x0;
     }
 }
 
+/// Code to call `rt_sigreturn`, placed at user space
+/// as the default return address of signal handlers
+///
+/// mov x8, #139; svc #0
+pub const SIGRETURN_TRAMPOLINE: [u8; 8] = [0x68, 0x11, 0x80, 0xd2, 0x01, 0x00, 0x00, 0xd4];
+
 /// 新线程的内核栈初始内容
 #[derive(Debug)]
 #[repr(C)]
@@ -184,17 +230,21 @@ impl Context {
     }
     pub unsafe fn new_clone(
         tf: &TrapFrame,
-        ustack_top: usize,
+        ustack_top: Option<usize>,
         kstack_top: usize,
         ttbr: usize,
-        tls: usize,
+        tls: Option<usize>,
     ) -> Self {
         InitStack {
             context: ContextData::new(),
             tf: {
                 let mut tf = tf.clone();
-                tf.sp = ustack_top;
-                tf.tpidr = tls;
+                if let Some(ustack_top) = ustack_top {
+                    tf.sp = ustack_top;
+                }
+                if let Some(tls) = tls {
+                    tf.tpidr = tls;
+                }
                 tf.x0 = 0;
                 tf
             },
@@ -3,6 +3,7 @@
 use super::context::TrapFrame;
 use super::syndrome::{Fault, Syndrome};
 use crate::arch::board::irq::handle_irq;
+use crate::arch::syscall::SYS_RT_SIGRETURN;
 
 use aarch64::regs::*;
 use log::*;
@@ -42,6 +43,7 @@ pub struct Info {
 #[no_mangle]
 pub extern "C" fn rust_trap(info: Info, esr: u32, tf: &mut TrapFrame) {
     trace!("Interrupt: {:?}, ELR: {:#x?}", info, tf.elr);
+    crate::trap::enter(tf);
     match info.kind {
         Kind::Synchronous => {
             let syndrome = Syndrome::from(esr);
@@ -63,6 +65,10 @@ pub extern "C" fn rust_trap(info: Info, esr: u32, tf: &mut TrapFrame) {
         Kind::Irq => handle_irq(tf),
         _ => crate::trap::error(tf),
     }
+    if tf.is_user() {
+        crate::signal::handle_signal(tf);
+    }
+    crate::trap::exit(tf);
     trace!("Interrupt end");
 }
 
@@ -77,8 +83,9 @@ fn handle_syscall(num: u16, tf: &mut TrapFrame) {
     }
 
     // svc instruction has been skipped in syscall (ref: J1.1.2, page 6152)
+    let id = tf.x1to29[7] as usize;
     let ret = crate::syscall::syscall(
-        tf.x1to29[7] as usize,
+        id,
         [
             tf.x0,
             tf.x1to29[0],
@@ -89,13 +96,16 @@ fn handle_syscall(num: u16, tf: &mut TrapFrame) {
         ],
         tf,
     );
-    tf.x0 = ret as usize;
+    // the whole TrapFrame is restored by sigreturn
+    if id != SYS_RT_SIGRETURN {
+        tf.x0 = ret as usize;
+    }
 }
 
 fn handle_page_fault(tf: &mut TrapFrame) {
     let addr = FAR_EL1.get() as usize;
     if !crate::memory::handle_page_fault(addr) {
         error!("\nEXCEPTION: Page Fault @ {:#x}", addr);
-        crate::trap::error(tf);
+        crate::trap::segfault(tf);
     }
 }
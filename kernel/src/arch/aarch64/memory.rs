@@ -2,7 +2,7 @@
 
 use super::paging::MMIOType;
 use crate::consts::{KERNEL_OFFSET, MEMORY_OFFSET};
-use crate::memory::{init_heap, Linear, MemoryAttr, MemorySet, FRAME_ALLOCATOR};
+use crate::memory::{init_heap, insert_frames, Linear, MemoryAttr, MemorySet, FRAME_ALLOCATOR};
 use aarch64::regs::*;
 use log::*;
 use rcore_memory::PAGE_SIZE;
@@ -16,7 +16,6 @@ pub fn init() {
 }
 
 fn init_frame_allocator() {
-    use bitmap_allocator::BitAlloc;
     use core::ops::Range;
 
     let end = super::board::probe_memory()
@@ -24,7 +23,7 @@ fn init_frame_allocator() {
         .1;
     let start = (_end as u64 + PAGE_SIZE as u64).wrapping_sub(KERNEL_OFFSET as u64) as usize;
     let mut ba = FRAME_ALLOCATOR.lock();
-    ba.insert(to_range(start, end));
+    insert_frames(&mut ba, to_range(start, end));
     info!("FrameAllocator init end");
 
     /// Transform memory area `[start, end)` to integer range for `FrameAllocator`
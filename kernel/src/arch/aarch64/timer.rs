@@ -1,3 +1,14 @@
+use aarch64::regs::*;
+use core::time::Duration;
+
+/// Time since boot, by the counter of the generic timer
+pub fn timer_now() -> Duration {
+    let (count, freq) = (CNTPCT_EL0.get(), CNTFRQ_EL0.get() as u64);
+    Duration::from_secs(count / freq) + Duration::from_nanos(count % freq * 1_000_000_000 / freq)
+}
+
 pub fn read_epoch() -> u64 {
     0
 }
+
+pub fn write_epoch(_epoch: u64) {}
@@ -87,8 +87,66 @@ impl TrapFrame {
         tf.status.set_exl();
         tf
     }
+
+    /// Whether the trap comes from user mode
+    pub fn is_user(&self) -> bool {
+        // KSU == 0b10
+        self.status.bits & 0x18 == 0x10
+    }
+
+    /// Get user stack pointer
+    pub fn get_sp(&self) -> usize {
+        self.sp
+    }
+
+    /// Rewind to the `syscall` instruction, to restart syscall `id` with `args`,
+    /// as the return value and error flag overwrite v0 and a3
+    pub fn restart_syscall(&mut self, id: usize, args: &[usize; 6]) {
+        self.epc -= 4;
+        self.v0 = id;
+        self.a3 = args[3];
+    }
+
+    /// Redirect to the signal handler at `handler` with user stack `sp`.
+    ///
+    /// `ret` is set as the return address, `args` are passed in a0, a1, a2.
+    pub fn enter_signal_handler(
+        &mut self,
+        handler: usize,
+        sp: usize,
+        ret: usize,
+        args: [usize; 3],
+    ) {
+        self.epc = handler;
+        self.sp = sp;
+        self.ra = ret;
+        // PIC code expects function address in t9
+        self.t9 = handler;
+        self.a0 = args[0];
+        self.a1 = args[1];
+        self.a2 = args[2];
+    }
+
+    /// Restore user registers saved in a signal frame.
+    ///
+    /// CP0 registers except EPC are kept.
+    pub fn restore_from_signal(&mut self, saved: &TrapFrame) {
+        let status = self.status.clone();
+        let cause = self.cause.clone();
+        let vaddr = self.vaddr;
+        *self = saved.clone();
+        self.status = status;
+        self.cause = cause;
+        self.vaddr = vaddr;
+    }
 }
 
+/// Code to call `rt_sigreturn`, placed at user space
+/// as the default return address of signal handlers
+///
+/// li v0, 4193; syscall
+pub const SIGRETURN_TRAMPOLINE: [u8; 8] = [0x61, 0x10, 0x02, 0x24, 0x0c, 0x00, 0x00, 0x00];
+
 use core::fmt::{Debug, Error, Formatter};
 impl Debug for TrapFrame {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
@@ -242,22 +300,26 @@ impl Context {
     ///
     /// The stack pointer in kernel mode will be set to `kstack_top`.
     /// The SATP register will be set to `satp`.
-    /// The new user stack will be set to `ustack_top`.
-    /// The new thread pointer will be set to `tls`.
+    /// The new user stack will be set to `ustack_top` if specified.
+    /// The new thread pointer will be set to `tls` if specified.
     /// All the other registers are same as the original.
     pub unsafe fn new_clone(
         tf: &TrapFrame,
-        ustack_top: usize,
+        ustack_top: Option<usize>,
         kstack_top: usize,
         satp: usize,
-        tls: usize,
+        tls: Option<usize>,
     ) -> Self {
         InitStack {
             context: ContextData::new(satp),
             tf: {
                 let mut tf = tf.clone();
-                tf.sp = ustack_top; // sp
-                tf.v1 = tls;
+                if let Some(ustack_top) = ustack_top {
+                    tf.sp = ustack_top; // sp
+                }
+                if let Some(tls) = tls {
+                    tf.v1 = tls;
+                }
                 tf.v0 = 0; // return value
                 tf
             },
@@ -64,6 +64,7 @@ pub unsafe fn restore(flags: usize) {
 pub extern "C" fn rust_trap(tf: &mut TrapFrame) {
     use cp0::cause::Exception as E;
     trace!("Exception @ CPU{}: {:?} ", 0, tf.cause.cause());
+    crate::trap::enter(tf);
     match tf.cause.cause() {
         E::Interrupt => interrupt_dispatcher(tf),
         E::Syscall => syscall(tf),
@@ -75,6 +76,10 @@ pub extern "C" fn rust_trap(tf: &mut TrapFrame) {
             crate::trap::error(tf)
         }
     }
+    if tf.is_user() {
+        crate::signal::handle_signal(tf);
+    }
+    crate::trap::exit(tf);
     trace!("Interrupt end");
 }
 
@@ -82,7 +87,7 @@ fn interrupt_dispatcher(tf: &mut TrapFrame) {
     let pint = tf.cause.pending_interrupt();
     trace!("  Interrupt {:08b} ", pint);
     if (pint & 0b100_000_00) != 0 {
-        timer();
+        timer(tf);
     } else if (pint & 0b011_111_00) != 0 {
         external();
     } else {
@@ -127,9 +132,9 @@ fn ipi() {
     cp0::cause::reset_soft_int1();
 }
 
-fn timer() {
+fn timer(tf: &TrapFrame) {
     super::timer::set_next();
-    crate::trap::timer();
+    crate::trap::timer(tf);
 }
 
 fn syscall(tf: &mut TrapFrame) {
@@ -137,7 +142,12 @@ fn syscall(tf: &mut TrapFrame) {
     let arguments = [tf.a0, tf.a1, tf.a2, tf.a3, tf.t0, tf.t1];
     trace!("MIPS syscall {} invoked with {:?}", tf.v0, arguments);
 
-    let ret = crate::syscall::syscall(tf.v0, arguments, tf) as isize;
+    let id = tf.v0;
+    let ret = crate::syscall::syscall(id, arguments, tf) as isize;
+    // the whole TrapFrame is restored by sigreturn
+    if id == super::syscall::SYS_RT_SIGRETURN {
+        return;
+    }
     // comply with mips n32 abi, always return a positive value
     // https://git.musl-libc.org/cgit/musl/tree/arch/mipsn32/syscall_arch.h
     if (ret < 0) {
@@ -168,7 +178,7 @@ fn page_fault(tf: &mut TrapFrame) {
         }
         Err(()) => {
             if !crate::memory::handle_page_fault(addr) {
-                crate::trap::error(tf);
+                crate::trap::segfault(tf);
             }
         }
     }
@@ -1,11 +1,35 @@
+use crate::consts::USEC_PER_TICK;
+use core::time::Duration;
 use log::*;
 use mips::registers::cp0;
 
+/// Cycles of the count register per tick, i.e. 100Hz @ QEMU
+const TIMEBASE: u32 = 250000;
+
+/// Time since boot, by ticks and the count register reset on each of them
+pub fn timer_now() -> Duration {
+    let nsec_per_cycle = USEC_PER_TICK as u64 * 1000 / TIMEBASE as u64;
+    let read_tick = || unsafe { core::ptr::read_volatile(&crate::trap::TICK) };
+    loop {
+        let tick = read_tick();
+        // it may go beyond the timebase before the tick is handled
+        let count = cp0::count::read_u32().min(TIMEBASE) as u64;
+        if tick == read_tick() {
+            return Duration::from_micros((tick * USEC_PER_TICK) as u64)
+                + Duration::from_nanos(count * nsec_per_cycle);
+        }
+    }
+}
+
 pub fn read_epoch() -> u64 {
     // TODO: support RTC
     0
 }
 
+pub fn write_epoch(_epoch: u64) {
+    // TODO: support RTC
+}
+
 /// Enable timer interrupt
 pub fn init() {
     // Enable supervisor timer interrupt
@@ -17,8 +41,6 @@ pub fn init() {
 
 /// Set the next timer interrupt
 pub fn set_next() {
-    // 100Hz @ QEMU
-    let timebase = 250000;
     cp0::count::write_u32(0);
-    cp0::compare::write_u32(timebase);
+    cp0::compare::write_u32(TIMEBASE);
 }
@@ -51,8 +51,58 @@ impl TrapFrame {
         tf.sstatus.set_spp(sstatus::SPP::User);
         tf
     }
+
+    /// Whether the trap comes from user mode
+    pub fn is_user(&self) -> bool {
+        self.sstatus.spp() == sstatus::SPP::User
+    }
+
+    /// Get user stack pointer
+    pub fn get_sp(&self) -> usize {
+        self.x[2]
+    }
+
+    /// Rewind to the `ecall` instruction, to restart the syscall with `args`,
+    /// whose first one is overwritten by the return value
+    pub fn restart_syscall(&mut self, _id: usize, args: &[usize; 6]) {
+        self.sepc -= 4;
+        self.x[10] = args[0];
+    }
+
+    /// Redirect to the signal handler at `handler` with user stack `sp`.
+    ///
+    /// `ret` is set as the return address, `args` are passed in a0, a1, a2.
+    pub fn enter_signal_handler(
+        &mut self,
+        handler: usize,
+        sp: usize,
+        ret: usize,
+        args: [usize; 3],
+    ) {
+        self.sepc = handler;
+        self.x[2] = sp;
+        self.x[1] = ret;
+        self.x[10] = args[0];
+        self.x[11] = args[1];
+        self.x[12] = args[2];
+    }
+
+    /// Restore user registers saved in a signal frame.
+    ///
+    /// `sstatus` is kept.
+    pub fn restore_from_signal(&mut self, saved: &TrapFrame) {
+        self.x = saved.x;
+        self.x[0] = 0;
+        self.sepc = saved.sepc;
+    }
 }
 
+/// Code to call `rt_sigreturn`, placed at user space
+/// as the default return address of signal handlers
+///
+/// li a7, 139; ecall
+pub const SIGRETURN_TRAMPOLINE: [u8; 8] = [0x93, 0x08, 0xb0, 0x08, 0x73, 0x00, 0x00, 0x00];
+
 use core::fmt::{Debug, Error, Formatter};
 impl Debug for TrapFrame {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
@@ -267,22 +317,26 @@ impl Context {
     ///
     /// The stack pointer in kernel mode will be set to `kstack_top`.
     /// The SATP register will be set to `satp`.
-    /// The new user stack will be set to `ustack_top`.
-    /// The new thread pointer will be set to `tls`.
+    /// The new user stack will be set to `ustack_top` if specified.
+    /// The new thread pointer will be set to `tls` if specified.
     /// All the other registers are same as the original.
     pub unsafe fn new_clone(
         tf: &TrapFrame,
-        ustack_top: usize,
+        ustack_top: Option<usize>,
         kstack_top: usize,
         satp: usize,
-        tls: usize,
+        tls: Option<usize>,
     ) -> Self {
         InitStack {
             context: ContextData::new(satp),
             tf: {
                 let mut tf = tf.clone();
-                tf.x[2] = ustack_top; // sp
-                tf.x[4] = tls; // tp
+                if let Some(ustack_top) = ustack_top {
+                    tf.x[2] = ustack_top; // sp
+                }
+                if let Some(tls) = tls {
+                    tf.x[4] = tls; // tp
+                }
                 tf.x[10] = 0; // a0
                 tf
             },
@@ -60,16 +60,21 @@ pub extern "C" fn rust_trap(tf: &mut TrapFrame) {
         super::cpu::id(),
         tf.scause.cause()
     );
+    crate::trap::enter(tf);
     match tf.scause.cause() {
         Trap::Interrupt(I::SupervisorExternal) => external(),
         Trap::Interrupt(I::SupervisorSoft) => ipi(),
-        Trap::Interrupt(I::SupervisorTimer) => timer(),
+        Trap::Interrupt(I::SupervisorTimer) => timer(tf),
         Trap::Exception(E::UserEnvCall) => syscall(tf),
         Trap::Exception(E::LoadPageFault) => page_fault(tf),
         Trap::Exception(E::StorePageFault) => page_fault(tf),
         Trap::Exception(E::InstructionPageFault) => page_fault(tf),
         _ => crate::trap::error(tf),
     }
+    if tf.is_user() {
+        crate::signal::handle_signal(tf);
+    }
+    crate::trap::exit(tf);
     trace!("Interrupt end");
 }
 
@@ -112,19 +117,23 @@ fn ipi() {
     super::sbi::clear_ipi();
 }
 
-fn timer() {
+fn timer(tf: &TrapFrame) {
     super::timer::set_next();
-    crate::trap::timer();
+    crate::trap::timer(tf);
 }
 
 fn syscall(tf: &mut TrapFrame) {
     tf.sepc += 4; // Must before syscall, because of fork.
+    let id = tf.x[17];
     let ret = crate::syscall::syscall(
-        tf.x[17],
+        id,
         [tf.x[10], tf.x[11], tf.x[12], tf.x[13], tf.x[14], tf.x[15]],
         tf,
     );
-    tf.x[10] = ret as usize;
+    // the whole TrapFrame is restored by sigreturn
+    if id != super::syscall::SYS_RT_SIGRETURN {
+        tf.x[10] = ret as usize;
+    }
 }
 
 fn page_fault(tf: &mut TrapFrame) {
@@ -132,6 +141,6 @@ fn page_fault(tf: &mut TrapFrame) {
     trace!("\nEXCEPTION: Page Fault @ {:#x}", addr);
 
     if !crate::memory::handle_page_fault(addr) {
-        crate::trap::error(tf);
+        crate::trap::segfault(tf);
     }
 }
@@ -1,7 +1,12 @@
 use super::sbi;
+use crate::consts::USEC_PER_TICK;
+use core::time::Duration;
 use log::*;
 use riscv::register::*;
 
+/// Cycles of the `time` CSR per tick, i.e. 100Hz @ QEMU
+const TIMEBASE: u64 = 250000;
+
 #[cfg(target_pointer_width = "64")]
 pub fn get_cycle() -> u64 {
     time::read() as u64
@@ -19,16 +24,38 @@ pub fn get_cycle() -> u64 {
     }
 }
 
+/// Cycles of the `time` CSR per second
+pub fn cycle_frequency() -> u64 {
+    TIMEBASE * (1_000_000 / USEC_PER_TICK) as u64
+}
+
+/// Time since boot at `cycle` of the `time` CSR
+pub fn cycle_to_duration(cycle: u64) -> Duration {
+    let nsec_per_cycle = USEC_PER_TICK as u64 * 1000 / TIMEBASE;
+    Duration::from_nanos(cycle * nsec_per_cycle)
+}
+
+/// Time since boot, by the `time` CSR
+pub fn timer_now() -> Duration {
+    cycle_to_duration(get_cycle())
+}
+
 pub fn read_epoch() -> u64 {
     // TODO: support RTC
     0
 }
 
+pub fn write_epoch(_epoch: u64) {
+    // TODO: support RTC
+}
+
 /// Enable timer interrupt
 pub fn init() {
     // Enable supervisor timer interrupt
     unsafe {
         sie::set_stimer();
+        // Let user mode read the time CSR, for the vDSO
+        asm!("csrs scounteren, $0" :: "r"(1 << 1) :: "volatile");
     }
     set_next();
     info!("timer: init end");
@@ -36,7 +63,5 @@ pub fn init() {
 
 /// Set the next timer interrupt
 pub fn set_next() {
-    // 100Hz @ QEMU
-    let timebase = 250000;
-    sbi::set_timer(get_cycle() + timebase);
+    sbi::set_timer(get_cycle() + TIMEBASE);
 }
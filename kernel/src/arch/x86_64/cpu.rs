@@ -39,6 +39,9 @@ pub fn init() {
         Cr0::update(|cr0| {
             cr0.remove(Cr0Flags::EMULATE_COPROCESSOR);
             cr0.insert(Cr0Flags::MONITOR_COPROCESSOR);
+            // kernel should also fault on writing readonly pages,
+            // so that copy-on-write pages are copied before written by syscalls
+            cr0.insert(Cr0Flags::WRITE_PROTECT);
         });
     }
 }
@@ -1,3 +1,4 @@
+use core::arch::x86_64::_rdtsc;
 use log::*;
 use once::*;
 use x86_64::instructions::port::Port;
@@ -41,5 +42,29 @@ impl Pit {
 
 const TIMER_FREQ: u32 = 1193182;
 const TIMER_SEL0: u8 = 0x00; // select counter 0
+const TIMER_SEL2: u8 = 0x80; // select counter 2
+const TIMER_INTTC: u8 = 0x00; // mode 0, intr on terminal cnt
 const TIMER_RATEGEN: u8 = 0x04; // mode 2, rate generator
 const TIMER_16BIT: u8 = 0x30; // r/w counter 16 bits, LSB first
+
+/// Measure the frequency of the TSC, by counting it for 10 ms on channel 2
+pub fn measure_tsc_frequency() -> u64 {
+    const MSEC: u32 = 10;
+    let mut pit = Pit::new(0x40);
+    let mut gate = Port::<u8>::new(0x61);
+    unsafe {
+        // enable the gate of channel 2, with the speaker off
+        let value = gate.read();
+        gate.write(value & !0x02 | 0x01);
+        pit.command.write(TIMER_SEL2 | TIMER_INTTC | TIMER_16BIT);
+        let count = TIMER_FREQ * MSEC / 1000;
+        pit.chan2.write((count & 0xFF) as u8);
+        pit.chan2.write((count >> 8) as u8);
+        let start = _rdtsc();
+        // the output goes high when it counts to 0
+        while gate.read() & 0x20 == 0 {}
+        let end = _rdtsc();
+        gate.write(value);
+        (end - start) * 1000 / MSEC as u64
+    }
+}
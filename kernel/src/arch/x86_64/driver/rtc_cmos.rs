@@ -21,10 +21,22 @@ unsafe fn read_rtc(reg: u8) -> u8 {
     return data.read();
 }
 
+unsafe fn write_rtc(reg: u8, value: u8) {
+    let mut addr = Port::<u8>::new(CMOS_ADDR);
+    let mut data = Port::<u8>::new(CMOS_DATA);
+
+    addr.write(reg);
+    data.write(value);
+}
+
 fn bcd2bin(num: u64) -> u64 {
     (num & 0x0f) + (num >> 4) * 10
 }
 
+fn bin2bcd(num: u64) -> u64 {
+    (num / 10) << 4 | num % 10
+}
+
 // read seconds since 1970-01-01
 pub fn read_epoch() -> u64 {
     unsafe {
@@ -76,3 +88,50 @@ pub fn read_epoch() -> u64 {
         result
     }
 }
+
+// set the clock to seconds since 1970-01-01
+pub fn write_epoch(epoch: u64) {
+    let mut second = epoch % 60;
+    let mut minute = epoch / 60 % 60;
+    let mut hour = epoch / 3600 % 24;
+
+    // days to civil date
+    let days = epoch / 86400 + 719468;
+    let era = days / 146097;
+    let doe = days % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let mut day = doy - (153 * mp + 2) / 5 + 1;
+    let mut month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let mut year = (era * 400 + yoe + (month <= 2) as u64) % 100;
+
+    unsafe {
+        let flags = interrupt::disable_and_store();
+
+        // stop updating while setting
+        let control = read_rtc(0x0B);
+        write_rtc(0x0B, control | 0x80);
+
+        if (control & 0x04) == 0 {
+            // BCD
+            second = bin2bcd(second);
+            minute = bin2bcd(minute);
+            hour = bin2bcd(hour);
+            day = bin2bcd(day);
+            month = bin2bcd(month);
+            year = bin2bcd(year);
+        }
+
+        write_rtc(0x00, second as u8);
+        write_rtc(0x02, minute as u8);
+        write_rtc(0x04, hour as u8);
+        write_rtc(0x07, day as u8);
+        write_rtc(0x08, month as u8);
+        write_rtc(0x09, year as u8);
+
+        write_rtc(0x0B, control & !0x80);
+
+        interrupt::restore(flags);
+    }
+}
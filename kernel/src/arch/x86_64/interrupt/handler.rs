@@ -66,6 +66,7 @@
 
 use super::consts::*;
 use super::TrapFrame;
+use crate::arch::syscall::SYS_RT_SIGRETURN;
 use crate::drivers::DRIVERS;
 use bitflags::*;
 use log::*;
@@ -81,6 +82,7 @@ pub extern "C" fn rust_trap(tf: &mut TrapFrame) {
         tf.trap_num,
         super::super::cpu::id()
     );
+    crate::trap::enter(tf);
     // Dispatch
     match tf.trap_num as u8 {
         Breakpoint => breakpoint(),
@@ -90,19 +92,20 @@ pub extern "C" fn rust_trap(tf: &mut TrapFrame) {
             let irq = tf.trap_num as u8 - IRQ0;
             super::ack(irq); // must ack before switching
             match irq {
-                Timer => crate::trap::timer(),
+                Timer => crate::trap::timer(tf),
                 Keyboard => keyboard(),
                 COM1 => com1(),
                 COM2 => com2(),
                 IDE => ide(),
                 _ => {
-                    for driver in DRIVERS.read().iter() {
-                        if driver.try_handle_interrupt(Some(irq.into())) == true {
-                            debug!("driver processed interrupt");
-                            return;
-                        }
+                    let handled = DRIVERS
+                        .read()
+                        .iter()
+                        .any(|driver| driver.try_handle_interrupt(Some(irq.into())));
+                    match handled {
+                        true => debug!("driver processed interrupt"),
+                        false => warn!("unhandled external IRQ number: {}", irq),
                     }
-                    warn!("unhandled external IRQ number: {}", irq);
                 }
             }
         }
@@ -111,6 +114,10 @@ pub extern "C" fn rust_trap(tf: &mut TrapFrame) {
         DivideError | GeneralProtectionFault => error(tf),
         _ => panic!("Unhandled interrupt {:x}", tf.trap_num),
     }
+    if tf.is_user() {
+        crate::signal::handle_signal(tf);
+    }
+    crate::trap::exit(tf);
 }
 
 fn breakpoint() {
@@ -143,7 +150,7 @@ fn page_fault(tf: &mut TrapFrame) {
         return;
     }
     error!("\nEXCEPTION: Page Fault @ {:#x}, code: {:?}", addr, code);
-    error(tf);
+    crate::trap::segfault(tf);
 }
 
 fn keyboard() {
@@ -185,11 +192,30 @@ fn ide() {
     trace!("\nInterupt: IDE");
 }
 
+/// Entry of `syscall` instruction, called from `syscall_entry` at 'trap.asm'
+///
+/// Return true if the TrapFrame is restored by `rt_sigreturn`,
+/// then we must return by `iret` instead of `sysret`,
+/// which would clobber rcx and r11.
 #[no_mangle]
-pub extern "C" fn syscall(tf: &mut TrapFrame) {
+pub extern "C" fn syscall(tf: &mut TrapFrame) -> bool {
+    crate::trap::enter(tf);
+    let restored = syscall64(tf);
+    crate::signal::handle_signal(tf);
+    crate::trap::exit(tf);
+    restored
+}
+
+/// Return true if the TrapFrame is restored by `rt_sigreturn`
+fn syscall64(tf: &mut TrapFrame) -> bool {
     trace!("\nInterupt: Syscall {:#x?}", tf.rax);
-    let ret = crate::syscall::syscall(tf.rax, [tf.rdi, tf.rsi, tf.rdx, tf.r10, tf.r8, tf.r9], tf);
+    let id = tf.rax;
+    let ret = crate::syscall::syscall(id, [tf.rdi, tf.rsi, tf.rdx, tf.r10, tf.r8, tf.r9], tf);
+    if id == SYS_RT_SIGRETURN {
+        return true;
+    }
     tf.rax = ret as usize;
+    false
 }
 
 fn syscall32(tf: &mut TrapFrame) {
@@ -204,7 +230,7 @@ fn invalid_opcode(tf: &mut TrapFrame) {
     const SYSCALL_OPCODE: u16 = 0x05_0f;
     if opcode == SYSCALL_OPCODE {
         tf.rip += 2; // must before syscall
-        syscall(tf);
+        syscall64(tf);
     } else {
         crate::trap::error(tf);
     }
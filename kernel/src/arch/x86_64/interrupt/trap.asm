@@ -168,11 +168,15 @@ syscall_entry:
     mov rdi, rsp
     call syscall
 
-syscall_return:
-
     # disable interrupt
     cli
 
+    # return by iret if the whole TrapFrame is restored by sigreturn
+    test al, al
+    jnz trap_ret
+
+syscall_return:
+
     mov rdi, rsp
     call set_return_rsp
 
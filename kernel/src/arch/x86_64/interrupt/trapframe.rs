@@ -84,8 +84,83 @@ impl TrapFrame {
         tf.fpstate_offset = 16; // skip restoring for first time
         tf
     }
+    /// Whether the trap comes from user mode
+    pub fn is_user(&self) -> bool {
+        self.cs & 0x3 == 0x3
+    }
+    /// Get user stack pointer
+    pub fn get_sp(&self) -> usize {
+        self.rsp
+    }
+    /// Rewind to the `syscall` or `int 0x80` instruction, to restart syscall `id`
+    pub fn restart_syscall(&mut self, id: usize, _args: &[usize; 6]) {
+        self.rip -= 2;
+        self.rax = id;
+    }
+    /// Redirect to the signal handler at `handler` with user stack `sp`.
+    ///
+    /// `ret` is pushed as the return address, `args` are passed in rdi, rsi, rdx.
+    pub fn enter_signal_handler(
+        &mut self,
+        handler: usize,
+        sp: usize,
+        ret: usize,
+        args: [usize; 3],
+    ) {
+        let sp = sp - 8;
+        unsafe {
+            (sp as *mut usize).write(ret);
+        }
+        self.rip = handler;
+        self.rsp = sp;
+        self.rdi = args[0];
+        self.rsi = args[1];
+        self.rdx = args[2];
+        // clear DF and TF
+        self.rflags &= !0x500;
+    }
+    /// Restore user registers saved in a signal frame.
+    ///
+    /// Privileged state like segments and IF flag is kept.
+    pub fn restore_from_signal(&mut self, saved: &TrapFrame) {
+        // CF, PF, AF, ZF, SF, TF, DF, OF, RF, AC
+        const USER_FLAGS: usize = 0x50dd5;
+        // copy FPU state if both are valid
+        if self.fpstate_offset < 16 && saved.fpstate_offset < 16 {
+            let dst = 16 - self.fpstate_offset;
+            let src = 16 - saved.fpstate_offset;
+            (self.fpstate.0)[dst..dst + 512].copy_from_slice(&(saved.fpstate.0)[src..src + 512]);
+            // clear reserved bits of MXCSR, otherwise fxrstor faults
+            (self.fpstate.0)[dst + 26] = 0;
+            (self.fpstate.0)[dst + 27] = 0;
+        }
+        self.r15 = saved.r15;
+        self.r14 = saved.r14;
+        self.r13 = saved.r13;
+        self.r12 = saved.r12;
+        self.rbp = saved.rbp;
+        self.rbx = saved.rbx;
+        self.r11 = saved.r11;
+        self.r10 = saved.r10;
+        self.r9 = saved.r9;
+        self.r8 = saved.r8;
+        self.rsi = saved.rsi;
+        self.rdi = saved.rdi;
+        self.rdx = saved.rdx;
+        self.rcx = saved.rcx;
+        self.rax = saved.rax;
+        self.rip = saved.rip;
+        self.rsp = saved.rsp;
+        self.rflags = (self.rflags & !USER_FLAGS) | (saved.rflags & USER_FLAGS);
+    }
 }
 
+/// Code to call `rt_sigreturn`, placed at user space
+/// as the default return address of signal handlers
+///
+/// mov eax, 15; syscall; nop
+pub const SIGRETURN_TRAMPOLINE: [u8; 8] = [0xb8, 0x0f, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x90];
+
 #[derive(Debug, Default)]
 #[repr(C)]
 struct ContextData {
@@ -217,17 +292,21 @@ impl Context {
     }
     pub unsafe fn new_clone(
         tf: &TrapFrame,
-        ustack_top: usize,
+        ustack_top: Option<usize>,
         kstack_top: usize,
         cr3: usize,
-        tls: usize,
+        tls: Option<usize>,
     ) -> Self {
         InitStack {
             context: ContextData::new(cr3),
             tf: {
                 let mut tf = tf.clone();
-                tf.rsp = ustack_top;
-                tf.fsbase = tls;
+                if let Some(ustack_top) = ustack_top {
+                    tf.rsp = ustack_top;
+                }
+                if let Some(tls) = tls {
+                    tf.fsbase = tls;
+                }
                 tf.rax = 0;
                 tf
             },
@@ -1,8 +1,7 @@
 use crate::consts::KERNEL_OFFSET;
-use bitmap_allocator::BitAlloc;
 // Depends on kernel
 use super::{BootInfo, MemoryRegionType};
-use crate::memory::{active_table, alloc_frame, init_heap, FRAME_ALLOCATOR};
+use crate::memory::{active_table, alloc_frame, init_heap, insert_frames, FRAME_ALLOCATOR};
 use crate::HEAP_ALLOCATOR;
 use alloc::vec::Vec;
 use log::*;
@@ -24,7 +23,8 @@ fn init_frame_allocator(boot_info: &BootInfo) {
     let mut ba = FRAME_ALLOCATOR.lock();
     for region in boot_info.memory_map.iter() {
         if region.region_type == MemoryRegionType::Usable {
-            ba.insert(
+            insert_frames(
+                &mut ba,
                 region.range.start_frame_number as usize..region.range.end_frame_number as usize,
             );
         }
@@ -46,6 +46,8 @@ pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
 
     driver::init();
 
+    timer::init();
+
     crate::drivers::init();
 
     crate::process::init();
@@ -1,3 +1,44 @@
+use super::driver::{pit, rtc_cmos};
+use core::arch::x86_64::_rdtsc;
+use core::time::Duration;
+use log::*;
+
+/// Cycles of the TSC per second, measured at boot
+static mut TSC_FREQUENCY: u64 = 0;
+
+/// Measure the frequency of the TSC
+pub fn init() {
+    let freq = pit::measure_tsc_frequency();
+    unsafe {
+        TSC_FREQUENCY = freq;
+    }
+    info!("timer: TSC frequency {} Hz", freq);
+}
+
+pub fn get_cycle() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Cycles of the TSC per second
+pub fn cycle_frequency() -> u64 {
+    unsafe { TSC_FREQUENCY }
+}
+
+/// Time since boot at TSC `cycle`
+pub fn cycle_to_duration(cycle: u64) -> Duration {
+    let freq = cycle_frequency();
+    Duration::from_secs(cycle / freq) + Duration::from_nanos(cycle % freq * 1_000_000_000 / freq)
+}
+
+/// Time since boot, by the TSC
+pub fn timer_now() -> Duration {
+    cycle_to_duration(get_cycle())
+}
+
 pub fn read_epoch() -> u64 {
-    super::driver::rtc_cmos::read_epoch()
+    rtc_cmos::read_epoch()
+}
+
+pub fn write_epoch(epoch: u64) {
+    rtc_cmos::write_epoch(epoch)
 }
@@ -1,4 +1,5 @@
 use alloc::boxed::Box;
+use alloc::collections::VecDeque;
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec;
@@ -20,7 +21,8 @@ use crate::memory::active_table;
 use crate::sync::SpinNoIrqLock as Mutex;
 
 use super::super::bus::virtio_mmio::*;
-use super::super::{DeviceType, Driver, DRIVERS};
+use super::super::{DeviceType, Driver, DRIVERS, INPUT_ACTIVITY};
+use super::InputEvent;
 
 struct VirtIOInput {
     interrupt_parent: u32,
@@ -30,8 +32,13 @@ struct VirtIOInput {
     queues: [VirtIOVirtqueue; 2],
     x: isize,
     y: isize,
+    /// events not read yet, for /dev/input
+    events: VecDeque<InputEvent>,
 }
 
+/// Max number of unread events, older ones are dropped
+const MAX_EVENTS: usize = 256;
+
 const VIRTIO_INPUT_CFG_UNSET: u8 = 0x00;
 const VIRTIO_INPUT_CFG_ID_NAME: u8 = 0x01;
 const VIRTIO_INPUT_CFG_ID_SERIAL: u8 = 0x02;
@@ -145,12 +152,24 @@ impl VirtIOInput {
                         self.y += event.value as isize;
                     }
                     trace!("got {}", event);
+                    let msec = crate::trap::uptime_msec();
+                    if self.events.len() == MAX_EVENTS {
+                        self.events.pop_front();
+                    }
+                    self.events.push_back(InputEvent {
+                        sec: msec / 1000,
+                        usec: msec % 1000 * 1000,
+                        event_type: event.event_type,
+                        code: event.code,
+                        value: event.value as i32,
+                    });
                     self.queues[VIRTIO_QUEUE_EVENT].add(&input, &output, 0);
                 } else {
                     break;
                 }
             }
             println!("mouse is at x {} y {}", self.x, self.y);
+            INPUT_ACTIVITY.notify_all();
             return true;
         }
         return false;
@@ -169,6 +188,14 @@ impl Driver for VirtIOInputDriver {
     fn get_id(&self) -> String {
         String::from("virtio_input")
     }
+
+    fn read_event(&self) -> Option<InputEvent> {
+        self.0.lock().events.pop_front()
+    }
+
+    fn has_event(&self) -> bool {
+        !self.0.lock().events.is_empty()
+    }
 }
 
 pub fn virtio_input_init(node: &Node) {
@@ -207,6 +234,7 @@ pub fn virtio_input_init(node: &Node) {
         queues,
         x: 0,
         y: 0,
+        events: VecDeque::new(),
     };
 
     let buffer = vec![VirtIOInputEvent::default(); queue_num];
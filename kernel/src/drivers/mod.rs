@@ -6,6 +6,7 @@ use lazy_static::lazy_static;
 use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, Ipv4Address};
 use spin::RwLock;
 
+use self::input::InputEvent;
 use crate::sync::Condvar;
 use rcore_fs::dev::BlockDevice;
 
@@ -17,7 +18,7 @@ mod device_tree;
 #[allow(dead_code)]
 mod gpu;
 #[allow(dead_code)]
-mod input;
+pub mod input;
 #[allow(dead_code)]
 pub mod net;
 mod provider;
@@ -89,6 +90,17 @@ pub trait Driver: Send + Sync {
     fn write_block(&self, block_id: usize, buf: &[u8]) -> bool {
         unimplemented!("not a block driver")
     }
+
+    // input related drivers should implement these
+    // pop the earliest input event
+    fn read_event(&self) -> Option<InputEvent> {
+        unimplemented!("not an input driver")
+    }
+
+    // whether there is an input event to read
+    fn has_event(&self) -> bool {
+        unimplemented!("not an input driver")
+    }
 }
 
 lazy_static! {
@@ -112,7 +124,8 @@ impl BlockDevice for BlockDriver {
 }
 
 lazy_static! {
-    pub static ref SOCKET_ACTIVITY: Condvar = Condvar::new();
+    pub static ref SOCKET_ACTIVITY: Arc<Condvar> = Arc::new(Condvar::new());
+    pub static ref INPUT_ACTIVITY: Arc<Condvar> = Arc::new(Condvar::new());
 }
 
 #[cfg(any(target_arch = "riscv32", target_arch = "riscv64", target_arch = "mips"))]
@@ -0,0 +1,700 @@
+//! Device file system, publishing kernel devices as inodes under /dev
+
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::any::Any;
+use core::mem::size_of;
+use core::slice;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use rcore_fs::dev::Device;
+use rcore_fs::vfs::*;
+use spin::RwLock;
+
+use super::{Pty, Tty, CONSOLE};
+use crate::drivers::input::InputEvent;
+use crate::drivers::{DeviceType, Driver, BLK_DRIVERS, DRIVERS, INPUT_ACTIVITY};
+use crate::process::Credentials;
+use crate::signal::has_signal_to_handle;
+use crate::sync::Condvar;
+use crate::syscall::SysError;
+
+lazy_static! {
+    /// The device file system
+    pub static ref DEVFS: Arc<DevFS> = DevFS::new();
+}
+
+/// Allocate an inode number, unique in devfs
+fn new_inode_id() -> usize {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+    NEXT_ID.fetch_add(1, Ordering::SeqCst)
+}
+
+fn metadata(inode: usize, type_: FileType, mode: u16, size: usize) -> Metadata {
+    Metadata {
+        dev: 0,
+        inode,
+        size,
+        blk_size: 0,
+        blocks: 0,
+        atime: Timespec { sec: 0, nsec: 0 },
+        mtime: Timespec { sec: 0, nsec: 0 },
+        ctime: Timespec { sec: 0, nsec: 0 },
+        type_,
+        mode,
+        nlinks: 1,
+        uid: 0,
+        gid: 0,
+    }
+}
+
+pub struct DevFS {
+    root: Arc<DevDir>,
+    /// `/dev/pts`, the slaves of pseudo-terminals
+    pts: Arc<DevDir>,
+}
+
+impl DevFS {
+    fn new() -> Arc<Self> {
+        let root = DevDir::new(None);
+        root.add("null", Arc::new(NullINode::new()));
+        root.add("zero", Arc::new(ZeroINode::new()));
+        let random = Arc::new(RandomINode::new());
+        root.add("random", random.clone());
+        root.add("urandom", random);
+        root.add("tty", Arc::new(TtyINode::new()));
+        root.add("ptmx", Arc::new(PtmxINode::new()));
+        let pts = DevDir::new(Some(&root));
+        root.add("pts", pts.clone());
+
+        #[cfg(any(target_arch = "aarch64", target_arch = "mips"))]
+        {
+            if crate::arch::board::fb::FRAME_BUFFER.lock().is_some() {
+                root.add("fb0", Arc::new(FbINode::new()));
+            }
+        }
+
+        for (i, driver) in BLK_DRIVERS.read().iter().enumerate() {
+            let name = format!("sd{}", (b'a' + i as u8) as char);
+            root.add(&name, Arc::new(BlockINode::new(driver.clone())));
+        }
+
+        let input = DevDir::new(Some(&root));
+        let input_drivers: Vec<_> = DRIVERS
+            .read()
+            .iter()
+            .filter(|driver| driver.device_type() == DeviceType::Input)
+            .cloned()
+            .collect();
+        for (i, driver) in input_drivers.into_iter().enumerate() {
+            let name = format!("event{}", i);
+            input.add(&name, Arc::new(InputINode::new(driver)));
+        }
+        root.add("input", input);
+
+        Arc::new(DevFS { root, pts })
+    }
+}
+
+impl FileSystem for DevFS {
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn root_inode(&self) -> Arc<INode> {
+        self.root.clone()
+    }
+
+    fn info(&self) -> FsInfo {
+        FsInfo { max_file_size: 0 }
+    }
+}
+
+/// Directory of devices
+struct DevDir {
+    id: usize,
+    entries: RwLock<BTreeMap<String, Arc<INode>>>,
+    this: RwLock<Weak<DevDir>>,
+    parent: RwLock<Weak<DevDir>>,
+}
+
+impl DevDir {
+    /// Create a directory under `parent`, or a root directory
+    fn new(parent: Option<&Arc<DevDir>>) -> Arc<Self> {
+        let dir = Arc::new(DevDir {
+            id: new_inode_id(),
+            entries: RwLock::new(BTreeMap::new()),
+            this: RwLock::new(Weak::new()),
+            parent: RwLock::new(Weak::new()),
+        });
+        *dir.this.write() = Arc::downgrade(&dir);
+        *dir.parent.write() = Arc::downgrade(parent.unwrap_or(&dir));
+        dir
+    }
+
+    fn add(&self, name: &str, inode: Arc<INode>) {
+        self.entries.write().insert(String::from(name), inode);
+    }
+
+    fn remove(&self, name: &str) {
+        self.entries.write().remove(name);
+    }
+}
+
+impl INode for DevDir {
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+    fn poll(&self) -> Result<PollStatus> {
+        Err(FsError::IsDir)
+    }
+    fn metadata(&self) -> Result<Metadata> {
+        let size = self.entries.read().len();
+        Ok(metadata(self.id, FileType::Dir, 0o755, size))
+    }
+    fn set_metadata(&self, _metadata: &Metadata) -> Result<()> {
+        Ok(())
+    }
+    fn sync_all(&self) -> Result<()> {
+        Ok(())
+    }
+    fn sync_data(&self) -> Result<()> {
+        Ok(())
+    }
+    fn resize(&self, _len: usize) -> Result<()> {
+        Err(FsError::IsDir)
+    }
+    fn create(&self, _name: &str, _type_: FileType, _mode: u32) -> Result<Arc<INode>> {
+        Err(FsError::NotSupported)
+    }
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn link(&self, _name: &str, _other: &Arc<INode>) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn move_(&self, _old_name: &str, _target: &Arc<INode>, _new_name: &str) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn find(&self, name: &str) -> Result<Arc<INode>> {
+        let dir = match name {
+            "." => self.this.read().upgrade(),
+            ".." => self.parent.read().upgrade(),
+            _ => None,
+        };
+        if let Some(dir) = dir {
+            return Ok(dir);
+        }
+        self.entries
+            .read()
+            .get(name)
+            .cloned()
+            .ok_or(FsError::EntryNotFound)
+    }
+    fn get_entry(&self, id: usize) -> Result<String> {
+        match id {
+            0 => Ok(String::from(".")),
+            1 => Ok(String::from("..")),
+            _ => self
+                .entries
+                .read()
+                .keys()
+                .nth(id - 2)
+                .cloned()
+                .ok_or(FsError::EntryNotFound),
+        }
+    }
+    fn io_control(&self, _cmd: u32, _data: u32) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn fs(&self) -> Arc<FileSystem> {
+        DEVFS.clone()
+    }
+    fn as_any_ref(&self) -> &Any {
+        self
+    }
+}
+
+// TODO: better way to provide default impl?
+macro_rules! impl_device_inode {
+    ($type_:expr, $mode:expr) => {
+        fn metadata(&self) -> Result<Metadata> { Ok(metadata(self.id, $type_, $mode, 0)) }
+        impl_device_inode!();
+    };
+    () => {
+        fn set_metadata(&self, _metadata: &Metadata) -> Result<()> { Ok(()) }
+        fn sync_all(&self) -> Result<()> { Ok(()) }
+        fn sync_data(&self) -> Result<()> { Ok(()) }
+        // truncating a device is a no-op, e.g. `echo > /dev/null`
+        fn resize(&self, _len: usize) -> Result<()> { Ok(()) }
+        fn create(&self, _name: &str, _type_: FileType, _mode: u32) -> Result<Arc<INode>> { Err(FsError::NotDir) }
+        fn unlink(&self, _name: &str) -> Result<()> { Err(FsError::NotDir) }
+        fn link(&self, _name: &str, _other: &Arc<INode>) -> Result<()> { Err(FsError::NotDir) }
+        fn move_(&self, _old_name: &str, _target: &Arc<INode>, _new_name: &str) -> Result<()> { Err(FsError::NotDir) }
+        fn find(&self, _name: &str) -> Result<Arc<INode>> { Err(FsError::NotDir) }
+        fn get_entry(&self, _id: usize) -> Result<String> { Err(FsError::NotDir) }
+        fn io_control(&self, _cmd: u32, _data: u32) -> Result<()> { Err(FsError::NotSupported) }
+        fn fs(&self) -> Arc<FileSystem> { DEVFS.clone() }
+        fn as_any_ref(&self) -> &Any { self }
+    };
+}
+
+/// `/dev/null`, discarding all writes
+struct NullINode {
+    id: usize,
+}
+
+impl NullINode {
+    fn new() -> Self {
+        NullINode { id: new_inode_id() }
+    }
+}
+
+impl INode for NullINode {
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Ok(0)
+    }
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        Ok(buf.len())
+    }
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: true,
+            write: true,
+            error: false,
+        })
+    }
+    impl_device_inode!(FileType::CharDevice, 0o666);
+}
+
+/// `/dev/zero`, reading zeros forever
+struct ZeroINode {
+    id: usize,
+}
+
+impl ZeroINode {
+    fn new() -> Self {
+        ZeroINode { id: new_inode_id() }
+    }
+}
+
+impl INode for ZeroINode {
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        buf.iter_mut().for_each(|x| *x = 0);
+        Ok(buf.len())
+    }
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        Ok(buf.len())
+    }
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: true,
+            write: true,
+            error: false,
+        })
+    }
+    impl_device_inode!(FileType::CharDevice, 0o666);
+}
+
+/// `/dev/random` and `/dev/urandom`, backed by `arch::rand`
+struct RandomINode {
+    id: usize,
+}
+
+impl RandomINode {
+    fn new() -> Self {
+        RandomINode { id: new_inode_id() }
+    }
+}
+
+impl INode for RandomINode {
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        for chunk in buf.chunks_mut(8) {
+            let bytes: [u8; 8] = unsafe { core::mem::transmute(crate::arch::rand::rand()) };
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+        Ok(buf.len())
+    }
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        Ok(buf.len())
+    }
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: true,
+            write: true,
+            error: false,
+        })
+    }
+    impl_device_inode!(FileType::CharDevice, 0o666);
+}
+
+/// `/dev/tty`, the console behind stdin and stdout
+pub struct TtyINode {
+    id: usize,
+}
+
+impl TtyINode {
+    fn new() -> Self {
+        TtyINode { id: new_inode_id() }
+    }
+}
+
+impl INode for TtyINode {
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        CONSOLE.read(buf).map_err(|_| FsError::InvalidParam)
+    }
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        CONSOLE.write(buf).map_err(|_| FsError::InvalidParam)
+    }
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: CONSOLE.can_read(),
+            write: true,
+            error: false,
+        })
+    }
+    impl_device_inode!(FileType::CharDevice, 0o666);
+}
+
+/// `/dev/fb0`, the memory of the framebuffer
+#[cfg(any(target_arch = "aarch64", target_arch = "mips"))]
+struct FbINode {
+    id: usize,
+}
+
+#[cfg(any(target_arch = "aarch64", target_arch = "mips"))]
+impl FbINode {
+    fn new() -> Self {
+        FbINode { id: new_inode_id() }
+    }
+
+    /// The framebuffer memory
+    fn buffer(&self) -> Result<&'static mut [u8]> {
+        let fb = crate::arch::board::fb::FRAME_BUFFER.lock();
+        let fb = fb.as_ref().ok_or(FsError::DeviceError)?;
+        let size = fb.fb_info.screen_size as usize;
+        Ok(unsafe { slice::from_raw_parts_mut(fb.base_addr() as *mut u8, size) })
+    }
+}
+
+#[cfg(any(target_arch = "aarch64", target_arch = "mips"))]
+impl INode for FbINode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let data = self.buffer()?;
+        if offset >= data.len() {
+            return Ok(0);
+        }
+        let len = buf.len().min(data.len() - offset);
+        buf[..len].copy_from_slice(&data[offset..offset + len]);
+        Ok(len)
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        let data = self.buffer()?;
+        if offset >= data.len() {
+            return Err(FsError::NoDeviceSpace);
+        }
+        let len = buf.len().min(data.len() - offset);
+        data[offset..offset + len].copy_from_slice(&buf[..len]);
+        Ok(len)
+    }
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: true,
+            write: true,
+            error: false,
+        })
+    }
+    impl_device_inode!(FileType::CharDevice, 0o660);
+}
+
+/// Block device, e.g. `/dev/sda`
+struct BlockINode {
+    id: usize,
+    device: Arc<Device>,
+}
+
+impl BlockINode {
+    fn new(device: Arc<Device>) -> Self {
+        BlockINode {
+            id: new_inode_id(),
+            device,
+        }
+    }
+}
+
+impl INode for BlockINode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        self.device.read_at(offset, buf).ok_or(FsError::DeviceError)
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        self.device
+            .write_at(offset, buf)
+            .ok_or(FsError::DeviceError)
+    }
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: true,
+            write: true,
+            error: false,
+        })
+    }
+    impl_device_inode!(FileType::BlockDevice, 0o660);
+}
+
+/// Input event device, e.g. `/dev/input/event0`
+pub struct InputINode {
+    id: usize,
+    driver: Arc<Driver>,
+}
+
+impl InputINode {
+    fn new(driver: Arc<Driver>) -> Self {
+        InputINode {
+            id: new_inode_id(),
+            driver,
+        }
+    }
+
+    /// Read whole events, blocking until there is one.
+    /// Fail with EINTR if a signal arrives meanwhile.
+    pub fn read(&self, buf: &mut [u8]) -> core::result::Result<usize, SysError> {
+        const EVENT_SIZE: usize = size_of::<InputEvent>();
+        if buf.len() < EVENT_SIZE {
+            return Err(SysError::EINVAL);
+        }
+        loop {
+            let seq = INPUT_ACTIVITY.events();
+            let mut len = 0;
+            while len + EVENT_SIZE <= buf.len() {
+                let event = match self.driver.read_event() {
+                    Some(event) => event,
+                    None => break,
+                };
+                let data = unsafe {
+                    slice::from_raw_parts(&event as *const InputEvent as *const u8, EVENT_SIZE)
+                };
+                buf[len..len + EVENT_SIZE].copy_from_slice(data);
+                len += EVENT_SIZE;
+            }
+            if len > 0 {
+                return Ok(len);
+            }
+            // the events arrived since `seq` are not missed
+            Condvar::wait_any_unless(&[&INPUT_ACTIVITY], || INPUT_ACTIVITY.events() != seq);
+            if has_signal_to_handle() {
+                return Err(SysError::EINTR);
+            }
+        }
+    }
+}
+
+impl INode for InputINode {
+    /// EINTR can not be told by `FsError`, so `FileLike` reads by `InputINode::read`
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        self.read(buf).map_err(|_| FsError::InvalidParam)
+    }
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::NotSupported)
+    }
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: self.driver.has_event(),
+            write: false,
+            error: false,
+        })
+    }
+    impl_device_inode!(FileType::CharDevice, 0o660);
+}
+
+/// Open `inode` found in devfs, which may become another one:
+/// opening `/dev/ptmx` makes a new pseudo-terminal, the slave is counted,
+/// and `/dev/tty` is the slave if the controlling terminal `ctty` is one.
+/// A new slave is owned by the user of `cred`, which opens `/dev/ptmx`.
+pub fn open_device(
+    inode: Arc<INode>,
+    ctty: Option<Arc<Tty>>,
+    cred: &Credentials,
+) -> core::result::Result<Arc<INode>, SysError> {
+    let any = inode.as_any_ref();
+    if any.is::<PtmxINode>() {
+        return Ok(Arc::new(PtyMaster::new(cred)));
+    }
+    let entry = if any.is::<PtySlave>() {
+        Some(inode.clone())
+    } else if any.is::<TtyINode>() {
+        ctty.and_then(find_pty_slave)
+    } else {
+        None
+    };
+    match entry {
+        Some(entry) => {
+            let slave = entry.as_any_ref().downcast_ref::<PtySlave>().unwrap();
+            Ok(Arc::new(slave.open()?))
+        }
+        None => Ok(inode),
+    }
+}
+
+/// The entry in `/dev/pts` of the pseudo-terminal slave `tty`, if it is one
+fn find_pty_slave(tty: Arc<Tty>) -> Option<Arc<INode>> {
+    DEVFS.pts.entries.read().values().find_map(|inode| {
+        let slave = inode.as_any_ref().downcast_ref::<PtySlave>()?;
+        match Arc::ptr_eq(slave.pty.tty(), &tty) {
+            true => Some(inode.clone()),
+            false => None,
+        }
+    })
+}
+
+/// `/dev/ptmx`, opened as a new pseudo-terminal master
+struct PtmxINode {
+    id: usize,
+}
+
+impl PtmxINode {
+    fn new() -> Self {
+        PtmxINode { id: new_inode_id() }
+    }
+}
+
+impl INode for PtmxINode {
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Err(FsError::NotSupported)
+    }
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::NotSupported)
+    }
+    fn poll(&self) -> Result<PollStatus> {
+        Err(FsError::NotSupported)
+    }
+    impl_device_inode!(FileType::CharDevice, 0o666);
+}
+
+/// Master of a pseudo-terminal, which is closed when dropped
+pub struct PtyMaster {
+    id: usize,
+    pty: Arc<Pty>,
+}
+
+impl PtyMaster {
+    /// Make a pseudo-terminal with the lowest free number,
+    /// and publish its slave in `/dev/pts`, owned by the user of `cred`
+    fn new(cred: &Credentials) -> Self {
+        let mut entries = DEVFS.pts.entries.write();
+        let index = (0..)
+            .find(|i| !entries.contains_key(&format!("{}", i)))
+            .unwrap();
+        let pty = Arc::new(Pty::new(index));
+        let slave = PtySlave {
+            id: new_inode_id(),
+            pty: pty.clone(),
+            uid: cred.euid as usize,
+            gid: cred.egid as usize,
+            opened: false,
+        };
+        entries.insert(format!("{}", index), Arc::new(slave));
+        PtyMaster {
+            id: new_inode_id(),
+            pty,
+        }
+    }
+
+    pub fn pty(&self) -> &Arc<Pty> {
+        &self.pty
+    }
+}
+
+impl Drop for PtyMaster {
+    fn drop(&mut self) {
+        DEVFS.pts.remove(&format!("{}", self.pty.index()));
+        self.pty.close_master();
+    }
+}
+
+impl INode for PtyMaster {
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        self.pty.master_read(buf).map_err(|_| FsError::InvalidParam)
+    }
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        self.pty
+            .master_write(buf)
+            .map_err(|_| FsError::InvalidParam)
+    }
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: self.pty.master_can_read(),
+            write: self.pty.master_can_write(),
+            error: false,
+        })
+    }
+    impl_device_inode!(FileType::CharDevice, 0o666);
+}
+
+/// Slave of a pseudo-terminal, e.g. `/dev/pts/0`.
+/// The one in `/dev/pts` is not opened, while each open makes a new one,
+/// which is counted until dropped.
+pub struct PtySlave {
+    id: usize,
+    pty: Arc<Pty>,
+    /// owner, i.e. the user opening `/dev/ptmx`
+    uid: usize,
+    gid: usize,
+    opened: bool,
+}
+
+impl PtySlave {
+    /// Open the slave of the entry in `/dev/pts`
+    fn open(&self) -> core::result::Result<Self, SysError> {
+        self.pty.open_slave()?;
+        Ok(PtySlave {
+            id: self.id,
+            pty: self.pty.clone(),
+            uid: self.uid,
+            gid: self.gid,
+            opened: true,
+        })
+    }
+
+    pub fn pty(&self) -> &Arc<Pty> {
+        &self.pty
+    }
+}
+
+impl Drop for PtySlave {
+    fn drop(&mut self) {
+        if self.opened {
+            self.pty.close_slave();
+        }
+    }
+}
+
+impl INode for PtySlave {
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        self.pty.tty().read(buf).map_err(|_| FsError::InvalidParam)
+    }
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        self.pty.tty().write(buf).map_err(|_| FsError::InvalidParam)
+    }
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: self.pty.tty().can_read(),
+            write: self.pty.slave_can_write(),
+            error: self.pty.tty().is_hung_up(),
+        })
+    }
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata {
+            uid: self.uid,
+            gid: self.gid,
+            ..metadata(self.id, FileType::CharDevice, 0o620, 0)
+        })
+    }
+    impl_device_inode!();
+}
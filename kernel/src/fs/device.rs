@@ -1,6 +1,8 @@
 //! Implement Device
 
+use alloc::sync::Arc;
 use rcore_fs::dev::*;
+use rcore_fs::vfs::INode;
 use spin::RwLock;
 
 #[cfg(target_arch = "x86_64")]
@@ -33,6 +35,18 @@ impl Device for MemBuf {
     }
 }
 
+/// Device backed by an inode, e.g. a block device node or an image file
+pub struct INodeDevice(pub Arc<INode>);
+
+impl Device for INodeDevice {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Option<usize> {
+        self.0.read_at(offset, buf).ok()
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Option<usize> {
+        self.0.write_at(offset, buf).ok()
+    }
+}
+
 #[cfg(target_arch = "x86_64")]
 impl BlockDevice for ide::IDE {
     const BLOCK_SIZE_LOG2: u8 = 9;
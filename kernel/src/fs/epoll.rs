@@ -0,0 +1,167 @@
+//! Epoll instance, i.e. the interest list of an epoll file descriptor
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use bitflags::*;
+use rcore_fs::vfs::PollStatus;
+
+use crate::syscall::SysError;
+
+bitflags! {
+    pub struct EpollFlags: u32 {
+        /// There is data to read.
+        const IN = 0x001;
+        /// There is urgent data to read.
+        const PRI = 0x002;
+        /// Writing is now possible.
+        const OUT = 0x004;
+        /// Error condition, always reported.
+        const ERR = 0x008;
+        /// Hang up, always reported.
+        const HUP = 0x010;
+        /// Peer closed its writing half.
+        const RDHUP = 0x2000;
+        /// Wake up only one of the epoll instances waiting on the fd.
+        const EXCLUSIVE = 1 << 28;
+        /// Prevent system suspend. (ignored)
+        const WAKEUP = 1 << 29;
+        /// Disable the fd after an event is reported, until it is modified.
+        const ONESHOT = 1 << 30;
+        /// Edge-triggered, report only when the fd becomes ready.
+        const ET = 1 << 31;
+    }
+}
+
+impl EpollFlags {
+    /// Events of the poll status
+    pub fn from_status(status: &PollStatus) -> Self {
+        let mut flags = EpollFlags::empty();
+        if status.read {
+            flags |= EpollFlags::IN;
+        }
+        if status.write {
+            flags |= EpollFlags::OUT;
+        }
+        if status.error {
+            flags |= EpollFlags::HUP;
+        }
+        flags
+    }
+}
+
+/// `struct epoll_event`, which is packed on x86_64
+#[repr(C)]
+#[cfg_attr(target_arch = "x86_64", repr(packed))]
+#[derive(Debug, Copy, Clone)]
+pub struct EpollEvent {
+    pub events: EpollFlags,
+    pub data: u64,
+}
+
+pub const EPOLL_CLOEXEC: usize = 0o2000000;
+
+pub const EPOLL_CTL_ADD: usize = 1;
+pub const EPOLL_CTL_DEL: usize = 2;
+pub const EPOLL_CTL_MOD: usize = 3;
+
+#[derive(Debug)]
+struct EpollEntry {
+    event: EpollEvent,
+    /// the event sequence of the fd reported last time, for edge-triggered mode
+    last_seq: Option<usize>,
+    /// a oneshot fd is disabled after reported
+    disabled: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct EpollInstance {
+    entries: BTreeMap<usize, EpollEntry>,
+}
+
+impl EpollInstance {
+    pub fn new() -> Self {
+        EpollInstance::default()
+    }
+
+    /// Add, modify or delete the interest of `fd` by `op`
+    pub fn control(&mut self, op: usize, fd: usize, event: EpollEvent) -> Result<(), SysError> {
+        match op {
+            EPOLL_CTL_ADD => {
+                if self.entries.contains_key(&fd) {
+                    return Err(SysError::EEXIST);
+                }
+                self.entries.insert(
+                    fd,
+                    EpollEntry {
+                        event,
+                        last_seq: None,
+                        disabled: false,
+                    },
+                );
+            }
+            EPOLL_CTL_MOD => {
+                let entry = self.entries.get_mut(&fd).ok_or(SysError::ENOENT)?;
+                entry.event = event;
+                entry.last_seq = None;
+                entry.disabled = false;
+            }
+            EPOLL_CTL_DEL => {
+                self.entries.remove(&fd).ok_or(SysError::ENOENT)?;
+            }
+            _ => return Err(SysError::EINVAL),
+        }
+        Ok(())
+    }
+
+    /// Forget the closed `fd`
+    pub fn remove(&mut self, fd: usize) {
+        self.entries.remove(&fd);
+    }
+
+    /// The fds to be polled
+    pub fn interests(&self) -> Vec<usize> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| !entry.disabled)
+            .map(|(&fd, _)| fd)
+            .collect()
+    }
+
+    /// Collect at most `max_events` events from the current `ready` events of fds,
+    /// each with its event sequence read before polling.
+    ///
+    /// In level-triggered mode, an fd is reported as long as it is ready.
+    /// In edge-triggered mode, it is reported only when its event sequence changes
+    /// since the last report, i.e. on every readiness change of the file.
+    pub fn collect(
+        &mut self,
+        ready: &[(usize, EpollFlags, usize)],
+        max_events: usize,
+    ) -> Vec<EpollEvent> {
+        let mut events = Vec::new();
+        for &(fd, flags, seq) in ready.iter() {
+            let entry = match self.entries.get_mut(&fd) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let interest = entry.event.events | EpollFlags::ERR | EpollFlags::HUP;
+            let flags = flags & interest;
+            let edge = entry.event.events.contains(EpollFlags::ET);
+            if flags.is_empty() || entry.disabled || (edge && entry.last_seq == Some(seq)) {
+                continue;
+            }
+            if events.len() == max_events {
+                continue;
+            }
+            entry.last_seq = Some(seq);
+            if entry.event.events.contains(EpollFlags::ONESHOT) {
+                entry.disabled = true;
+            }
+            events.push(EpollEvent {
+                events: flags,
+                data: entry.event.data,
+            });
+        }
+        events
+    }
+}
@@ -4,11 +4,16 @@ use alloc::{string::String, sync::Arc};
 
 use rcore_fs::vfs::{FsError, INode, Metadata, PollStatus, Result};
 
+use crate::process::Credentials;
+use crate::syscall::SysError;
+
 #[derive(Clone)]
 pub struct FileHandle {
     inode: Arc<INode>,
     offset: u64,
     options: OpenOptions,
+    /// The absolute path it was opened with, empty if unknown
+    path: String,
 }
 
 #[derive(Debug, Clone)]
@@ -32,9 +37,16 @@ impl FileHandle {
             inode,
             offset: 0,
             options,
+            path: String::new(),
         }
     }
 
+    /// Remember the absolute `path` the file is opened with
+    pub fn with_path(mut self, path: String) -> Self {
+        self.path = path;
+        self
+    }
+
     pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         let len = self.read_at(self.offset as usize, buf)?;
         self.offset += len as u64;
@@ -76,6 +88,10 @@ impl FileHandle {
         Ok(self.offset)
     }
 
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
     pub fn set_len(&mut self, len: u64) -> Result<()> {
         if !self.options.write {
             return Err(FsError::InvalidParam); // FIXME: => EBADF
@@ -96,8 +112,14 @@ impl FileHandle {
         self.inode.metadata()
     }
 
-    pub fn lookup_follow(&self, path: &str, max_follow: usize) -> Result<Arc<INode>> {
-        self.inode.lookup_follow(path, max_follow)
+    /// Lookup `path` from it as a directory, as `lookup_at`
+    pub fn lookup_follow(
+        &self,
+        path: &str,
+        max_follow: usize,
+        cred: Option<&Credentials>,
+    ) -> core::result::Result<Arc<INode>, SysError> {
+        super::lookup_at(&self.inode, path, max_follow, cred)
     }
 
     pub fn read_entry(&mut self) -> Result<String> {
@@ -116,4 +138,16 @@ impl FileHandle {
     pub fn io_control(&self, cmd: u32, arg: u32) -> Result<()> {
         self.inode.io_control(cmd, arg)
     }
+
+    pub fn inode(&self) -> Arc<INode> {
+        self.inode.clone()
+    }
+
+    pub fn options(&self) -> &OpenOptions {
+        &self.options
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
 }
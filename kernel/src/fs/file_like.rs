@@ -1,9 +1,15 @@
 use core::fmt;
 
-use super::FileHandle;
+use super::devfs::{InputINode, PtyMaster};
+use super::pipe::Pipe;
+use super::procfs::ProcINode;
+use super::{inode_tty, EpollInstance, FileHandle, Pty, TimerFd, Tty};
+use crate::drivers::{INPUT_ACTIVITY, SOCKET_ACTIVITY};
 use crate::net::Socket;
+use crate::sync::{Condvar, SpinNoIrqLock as Mutex};
 use crate::syscall::{SysError, SysResult};
-use alloc::boxed::Box;
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
+use core::ops::{Deref, DerefMut};
 use rcore_fs::vfs::PollStatus;
 
 // TODO: merge FileLike to FileHandle ?
@@ -12,20 +18,55 @@ use rcore_fs::vfs::PollStatus;
 pub enum FileLike {
     File(FileHandle),
     Socket(Box<dyn Socket>),
+    /// shared by dup and fork, as the interest list belongs to the open file
+    Epoll(Arc<Mutex<EpollInstance>>),
+    TimerFd(TimerFd),
 }
 
 impl FileLike {
     pub fn read(&mut self, buf: &mut [u8]) -> SysResult {
         let len = match self {
-            FileLike::File(file) => file.read(buf)?,
+            FileLike::File(file) => {
+                let inode = file.inode();
+                if let Some(tty) = inode_tty(&inode) {
+                    // EINTR can not be told by FsError
+                    return tty.read(buf);
+                }
+                if let Some(master) = inode.as_any_ref().downcast_ref::<PtyMaster>() {
+                    return master.pty().master_read(buf);
+                }
+                if let Some(pipe) = inode.as_any_ref().downcast_ref::<Pipe>() {
+                    return pipe.read(buf);
+                }
+                if let Some(input) = inode.as_any_ref().downcast_ref::<InputINode>() {
+                    return input.read(buf);
+                }
+                file.read(buf)?
+            }
             FileLike::Socket(socket) => socket.read(buf).0?,
+            FileLike::Epoll(_) => return Err(SysError::EINVAL),
+            FileLike::TimerFd(timer) => timer.read(buf)?,
         };
         Ok(len)
     }
     pub fn write(&mut self, buf: &[u8]) -> SysResult {
         let len = match self {
-            FileLike::File(file) => file.write(buf)?,
+            FileLike::File(file) => {
+                let inode = file.inode();
+                if let Some(pipe) = inode.as_any_ref().downcast_ref::<Pipe>() {
+                    // EPIPE and EINTR can not be told by FsError
+                    return pipe.write(buf);
+                }
+                if let Some(tty) = inode_tty(&inode) {
+                    return tty.write(buf);
+                }
+                if let Some(master) = inode.as_any_ref().downcast_ref::<PtyMaster>() {
+                    return master.pty().master_write(buf);
+                }
+                file.write(buf)?
+            }
             FileLike::Socket(socket) => socket.write(buf, None)?,
+            FileLike::Epoll(_) | FileLike::TimerFd(_) => return Err(SysError::EINVAL),
         };
         Ok(len)
     }
@@ -35,6 +76,7 @@ impl FileLike {
             FileLike::Socket(socket) => {
                 socket.ioctl(request, arg1, arg2, arg3)?;
             }
+            FileLike::Epoll(_) | FileLike::TimerFd(_) => return Err(SysError::ENOTTY),
         }
         Ok(0)
     }
@@ -45,9 +87,89 @@ impl FileLike {
                 let (read, write, error) = socket.poll();
                 PollStatus { read, write, error }
             }
+            // nested epoll is not supported
+            FileLike::Epoll(_) => PollStatus {
+                read: false,
+                write: false,
+                error: false,
+            },
+            FileLike::TimerFd(timer) => timer.poll(),
         };
         Ok(status)
     }
+    /// A clone sharing all states with this file, if its reads and writes
+    /// may block waiting for other processes, or lock them as procfs does.
+    /// Use it without holding the process lock, as those processes may lock
+    /// us meanwhile. The offset of a file is copied, not shared.
+    pub fn shared_clone(&self) -> Option<FileLike> {
+        match self {
+            FileLike::File(file) if file.inode().as_any_ref().is::<ProcINode>() => {
+                Some(self.clone())
+            }
+            FileLike::File(file) if file.inode().as_any_ref().is::<Pipe>() => Some(self.clone()),
+            FileLike::File(file) if inode_tty(&file.inode()).is_some() => Some(self.clone()),
+            FileLike::File(file) if file.inode().as_any_ref().is::<PtyMaster>() => {
+                Some(self.clone())
+            }
+            FileLike::File(file) if file.inode().as_any_ref().is::<InputINode>() => {
+                Some(self.clone())
+            }
+            FileLike::Socket(socket) => socket.shared_clone().map(FileLike::Socket),
+            FileLike::TimerFd(_) => Some(self.clone()),
+            _ => None,
+        }
+    }
+    /// The terminal it refers to, if it is one
+    pub fn tty(&self) -> Option<Arc<Tty>> {
+        match self {
+            FileLike::File(file) => inode_tty(&file.inode()),
+            _ => None,
+        }
+    }
+    /// The pseudo-terminal, if it is a master
+    pub fn pty_master(&self) -> Option<Arc<Pty>> {
+        match self {
+            FileLike::File(file) => file
+                .inode()
+                .as_any_ref()
+                .downcast_ref::<PtyMaster>()
+                .map(|master| master.pty().clone()),
+            _ => None,
+        }
+    }
+    /// Condvars notified when the poll status may change
+    pub fn wakeup_sources(&self) -> Vec<Arc<Condvar>> {
+        match self {
+            FileLike::File(file) => {
+                let inode = file.inode();
+                let any = inode.as_any_ref();
+                if let Some(tty) = inode_tty(&inode) {
+                    vec![tty.readable()]
+                } else if let Some(pipe) = any.downcast_ref::<Pipe>() {
+                    vec![pipe.wakeup_source()]
+                } else if let Some(master) = any.downcast_ref::<PtyMaster>() {
+                    vec![
+                        master.pty().master_readable(),
+                        master.pty().tty().writable(),
+                    ]
+                } else if any.is::<InputINode>() {
+                    vec![INPUT_ACTIVITY.clone()]
+                } else {
+                    Vec::new()
+                }
+            }
+            FileLike::Socket(_) => vec![SOCKET_ACTIVITY.clone()],
+            FileLike::Epoll(_) => Vec::new(),
+            FileLike::TimerFd(timer) => vec![timer.wakeup_source()],
+        }
+    }
+    /// Sequence of events, which changes whenever the poll status may change.
+    /// Read it before polling, so no change after the poll is missed.
+    pub fn event_seq(&self) -> usize {
+        self.wakeup_sources()
+            .iter()
+            .fold(0, |seq, condvar| seq.wrapping_add(condvar.events()))
+    }
 }
 
 impl fmt::Debug for FileLike {
@@ -55,6 +177,37 @@ impl fmt::Debug for FileLike {
         match self {
             FileLike::File(_) => write!(f, "File"),
             FileLike::Socket(_) => write!(f, "Socket"),
+            FileLike::Epoll(_) => write!(f, "Epoll"),
+            FileLike::TimerFd(_) => write!(f, "TimerFd"),
         }
     }
 }
+
+/// File descriptor table of processes, which may be shared by `CLONE_FILES`
+#[derive(Clone, Default)]
+pub struct FileTable(BTreeMap<usize, FileLike>);
+
+impl FileTable {
+    pub fn get_file_like(&mut self, fd: usize) -> Result<&mut FileLike, SysError> {
+        self.0.get_mut(&fd).ok_or(SysError::EBADF)
+    }
+    pub fn get_file(&mut self, fd: usize) -> Result<&mut FileHandle, SysError> {
+        match self.get_file_like(fd)? {
+            FileLike::File(file) => Ok(file),
+            _ => Err(SysError::EBADF),
+        }
+    }
+}
+
+impl Deref for FileTable {
+    type Target = BTreeMap<usize, FileLike>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for FileTable {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
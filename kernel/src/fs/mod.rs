@@ -1,21 +1,42 @@
 use alloc::{sync::Arc, vec::Vec};
+use core::fmt;
 
+use rcore_fs::dev::Device;
 use rcore_fs::vfs::*;
 use rcore_fs_sfs::SimpleFileSystem;
+use rcore_memory::memory_set::handler::MappedFile;
 
 #[cfg(target_arch = "x86_64")]
 use crate::arch::driver::ide;
+use crate::syscall::SysError;
 
+pub use self::devfs::{open_device, DEVFS};
+pub use self::device::INodeDevice;
+pub use self::epoll::*;
 pub use self::file::*;
 pub use self::file_like::*;
+pub use self::mount::*;
 pub use self::pipe::Pipe;
+pub use self::procfs::PROCFS;
+pub use self::pty::Pty;
 pub use self::stdio::{STDIN, STDOUT};
+pub use self::timerfd::TimerFd;
+pub use self::tmpfs::TmpFS;
+pub use self::tty::*;
 
+mod devfs;
 mod device;
+mod epoll;
 mod file;
 mod file_like;
+mod mount;
 mod pipe;
+mod procfs;
+mod pty;
 mod stdio;
+mod timerfd;
+mod tmpfs;
+mod tty;
 
 /// Hard link user programs
 #[cfg(feature = "link_user")]
@@ -34,37 +55,66 @@ _user_img_end:
 
 lazy_static! {
     /// The root of file system
+    ///
+    /// It is a tmpfs if no block device is found.
     pub static ref ROOT_INODE: Arc<INode> = {
         #[cfg(not(feature = "link_user"))]
-        let device = {
+        let device: Option<Arc<Device>> = {
             #[cfg(any(target_arch = "riscv32", target_arch = "riscv64", target_arch = "x86_64"))]
             {
                 crate::drivers::BLK_DRIVERS.read().iter()
-                    .next().expect("Block device not found")
-                    .clone()
+                    .next()
+                    .map(|driver| driver.clone() as Arc<Device>)
             }
             #[cfg(target_arch = "aarch64")]
             {
-                unimplemented!()
+                None
             }
         };
         #[cfg(feature = "link_user")]
-        let device = {
+        let device: Option<Arc<Device>> = {
             extern {
                 fn _user_img_start();
                 fn _user_img_end();
             }
             info!("SFS linked to kernel, from {:08x} to {:08x}", _user_img_start as usize, _user_img_end as usize);
-            Arc::new(unsafe { device::MemBuf::new(_user_img_start, _user_img_end) })
+            Some(Arc::new(unsafe { device::MemBuf::new(_user_img_start, _user_img_end) }))
         };
 
-        let sfs = SimpleFileSystem::open(device).expect("failed to open SFS");
-        sfs.root_inode()
+        match device {
+            Some(device) => {
+                let sfs = SimpleFileSystem::open(device).expect("failed to open SFS");
+                sfs.root_inode()
+            }
+            None => {
+                warn!("block device not found, use tmpfs as root");
+                let tmpfs = TmpFS::new();
+                let root = tmpfs.root_inode();
+                // inodes refer to tmpfs weakly, and the root file system is never dropped
+                core::mem::forget(tmpfs);
+                root
+            }
+        }
     };
 }
 
 pub const FOLLOW_MAX_DEPTH: usize = 1;
 
+/// Mount pseudo file systems, creating the mount points if they are missing
+pub fn init() {
+    for &(fstype, name) in [("devfs", "dev"), ("proc", "proc"), ("tmpfs", "tmp")].iter() {
+        let path = format!("/{}", name);
+        let result = ROOT_INODE
+            .find(name)
+            .or_else(|_| ROOT_INODE.create(name, FileType::Dir, 0o755))
+            .map_err(SysError::from)
+            .and_then(|dir| mount(create_fs(fstype, None)?, dir, fstype, fstype, &path));
+        if let Err(err) = result {
+            warn!("failed to mount {} at {}: {:?}", fstype, path, err);
+        }
+    }
+}
+
 pub trait INodeExt {
     fn read_as_vec(&self) -> Result<Vec<u8>>;
 }
@@ -80,3 +130,22 @@ impl INodeExt for INode {
         Ok(buf)
     }
 }
+
+/// INode to be mapped into memory by `File` handler
+#[derive(Clone)]
+pub struct INodeForMap(pub Arc<INode>);
+
+impl MappedFile for INodeForMap {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        self.0.read_at(offset, buf).unwrap_or(0)
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        self.0.write_at(offset, buf).unwrap_or(0)
+    }
+}
+
+impl fmt::Debug for INodeForMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "INodeForMap")
+    }
+}
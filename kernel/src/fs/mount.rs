@@ -0,0 +1,223 @@
+//! Mount table, overlaying file systems at directories
+//!
+//! A mounted file system covers a directory, which is identified by
+//! its file system and inode number. Path lookup crosses mount points
+//! downwards at covered directories, and upwards at `..` of mounted roots.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::str;
+
+use rcore_fs::vfs::*;
+use rcore_fs_sfs::SimpleFileSystem;
+use spin::RwLock;
+
+use super::{INodeDevice, TmpFS, DEVFS, PROCFS, ROOT_INODE};
+use crate::process::{Access, Credentials};
+use crate::syscall::SysError;
+
+/// Identity of an inode: the address of its file system and the inode number
+type INodeId = (usize, usize);
+
+fn inode_id(inode: &Arc<INode>) -> Result<INodeId> {
+    let fs = inode.fs();
+    let fs_addr = &*fs as *const FileSystem as *const u8 as usize;
+    Ok((fs_addr, inode.metadata()?.inode))
+}
+
+/// A file system mounted at a directory
+pub struct MountPoint {
+    /// the mounted file system
+    pub fs: Arc<FileSystem>,
+    /// the file system type, e.g. "sfs"
+    pub fstype: String,
+    /// the source passed to mount, e.g. a device path
+    pub source: String,
+    /// the absolute path of the mount point
+    pub target: String,
+    /// the directory covered by the root of `fs`
+    covered: Arc<INode>,
+    covered_id: INodeId,
+    root_id: INodeId,
+}
+
+lazy_static! {
+    /// Mounted file systems in mount order, except the root file system
+    pub static ref MOUNTS: RwLock<Vec<MountPoint>> = RwLock::new(Vec::new());
+}
+
+/// Mount `fs` at the directory `target`, whose absolute path is `path`
+pub fn mount(
+    fs: Arc<FileSystem>,
+    target: Arc<INode>,
+    fstype: &str,
+    source: &str,
+    path: &str,
+) -> core::result::Result<(), SysError> {
+    if target.metadata()?.type_ != FileType::Dir {
+        return Err(SysError::ENOTDIR);
+    }
+    let covered_id = inode_id(&target)?;
+    let root_id = inode_id(&fs.root_inode())?;
+    let mut mounts = MOUNTS.write();
+    if mounts.iter().any(|mp| mp.root_id == root_id) {
+        // the file system is mounted already
+        return Err(SysError::EBUSY);
+    }
+    info!("mount: {} at {}, type {}", source, path, fstype);
+    mounts.push(MountPoint {
+        fs,
+        fstype: String::from(fstype),
+        source: String::from(source),
+        target: String::from(path),
+        covered: target,
+        covered_id,
+        root_id,
+    });
+    Ok(())
+}
+
+/// Unmount the file system whose root is `target`
+pub fn umount(target: &Arc<INode>) -> core::result::Result<(), SysError> {
+    let root_id = inode_id(target)?;
+    let mut mounts = MOUNTS.write();
+    let index = mounts
+        .iter()
+        .position(|mp| mp.root_id == root_id)
+        .ok_or(SysError::EINVAL)?;
+    let fs_addr = root_id.0;
+    if mounts.iter().any(|mp| mp.covered_id.0 == fs_addr) {
+        // something is mounted inside
+        return Err(SysError::EBUSY);
+    }
+    let mp = mounts.remove(index);
+    info!("umount: {}", mp.target);
+    mp.fs.sync()?;
+    Ok(())
+}
+
+/// Create a file system of `fstype`, from the `source` device if it needs one
+pub fn create_fs(
+    fstype: &str,
+    source: Option<Arc<INode>>,
+) -> core::result::Result<Arc<FileSystem>, SysError> {
+    let fs: Arc<FileSystem> = match fstype {
+        "sfs" => {
+            let device = Arc::new(INodeDevice(source.ok_or(SysError::ENOTBLK)?));
+            SimpleFileSystem::open(device).map_err(|_| SysError::EINVAL)?
+        }
+        "devfs" => DEVFS.clone(),
+        "proc" => PROCFS.clone(),
+        "tmpfs" => TmpFS::new(),
+        _ => return Err(SysError::ENODEV),
+    };
+    Ok(fs)
+}
+
+/// Replace `inode` by the root of the file system mounted on it, if any
+fn cross_mount(mut inode: Arc<INode>) -> Result<Arc<INode>> {
+    loop {
+        if MOUNTS.read().is_empty() || inode.metadata()?.type_ != FileType::Dir {
+            return Ok(inode);
+        }
+        let id = inode_id(&inode)?;
+        let fs = match MOUNTS.read().iter().find(|mp| mp.covered_id == id) {
+            Some(mp) => mp.fs.clone(),
+            None => return Ok(inode),
+        };
+        // mount points may be stacked, try again
+        inode = fs.root_inode();
+    }
+}
+
+/// Parent of the directory `dir`, which is the parent of the covered directory
+/// if `dir` is the root of a mounted file system
+fn parent(dir: &Arc<INode>) -> Result<Arc<INode>> {
+    if !MOUNTS.read().is_empty() {
+        let id = inode_id(dir)?;
+        let covered = MOUNTS
+            .read()
+            .iter()
+            .find(|mp| mp.root_id == id)
+            .map(|mp| mp.covered.clone());
+        if let Some(covered) = covered {
+            return parent(&covered);
+        }
+    }
+    dir.find("..")
+}
+
+/// The root directory of the whole tree
+pub fn root_inode() -> Arc<INode> {
+    cross_mount(ROOT_INODE.clone()).unwrap_or_else(|_| ROOT_INODE.clone())
+}
+
+/// Lookup `path` from the directory `dir`, crossing mount points,
+/// and following symlinks at most `follow_times` times.
+/// Each directory searched needs the execute permission of `cred`, if given.
+pub fn lookup_at(
+    dir: &Arc<INode>,
+    path: &str,
+    mut follow_times: usize,
+    cred: Option<&Credentials>,
+) -> core::result::Result<Arc<INode>, SysError> {
+    if dir.metadata()?.type_ != FileType::Dir {
+        return Err(SysError::ENOTDIR);
+    }
+    let mut result = dir.clone();
+    let mut rest_path = String::from(path);
+    while rest_path != "" {
+        let metadata = result.metadata()?;
+        if metadata.type_ != FileType::Dir {
+            return Err(SysError::ENOTDIR);
+        }
+        // handle absolute path
+        if let Some('/') = rest_path.chars().next() {
+            result = root_inode();
+            rest_path = String::from(&rest_path[1..]);
+            continue;
+        }
+        if let Some(cred) = cred {
+            cred.check_access(&metadata, Access::EXEC)?;
+        }
+        let name = match rest_path.find('/') {
+            None => core::mem::replace(&mut rest_path, String::new()),
+            Some(pos) => {
+                let name = String::from(&rest_path[..pos]);
+                rest_path = String::from(&rest_path[pos + 1..]);
+                name
+            }
+        };
+        let inode = match name.as_str() {
+            "" | "." => continue,
+            ".." => parent(&result)?,
+            _ => result.find(&name)?,
+        };
+        // handle symlink
+        if inode.metadata()?.type_ == FileType::SymLink && follow_times > 0 {
+            follow_times -= 1;
+            let mut content = [0u8; 256];
+            let len = inode.read_at(0, &mut content)?;
+            let path = str::from_utf8(&content[..len]).map_err(|_| SysError::ENOTDIR)?;
+            // result remains unchanged
+            let mut new_path = String::from(path);
+            if rest_path != "" {
+                new_path += "/";
+                new_path += &rest_path;
+            }
+            rest_path = new_path;
+        } else {
+            result = cross_mount(inode)?;
+        }
+    }
+    Ok(result)
+}
+
+/// Sync all mounted file systems
+pub fn sync_all() -> Result<()> {
+    ROOT_INODE.fs().sync()?;
+    let fss: Vec<_> = MOUNTS.read().iter().map(|mp| mp.fs.clone()).collect();
+    for fs in fss {
+        fs.sync()?;
+    }
+    Ok(())
+}
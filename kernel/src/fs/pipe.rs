@@ -5,8 +5,16 @@ use core::any::Any;
 
 use rcore_fs::vfs::*;
 
+use crate::signal::has_signal_to_handle;
 use crate::sync::Condvar;
 use crate::sync::SpinNoIrqLock as Mutex;
+use crate::syscall::{SysError, SysResult};
+
+/// Capacity of the pipe buffer, as linux
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+/// Writes of at most this many bytes are atomic, i.e. never interleaved with others
+const PIPE_BUF: usize = 4096;
 
 #[derive(Clone)]
 pub enum PipeEnd {
@@ -16,10 +24,17 @@ pub enum PipeEnd {
 
 pub struct PipeData {
     buf: VecDeque<u8>,
-    new_data: Condvar,
+    /// notified when data is written, or the write end is closed
+    new_data: Arc<Condvar>,
+    /// notified when data is read, or the read end is closed
+    new_space: Arc<Condvar>,
+    /// whether the read end is still open
+    has_reader: bool,
+    /// whether the write end is still open
+    has_writer: bool,
 }
 
-#[derive(Clone)]
+/// An end of a pipe, which is closed when dropped
 pub struct Pipe {
     data: Arc<Mutex<PipeData>>,
     direction: PipeEnd,
@@ -30,7 +45,10 @@ impl Pipe {
     pub fn create_pair() -> (Pipe, Pipe) {
         let inner = PipeData {
             buf: VecDeque::new(),
-            new_data: Condvar::new(),
+            new_data: Arc::new(Condvar::new()),
+            new_space: Arc::new(Condvar::new()),
+            has_reader: true,
+            has_writer: true,
         };
         let data = Arc::new(Mutex::new(inner));
         (
@@ -45,19 +63,127 @@ impl Pipe {
         )
     }
 
+    /// Whether a read would not block, i.e. there is data or EOF is reached
     pub fn can_read(&self) -> bool {
         if let PipeEnd::Read = self.direction {
-            self.data.lock().buf.len() > 0
+            let data = self.data.lock();
+            data.buf.len() > 0 || !data.has_writer
+        } else {
+            false
+        }
+    }
+
+    /// Whether a write would not block, i.e. there is space or it is broken
+    pub fn can_write(&self) -> bool {
+        if let PipeEnd::Write = self.direction {
+            let data = self.data.lock();
+            data.buf.len() < PIPE_CAPACITY || !data.has_reader
         } else {
             false
         }
     }
+
+    /// Condvar notified when this end may become ready
+    pub fn wakeup_source(&self) -> Arc<Condvar> {
+        let data = self.data.lock();
+        match self.direction {
+            PipeEnd::Read => data.new_data.clone(),
+            PipeEnd::Write => data.new_space.clone(),
+        }
+    }
+
+    /// Read available data, blocking until there is some.
+    /// Return 0 after the write end is closed and the data is drained,
+    /// or EINTR if a signal arrives while blocking.
+    pub fn read(&self, buf: &mut [u8]) -> SysResult {
+        if let PipeEnd::Write = self.direction {
+            return Err(SysError::EBADF);
+        }
+        if buf.len() == 0 {
+            return Ok(0);
+        }
+        let mut data = self.data.lock();
+        loop {
+            if data.buf.len() > 0 {
+                let len = buf.len().min(data.buf.len());
+                for (dst, src) in buf.iter_mut().zip(data.buf.drain(..len)) {
+                    *dst = src;
+                }
+                data.new_space.notify_all();
+                return Ok(len);
+            }
+            if !data.has_writer {
+                return Ok(0);
+            }
+            let new_data = data.new_data.clone();
+            drop(new_data.wait(data));
+            if has_signal_to_handle() {
+                return Err(SysError::EINTR);
+            }
+            data = self.data.lock();
+        }
+    }
+
+    /// Write all of `buf`, blocking while the pipe is full.
+    /// A write of at most `PIPE_BUF` bytes waits for space for all of them.
+    /// Fail with EPIPE if the read end is closed, or EINTR if a signal arrives,
+    /// before anything is written. Otherwise return the bytes written so far.
+    pub fn write(&self, buf: &[u8]) -> SysResult {
+        if let PipeEnd::Read = self.direction {
+            return Err(SysError::EBADF);
+        }
+        let atomic = buf.len() <= PIPE_BUF;
+        let mut data = self.data.lock();
+        let mut written = 0;
+        while written < buf.len() {
+            if !data.has_reader {
+                return match written {
+                    0 => Err(SysError::EPIPE),
+                    _ => Ok(written),
+                };
+            }
+            let space = PIPE_CAPACITY - data.buf.len();
+            let len = space.min(buf.len() - written);
+            if len == 0 || (atomic && space < buf.len()) {
+                let new_space = data.new_space.clone();
+                drop(new_space.wait(data));
+                if has_signal_to_handle() {
+                    return match written {
+                        0 => Err(SysError::EINTR),
+                        _ => Ok(written),
+                    };
+                }
+                data = self.data.lock();
+                continue;
+            }
+            data.buf.extend(buf[written..written + len].iter());
+            data.new_data.notify_all();
+            written += len;
+        }
+        Ok(written)
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        // wake up the other end to see EOF or EPIPE
+        let mut data = self.data.lock();
+        match self.direction {
+            PipeEnd::Read => {
+                data.has_reader = false;
+                data.new_space.notify_all();
+            }
+            PipeEnd::Write => {
+                data.has_writer = false;
+                data.new_data.notify_all();
+            }
+        }
+    }
 }
 
 // TODO: better way to provide default impl?
 macro_rules! impl_inode {
     () => {
-        fn poll(&self) -> Result<PollStatus> { Err(FsError::NotSupported) }
         fn metadata(&self) -> Result<Metadata> { Err(FsError::NotSupported) }
         fn set_metadata(&self, _metadata: &Metadata) -> Result<()> { Ok(()) }
         fn sync_all(&self) -> Result<()> { Ok(()) }
@@ -77,32 +203,27 @@ macro_rules! impl_inode {
 
 impl INode for Pipe {
     fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
-        if let PipeEnd::Read = self.direction {
-            let mut data = self.data.lock();
-            if let Some(ch) = data.buf.pop_front() {
-                buf[0] = ch;
-                Ok(1)
-            } else {
-                Ok(0)
-            }
-        } else {
-            Ok(0)
-        }
+        self.read(buf).map_err(|_| FsError::InvalidParam)
     }
 
+    /// EPIPE can not be told by `FsError`, so `FileLike` writes by `Pipe::write`
     fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
-        if let PipeEnd::Write = self.direction {
-            if buf.len() > 0 {
-                let mut data = self.data.lock();
-                data.buf.push_back(buf[0]);
-                data.new_data.notify_all();
-                Ok(1)
-            } else {
-                Ok(0)
+        self.write(buf).map_err(|_| FsError::InvalidParam)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        let broken = {
+            let data = self.data.lock();
+            match self.direction {
+                PipeEnd::Read => false,
+                PipeEnd::Write => !data.has_reader,
             }
-        } else {
-            Ok(0)
-        }
+        };
+        Ok(PollStatus {
+            read: self.can_read(),
+            write: self.can_write(),
+            error: broken,
+        })
     }
     impl_inode!();
 }
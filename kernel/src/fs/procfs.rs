@@ -0,0 +1,542 @@
+//! Process information pseudo file system, mounted at /proc
+//!
+//! Nothing is stored here: directories are listed and files are generated
+//! from the kernel state each time they are accessed.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::any::Any;
+use core::fmt::Write;
+use core::sync::atomic::Ordering;
+
+use rcore_fs::vfs::*;
+use rcore_memory::PAGE_SIZE;
+use smoltcp::socket::{Socket, TcpState};
+use smoltcp::wire::{IpAddress, IpEndpoint};
+
+use super::pipe::Pipe;
+use super::stdio::{Stdin, Stdout};
+use super::FileLike;
+use crate::drivers::NET_DRIVERS;
+use crate::memory::{frame_stats, heap_stats};
+use crate::net::SOCKETS;
+use crate::process::{current_thread, Process, PROCESSES};
+use crate::sync::SpinNoIrqLock as Mutex;
+
+lazy_static! {
+    /// The process information file system
+    pub static ref PROCFS: Arc<ProcFS> = Arc::new(ProcFS);
+}
+
+pub struct ProcFS;
+
+impl FileSystem for ProcFS {
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn root_inode(&self) -> Arc<INode> {
+        Arc::new(ProcINode(Node::Root))
+    }
+
+    fn info(&self) -> FsInfo {
+        FsInfo { max_file_size: 0 }
+    }
+}
+
+/// Everything that can be found in procfs
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Node {
+    Root,
+    SelfLink,
+    Meminfo,
+    Cpuinfo,
+    Uptime,
+    Net,
+    NetDev,
+    NetTcp,
+    NetUdp,
+    Pid(usize),
+    Status(usize),
+    Cmdline(usize),
+    Maps(usize),
+    Cwd(usize),
+    Exe(usize),
+    FdDir(usize),
+    Fd(usize, usize),
+}
+
+impl Node {
+    /// Inode number, 16 numbers for each process after the global ones
+    fn id(&self) -> usize {
+        let pid_id = |pid: usize, index: usize| ((pid + 1) << 16) | index;
+        match *self {
+            Node::Root => 1,
+            Node::SelfLink => 2,
+            Node::Meminfo => 3,
+            Node::Cpuinfo => 4,
+            Node::Uptime => 5,
+            Node::Net => 6,
+            Node::NetDev => 7,
+            Node::NetTcp => 8,
+            Node::NetUdp => 9,
+            Node::Pid(pid) => pid_id(pid, 0),
+            Node::Status(pid) => pid_id(pid, 1),
+            Node::Cmdline(pid) => pid_id(pid, 2),
+            Node::Maps(pid) => pid_id(pid, 3),
+            Node::Cwd(pid) => pid_id(pid, 4),
+            Node::Exe(pid) => pid_id(pid, 5),
+            Node::FdDir(pid) => pid_id(pid, 6),
+            Node::Fd(pid, fd) => pid_id(pid, 16 + fd),
+        }
+    }
+
+    fn type_(&self) -> FileType {
+        match self {
+            Node::Root | Node::Net | Node::Pid(_) | Node::FdDir(_) => FileType::Dir,
+            Node::SelfLink | Node::Cwd(_) | Node::Exe(_) | Node::Fd(_, _) => FileType::SymLink,
+            _ => FileType::File,
+        }
+    }
+
+    fn parent(&self) -> Node {
+        match *self {
+            Node::NetDev | Node::NetTcp | Node::NetUdp => Node::Net,
+            Node::Status(pid)
+            | Node::Cmdline(pid)
+            | Node::Maps(pid)
+            | Node::Cwd(pid)
+            | Node::Exe(pid)
+            | Node::FdDir(pid) => Node::Pid(pid),
+            Node::Fd(pid, _) => Node::FdDir(pid),
+            _ => Node::Root,
+        }
+    }
+
+    /// Entries of a directory, except `.` and `..`
+    fn entries(&self) -> Result<Vec<(String, Node)>> {
+        let entries: Vec<(String, Node)> = match *self {
+            Node::Root => {
+                let mut entries = vec![
+                    (String::from("cpuinfo"), Node::Cpuinfo),
+                    (String::from("meminfo"), Node::Meminfo),
+                    (String::from("net"), Node::Net),
+                    (String::from("self"), Node::SelfLink),
+                    (String::from("uptime"), Node::Uptime),
+                ];
+                for (&pid, proc) in PROCESSES.read().iter() {
+                    if proc.upgrade().is_some() {
+                        entries.push((format!("{}", pid), Node::Pid(pid)));
+                    }
+                }
+                entries
+            }
+            Node::Net => vec![
+                (String::from("dev"), Node::NetDev),
+                (String::from("tcp"), Node::NetTcp),
+                (String::from("udp"), Node::NetUdp),
+            ],
+            Node::Pid(pid) => {
+                find_process(pid)?;
+                vec![
+                    (String::from("cmdline"), Node::Cmdline(pid)),
+                    (String::from("cwd"), Node::Cwd(pid)),
+                    (String::from("exe"), Node::Exe(pid)),
+                    (String::from("fd"), Node::FdDir(pid)),
+                    (String::from("maps"), Node::Maps(pid)),
+                    (String::from("status"), Node::Status(pid)),
+                ]
+            }
+            Node::FdDir(pid) => with_process(pid, |proc| {
+                proc.files()
+                    .keys()
+                    .map(|&fd| (format!("{}", fd), Node::Fd(pid, fd)))
+                    .collect()
+            })?,
+            _ => return Err(FsError::NotDir),
+        };
+        Ok(entries)
+    }
+
+    /// Content of a file, or the target of a symlink
+    fn content(&self) -> Result<String> {
+        let content = match *self {
+            Node::SelfLink => {
+                let pid = pid_of(&current_thread().proc).ok_or(FsError::EntryNotFound)?;
+                format!("/proc/{}", pid)
+            }
+            Node::Meminfo => meminfo(),
+            Node::Cpuinfo => cpuinfo(),
+            Node::Uptime => {
+                let msec = crate::trap::uptime_msec();
+                format!("{}.{:02} 0.00\n", msec / 1000, msec % 1000 / 10)
+            }
+            Node::NetDev => net_dev(),
+            Node::NetTcp => net_sockets(true),
+            Node::NetUdp => net_sockets(false),
+            Node::Status(pid) => with_process(pid, |proc| status(pid, proc))?,
+            Node::Cmdline(pid) => with_process(pid, |proc| {
+                let mut cmdline = String::new();
+                for arg in proc.cmdline.iter() {
+                    cmdline += arg;
+                    cmdline.push('\0');
+                }
+                cmdline
+            })?,
+            Node::Maps(pid) => with_process(pid, maps)?,
+            Node::Cwd(pid) => with_process(pid, |proc| proc.cwd().clone())?,
+            Node::Exe(pid) => with_process(pid, |proc| proc.exec_path.clone())?,
+            Node::Fd(pid, fd) => with_process(pid, |proc| proc.files().get(&fd).map(fd_target))?
+                .ok_or(FsError::EntryNotFound)?,
+            _ => return Err(FsError::IsDir),
+        };
+        Ok(content)
+    }
+}
+
+fn find_process(pid: usize) -> Result<Arc<Mutex<Process>>> {
+    PROCESSES
+        .read()
+        .get(&pid)
+        .and_then(|proc| proc.upgrade())
+        .ok_or(FsError::EntryNotFound)
+}
+
+/// Find the pid of `proc` without locking it
+fn pid_of(proc: &Arc<Mutex<Process>>) -> Option<usize> {
+    PROCESSES
+        .read()
+        .iter()
+        .find(|(_, other)| {
+            other
+                .upgrade()
+                .map_or(false, |other| Arc::ptr_eq(&other, proc))
+        })
+        .map(|(&pid, _)| pid)
+}
+
+/// Call `f` with the process `pid` locked, so it must not be locked by
+/// the syscall accessing procfs, see `FileLike::shared_clone`
+fn with_process<T>(pid: usize, f: impl FnOnce(&Process) -> T) -> Result<T> {
+    let proc = find_process(pid)?;
+    let proc = proc.lock();
+    Ok(f(&proc))
+}
+
+fn status(pid: usize, proc: &Process) -> String {
+    let name = proc.exec_path.rsplit('/').next().unwrap_or("");
+    let ppid = proc.parent.as_ref().and_then(pid_of).unwrap_or(0);
+    let vm_size: usize = proc
+        .vm()
+        .iter()
+        .filter(|area| area.attr().is_user())
+        .map(|area| area.end_addr() - area.start_addr())
+        .sum();
+    let state = match proc.exit_status {
+        Some(_) => "Z (zombie)",
+        None if proc.stopped => "T (stopped)",
+        None => "R (running)",
+    };
+    let mut status = String::new();
+    writeln!(status, "Name:\t{}", name).unwrap();
+    writeln!(status, "State:\t{}", state).unwrap();
+    writeln!(status, "Tgid:\t{}", pid).unwrap();
+    writeln!(status, "Pid:\t{}", pid).unwrap();
+    writeln!(status, "PPid:\t{}", ppid).unwrap();
+    let cred = &proc.cred;
+    writeln!(
+        status,
+        "Uid:\t{}\t{}\t{}\t{}",
+        cred.ruid, cred.euid, cred.suid, cred.euid
+    )
+    .unwrap();
+    writeln!(
+        status,
+        "Gid:\t{}\t{}\t{}\t{}",
+        cred.rgid, cred.egid, cred.sgid, cred.egid
+    )
+    .unwrap();
+    write!(status, "Groups:\t").unwrap();
+    for gid in cred.groups.iter() {
+        write!(status, "{} ", gid).unwrap();
+    }
+    writeln!(status).unwrap();
+    writeln!(status, "Threads:\t{}", proc.threads.len()).unwrap();
+    writeln!(status, "VmSize:\t{} kB", vm_size / 1024).unwrap();
+    status
+}
+
+fn maps(proc: &Process) -> String {
+    let mut maps = String::new();
+    for area in proc.vm().iter() {
+        let attr = area.attr();
+        let read = if attr.is_user() { 'r' } else { '-' };
+        let write = if attr.is_user() && !attr.is_readonly() {
+            'w'
+        } else {
+            '-'
+        };
+        let execute = if attr.is_execute() { 'x' } else { '-' };
+        let shared = if area.name() == "mmap_anon_shared" {
+            's'
+        } else {
+            'p'
+        };
+        let name = match area.name() {
+            "" => proc.exec_path.as_str(),
+            "heap" => "[heap]",
+            "user_stack" => "[stack]",
+            name if name.starts_with("mmap") => "",
+            name => name,
+        };
+        writeln!(
+            maps,
+            "{:08x}-{:08x} {}{}{}{} 00000000 00:00 0 {}",
+            area.start_addr(),
+            area.end_addr(),
+            read,
+            write,
+            execute,
+            shared,
+            name
+        )
+        .unwrap();
+    }
+    maps
+}
+
+/// Target of `/proc/<pid>/fd/<fd>`
+fn fd_target(file: &FileLike) -> String {
+    match file {
+        FileLike::File(file) if file.path() != "" => String::from(file.path()),
+        FileLike::File(file) => {
+            let inode = file.inode();
+            let id = &*inode as *const INode as *const u8 as usize;
+            let any = inode.as_any_ref();
+            if any.is::<Pipe>() {
+                format!("pipe:[{}]", id)
+            } else if any.is::<Stdin>() || any.is::<Stdout>() {
+                String::from("/dev/tty")
+            } else {
+                format!("anon_inode:[{}]", id)
+            }
+        }
+        FileLike::Socket(socket) => {
+            let id = &**socket as *const _ as *const u8 as usize;
+            format!("socket:[{}]", id)
+        }
+        FileLike::Epoll(_) => String::from("anon_inode:[eventpoll]"),
+        FileLike::TimerFd(_) => String::from("anon_inode:[timerfd]"),
+    }
+}
+
+fn meminfo() -> String {
+    let (frames, frames_allocated) = frame_stats();
+    let (heap, heap_allocated) = heap_stats();
+    let kb = |frames: usize| frames * PAGE_SIZE / 1024;
+    let free = kb(frames - frames_allocated);
+    let mut meminfo = String::new();
+    writeln!(meminfo, "MemTotal:\t{} kB", kb(frames)).unwrap();
+    writeln!(meminfo, "MemFree:\t{} kB", free).unwrap();
+    writeln!(meminfo, "MemAvailable:\t{} kB", free).unwrap();
+    writeln!(meminfo, "KernelHeap:\t{} kB", heap / 1024).unwrap();
+    writeln!(meminfo, "KernelHeapUsed:\t{} kB", heap_allocated / 1024).unwrap();
+    meminfo
+}
+
+fn cpuinfo() -> String {
+    let arch = if cfg!(target_arch = "x86_64") {
+        "x86_64"
+    } else if cfg!(target_arch = "aarch64") {
+        "aarch64"
+    } else if cfg!(target_arch = "riscv64") {
+        "riscv64"
+    } else if cfg!(target_arch = "riscv32") {
+        "riscv32"
+    } else {
+        "mips"
+    };
+    let mut cpuinfo = String::new();
+    for id in 0..crate::CPU_ONLINE.load(Ordering::SeqCst) {
+        writeln!(cpuinfo, "processor\t: {}", id).unwrap();
+        writeln!(cpuinfo, "arch\t\t: {}", arch).unwrap();
+        writeln!(cpuinfo).unwrap();
+    }
+    cpuinfo
+}
+
+/// Network interfaces, the drivers have no statistics yet
+fn net_dev() -> String {
+    let mut dev = String::new();
+    dev += "Inter-|   Receive                                                |  Transmit\n";
+    dev += " face |bytes    packets errs drop fifo frame compressed multicast|";
+    dev += "bytes    packets errs drop fifo colls carrier compressed\n";
+    for iface in NET_DRIVERS.read().iter() {
+        writeln!(
+            dev,
+            "{:>6}: 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0",
+            iface.get_ifname()
+        )
+        .unwrap();
+    }
+    dev
+}
+
+/// TCP or UDP sockets, addresses in hex as linux
+fn net_sockets(tcp: bool) -> String {
+    fn endpoint(endpoint: IpEndpoint) -> String {
+        let addr = match endpoint.addr {
+            IpAddress::Ipv4(addr) => u32::from_le_bytes(addr.0),
+            _ => 0,
+        };
+        format!("{:08X}:{:04X}", addr, endpoint.port)
+    }
+    fn tcp_state(state: TcpState) -> usize {
+        match state {
+            TcpState::Established => 1,
+            TcpState::SynSent => 2,
+            TcpState::SynReceived => 3,
+            TcpState::FinWait1 => 4,
+            TcpState::FinWait2 => 5,
+            TcpState::TimeWait => 6,
+            TcpState::Closed => 7,
+            TcpState::CloseWait => 8,
+            TcpState::LastAck => 9,
+            TcpState::Listen => 10,
+            TcpState::Closing => 11,
+        }
+    }
+    let mut result = String::from("  sl  local_address rem_address   st\n");
+    let sockets = SOCKETS.lock();
+    let entries = sockets.iter().filter_map(|socket| match socket {
+        Socket::Tcp(socket) if tcp => Some((
+            socket.local_endpoint(),
+            socket.remote_endpoint(),
+            tcp_state(socket.state()),
+        )),
+        // unconnected UDP sockets are listed as closed
+        Socket::Udp(socket) if !tcp => Some((socket.endpoint(), IpEndpoint::default(), 7)),
+        _ => None,
+    });
+    for (i, (local, remote, state)) in entries.enumerate() {
+        writeln!(
+            result,
+            "{:>4}: {} {} {:02X}",
+            i,
+            endpoint(local),
+            endpoint(remote),
+            state
+        )
+        .unwrap();
+    }
+    result
+}
+
+pub struct ProcINode(Node);
+
+impl INode for ProcINode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let content = self.0.content()?;
+        let data = content.as_bytes();
+        if offset >= data.len() {
+            return Ok(0);
+        }
+        let len = buf.len().min(data.len() - offset);
+        buf[..len].copy_from_slice(&data[offset..offset + len]);
+        Ok(len)
+    }
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::NotSupported)
+    }
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: true,
+            write: false,
+            error: false,
+        })
+    }
+    fn metadata(&self) -> Result<Metadata> {
+        let type_ = self.0.type_();
+        let mode = match type_ {
+            FileType::Dir => 0o555,
+            FileType::SymLink => 0o777,
+            _ => 0o444,
+        };
+        Ok(Metadata {
+            dev: 0,
+            inode: self.0.id(),
+            // size of generated files is unknown until read, as linux
+            size: 0,
+            blk_size: 0,
+            blocks: 0,
+            atime: Timespec { sec: 0, nsec: 0 },
+            mtime: Timespec { sec: 0, nsec: 0 },
+            ctime: Timespec { sec: 0, nsec: 0 },
+            type_,
+            mode,
+            nlinks: 1,
+            uid: 0,
+            gid: 0,
+        })
+    }
+    fn set_metadata(&self, _metadata: &Metadata) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn sync_all(&self) -> Result<()> {
+        Ok(())
+    }
+    fn sync_data(&self) -> Result<()> {
+        Ok(())
+    }
+    fn resize(&self, _len: usize) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn create(&self, _name: &str, _type_: FileType, _mode: u32) -> Result<Arc<INode>> {
+        Err(FsError::NotSupported)
+    }
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn link(&self, _name: &str, _other: &Arc<INode>) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn move_(&self, _old_name: &str, _target: &Arc<INode>, _new_name: &str) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn find(&self, name: &str) -> Result<Arc<INode>> {
+        let node = match name {
+            "." => self.0,
+            ".." => self.0.parent(),
+            _ => {
+                self.0
+                    .entries()?
+                    .into_iter()
+                    .find(|(entry, _)| entry == name)
+                    .ok_or(FsError::EntryNotFound)?
+                    .1
+            }
+        };
+        Ok(Arc::new(ProcINode(node)))
+    }
+    fn get_entry(&self, id: usize) -> Result<String> {
+        match id {
+            0 => Ok(String::from(".")),
+            1 => Ok(String::from("..")),
+            _ => self
+                .0
+                .entries()?
+                .into_iter()
+                .nth(id - 2)
+                .map(|(entry, _)| entry)
+                .ok_or(FsError::EntryNotFound),
+        }
+    }
+    fn io_control(&self, _cmd: u32, _data: u32) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn fs(&self) -> Arc<FileSystem> {
+        PROCFS.clone()
+    }
+    fn as_any_ref(&self) -> &Any {
+        self
+    }
+}
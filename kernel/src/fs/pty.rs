@@ -0,0 +1,226 @@
+//! Pseudo-terminals
+//!
+//! A pseudo-terminal is a pair of devices. The slave is a terminal as the console,
+//! with the same line discipline, while the master plays the role of the hardware:
+//! what is written to the master is the input of the slave,
+//! and the output of the slave is read from the master.
+//!
+//! Opening `/dev/ptmx` makes a new pair, whose slave is `/dev/pts/<index>`.
+//! The slave can not be opened until it is unlocked by `TIOCSPTLCK`,
+//! and it is hung up when the master is closed.
+
+use alloc::{collections::VecDeque, sync::Arc};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::{Tty, TtyDevice};
+use crate::signal::has_signal_to_handle;
+use crate::sync::Condvar;
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::syscall::SysError;
+
+/// Capacity of the output of the slave, not read by the master yet
+const PTY_CAPACITY: usize = 64 * 1024;
+
+struct PtyOutput {
+    buf: VecDeque<u8>,
+    /// whether the master is still open
+    master_open: bool,
+    /// number of open files of the slave
+    slaves: usize,
+    /// whether the slave has been opened
+    slave_opened: bool,
+}
+
+impl PtyOutput {
+    /// The slave is closed after it has been opened
+    fn slave_closed(&self) -> bool {
+        self.slave_opened && self.slaves == 0
+    }
+}
+
+/// The device of the slave, i.e. the master end
+struct PtyDevice {
+    output: Mutex<PtyOutput>,
+    /// notified when there is output to read, or the slave is closed
+    readable: Arc<Condvar>,
+    /// notified when the output is read, or the master is closed
+    writable: Arc<Condvar>,
+}
+
+impl TtyDevice for PtyDevice {
+    /// Write all of `buf` at once, blocking while there is no room
+    fn write(&self, buf: &[u8]) -> Result<(), SysError> {
+        let mut output = self.output.lock();
+        loop {
+            if !output.master_open {
+                return Err(SysError::EIO);
+            }
+            // a write larger than the capacity goes when the buffer is empty
+            if output.buf.is_empty() || output.buf.len() + buf.len() <= PTY_CAPACITY {
+                break;
+            }
+            let writable = self.writable.clone();
+            output = writable.wait(output);
+            if has_signal_to_handle() {
+                return Err(SysError::EINTR);
+            }
+        }
+        output.buf.extend(buf.iter());
+        self.readable.notify_all();
+        Ok(())
+    }
+
+    /// Write what there is room for, the rest is lost
+    fn echo(&self, buf: &[u8]) {
+        let mut output = self.output.lock();
+        if !output.master_open {
+            return;
+        }
+        let len = buf.len().min(PTY_CAPACITY.saturating_sub(output.buf.len()));
+        output.buf.extend(buf[..len].iter());
+        self.readable.notify_all();
+    }
+}
+
+/// A pair of master and slave
+pub struct Pty {
+    index: usize,
+    tty: Arc<Tty>,
+    device: Arc<PtyDevice>,
+    /// the slave can not be opened until unlocked
+    locked: AtomicBool,
+}
+
+impl Pty {
+    /// Make a pair with the slave numbered `index`, which is locked
+    pub fn new(index: usize) -> Self {
+        let device = Arc::new(PtyDevice {
+            output: Mutex::new(PtyOutput {
+                buf: VecDeque::new(),
+                master_open: true,
+                slaves: 0,
+                slave_opened: false,
+            }),
+            readable: Arc::new(Condvar::new()),
+            writable: Arc::new(Condvar::new()),
+        });
+        Pty {
+            index,
+            tty: Arc::new(Tty::new(device.clone())),
+            device,
+            locked: AtomicBool::new(true),
+        }
+    }
+
+    /// The number of the slave, i.e. `TIOCGPTN`
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The terminal of the slave
+    pub fn tty(&self) -> &Arc<Tty> {
+        &self.tty
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::SeqCst)
+    }
+
+    pub fn set_locked(&self, locked: bool) {
+        self.locked.store(locked, Ordering::SeqCst);
+    }
+
+    /// Count an open file of the slave.
+    /// Fail with EIO if it is locked, or the master is closed.
+    pub fn open_slave(&self) -> Result<(), SysError> {
+        let mut output = self.device.output.lock();
+        if self.is_locked() || !output.master_open {
+            return Err(SysError::EIO);
+        }
+        output.slaves += 1;
+        output.slave_opened = true;
+        Ok(())
+    }
+
+    /// Called when an open file of the slave is closed
+    pub fn close_slave(&self) {
+        let mut output = self.device.output.lock();
+        output.slaves -= 1;
+        if output.slaves == 0 {
+            // the master reads EIO now
+            self.device.readable.notify_all();
+        }
+    }
+
+    /// Called when the master is closed, hanging up the slave
+    pub fn close_master(&self) {
+        {
+            let mut output = self.device.output.lock();
+            output.master_open = false;
+            output.buf.clear();
+            self.device.writable.notify_all();
+        }
+        self.tty.hangup();
+    }
+
+    /// Read the output of the slave, blocking until there is some.
+    /// Fail with EIO if the slave is closed.
+    pub fn master_read(&self, buf: &mut [u8]) -> Result<usize, SysError> {
+        if buf.len() == 0 {
+            return Ok(0);
+        }
+        let mut output = self.device.output.lock();
+        loop {
+            if !output.buf.is_empty() {
+                let len = buf.len().min(output.buf.len());
+                for (dst, src) in buf.iter_mut().zip(output.buf.drain(..len)) {
+                    *dst = src;
+                }
+                self.device.writable.notify_all();
+                return Ok(len);
+            }
+            if output.slave_closed() {
+                return Err(SysError::EIO);
+            }
+            let readable = self.device.readable.clone();
+            output = readable.wait(output);
+            if has_signal_to_handle() {
+                return Err(SysError::EINTR);
+            }
+        }
+    }
+
+    /// Write `buf` as the input of the slave, blocking while there is no room
+    /// until the slave reads. Return the number of bytes written, fewer than
+    /// `buf.len()` if interrupted by a signal after some are written.
+    pub fn master_write(&self, buf: &[u8]) -> Result<usize, SysError> {
+        self.tty.receive_all(buf)
+    }
+
+    /// Whether a write of the master would not block
+    pub fn master_can_write(&self) -> bool {
+        self.tty.can_receive()
+    }
+
+    /// Whether a read of the master would not block
+    pub fn master_can_read(&self) -> bool {
+        let output = self.device.output.lock();
+        !output.buf.is_empty() || output.slave_closed()
+    }
+
+    /// Condvar notified when the master may become readable
+    pub fn master_readable(&self) -> Arc<Condvar> {
+        self.device.readable.clone()
+    }
+
+    /// Number of bytes the master can read, i.e. `FIONREAD` of the master
+    pub fn output_len(&self) -> usize {
+        self.device.output.lock().buf.len()
+    }
+
+    /// Whether a write of the slave would not block
+    pub fn slave_can_write(&self) -> bool {
+        let output = self.device.output.lock();
+        output.buf.len() < PTY_CAPACITY || !output.master_open
+    }
+}
@@ -1,39 +1,14 @@
-//! Implement INode for Stdin & Stdout
+//! Implement INode for Stdin & Stdout, the input and output of the console
 
-use alloc::{collections::vec_deque::VecDeque, string::String, sync::Arc};
+use alloc::{string::String, sync::Arc};
 use core::any::Any;
 
 use rcore_fs::vfs::*;
 
-use crate::sync::Condvar;
-use crate::sync::SpinNoIrqLock as Mutex;
+use super::CONSOLE;
 
 #[derive(Default)]
-pub struct Stdin {
-    buf: Mutex<VecDeque<char>>,
-    pub pushed: Condvar,
-}
-
-impl Stdin {
-    pub fn push(&self, c: char) {
-        self.buf.lock().push_back(c);
-        self.pushed.notify_one();
-    }
-    pub fn pop(&self) -> char {
-        // QEMU v3.0 don't support M-mode external interrupt (bug?)
-        // So we have to use polling.
-        loop {
-            let ret = self.buf.lock().pop_front();
-            match ret {
-                Some(c) => return c,
-                None => self.pushed._wait(),
-            }
-        }
-    }
-    pub fn can_read(&self) -> bool {
-        self.buf.lock().len() > 0
-    }
-}
+pub struct Stdin;
 
 #[derive(Default)]
 pub struct Stdout;
@@ -64,16 +39,16 @@ macro_rules! impl_inode {
 }
 
 impl INode for Stdin {
-    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
-        buf[0] = self.pop() as u8;
-        Ok(1)
+    /// EINTR can not be told by `FsError`, so `FileLike` reads by `Tty::read`
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        CONSOLE.read(buf).map_err(|_| FsError::InvalidParam)
     }
     fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
         unimplemented!()
     }
     fn poll(&self) -> Result<PollStatus> {
         Ok(PollStatus {
-            read: self.can_read(),
+            read: CONSOLE.can_read(),
             write: false,
             error: false,
         })
@@ -86,11 +61,7 @@ impl INode for Stdout {
         unimplemented!()
     }
     fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
-        use core::str;
-        //we do not care the utf-8 things, we just want to print it!
-        let s = unsafe { str::from_utf8_unchecked(buf) };
-        print!("{}", s);
-        Ok(buf.len())
+        CONSOLE.write(buf).map_err(|_| FsError::InvalidParam)
     }
     fn poll(&self) -> Result<PollStatus> {
         Ok(PollStatus {
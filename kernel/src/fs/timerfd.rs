@@ -0,0 +1,122 @@
+//! Timer files of `timerfd_create`
+//!
+//! Reading one gets the number of expirations since it was set or read,
+//! as a `u64`, blocking until there is any.
+
+use alloc::sync::Arc;
+
+use rcore_fs::vfs::PollStatus;
+
+use crate::signal::has_signal_to_handle;
+use crate::sync::{Condvar, SpinNoIrqLock as Mutex};
+use crate::syscall::{SysError, SysResult};
+use crate::timer::Timer;
+
+#[derive(Default)]
+struct TimerState {
+    timer: Option<Timer>,
+    interval: usize,
+    expirations: u64,
+}
+
+impl TimerState {
+    fn get(&self) -> (usize, usize) {
+        let value = self.timer.as_ref().and_then(Timer::remaining);
+        (value.unwrap_or(0), self.interval)
+    }
+}
+
+struct TimerFdInner {
+    state: Mutex<TimerState>,
+    /// notified when the timer expires
+    expired: Arc<Condvar>,
+}
+
+impl TimerFdInner {
+    fn expire(&self) {
+        self.state.lock().expirations += 1;
+        self.expired.notify_all();
+    }
+}
+
+/// A timer file, sharing its timer with the duplicated ones
+#[derive(Clone)]
+pub struct TimerFd {
+    inner: Arc<TimerFdInner>,
+    /// the clock measuring it
+    pub clock: usize,
+    nonblock: bool,
+}
+
+impl TimerFd {
+    /// Create a disarmed timer measured by `clock`.
+    /// Reading it fails with EAGAIN instead of blocking if `nonblock`.
+    pub fn new(clock: usize, nonblock: bool) -> Self {
+        TimerFd {
+            inner: Arc::new(TimerFdInner {
+                state: Mutex::new(TimerState::default()),
+                expired: Arc::new(Condvar::new()),
+            }),
+            clock,
+            nonblock,
+        }
+    }
+
+    /// Ticks until it expires, and the interval
+    pub fn get(&self) -> (usize, usize) {
+        self.inner.state.lock().get()
+    }
+
+    /// Arm it to expire at tick `deadline`, then every `interval` ticks,
+    /// or disarm it if `deadline` is `None`. Return the old value and interval.
+    pub fn set(&self, deadline: Option<usize>, interval: usize) -> (usize, usize) {
+        let mut state = self.inner.state.lock();
+        let old = state.get();
+        *state = TimerState::default();
+        if let Some(deadline) = deadline {
+            let inner = Arc::downgrade(&self.inner);
+            state.timer = Some(Timer::new(deadline, interval, move || {
+                if let Some(inner) = inner.upgrade() {
+                    inner.expire();
+                }
+            }));
+            state.interval = interval;
+        }
+        old
+    }
+
+    /// Read the expirations, blocking until there is any
+    pub fn read(&self, buf: &mut [u8]) -> SysResult {
+        if buf.len() < 8 {
+            return Err(SysError::EINVAL);
+        }
+        loop {
+            let mut state = self.inner.state.lock();
+            if state.expirations > 0 {
+                buf[..8].copy_from_slice(&state.expirations.to_ne_bytes());
+                state.expirations = 0;
+                return Ok(8);
+            }
+            if self.nonblock {
+                return Err(SysError::EAGAIN);
+            }
+            drop(self.inner.expired.wait(state));
+            if has_signal_to_handle() {
+                return Err(SysError::EINTR);
+            }
+        }
+    }
+
+    pub fn poll(&self) -> PollStatus {
+        PollStatus {
+            read: self.inner.state.lock().expirations > 0,
+            write: false,
+            error: false,
+        }
+    }
+
+    /// Condvar notified when it expires
+    pub fn wakeup_source(&self) -> Arc<Condvar> {
+        self.inner.expired.clone()
+    }
+}
@@ -0,0 +1,486 @@
+//! In-memory file system
+//!
+//! File content is kept in frames from the frame allocator, page by page,
+//! so it is accounted as used memory, and a hole in a file takes no frame.
+
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::any::Any;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use rcore_fs::vfs::*;
+use rcore_memory::paging::PageTableExt;
+use rcore_memory::PAGE_SIZE;
+use spin::RwLock;
+
+use crate::memory::{active_table, alloc_frame, dealloc_frame};
+use crate::sync::FlagsGuard;
+use crate::syscall::TimeSpec;
+
+pub struct TmpFS {
+    root: RwLock<Option<Arc<TmpINode>>>,
+    next_id: AtomicUsize,
+}
+
+impl TmpFS {
+    pub fn new() -> Arc<Self> {
+        let fs = Arc::new(TmpFS {
+            root: RwLock::new(None),
+            next_id: AtomicUsize::new(1),
+        });
+        let root = TmpINode::new(&fs, FileType::Dir, 0o777, None);
+        *fs.root.write() = Some(root);
+        fs
+    }
+}
+
+impl FileSystem for TmpFS {
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn root_inode(&self) -> Arc<INode> {
+        self.root.read().clone().unwrap()
+    }
+
+    fn info(&self) -> FsInfo {
+        FsInfo {
+            max_file_size: usize::max_value(),
+        }
+    }
+}
+
+fn now() -> Timespec {
+    TimeSpec::get_epoch().into()
+}
+
+/// A zeroed frame holding a page of file content
+struct Frame(usize);
+
+impl Frame {
+    fn new() -> Result<Self> {
+        let frame = Frame(alloc_frame().ok_or(FsError::NoDeviceSpace)?);
+        frame.write(0, &[0; PAGE_SIZE]);
+        Ok(frame)
+    }
+
+    /// Access the frame by the temporary map, which is not reentrant,
+    /// so user memory must not be touched inside `f` in case of page fault.
+    fn with<T>(&self, f: impl FnOnce(&mut [u8; PAGE_SIZE]) -> T) -> T {
+        let _guard = FlagsGuard::no_irq_region();
+        active_table().with_temporary_map(self.0, |_, page: &mut [u8; PAGE_SIZE]| f(page))
+    }
+
+    fn read(&self, offset: usize, buf: &mut [u8]) {
+        let mut data = [0u8; PAGE_SIZE];
+        self.with(|page| data.copy_from_slice(page));
+        buf.copy_from_slice(&data[offset..offset + buf.len()]);
+    }
+
+    fn write(&self, offset: usize, buf: &[u8]) {
+        let mut data = [0u8; PAGE_SIZE];
+        let len = buf.len();
+        data[..len].copy_from_slice(buf);
+        self.with(|page| page[offset..offset + len].copy_from_slice(&data[..len]));
+    }
+}
+
+impl Drop for Frame {
+    fn drop(&mut self) {
+        dealloc_frame(self.0);
+    }
+}
+
+struct TmpINode {
+    fs: Weak<TmpFS>,
+    inner: RwLock<TmpINodeInner>,
+}
+
+struct TmpINodeInner {
+    metadata: Metadata,
+    /// content of a file or symlink, by page index
+    pages: BTreeMap<usize, Frame>,
+    /// entries of a directory, except `.` and `..`
+    entries: BTreeMap<String, Arc<TmpINode>>,
+    this: Weak<TmpINode>,
+    parent: Weak<TmpINode>,
+}
+
+impl TmpINode {
+    /// Create an inode in `fs`, under `parent` or as the root directory
+    fn new(
+        fs: &Arc<TmpFS>,
+        type_: FileType,
+        mode: u16,
+        parent: Option<&Arc<TmpINode>>,
+    ) -> Arc<Self> {
+        let id = fs.next_id.fetch_add(1, Ordering::SeqCst);
+        let time = now();
+        let inode = Arc::new(TmpINode {
+            fs: Arc::downgrade(fs),
+            inner: RwLock::new(TmpINodeInner {
+                metadata: Metadata {
+                    dev: 0,
+                    inode: id,
+                    size: 0,
+                    blk_size: PAGE_SIZE,
+                    blocks: 0,
+                    atime: time,
+                    mtime: time,
+                    ctime: time,
+                    type_,
+                    mode,
+                    nlinks: if type_ == FileType::Dir { 2 } else { 1 },
+                    uid: 0,
+                    gid: 0,
+                },
+                pages: BTreeMap::new(),
+                entries: BTreeMap::new(),
+                this: Weak::new(),
+                parent: Weak::new(),
+            }),
+        });
+        {
+            let mut inner = inode.inner.write();
+            inner.this = Arc::downgrade(&inode);
+            inner.parent = Arc::downgrade(parent.unwrap_or(&inode));
+        }
+        inode
+    }
+
+    fn this(&self) -> Arc<TmpINode> {
+        self.inner.read().this.upgrade().unwrap()
+    }
+
+    fn fs_arc(&self) -> Arc<TmpFS> {
+        self.fs.upgrade().expect("tmpfs is dropped")
+    }
+
+    fn type_(&self) -> FileType {
+        self.inner.read().metadata.type_
+    }
+
+    /// Downcast `inode` to an inode in the same file system
+    fn same_fs(&self, inode: &Arc<INode>) -> Result<Arc<TmpINode>> {
+        let inode = inode
+            .as_any_ref()
+            .downcast_ref::<TmpINode>()
+            .ok_or(FsError::NotSameFs)?;
+        if !Arc::ptr_eq(&inode.fs_arc(), &self.fs_arc()) {
+            return Err(FsError::NotSameFs);
+        }
+        Ok(inode.this())
+    }
+}
+
+impl TmpINodeInner {
+    fn check_dir(&self) -> Result<()> {
+        match self.metadata.type_ {
+            FileType::Dir => Ok(()),
+            _ => Err(FsError::NotDir),
+        }
+    }
+
+    fn check_not_dir(&self) -> Result<()> {
+        match self.metadata.type_ {
+            FileType::Dir => Err(FsError::IsDir),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check `name` can be added to the directory, which must not be removed
+    fn check_new_name(&self, name: &str) -> Result<()> {
+        if name == "" || name == "." || name == ".." || name.contains('/') {
+            return Err(FsError::InvalidParam);
+        }
+        if self.metadata.nlinks == 0 {
+            return Err(FsError::DirRemoved);
+        }
+        if self.entries.contains_key(name) {
+            return Err(FsError::EntryExist);
+        }
+        Ok(())
+    }
+
+    fn touch(&mut self) {
+        let time = now();
+        self.metadata.mtime = time;
+        self.metadata.ctime = time;
+    }
+}
+
+impl INode for TmpINode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let inner = self.inner.read();
+        inner.check_not_dir()?;
+        let size = inner.metadata.size;
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min(size - offset);
+        let mut pos = offset;
+        while pos < offset + len {
+            let page_offset = pos % PAGE_SIZE;
+            let chunk_len = (PAGE_SIZE - page_offset).min(offset + len - pos);
+            let chunk = &mut buf[pos - offset..pos - offset + chunk_len];
+            match inner.pages.get(&(pos / PAGE_SIZE)) {
+                Some(frame) => frame.read(page_offset, chunk),
+                // a hole
+                None => chunk.iter_mut().for_each(|x| *x = 0),
+            }
+            pos += chunk_len;
+        }
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        let mut inner = self.inner.write();
+        inner.check_not_dir()?;
+        let mut pos = offset;
+        while pos < offset + buf.len() {
+            let page_offset = pos % PAGE_SIZE;
+            let chunk_len = (PAGE_SIZE - page_offset).min(offset + buf.len() - pos);
+            let chunk = &buf[pos - offset..pos - offset + chunk_len];
+            let index = pos / PAGE_SIZE;
+            if !inner.pages.contains_key(&index) {
+                match Frame::new() {
+                    Ok(frame) => inner.pages.insert(index, frame),
+                    // write as much as possible
+                    Err(_) if pos > offset => break,
+                    Err(err) => return Err(err),
+                };
+            }
+            inner.pages[&index].write(page_offset, chunk);
+            pos += chunk_len;
+        }
+        inner.metadata.size = inner.metadata.size.max(pos);
+        inner.touch();
+        Ok(pos - offset)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: true,
+            write: true,
+            error: false,
+        })
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        let inner = self.inner.read();
+        let mut metadata = inner.metadata.clone();
+        metadata.blocks = inner.pages.len();
+        Ok(metadata)
+    }
+
+    fn set_metadata(&self, metadata: &Metadata) -> Result<()> {
+        let mut inner = self.inner.write();
+        inner.metadata.atime = metadata.atime;
+        inner.metadata.mtime = metadata.mtime;
+        inner.metadata.ctime = metadata.ctime;
+        inner.metadata.mode = metadata.mode;
+        inner.metadata.uid = metadata.uid;
+        inner.metadata.gid = metadata.gid;
+        Ok(())
+    }
+
+    fn sync_all(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn sync_data(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn resize(&self, len: usize) -> Result<()> {
+        let mut inner = self.inner.write();
+        inner.check_not_dir()?;
+        if len < inner.metadata.size {
+            // free the pages after the end, and zero the rest of the last page
+            let pages = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+            let removed: Vec<usize> = inner.pages.range(pages..).map(|(&i, _)| i).collect();
+            for index in removed {
+                inner.pages.remove(&index);
+            }
+            let page_offset = len % PAGE_SIZE;
+            if page_offset != 0 {
+                if let Some(frame) = inner.pages.get(&(len / PAGE_SIZE)) {
+                    frame.write(page_offset, &[0; PAGE_SIZE][page_offset..]);
+                }
+            }
+        }
+        inner.metadata.size = len;
+        inner.touch();
+        Ok(())
+    }
+
+    fn create(&self, name: &str, type_: FileType, mode: u32) -> Result<Arc<INode>> {
+        let mut inner = self.inner.write();
+        inner.check_dir()?;
+        inner.check_new_name(name)?;
+        let parent = inner.this.upgrade().unwrap();
+        let inode = TmpINode::new(&self.fs_arc(), type_, mode as u16, Some(&parent));
+        if type_ == FileType::Dir {
+            inner.metadata.nlinks += 1;
+        }
+        inner.entries.insert(String::from(name), inode.clone());
+        inner.touch();
+        Ok(inode)
+    }
+
+    fn unlink(&self, name: &str) -> Result<()> {
+        let mut inner = self.inner.write();
+        inner.check_dir()?;
+        if name == "." || name == ".." {
+            return Err(FsError::IsDir);
+        }
+        let inode = inner
+            .entries
+            .get(name)
+            .ok_or(FsError::EntryNotFound)?
+            .clone();
+        {
+            let mut child = inode.inner.write();
+            if child.metadata.type_ == FileType::Dir {
+                if !child.entries.is_empty() {
+                    return Err(FsError::DirNotEmpty);
+                }
+                child.metadata.nlinks = 0;
+                inner.metadata.nlinks -= 1;
+            } else {
+                child.metadata.nlinks -= 1;
+            }
+            child.metadata.ctime = now();
+        }
+        inner.entries.remove(name);
+        inner.touch();
+        Ok(())
+    }
+
+    fn link(&self, name: &str, other: &Arc<INode>) -> Result<()> {
+        let other = self.same_fs(other)?;
+        if other.type_() == FileType::Dir {
+            return Err(FsError::IsDir);
+        }
+        let mut inner = self.inner.write();
+        inner.check_dir()?;
+        inner.check_new_name(name)?;
+        {
+            let mut other = other.inner.write();
+            other.metadata.nlinks += 1;
+            other.metadata.ctime = now();
+        }
+        inner.entries.insert(String::from(name), other);
+        inner.touch();
+        Ok(())
+    }
+
+    fn move_(&self, old_name: &str, target: &Arc<INode>, new_name: &str) -> Result<()> {
+        let target = self.same_fs(target)?;
+        {
+            let target = target.inner.read();
+            target.check_dir()?;
+            target.check_new_name(new_name)?;
+        }
+        let inode = {
+            let inner = self.inner.read();
+            inner.check_dir()?;
+            if old_name == "." || old_name == ".." {
+                return Err(FsError::IsDir);
+            }
+            inner
+                .entries
+                .get(old_name)
+                .ok_or(FsError::EntryNotFound)?
+                .clone()
+        };
+        let is_dir = inode.type_() == FileType::Dir;
+        if is_dir {
+            // a directory can not be moved into itself
+            let mut dir = target.clone();
+            loop {
+                if Arc::ptr_eq(&dir, &inode) {
+                    return Err(FsError::InvalidParam);
+                }
+                let parent = dir
+                    .inner
+                    .read()
+                    .parent
+                    .upgrade()
+                    .ok_or(FsError::DirRemoved)?;
+                if Arc::ptr_eq(&parent, &dir) {
+                    break;
+                }
+                dir = parent;
+            }
+        }
+        {
+            let mut inner = self.inner.write();
+            inner.entries.remove(old_name);
+            if is_dir {
+                inner.metadata.nlinks -= 1;
+            }
+            inner.touch();
+        }
+        {
+            let mut target_inner = target.inner.write();
+            target_inner
+                .entries
+                .insert(String::from(new_name), inode.clone());
+            if is_dir {
+                target_inner.metadata.nlinks += 1;
+            }
+            target_inner.touch();
+        }
+        let mut inner = inode.inner.write();
+        inner.parent = Arc::downgrade(&target);
+        inner.metadata.ctime = now();
+        Ok(())
+    }
+
+    fn find(&self, name: &str) -> Result<Arc<INode>> {
+        let inner = self.inner.read();
+        inner.check_dir()?;
+        let inode = match name {
+            "." => inner.this.upgrade().unwrap(),
+            ".." => inner.parent.upgrade().ok_or(FsError::DirRemoved)?,
+            _ => inner
+                .entries
+                .get(name)
+                .cloned()
+                .ok_or(FsError::EntryNotFound)?,
+        };
+        Ok(inode)
+    }
+
+    fn get_entry(&self, id: usize) -> Result<String> {
+        let inner = self.inner.read();
+        inner.check_dir()?;
+        match id {
+            0 => Ok(String::from(".")),
+            1 => Ok(String::from("..")),
+            _ => inner
+                .entries
+                .keys()
+                .nth(id - 2)
+                .cloned()
+                .ok_or(FsError::EntryNotFound),
+        }
+    }
+
+    fn io_control(&self, _cmd: u32, _data: u32) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+
+    fn fs(&self) -> Arc<FileSystem> {
+        self.fs_arc()
+    }
+
+    fn as_any_ref(&self) -> &Any {
+        self
+    }
+}
@@ -0,0 +1,872 @@
+//! Terminals, with the line discipline and job control
+//!
+//! Input from the device is processed by the line discipline as its termios says:
+//! signal characters are sent to the foreground process group, lines are edited
+//! in canonical mode, and characters are echoed. Output is post-processed too,
+//! e.g. a newline becomes CR-NL.
+//!
+//! A terminal may be the controlling terminal of a session, which is
+//! inherited by the processes in it. Only the foreground process group
+//! of the session may read from it, while a background group reading it
+//! is stopped by `SIGTTIN`.
+//!
+//! The device behind a terminal is the console, or the master of a
+//! pseudo-terminal. A terminal is hung up when its device is gone.
+
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::str;
+
+use bitflags::bitflags;
+use rcore_fs::vfs::INode;
+
+use super::devfs::{PtySlave, TtyINode};
+use super::stdio::{Stdin, Stdout};
+use crate::consts::USEC_PER_TICK;
+use crate::signal::{
+    send_signal_to_pgid, send_signal_to_pid, SIGCONT, SIGHUP, SIGINT, SIGQUIT, SIGTSTP,
+};
+use crate::sync::Condvar;
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::syscall::SysError;
+use crate::timer::{self, Timer};
+
+/// ioctl requests of terminals
+#[cfg(not(target_arch = "mips"))]
+pub mod ioctl {
+    pub const TCGETS: usize = 0x5401;
+    pub const TCSETS: usize = 0x5402;
+    pub const TCSETSW: usize = 0x5403;
+    pub const TCSETSF: usize = 0x5404;
+    pub const TCFLSH: usize = 0x540b;
+    pub const TIOCSCTTY: usize = 0x540e;
+    pub const TIOCGPGRP: usize = 0x540f;
+    pub const TIOCSPGRP: usize = 0x5410;
+    pub const TIOCGWINSZ: usize = 0x5413;
+    pub const TIOCSWINSZ: usize = 0x5414;
+    pub const FIONREAD: usize = 0x541b;
+    pub const TIOCNOTTY: usize = 0x5422;
+    pub const TIOCGSID: usize = 0x5429;
+    pub const TIOCGPTN: usize = 0x8004_5430;
+    pub const TIOCSPTLCK: usize = 0x4004_5431;
+    pub const TIOCGPTLCK: usize = 0x8004_5439;
+}
+
+/// ioctl requests of terminals
+#[cfg(target_arch = "mips")]
+pub mod ioctl {
+    pub const TCGETS: usize = 0x540d;
+    pub const TCSETS: usize = 0x540e;
+    pub const TCSETSW: usize = 0x540f;
+    pub const TCSETSF: usize = 0x5410;
+    pub const TCFLSH: usize = 0x5407;
+    pub const TIOCSCTTY: usize = 0x5480;
+    pub const TIOCGPGRP: usize = 0x4004_7477;
+    pub const TIOCSPGRP: usize = 0x8004_7476;
+    pub const TIOCGWINSZ: usize = 0x4008_7468;
+    pub const TIOCSWINSZ: usize = 0x8008_7467;
+    pub const FIONREAD: usize = 0x467f;
+    pub const TIOCNOTTY: usize = 0x5471;
+    pub const TIOCGSID: usize = 0x7416;
+    pub const TIOCGPTN: usize = 0x4004_5430;
+    pub const TIOCSPTLCK: usize = 0x8004_5431;
+    pub const TIOCGPTLCK: usize = 0x4004_5439;
+}
+
+/// Indexes of control characters in `Termios::cc`
+#[cfg(not(target_arch = "mips"))]
+pub mod cc {
+    pub const NCCS: usize = 19;
+    pub const VINTR: usize = 0;
+    pub const VQUIT: usize = 1;
+    pub const VERASE: usize = 2;
+    pub const VKILL: usize = 3;
+    pub const VEOF: usize = 4;
+    pub const VTIME: usize = 5;
+    pub const VMIN: usize = 6;
+    pub const VSTART: usize = 8;
+    pub const VSTOP: usize = 9;
+    pub const VSUSP: usize = 10;
+    pub const VEOL: usize = 11;
+    pub const VREPRINT: usize = 12;
+    pub const VDISCARD: usize = 13;
+    pub const VWERASE: usize = 14;
+    pub const VLNEXT: usize = 15;
+    pub const VEOL2: usize = 16;
+}
+
+/// Indexes of control characters in `Termios::cc`
+#[cfg(target_arch = "mips")]
+pub mod cc {
+    pub const NCCS: usize = 23;
+    pub const VINTR: usize = 0;
+    pub const VQUIT: usize = 1;
+    pub const VERASE: usize = 2;
+    pub const VKILL: usize = 3;
+    pub const VMIN: usize = 4;
+    pub const VTIME: usize = 5;
+    pub const VEOL2: usize = 6;
+    pub const VSTART: usize = 8;
+    pub const VSTOP: usize = 9;
+    pub const VSUSP: usize = 10;
+    pub const VREPRINT: usize = 12;
+    pub const VDISCARD: usize = 13;
+    pub const VWERASE: usize = 14;
+    pub const VLNEXT: usize = 15;
+    pub const VEOF: usize = 16;
+    pub const VEOL: usize = 17;
+}
+
+use self::cc::*;
+
+bitflags! {
+    /// Input modes, `c_iflag`
+    pub struct InputFlags: u32 {
+        const IGNBRK = 0x1;
+        const BRKINT = 0x2;
+        const ISTRIP = 0x20;
+        const INLCR = 0x40;
+        const IGNCR = 0x80;
+        const ICRNL = 0x100;
+        const IXON = 0x400;
+        const IUTF8 = 0x4000;
+    }
+}
+
+bitflags! {
+    /// Output modes, `c_oflag`
+    pub struct OutputFlags: u32 {
+        const OPOST = 0x1;
+        const ONLCR = 0x4;
+        const OCRNL = 0x8;
+    }
+}
+
+#[cfg(not(target_arch = "mips"))]
+bitflags! {
+    /// Local modes, `c_lflag`
+    pub struct LocalFlags: u32 {
+        const ISIG = 0x1;
+        const ICANON = 0x2;
+        const ECHO = 0x8;
+        const ECHOE = 0x10;
+        const ECHOK = 0x20;
+        const ECHONL = 0x40;
+        const NOFLSH = 0x80;
+        const TOSTOP = 0x100;
+        const ECHOCTL = 0x200;
+        const ECHOKE = 0x800;
+        const IEXTEN = 0x8000;
+    }
+}
+
+#[cfg(target_arch = "mips")]
+bitflags! {
+    /// Local modes, `c_lflag`
+    pub struct LocalFlags: u32 {
+        const ISIG = 0x1;
+        const ICANON = 0x2;
+        const ECHO = 0x8;
+        const ECHOE = 0x10;
+        const ECHOK = 0x20;
+        const ECHONL = 0x40;
+        const NOFLSH = 0x80;
+        const IEXTEN = 0x100;
+        const ECHOCTL = 0x200;
+        const ECHOKE = 0x800;
+        const TOSTOP = 0x8000;
+    }
+}
+
+/// Max length of the input not read yet, including the line being edited in canonical mode
+const MAX_INPUT: usize = 4096;
+
+/// `struct termios` of `TCGETS` and `TCSETS`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Termios {
+    pub iflag: u32,
+    pub oflag: u32,
+    pub cflag: u32,
+    pub lflag: u32,
+    pub line: u8,
+    pub cc: [u8; NCCS],
+}
+
+impl Default for Termios {
+    /// The modes of a newly opened terminal in linux
+    fn default() -> Self {
+        let mut cc = [0u8; NCCS];
+        cc[VINTR] = 0x03; // ^C
+        cc[VQUIT] = 0x1c; // ^\
+        cc[VERASE] = 0x7f; // DEL
+        cc[VKILL] = 0x15; // ^U
+        cc[VEOF] = 0x04; // ^D
+        cc[VMIN] = 1;
+        cc[VSTART] = 0x11; // ^Q
+        cc[VSTOP] = 0x13; // ^S
+        cc[VSUSP] = 0x1a; // ^Z
+        cc[VREPRINT] = 0x12; // ^R
+        cc[VDISCARD] = 0x0f; // ^O
+        cc[VWERASE] = 0x17; // ^W
+        cc[VLNEXT] = 0x16; // ^V
+        let lflag = LocalFlags::ISIG
+            | LocalFlags::ICANON
+            | LocalFlags::ECHO
+            | LocalFlags::ECHOE
+            | LocalFlags::ECHOK
+            | LocalFlags::ECHOCTL
+            | LocalFlags::ECHOKE
+            | LocalFlags::IEXTEN;
+        Termios {
+            iflag: (InputFlags::ICRNL | InputFlags::IXON | InputFlags::IUTF8).bits(),
+            oflag: (OutputFlags::OPOST | OutputFlags::ONLCR).bits(),
+            // B38400 | CS8 | CREAD | HUPCL
+            cflag: 0x4bf,
+            lflag: lflag.bits(),
+            line: 0,
+            cc,
+        }
+    }
+}
+
+impl Termios {
+    pub fn iflag(&self) -> InputFlags {
+        InputFlags::from_bits_truncate(self.iflag)
+    }
+    pub fn oflag(&self) -> OutputFlags {
+        OutputFlags::from_bits_truncate(self.oflag)
+    }
+    pub fn lflag(&self) -> LocalFlags {
+        LocalFlags::from_bits_truncate(self.lflag)
+    }
+    /// Turn off the processing of input and output, as `cfmakeraw`
+    pub fn make_raw(&mut self) {
+        let iflag = InputFlags::IGNBRK
+            | InputFlags::BRKINT
+            | InputFlags::ISTRIP
+            | InputFlags::INLCR
+            | InputFlags::IGNCR
+            | InputFlags::ICRNL
+            | InputFlags::IXON;
+        let lflag = LocalFlags::ECHO
+            | LocalFlags::ECHONL
+            | LocalFlags::ICANON
+            | LocalFlags::ISIG
+            | LocalFlags::IEXTEN;
+        self.iflag &= !iflag.bits();
+        self.oflag &= !OutputFlags::OPOST.bits();
+        self.lflag &= !lflag.bits();
+        self.cc[VMIN] = 1;
+        self.cc[VTIME] = 0;
+    }
+    /// Whether `c` is the control character at `index`, which is disabled if 0
+    fn is(&self, c: u8, index: usize) -> bool {
+        self.cc[index] != 0 && self.cc[index] == c
+    }
+}
+
+/// `struct winsize` of `TIOCGWINSZ` and `TIOCSWINSZ`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WinSize {
+    pub row: u16,
+    pub col: u16,
+    pub xpixel: u16,
+    pub ypixel: u16,
+}
+
+impl Default for WinSize {
+    fn default() -> Self {
+        WinSize {
+            row: 24,
+            col: 80,
+            xpixel: 0,
+            ypixel: 0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct TtyJob {
+    /// the session it controls
+    session: Option<usize>,
+    /// the foreground process group of the session
+    foreground: usize,
+}
+
+/// The line discipline
+#[derive(Default)]
+struct LineDiscipline {
+    termios: Termios,
+    winsize: WinSize,
+    /// complete lines to read in canonical mode, an empty one is EOF
+    lines: VecDeque<Vec<u8>>,
+    /// the line being edited in canonical mode
+    line: Vec<u8>,
+    /// input to read in non-canonical mode
+    raw: VecDeque<u8>,
+    /// the next character is taken literally, after `VLNEXT`
+    literal: bool,
+    /// the device is gone, reading gets EOF and writing fails
+    hung_up: bool,
+}
+
+/// The device of a terminal, where its processed output goes
+pub trait TtyDevice: Send + Sync {
+    /// Write the output, blocking while the device is busy
+    fn write(&self, buf: &[u8]) -> Result<(), SysError>;
+    /// Write the echo of the input, which must not block
+    fn echo(&self, buf: &[u8]);
+}
+
+pub struct Tty {
+    job: Mutex<TtyJob>,
+    ldisc: Mutex<LineDiscipline>,
+    /// notified when there is input to read
+    readable: Arc<Condvar>,
+    /// notified when the input is read or discarded, making room for more
+    writable: Arc<Condvar>,
+    device: Arc<dyn TtyDevice>,
+}
+
+/// The serial port and the screen
+struct Console;
+
+impl TtyDevice for Console {
+    fn write(&self, buf: &[u8]) -> Result<(), SysError> {
+        self.echo(buf);
+        Ok(())
+    }
+    fn echo(&self, buf: &[u8]) {
+        // we do not care the utf-8 things, we just want to print it!
+        print!("{}", unsafe { str::from_utf8_unchecked(buf) });
+    }
+}
+
+lazy_static! {
+    /// The kernel console, behind `STDIN` and `STDOUT`
+    pub static ref CONSOLE: Arc<Tty> = Arc::new(Tty::new(Arc::new(Console)));
+}
+
+/// Whether `c` is echoed as `^X` with `ECHOCTL`
+fn is_control(c: u8) -> bool {
+    (c < 0x20 && c != b'\t' && c != b'\n') || c == 0x7f
+}
+
+impl LineDiscipline {
+    /// Process input `c`, and push the characters to echo to `echo`.
+    /// Return the signal to send to the foreground process group if any.
+    /// Fail with EAGAIN if there is no room for it until the input is read,
+    /// leaving it unprocessed.
+    fn receive(&mut self, mut c: u8, echo: &mut Vec<u8>) -> Result<Option<usize>, SysError> {
+        let iflag = self.termios.iflag();
+        let lflag = self.termios.lflag();
+        if self.literal {
+            self.push(c, echo)?;
+            self.literal = false;
+            return Ok(None);
+        }
+        if iflag.contains(InputFlags::ISTRIP) {
+            c &= 0x7f;
+        }
+        if c == b'\r' {
+            if iflag.contains(InputFlags::IGNCR) {
+                return Ok(None);
+            }
+            if iflag.contains(InputFlags::ICRNL) {
+                c = b'\n';
+            }
+        } else if c == b'\n' && iflag.contains(InputFlags::INLCR) {
+            c = b'\r';
+        }
+        let termios = self.termios;
+        if lflag.contains(LocalFlags::ISIG) {
+            let sig = if termios.is(c, VINTR) {
+                Some(SIGINT)
+            } else if termios.is(c, VQUIT) {
+                Some(SIGQUIT)
+            } else if termios.is(c, VSUSP) {
+                Some(SIGTSTP)
+            } else {
+                None
+            };
+            if sig.is_some() {
+                if !lflag.contains(LocalFlags::NOFLSH) {
+                    self.flush_input();
+                }
+                self.echo(c, echo);
+                return Ok(sig);
+            }
+        }
+        if lflag.contains(LocalFlags::IEXTEN) && termios.is(c, VLNEXT) {
+            self.literal = true;
+            return Ok(None);
+        }
+        if lflag.contains(LocalFlags::ICANON) {
+            if termios.is(c, VERASE) {
+                self.erase(echo);
+                return Ok(None);
+            }
+            if lflag.contains(LocalFlags::IEXTEN) && termios.is(c, VWERASE) {
+                while self.line.last() == Some(&b' ') {
+                    self.erase(echo);
+                }
+                while self.line.last().map_or(false, |&c| c != b' ') {
+                    self.erase(echo);
+                }
+                return Ok(None);
+            }
+            if termios.is(c, VKILL) {
+                if lflag.contains(LocalFlags::ECHOKE) && lflag.contains(LocalFlags::ECHOE) {
+                    while self.erase(echo) {}
+                } else {
+                    self.line.clear();
+                    self.echo(c, echo);
+                    if lflag.contains(LocalFlags::ECHOK) {
+                        self.echo(b'\n', echo);
+                    }
+                }
+                return Ok(None);
+            }
+            if termios.is(c, VEOF) {
+                if !self.has_room(true)? {
+                    return Ok(None);
+                }
+                let line = core::mem::replace(&mut self.line, Vec::new());
+                self.lines.push_back(line);
+                return Ok(None);
+            }
+            if c == b'\n' || termios.is(c, VEOL) || termios.is(c, VEOL2) {
+                if !self.has_room(true)? {
+                    return Ok(None);
+                }
+                self.line.push(c);
+                if c == b'\n' && lflag.contains(LocalFlags::ECHONL) {
+                    echo.push(c);
+                } else {
+                    self.echo(c, echo);
+                }
+                let line = core::mem::replace(&mut self.line, Vec::new());
+                self.lines.push_back(line);
+                return Ok(None);
+            }
+        }
+        self.push(c, echo)?;
+        Ok(None)
+    }
+
+    /// Push `c` to the input, and echo it
+    fn push(&mut self, c: u8, echo: &mut Vec<u8>) -> Result<(), SysError> {
+        if !self.has_room(false)? {
+            return Ok(());
+        }
+        match self.termios.lflag().contains(LocalFlags::ICANON) {
+            true => self.line.push(c),
+            false => self.raw.push_back(c),
+        }
+        self.echo(c, echo);
+        Ok(())
+    }
+
+    /// Whether there is room for one more byte of input, the end of a line if `line_end`.
+    /// In canonical mode, the last byte is kept for the end of the line.
+    /// Fail with EAGAIN if the input can be read to make room. Otherwise the line
+    /// being edited is too long, and false means the byte is to be discarded.
+    fn has_room(&self, line_end: bool) -> Result<bool, SysError> {
+        let full = match self.termios.lflag().contains(LocalFlags::ICANON) {
+            true => self.input_len() + self.line.len() + (!line_end as usize) >= MAX_INPUT,
+            false => self.raw.len() >= MAX_INPUT,
+        };
+        match (full, self.can_read()) {
+            (false, _) => Ok(true),
+            (true, true) => Err(SysError::EAGAIN),
+            // the line must end to be read
+            (true, false) => Ok(line_end),
+        }
+    }
+
+    fn echo(&self, c: u8, echo: &mut Vec<u8>) {
+        let lflag = self.termios.lflag();
+        if !lflag.contains(LocalFlags::ECHO) {
+            return;
+        }
+        if lflag.contains(LocalFlags::ECHOCTL) && is_control(c) {
+            echo.push(b'^');
+            echo.push(c ^ 0x40);
+        } else {
+            echo.push(c);
+        }
+    }
+
+    /// Erase the last character of the line being edited.
+    /// Return false if the line is empty.
+    fn erase(&mut self, echo: &mut Vec<u8>) -> bool {
+        let mut c = match self.line.pop() {
+            Some(c) => c,
+            None => return false,
+        };
+        // erase a UTF-8 character at once
+        if self.termios.iflag().contains(InputFlags::IUTF8) {
+            while c & 0xc0 == 0x80 {
+                match self.line.pop() {
+                    Some(prev) => c = prev,
+                    None => break,
+                }
+            }
+        }
+        let lflag = self.termios.lflag();
+        if lflag.contains(LocalFlags::ECHO) && lflag.contains(LocalFlags::ECHOE) {
+            let width = match lflag.contains(LocalFlags::ECHOCTL) && is_control(c) {
+                true => 2,
+                false => 1,
+            };
+            for _ in 0..width {
+                echo.extend_from_slice(b"\x08 \x08");
+            }
+        } else {
+            self.echo(self.termios.cc[VERASE], echo);
+        }
+        true
+    }
+
+    fn flush_input(&mut self) {
+        self.lines.clear();
+        self.line.clear();
+        self.raw.clear();
+        self.literal = false;
+    }
+
+    /// Post-process output `buf` as `OPOST`
+    fn process_output(&self, buf: &[u8]) -> Vec<u8> {
+        let oflag = self.termios.oflag();
+        if !oflag.contains(OutputFlags::OPOST) {
+            return buf.to_vec();
+        }
+        let mut output = Vec::with_capacity(buf.len());
+        for &c in buf {
+            match c {
+                b'\n' if oflag.contains(OutputFlags::ONLCR) => output.extend_from_slice(b"\r\n"),
+                b'\r' if oflag.contains(OutputFlags::OCRNL) => output.push(b'\n'),
+                _ => output.push(c),
+            }
+        }
+        output
+    }
+
+    fn can_read(&self) -> bool {
+        if self.hung_up {
+            return true;
+        }
+        match self.termios.lflag().contains(LocalFlags::ICANON) {
+            true => !self.lines.is_empty(),
+            false => !self.raw.is_empty(),
+        }
+    }
+
+    /// Number of bytes to read
+    fn input_len(&self) -> usize {
+        match self.termios.lflag().contains(LocalFlags::ICANON) {
+            true => self.lines.iter().map(|line| line.len()).sum(),
+            false => self.raw.len(),
+        }
+    }
+
+    /// Switch between canonical and non-canonical mode, keeping the input
+    fn set_termios(&mut self, termios: Termios) {
+        let canonical = self.termios.lflag().contains(LocalFlags::ICANON);
+        self.termios = termios;
+        match (canonical, termios.lflag().contains(LocalFlags::ICANON)) {
+            (true, false) => {
+                for line in self.lines.drain(..) {
+                    self.raw.extend(line);
+                }
+                self.raw.extend(self.line.drain(..));
+            }
+            (false, true) => {
+                self.line.extend(self.raw.drain(..));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Tty {
+    /// Make a terminal of `device`
+    pub fn new(device: Arc<dyn TtyDevice>) -> Self {
+        Tty {
+            job: Mutex::new(TtyJob::default()),
+            ldisc: Mutex::new(LineDiscipline::default()),
+            readable: Arc::new(Condvar::new()),
+            writable: Arc::new(Condvar::new()),
+            device,
+        }
+    }
+
+    /// Process input `c` from the device, called by the driver.
+    /// Fail with EAGAIN if there is no room for it until the input is read.
+    pub fn receive(&self, c: u8) -> Result<(), SysError> {
+        let mut echo = Vec::new();
+        let (sig, echo) = {
+            let mut ldisc = self.ldisc.lock();
+            let sig = ldisc.receive(c, &mut echo)?;
+            (sig, ldisc.process_output(&echo))
+        };
+        if !echo.is_empty() {
+            self.device.echo(&echo);
+        }
+        if let Some(sig) = sig {
+            if let Some(pgid) = self.foreground() {
+                send_signal_to_pgid(pgid, sig);
+            }
+        }
+        self.readable.notify_all();
+        Ok(())
+    }
+
+    /// Process input `buf` from a device which can wait, i.e. the master of
+    /// a pseudo-terminal, blocking while there is no room until the input is read.
+    /// Return the number of bytes processed, fewer than `buf.len()` if interrupted
+    /// by a signal after some are processed.
+    pub fn receive_all(&self, buf: &[u8]) -> Result<usize, SysError> {
+        let mut len = 0;
+        while len < buf.len() {
+            let seq = self.writable.events();
+            match self.receive(buf[len]) {
+                Ok(()) => len += 1,
+                Err(SysError::EAGAIN) => {
+                    // the input read since `seq` is not missed
+                    Condvar::wait_any_unless(&[&self.writable], || self.writable.events() != seq);
+                    if crate::signal::has_signal_to_handle() {
+                        return match len {
+                            0 => Err(SysError::EINTR),
+                            len => Ok(len),
+                        };
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(len)
+    }
+
+    /// Read the input, blocking until there is some.
+    /// At most one line is read in canonical mode, and 0 is returned on EOF,
+    /// or after it is hung up.
+    /// In non-canonical mode, it returns when `VMIN` bytes are read,
+    /// or `VTIME` tenths of a second passed.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, SysError> {
+        if buf.len() == 0 {
+            return Ok(0);
+        }
+        let start = timer::now();
+        let mut timeout_timer = None;
+        let mut ldisc = self.ldisc.lock();
+        loop {
+            if ldisc.hung_up && ldisc.input_len() == 0 {
+                return Ok(0);
+            }
+            if ldisc.termios.lflag().contains(LocalFlags::ICANON) {
+                if let Some(mut line) = ldisc.lines.pop_front() {
+                    let len = buf.len().min(line.len());
+                    buf[..len].copy_from_slice(&line[..len]);
+                    if len < line.len() {
+                        line.drain(..len);
+                        ldisc.lines.push_front(line);
+                    }
+                    self.writable.notify_all();
+                    return Ok(len);
+                }
+            } else {
+                let min = ldisc.termios.cc[VMIN] as usize;
+                let time = ldisc.termios.cc[VTIME] as usize;
+                let available = ldisc.raw.len();
+                let deadline = start + time * 100_000 / USEC_PER_TICK;
+                let timed_out = time > 0 && timer::now() >= deadline;
+                let ready = match min {
+                    0 => available > 0 || time == 0 || timed_out,
+                    min => available >= min.min(buf.len()) || (available > 0 && timed_out),
+                };
+                if ready {
+                    let len = buf.len().min(available);
+                    for (dst, src) in buf.iter_mut().zip(ldisc.raw.drain(..len)) {
+                        *dst = src;
+                    }
+                    self.writable.notify_all();
+                    return Ok(len);
+                }
+                if time > 0 && timeout_timer.is_none() {
+                    // wake up readers on the timeout
+                    let readable = self.readable.clone();
+                    let notify = move || readable.notify_all();
+                    timeout_timer = Some(Timer::new(deadline, 0, notify));
+                }
+            }
+            let readable = self.readable.clone();
+            drop(readable.wait(ldisc));
+            if crate::signal::has_signal_to_handle() {
+                return Err(SysError::EINTR);
+            }
+            ldisc = self.ldisc.lock();
+        }
+    }
+
+    /// Write `buf` to the device, post-processed.
+    /// Fail with EIO after it is hung up.
+    pub fn write(&self, buf: &[u8]) -> Result<usize, SysError> {
+        let output = {
+            let ldisc = self.ldisc.lock();
+            if ldisc.hung_up {
+                return Err(SysError::EIO);
+            }
+            ldisc.process_output(buf)
+        };
+        self.device.write(&output)?;
+        Ok(buf.len())
+    }
+
+    /// Hang up as its device is gone: readers get EOF, and writers fail.
+    /// The session it controls loses it, while the session leader
+    /// and the foreground process group get `SIGHUP`.
+    pub fn hangup(&self) {
+        self.ldisc.lock().hung_up = true;
+        self.readable.notify_all();
+        self.writable.notify_all();
+        let job = {
+            let mut job = self.job.lock();
+            job.session.take().map(|sid| (sid, job.foreground))
+        };
+        if let Some((sid, foreground)) = job {
+            send_signal_to_pid(sid, SIGHUP);
+            send_signal_to_pid(sid, SIGCONT);
+            send_signal_to_pgid(foreground, SIGHUP);
+            send_signal_to_pgid(foreground, SIGCONT);
+        }
+    }
+
+    pub fn is_hung_up(&self) -> bool {
+        self.ldisc.lock().hung_up
+    }
+
+    /// Whether a read would not block
+    pub fn can_read(&self) -> bool {
+        self.ldisc.lock().can_read()
+    }
+
+    /// Condvar notified when there is input to read
+    pub fn readable(&self) -> Arc<Condvar> {
+        self.readable.clone()
+    }
+
+    /// Whether there is room for more input
+    pub fn can_receive(&self) -> bool {
+        self.ldisc.lock().has_room(false).is_ok()
+    }
+
+    /// Condvar notified when there may be room for more input
+    pub fn writable(&self) -> Arc<Condvar> {
+        self.writable.clone()
+    }
+
+    /// Number of bytes to read, i.e. `FIONREAD`
+    pub fn input_len(&self) -> usize {
+        self.ldisc.lock().input_len()
+    }
+
+    /// Discard the input not read yet
+    pub fn flush_input(&self) {
+        self.ldisc.lock().flush_input();
+        self.writable.notify_all();
+    }
+
+    pub fn termios(&self) -> Termios {
+        self.ldisc.lock().termios
+    }
+
+    /// Set the modes, discarding the input not read yet if `flush`
+    pub fn set_termios(&self, termios: Termios, flush: bool) {
+        {
+            let mut ldisc = self.ldisc.lock();
+            if flush {
+                ldisc.flush_input();
+            }
+            ldisc.set_termios(termios);
+        }
+        // the input may become ready in the new mode, and there may be room
+        self.readable.notify_all();
+        self.writable.notify_all();
+    }
+
+    pub fn winsize(&self) -> WinSize {
+        self.ldisc.lock().winsize
+    }
+
+    /// Set the window size, return whether it is changed
+    pub fn set_winsize(&self, winsize: WinSize) -> bool {
+        let mut ldisc = self.ldisc.lock();
+        let changed = ldisc.winsize != winsize;
+        ldisc.winsize = winsize;
+        changed
+    }
+
+    /// Whether a background process group writing it is stopped by `SIGTTOU`
+    pub fn tostop(&self) -> bool {
+        self.ldisc
+            .lock()
+            .termios
+            .lflag()
+            .contains(LocalFlags::TOSTOP)
+    }
+
+    /// The session it controls
+    pub fn session(&self) -> Option<usize> {
+        self.job.lock().session
+    }
+
+    /// The foreground process group, if it controls a session
+    pub fn foreground(&self) -> Option<usize> {
+        let job = self.job.lock();
+        job.session.map(|_| job.foreground)
+    }
+
+    /// Make it the controlling terminal of session `sid`, with group `pgid` in the foreground.
+    /// Return false if it controls another session, unless `steal`.
+    pub fn set_session(&self, sid: usize, pgid: usize, steal: bool) -> bool {
+        let mut job = self.job.lock();
+        match job.session {
+            Some(other) if other != sid && !steal => false,
+            _ => {
+                job.session = Some(sid);
+                job.foreground = pgid;
+                true
+            }
+        }
+    }
+
+    /// Stop controlling session `sid`.
+    /// Return the foreground process group, if it was controlling the session.
+    pub fn release(&self, sid: usize) -> Option<usize> {
+        let mut job = self.job.lock();
+        if job.session != Some(sid) {
+            return None;
+        }
+        job.session = None;
+        Some(job.foreground)
+    }
+
+    /// Put group `pgid` in the foreground
+    pub fn set_foreground(&self, pgid: usize) {
+        self.job.lock().foreground = pgid;
+    }
+}
+
+/// The terminal of `inode`, if it is one
+pub fn inode_tty(inode: &Arc<INode>) -> Option<Arc<Tty>> {
+    let any = inode.as_any_ref();
+    if any.is::<Stdin>() || any.is::<Stdout>() || any.is::<TtyINode>() {
+        Some(CONSOLE.clone())
+    } else if let Some(slave) = any.downcast_ref::<PtySlave>() {
+        Some(slave.pty().tty().clone())
+    } else {
+        None
+    }
+}
@@ -1,10 +1,14 @@
 use super::HEAP_ALLOCATOR;
+use crate::arch::cpu;
 pub use crate::arch::paging::*;
 use crate::consts::MEMORY_OFFSET;
-use crate::process::process_unsafe;
-use crate::sync::SpinNoIrqLock;
+use crate::process::current_thread;
+use crate::sync::{MutexGuard, SpinNoIrq, SpinNoIrqLock};
+use alloc::collections::BTreeMap;
 use bitmap_allocator::BitAlloc;
 use buddy_system_allocator::LockedHeap;
+use core::ops::{Deref, DerefMut, Range};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use lazy_static::*;
 use log::*;
 pub use rcore_memory::memory_set::{handler::*, MemoryArea, MemoryAttr};
@@ -12,6 +16,73 @@ use rcore_memory::*;
 
 pub type MemorySet = rcore_memory::memory_set::MemorySet<InactivePageTable0>;
 
+/// Lock of a memory set, which may be shared by threads of `CLONE_VM`
+///
+/// It records the CPU holding it, where no other thread runs meanwhile,
+/// as interrupts are disabled and the holder never sleeps. So a page fault
+/// taken with it held, e.g. copying user memory while checking it,
+/// is told from one taken by another thread, and handled without locking again.
+pub struct VmLock {
+    vm: SpinNoIrqLock<MemorySet>,
+    /// id of the CPU holding it plus one, or 0 if it is unlocked
+    owner: AtomicUsize,
+}
+
+/// Guard of `VmLock`, releasing it when dropped
+pub struct VmGuard<'a> {
+    guard: MutexGuard<'a, MemorySet, SpinNoIrq>,
+    owner: &'a AtomicUsize,
+}
+
+impl VmLock {
+    pub fn new(vm: MemorySet) -> Self {
+        VmLock {
+            vm: SpinNoIrqLock::new(vm),
+            owner: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn lock(&self) -> VmGuard<'_> {
+        let guard = self.vm.lock();
+        self.owner.store(cpu::id() + 1, Ordering::Relaxed);
+        VmGuard {
+            guard,
+            owner: &self.owner,
+        }
+    }
+
+    /// Run `f` with the memory set, on a page fault of the current thread.
+    /// If the thread holds the lock, it is stopped by the fault, so the memory set
+    /// is used without locking. Otherwise it waits for the holder as usual.
+    fn with_faulting<T>(&self, f: impl FnOnce(&mut MemorySet) -> T) -> T {
+        if self.owner.load(Ordering::Relaxed) == cpu::id() + 1 {
+            f(unsafe { self.vm.get_unchecked() })
+        } else {
+            f(&mut self.lock())
+        }
+    }
+}
+
+impl<'a> Deref for VmGuard<'a> {
+    type Target = MemorySet;
+    fn deref(&self) -> &MemorySet {
+        &self.guard
+    }
+}
+
+impl<'a> DerefMut for VmGuard<'a> {
+    fn deref_mut(&mut self) -> &mut MemorySet {
+        &mut self.guard
+    }
+}
+
+impl<'a> Drop for VmGuard<'a> {
+    /// Forget the owner before the lock is released
+    fn drop(&mut self) {
+        self.owner.store(0, Ordering::Relaxed);
+    }
+}
+
 // x86_64 support up to 64G memory
 #[cfg(target_arch = "x86_64")]
 pub type FrameAlloc = bitmap_allocator::BitAlloc16M;
@@ -29,6 +100,38 @@ lazy_static! {
         SpinNoIrqLock::new(FrameAlloc::default());
 }
 
+/// Number of frames inserted into `FRAME_ALLOCATOR`, and allocated from it
+static FRAMES_TOTAL: AtomicUsize = AtomicUsize::new(0);
+static FRAMES_ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+/// Insert the frames with id in `range` into the frame allocator
+pub fn insert_frames(ba: &mut FrameAlloc, range: Range<usize>) {
+    FRAMES_TOTAL.fetch_add(range.end - range.start, Ordering::Relaxed);
+    ba.insert(range);
+}
+
+/// Return the number of frames in total and allocated
+pub fn frame_stats() -> (usize, usize) {
+    (
+        FRAMES_TOTAL.load(Ordering::Relaxed),
+        FRAMES_ALLOCATED.load(Ordering::Relaxed),
+    )
+}
+
+/// Return the bytes of kernel heap in total and allocated
+pub fn heap_stats() -> (usize, usize) {
+    let heap = HEAP_ALLOCATOR.lock();
+    (heap.stats_total_bytes(), heap.stats_alloc_actual())
+}
+
+/// Extra reference counts of frames shared by copy-on-write
+///
+/// A frame not in this map has only one reference.
+lazy_static! {
+    static ref FRAME_SHARED_COUNT: SpinNoIrqLock<BTreeMap<usize, usize>> =
+        SpinNoIrqLock::new(BTreeMap::new());
+}
+
 /// The only way to get active page table
 ///
 /// ## CHANGE LOG
@@ -57,15 +160,36 @@ impl FrameAllocator for GlobalFrameAlloc {
             .lock()
             .alloc()
             .map(|id| id * PAGE_SIZE + MEMORY_OFFSET);
+        if ret.is_some() {
+            FRAMES_ALLOCATED.fetch_add(1, Ordering::Relaxed);
+        }
         trace!("Allocate frame: {:x?}", ret);
         ret
         // TODO: try to swap out when alloc failed
     }
     fn dealloc(&self, target: usize) {
         trace!("Deallocate frame: {:x}", target);
-        FRAME_ALLOCATOR
-            .lock()
-            .dealloc((target - MEMORY_OFFSET) / PAGE_SIZE);
+        let id = (target - MEMORY_OFFSET) / PAGE_SIZE;
+        let mut shared_count = FRAME_SHARED_COUNT.lock();
+        if let Some(count) = shared_count.get_mut(&id) {
+            // still referenced by others
+            *count -= 1;
+            if *count == 0 {
+                shared_count.remove(&id);
+            }
+            return;
+        }
+        FRAME_ALLOCATOR.lock().dealloc(id);
+        FRAMES_ALLOCATED.fetch_sub(1, Ordering::Relaxed);
+    }
+    fn share(&self, target: usize) -> bool {
+        let id = (target - MEMORY_OFFSET) / PAGE_SIZE;
+        *FRAME_SHARED_COUNT.lock().entry(id).or_insert(0) += 1;
+        true
+    }
+    fn ref_count(&self, target: usize) -> usize {
+        let id = (target - MEMORY_OFFSET) / PAGE_SIZE;
+        FRAME_SHARED_COUNT.lock().get(&id).cloned().unwrap_or(0) + 1
     }
 }
 
@@ -108,8 +232,21 @@ impl Drop for KernelStack {
 pub fn handle_page_fault(addr: usize) -> bool {
     debug!("page fault @ {:#x}", addr);
 
-    // This is safe as long as page fault never happens in page fault handler
-    unsafe { process_unsafe().vm.handle_page_fault(addr) }
+    // The process may be locked by the faulting code, or a sibling thread,
+    // so its memory is found from the thread. Exec replaces both.
+    let vm = current_thread().vm.clone();
+    vm.with_faulting(|vm| handle_page_fault_of(vm, addr))
+}
+
+/// Handle page fault at `addr` of `vm`, counted to the current thread.
+/// It is a major one if the page is read from a mapped file.
+fn handle_page_fault_of(vm: &mut MemorySet, addr: usize) -> bool {
+    let major = vm.is_major_fault(addr);
+    let handled = vm.handle_page_fault(addr);
+    if handled {
+        current_thread().usage.count_page_fault(major);
+    }
+    handled
 }
 
 pub fn init_heap() {
@@ -1,9 +1,11 @@
 use crate::arch::rand;
 use crate::drivers::{NET_DRIVERS, SOCKET_ACTIVITY};
+use crate::fs::FileLike;
 use crate::sync::SpinNoIrqLock as Mutex;
 use crate::syscall::*;
 use crate::util;
 use alloc::boxed::Box;
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use bitflags::*;
@@ -42,11 +44,46 @@ impl NetlinkEndpoint {
     }
 }
 
+/// Name of a unix domain socket
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum UnixEndpoint {
+    Unnamed,
+    /// absolute path in the file system
+    Path(String),
+    /// name in the abstract namespace, without the leading NUL
+    Abstract(Vec<u8>),
+}
+
 #[derive(Clone, Debug)]
 pub enum Endpoint {
     Ip(IpEndpoint),
     LinkLevel(LinkLevelEndpoint),
     Netlink(NetlinkEndpoint),
+    Unix(UnixEndpoint),
+}
+
+/// Credentials of a process, as `struct ucred`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UCred {
+    pub pid: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Ancillary data sent or received along with a message
+#[derive(Default)]
+pub struct Ancillary {
+    /// SCM_RIGHTS: files passed to the receiver
+    pub rights: Vec<FileLike>,
+    /// SCM_CREDENTIALS: credentials of the sender
+    pub cred: Option<UCred>,
+}
+
+impl Ancillary {
+    pub fn is_empty(&self) -> bool {
+        self.rights.is_empty() && self.cred.is_none()
+    }
 }
 
 /// Common methods that a socket must have
@@ -81,6 +118,29 @@ pub trait Socket: Send + Sync {
         warn!("ioctl is unimplemented for this socket");
         Ok(0)
     }
+    /// Read a message with ancillary data, which only unix sockets carry
+    fn read_msg(&self, data: &mut [u8]) -> (SysResult, Endpoint, Ancillary) {
+        let (result, endpoint) = self.read(data);
+        (result, endpoint, Ancillary::default())
+    }
+    /// Write a message with ancillary data, which only unix sockets carry
+    fn write_msg(
+        &self,
+        data: &[u8],
+        sendto_endpoint: Option<Endpoint>,
+        ancillary: Ancillary,
+    ) -> SysResult {
+        if !ancillary.is_empty() {
+            return Err(SysError::EINVAL);
+        }
+        self.write(data, sendto_endpoint)
+    }
+    /// A clone sharing all states with this socket, if there is one.
+    /// It is used without holding the process lock, as its operations may
+    /// block waiting for other processes.
+    fn shared_clone(&self) -> Option<Box<dyn Socket>> {
+        None
+    }
     fn box_clone(&self) -> Box<dyn Socket>;
 }
 
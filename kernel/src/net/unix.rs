@@ -0,0 +1,614 @@
+//! Unix domain sockets
+//!
+//! Every socket receives messages from its own queue. A connected socket
+//! sends to the queue of its peer, and an unconnected datagram socket sends
+//! to the queue of the socket bound to the destination name.
+//!
+//! Files passed by SCM_RIGHTS stay in the queue until received, so a socket
+//! sent over itself is never freed, as there is no garbage collection.
+
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::mem;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use spin::RwLock;
+
+use super::{Ancillary, Endpoint, Socket, UCred, UnixEndpoint};
+use crate::drivers::SOCKET_ACTIVITY;
+use crate::fs::FileLike;
+use crate::signal::has_signal_to_handle;
+use crate::sync::{Condvar, SpinNoIrqLock as Mutex};
+use crate::syscall::{SysError, SysResult};
+
+/// Capacity of a receive queue in bytes
+const UNIX_RECVBUF: usize = 208 * 1024;
+/// Maximum pending connections of a listening socket
+const UNIX_BACKLOG: usize = 128;
+
+const SOL_SOCKET: usize = 1;
+const SO_PASSCRED: usize = 16;
+
+lazy_static! {
+    /// Bound sockets by name.
+    ///
+    /// Entries of closed sockets are dead, and replaced by the next bind.
+    static ref UNIX_NAMES: RwLock<BTreeMap<UnixEndpoint, Weak<Mutex<UnixSocketInner>>>> =
+        RwLock::new(BTreeMap::new());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnixSocketType {
+    Stream,
+    Datagram,
+}
+
+struct UnixMessage {
+    data: Vec<u8>,
+    rights: Vec<FileLike>,
+    cred: UCred,
+    from: UnixEndpoint,
+}
+
+/// Messages sent to a socket
+struct UnixQueue {
+    inner: Mutex<UnixQueueInner>,
+    /// notified when a message is pushed, or no more will be
+    readable: Condvar,
+    /// notified when a message is popped, or no more will be
+    writable: Condvar,
+}
+
+#[derive(Default)]
+struct UnixQueueInner {
+    messages: VecDeque<UnixMessage>,
+    /// bytes of data in all messages
+    len: usize,
+    /// shut down for writing, readers see EOF after the messages
+    write_closed: bool,
+    /// the receiving socket is closed, writers see EPIPE
+    read_closed: bool,
+}
+
+impl UnixQueue {
+    fn new() -> Arc<Self> {
+        Arc::new(UnixQueue {
+            inner: Mutex::new(UnixQueueInner::default()),
+            readable: Condvar::new(),
+            writable: Condvar::new(),
+        })
+    }
+
+    /// Push a message, blocking while the queue is full.
+    /// Fail with EINTR if a signal arrives while blocking.
+    fn push(&self, message: UnixMessage) -> SysResult {
+        let len = message.data.len();
+        let mut queue = self.inner.lock();
+        loop {
+            if queue.read_closed || queue.write_closed {
+                return Err(SysError::EPIPE);
+            }
+            // an empty queue accepts a message of any size
+            if queue.len == 0 || queue.len + len <= UNIX_RECVBUF {
+                queue.len += len;
+                queue.messages.push_back(message);
+                self.readable.notify_all();
+                SOCKET_ACTIVITY.notify_all();
+                return Ok(len);
+            }
+            drop(self.writable.wait(queue));
+            if has_signal_to_handle() {
+                return Err(SysError::EINTR);
+            }
+            queue = self.inner.lock();
+        }
+    }
+
+    /// Pop data into `buf`, blocking until there is some.
+    ///
+    /// A datagram is popped as a whole, truncated to `buf`. Stream data
+    /// is popped across messages, but ancillary data is only taken from
+    /// the first one, and popping stops before the next one carrying files.
+    /// The length is 0 at EOF. Fail with EINTR if a signal arrives while blocking.
+    fn pop(
+        &self,
+        buf: &mut [u8],
+        type_: UnixSocketType,
+    ) -> Result<(usize, Ancillary, UnixEndpoint), SysError> {
+        let mut queue = self.inner.lock();
+        while queue.messages.is_empty() {
+            if queue.write_closed {
+                return Ok((0, Ancillary::default(), UnixEndpoint::Unnamed));
+            }
+            drop(self.readable.wait(queue));
+            if has_signal_to_handle() {
+                return Err(SysError::EINTR);
+            }
+            queue = self.inner.lock();
+        }
+        let mut ancillary = Ancillary::default();
+        let from;
+        let mut len = 0;
+        match type_ {
+            UnixSocketType::Datagram => {
+                let message = queue.messages.pop_front().unwrap();
+                queue.len -= message.data.len();
+                len = min(buf.len(), message.data.len());
+                buf[..len].copy_from_slice(&message.data[..len]);
+                ancillary.rights = message.rights;
+                ancillary.cred = Some(message.cred);
+                from = message.from;
+            }
+            UnixSocketType::Stream => {
+                {
+                    let first = queue.messages.front_mut().unwrap();
+                    ancillary.rights = mem::replace(&mut first.rights, Vec::new());
+                    ancillary.cred = Some(first.cred);
+                    from = first.from.clone();
+                }
+                while len < buf.len() {
+                    let message = match queue.messages.front_mut() {
+                        Some(message) => message,
+                        None => break,
+                    };
+                    if !message.rights.is_empty() {
+                        break;
+                    }
+                    let n = min(buf.len() - len, message.data.len());
+                    buf[len..len + n].copy_from_slice(&message.data[..n]);
+                    message.data.drain(..n);
+                    len += n;
+                    if message.data.is_empty() {
+                        queue.messages.pop_front();
+                    }
+                }
+                queue.len -= len;
+            }
+        }
+        self.writable.notify_all();
+        SOCKET_ACTIVITY.notify_all();
+        Ok((len, ancillary, from))
+    }
+
+    fn can_read(&self) -> bool {
+        let queue = self.inner.lock();
+        !queue.messages.is_empty() || queue.write_closed
+    }
+
+    fn can_write(&self) -> bool {
+        let queue = self.inner.lock();
+        queue.len < UNIX_RECVBUF || queue.read_closed
+    }
+
+    fn is_read_closed(&self) -> bool {
+        self.inner.lock().read_closed
+    }
+
+    /// No more messages will be popped, and the buffered ones are dropped
+    fn close_read(&self) {
+        let messages = {
+            let mut queue = self.inner.lock();
+            queue.read_closed = true;
+            queue.len = 0;
+            mem::replace(&mut queue.messages, VecDeque::new())
+        };
+        self.writable.notify_all();
+        SOCKET_ACTIVITY.notify_all();
+        // passed files may be sockets, drop them without the lock
+        drop(messages);
+    }
+
+    /// No more messages will be pushed
+    fn close_write(&self) {
+        self.inner.lock().write_closed = true;
+        self.readable.notify_all();
+        SOCKET_ACTIVITY.notify_all();
+    }
+}
+
+/// Pending connections of a listening socket
+struct UnixBacklog {
+    inner: Mutex<UnixBacklogInner>,
+    /// notified when a connection comes, or the socket is closed
+    new_conn: Condvar,
+}
+
+#[derive(Default)]
+struct UnixBacklogInner {
+    conns: VecDeque<UnixSocketState>,
+    closed: bool,
+}
+
+impl UnixBacklog {
+    fn new() -> Arc<Self> {
+        Arc::new(UnixBacklog {
+            inner: Mutex::new(UnixBacklogInner::default()),
+            new_conn: Condvar::new(),
+        })
+    }
+
+    /// Pop a connection, blocking until there is one.
+    /// Fail with EINTR if a signal arrives while blocking.
+    fn pop(&self) -> Result<UnixSocketState, SysError> {
+        let mut backlog = self.inner.lock();
+        loop {
+            if let Some(conn) = backlog.conns.pop_front() {
+                return Ok(conn);
+            }
+            if backlog.closed {
+                return Err(SysError::EINVAL);
+            }
+            drop(self.new_conn.wait(backlog));
+            if has_signal_to_handle() {
+                return Err(SysError::EINTR);
+            }
+            backlog = self.inner.lock();
+        }
+    }
+
+    fn close(&self) {
+        let conns = {
+            let mut backlog = self.inner.lock();
+            backlog.closed = true;
+            mem::replace(&mut backlog.conns, VecDeque::new())
+        };
+        self.new_conn.notify_all();
+        // refuse pending connections, so their peers see EOF
+        drop(conns);
+    }
+}
+
+struct UnixPeer {
+    endpoint: UnixEndpoint,
+    /// the receive queue of the peer
+    tx: Arc<UnixQueue>,
+}
+
+struct UnixSocketInner {
+    type_: UnixSocketType,
+    /// credentials of the creating process, sent unless given explicitly
+    cred: UCred,
+    endpoint: UnixEndpoint,
+    rx: Arc<UnixQueue>,
+    /// the peer, once connected
+    peer: Option<UnixPeer>,
+    /// pending connections, once listening
+    backlog: Option<Arc<UnixBacklog>>,
+    /// whether to receive SCM_CREDENTIALS
+    pass_cred: bool,
+}
+
+impl Drop for UnixSocketInner {
+    fn drop(&mut self) {
+        self.rx.close_read();
+        if let Some(peer) = &self.peer {
+            // the receive queue of a datagram peer is shared by all senders
+            if self.type_ == UnixSocketType::Stream {
+                peer.tx.close_write();
+            }
+        }
+        if let Some(backlog) = &self.backlog {
+            backlog.close();
+        }
+    }
+}
+
+/// A unix domain socket, whose clones share all states
+#[derive(Clone)]
+pub struct UnixSocketState {
+    inner: Arc<Mutex<UnixSocketInner>>,
+}
+
+impl UnixSocketState {
+    /// Create a socket, by the process with credentials `cred`
+    pub fn new(type_: UnixSocketType, cred: UCred) -> Self {
+        Self::from_inner(UnixSocketInner {
+            type_,
+            cred,
+            endpoint: UnixEndpoint::Unnamed,
+            rx: UnixQueue::new(),
+            peer: None,
+            backlog: None,
+            pass_cred: false,
+        })
+    }
+
+    /// Create a pair of connected unnamed sockets
+    pub fn new_pair(type_: UnixSocketType, cred: UCred) -> (Self, Self) {
+        let a = Self::new(type_, cred);
+        let b = Self::new(type_, cred);
+        {
+            let mut a_inner = a.inner.lock();
+            let mut b_inner = b.inner.lock();
+            a_inner.peer = Some(UnixPeer {
+                endpoint: UnixEndpoint::Unnamed,
+                tx: b_inner.rx.clone(),
+            });
+            b_inner.peer = Some(UnixPeer {
+                endpoint: UnixEndpoint::Unnamed,
+                tx: a_inner.rx.clone(),
+            });
+        }
+        (a, b)
+    }
+
+    fn from_inner(inner: UnixSocketInner) -> Self {
+        UnixSocketState {
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+}
+
+/// Find the socket bound to `endpoint`
+fn lookup(endpoint: &UnixEndpoint) -> Result<Arc<Mutex<UnixSocketInner>>, SysError> {
+    let socket = UNIX_NAMES
+        .read()
+        .get(endpoint)
+        .and_then(|weak| weak.upgrade());
+    socket.ok_or(SysError::ECONNREFUSED)
+}
+
+/// An unused abstract name, for binding without a name
+fn autobind_name() -> UnixEndpoint {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let names = UNIX_NAMES.read();
+    loop {
+        let id = NEXT.fetch_add(1, Ordering::SeqCst) & 0xfffff;
+        let name = UnixEndpoint::Abstract(format!("{:05x}", id).into_bytes());
+        if names.get(&name).and_then(|weak| weak.upgrade()).is_none() {
+            return name;
+        }
+    }
+}
+
+fn unix_endpoint(endpoint: Endpoint) -> Result<UnixEndpoint, SysError> {
+    match endpoint {
+        Endpoint::Unix(endpoint) => Ok(endpoint),
+        _ => Err(SysError::EINVAL),
+    }
+}
+
+impl Socket for UnixSocketState {
+    fn read(&self, data: &mut [u8]) -> (SysResult, Endpoint) {
+        // passed files are dropped, as linux
+        let (result, endpoint, _) = self.read_msg(data);
+        (result, endpoint)
+    }
+
+    fn write(&self, data: &[u8], sendto_endpoint: Option<Endpoint>) -> SysResult {
+        self.write_msg(data, sendto_endpoint, Ancillary::default())
+    }
+
+    fn poll(&self) -> (bool, bool, bool) {
+        let inner = self.inner.lock();
+        if let Some(backlog) = &inner.backlog {
+            return (!backlog.inner.lock().conns.is_empty(), false, false);
+        }
+        let input = inner.rx.can_read();
+        match &inner.peer {
+            Some(peer) => {
+                let hangup = inner.type_ == UnixSocketType::Stream && peer.tx.is_read_closed();
+                (input, peer.tx.can_write(), hangup)
+            }
+            None => (input, inner.type_ == UnixSocketType::Datagram, false),
+        }
+    }
+
+    fn connect(&mut self, endpoint: Endpoint) -> SysResult {
+        let endpoint = unix_endpoint(endpoint)?;
+        let type_ = self.inner.lock().type_;
+        let target = lookup(&endpoint)?;
+        match type_ {
+            UnixSocketType::Datagram => {
+                let tx = {
+                    let target = target.lock();
+                    if target.type_ != type_ {
+                        return Err(SysError::EPROTOTYPE);
+                    }
+                    target.rx.clone()
+                };
+                self.inner.lock().peer = Some(UnixPeer { endpoint, tx });
+            }
+            UnixSocketType::Stream => {
+                let (backlog, cred, pass_cred) = {
+                    let target = target.lock();
+                    if target.type_ != type_ {
+                        return Err(SysError::EPROTOTYPE);
+                    }
+                    let backlog = target.backlog.clone().ok_or(SysError::ECONNREFUSED)?;
+                    (backlog, target.cred, target.pass_cred)
+                };
+                let mut inner = self.inner.lock();
+                if inner.peer.is_some() {
+                    return Err(SysError::EISCONN);
+                }
+                if inner.backlog.is_some() {
+                    return Err(SysError::EINVAL);
+                }
+                let rx = UnixQueue::new();
+                {
+                    let mut pending = backlog.inner.lock();
+                    if pending.closed {
+                        return Err(SysError::ECONNREFUSED);
+                    }
+                    if pending.conns.len() >= UNIX_BACKLOG {
+                        return Err(SysError::EAGAIN);
+                    }
+                    // the accepted socket, created only when it can be queued,
+                    // as dropping it would shut down our receive queue
+                    let conn = Self::from_inner(UnixSocketInner {
+                        type_,
+                        cred,
+                        endpoint: endpoint.clone(),
+                        rx: rx.clone(),
+                        peer: Some(UnixPeer {
+                            endpoint: inner.endpoint.clone(),
+                            tx: inner.rx.clone(),
+                        }),
+                        backlog: None,
+                        pass_cred,
+                    });
+                    pending.conns.push_back(conn);
+                }
+                backlog.new_conn.notify_all();
+                SOCKET_ACTIVITY.notify_all();
+                inner.peer = Some(UnixPeer { endpoint, tx: rx });
+            }
+        }
+        Ok(0)
+    }
+
+    fn bind(&mut self, endpoint: Endpoint) -> SysResult {
+        let endpoint = match unix_endpoint(endpoint)? {
+            UnixEndpoint::Unnamed => autobind_name(),
+            endpoint => endpoint,
+        };
+        let mut inner = self.inner.lock();
+        if inner.endpoint != UnixEndpoint::Unnamed {
+            return Err(SysError::EINVAL);
+        }
+        let mut names = UNIX_NAMES.write();
+        if names
+            .get(&endpoint)
+            .and_then(|weak| weak.upgrade())
+            .is_some()
+        {
+            return Err(SysError::EADDRINUSE);
+        }
+        names.insert(endpoint.clone(), Arc::downgrade(&self.inner));
+        inner.endpoint = endpoint;
+        Ok(0)
+    }
+
+    fn listen(&mut self) -> SysResult {
+        let mut inner = self.inner.lock();
+        if inner.type_ != UnixSocketType::Stream {
+            return Err(SysError::EOPNOTSUPP);
+        }
+        if inner.peer.is_some() || inner.endpoint == UnixEndpoint::Unnamed {
+            return Err(SysError::EINVAL);
+        }
+        if inner.backlog.is_none() {
+            inner.backlog = Some(UnixBacklog::new());
+        }
+        Ok(0)
+    }
+
+    /// Shut down the sending direction, so the peer sees EOF
+    fn shutdown(&self) -> SysResult {
+        let inner = self.inner.lock();
+        match &inner.peer {
+            Some(peer) if inner.type_ == UnixSocketType::Stream => peer.tx.close_write(),
+            Some(_) => {}
+            None => return Err(SysError::ENOTCONN),
+        }
+        Ok(0)
+    }
+
+    fn accept(&mut self) -> Result<(Box<dyn Socket>, Endpoint), SysError> {
+        let backlog = self.inner.lock().backlog.clone().ok_or(SysError::EINVAL)?;
+        let conn = backlog.pop()?;
+        let remote_endpoint = conn.remote_endpoint().unwrap();
+        Ok((Box::new(conn), remote_endpoint))
+    }
+
+    fn endpoint(&self) -> Option<Endpoint> {
+        Some(Endpoint::Unix(self.inner.lock().endpoint.clone()))
+    }
+
+    fn remote_endpoint(&self) -> Option<Endpoint> {
+        let inner = self.inner.lock();
+        let peer = inner.peer.as_ref()?;
+        Some(Endpoint::Unix(peer.endpoint.clone()))
+    }
+
+    fn setsockopt(&mut self, level: usize, opt: usize, data: &[u8]) -> SysResult {
+        match (level, opt) {
+            (SOL_SOCKET, SO_PASSCRED) => {
+                self.inner.lock().pass_cred = data.iter().any(|&byte| byte != 0);
+            }
+            _ => warn!(
+                "setsockopt is unimplemented for unix socket: {} {}",
+                level, opt
+            ),
+        }
+        Ok(0)
+    }
+
+    fn read_msg(&self, data: &mut [u8]) -> (SysResult, Endpoint, Ancillary) {
+        let (type_, rx, connected, pass_cred) = {
+            let inner = self.inner.lock();
+            let connected = inner.peer.is_some();
+            (inner.type_, inner.rx.clone(), connected, inner.pass_cred)
+        };
+        let endpoint = Endpoint::Unix(UnixEndpoint::Unnamed);
+        if type_ == UnixSocketType::Stream && !connected {
+            return (Err(SysError::ENOTCONN), endpoint, Ancillary::default());
+        }
+        let (len, mut ancillary, from) = match rx.pop(data, type_) {
+            Ok(popped) => popped,
+            Err(err) => return (Err(err), endpoint, Ancillary::default()),
+        };
+        if !pass_cred {
+            ancillary.cred = None;
+        }
+        (Ok(len), Endpoint::Unix(from), ancillary)
+    }
+
+    fn write_msg(
+        &self,
+        data: &[u8],
+        sendto_endpoint: Option<Endpoint>,
+        ancillary: Ancillary,
+    ) -> SysResult {
+        let (type_, from, cred, peer_tx) = {
+            let inner = self.inner.lock();
+            let peer_tx = inner.peer.as_ref().map(|peer| peer.tx.clone());
+            (inner.type_, inner.endpoint.clone(), inner.cred, peer_tx)
+        };
+        let message = UnixMessage {
+            data: data.to_vec(),
+            rights: ancillary.rights,
+            cred: ancillary.cred.unwrap_or(cred),
+            from,
+        };
+        match type_ {
+            UnixSocketType::Stream => {
+                // the destination of a connected stream is ignored, as linux
+                let tx = peer_tx.ok_or(SysError::ENOTCONN)?;
+                tx.push(message)
+            }
+            UnixSocketType::Datagram => {
+                if data.len() > UNIX_RECVBUF {
+                    return Err(SysError::EMSGSIZE);
+                }
+                let tx = match sendto_endpoint {
+                    Some(endpoint) => {
+                        let target = lookup(&unix_endpoint(endpoint)?)?;
+                        let target = target.lock();
+                        if target.type_ != type_ {
+                            return Err(SysError::EPROTOTYPE);
+                        }
+                        target.rx.clone()
+                    }
+                    None => peer_tx.ok_or(SysError::ENOTCONN)?,
+                };
+                tx.push(message).map_err(|err| match err {
+                    SysError::EPIPE => SysError::ECONNREFUSED,
+                    err => err,
+                })
+            }
+        }
+    }
+
+    fn shared_clone(&self) -> Option<Box<dyn Socket>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn box_clone(&self) -> Box<dyn Socket> {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,207 @@
+//! Core dumps of processes killed by signals
+//!
+//! The core is an ELF file named `core` in the working directory,
+//! with a `PT_LOAD` segment for each user memory area, no larger than RLIMIT_CORE.
+//! Pages not present yet are dumped as zeros, and no notes of registers are written.
+
+use alloc::{sync::Arc, vec::Vec};
+use core::mem::size_of;
+use log::*;
+
+use rcore_fs::vfs::{FileType, INode};
+use rcore_memory::PAGE_SIZE;
+
+use super::{process, Access, RLIMIT_CORE};
+use crate::syscall::SysError;
+
+#[cfg(target_arch = "x86_64")]
+const ELF_MACHINE: u16 = 62;
+#[cfg(target_arch = "aarch64")]
+const ELF_MACHINE: u16 = 183;
+#[cfg(target_arch = "riscv32")]
+const ELF_MACHINE: u16 = 243;
+#[cfg(target_arch = "mips")]
+const ELF_MACHINE: u16 = 8;
+
+const ET_CORE: u16 = 4;
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const WORD_SIZE: usize = size_of::<usize>();
+const EHDR_SIZE: usize = 40 + 3 * WORD_SIZE;
+const PHDR_SIZE: usize = 8 + 6 * WORD_SIZE;
+
+/// A memory area to dump
+struct Segment {
+    start: usize,
+    end: usize,
+    flags: u32,
+}
+
+/// Little-endian ELF structures, whose words are of the pointer width
+struct ElfWriter(Vec<u8>);
+
+impl ElfWriter {
+    fn u16(&mut self, value: u16) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+    fn word(&mut self, value: usize) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn header(&mut self, phnum: usize) {
+        let class = match WORD_SIZE {
+            8 => 2,
+            _ => 1,
+        };
+        self.0
+            .extend_from_slice(&[0x7f, b'E', b'L', b'F', class, 1, 1, 0]);
+        self.0.extend_from_slice(&[0; 8]);
+        self.u16(ET_CORE);
+        self.u16(ELF_MACHINE);
+        self.u32(1); // version
+        self.word(0); // entry
+        self.word(EHDR_SIZE); // phoff
+        self.word(0); // shoff
+        self.u32(0); // flags
+        self.u16(EHDR_SIZE as u16);
+        self.u16(PHDR_SIZE as u16);
+        self.u16(phnum as u16);
+        self.u16(0); // shentsize
+        self.u16(0); // shnum
+        self.u16(0); // shstrndx
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    fn program_header(&mut self, segment: &Segment, offset: usize) {
+        let size = segment.end - segment.start;
+        self.u32(PT_LOAD);
+        self.u32(segment.flags);
+        self.word(offset);
+        self.word(segment.start);
+        self.word(0); // paddr
+        self.word(size); // filesz
+        self.word(size); // memsz
+        self.word(PAGE_SIZE);
+    }
+
+    #[cfg(target_pointer_width = "32")]
+    fn program_header(&mut self, segment: &Segment, offset: usize) {
+        let size = segment.end - segment.start;
+        self.u32(PT_LOAD);
+        self.word(offset);
+        self.word(segment.start);
+        self.word(0); // paddr
+        self.word(size); // filesz
+        self.word(size); // memsz
+        self.u32(segment.flags);
+        self.word(PAGE_SIZE);
+    }
+}
+
+/// Dump the core of the current process if RLIMIT_CORE allows,
+/// return whether it is dumped
+pub fn dump_core() -> bool {
+    let limit = process().rlimits.cur(RLIMIT_CORE);
+    if limit == 0 {
+        return false;
+    }
+    match write_core(limit) {
+        Ok(()) => true,
+        Err(err) => {
+            warn!("failed to dump core: {:?}", err);
+            false
+        }
+    }
+}
+
+/// Open `core` in the working directory, which must be a regular file
+/// owned by the process if it exists
+fn open_core() -> Result<Arc<INode>, SysError> {
+    let mut proc = process();
+    // a process running as other ids, e.g. set-user-ID, is not dumped
+    let cred = proc.cred.clone();
+    if cred.euid != cred.ruid || cred.egid != cred.rgid {
+        return Err(SysError::EPERM);
+    }
+    let dir = proc.lookup_inode(".")?;
+    let inode = match dir.find("core") {
+        Ok(inode) => {
+            let metadata = inode.metadata()?;
+            if metadata.type_ != FileType::File || metadata.uid != cred.euid as usize {
+                return Err(SysError::EPERM);
+            }
+            cred.check_access(&metadata, Access::WRITE)?;
+            inode
+        }
+        Err(_) => proc.create_at(&dir, "core", FileType::File, 0o600)?,
+    };
+    inode.resize(0)?;
+    Ok(inode)
+}
+
+/// Write the core of the current process, truncated to `limit` bytes
+fn write_core(limit: usize) -> Result<(), SysError> {
+    let inode = open_core()?;
+    let vm = process().vm.clone();
+    let segments: Vec<_> = vm
+        .lock()
+        .iter()
+        .filter(|area| area.attr().is_user())
+        .map(|area| {
+            let attr = area.attr();
+            let mut flags = PF_R;
+            if !attr.is_readonly() {
+                flags |= PF_W;
+            }
+            if attr.is_execute() {
+                flags |= PF_X;
+            }
+            Segment {
+                start: area.start_addr(),
+                end: area.end_addr(),
+                flags,
+            }
+        })
+        .collect();
+
+    // the headers, then the segments from a page boundary
+    let mut elf = ElfWriter(Vec::new());
+    elf.header(segments.len());
+    let mut offset = EHDR_SIZE + PHDR_SIZE * segments.len();
+    offset = (offset + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    let data_offset = offset;
+    for segment in segments.iter() {
+        elf.program_header(segment, offset);
+        offset += segment.end - segment.start;
+    }
+    let headers = &elf.0[..elf.0.len().min(limit)];
+    inode.write_at(0, headers)?;
+
+    let mut offset = data_offset;
+    let mut buf = [0u8; PAGE_SIZE];
+    for segment in segments.iter() {
+        let mut addr = segment.start;
+        while addr < segment.end && offset < limit {
+            let page_end = (addr & !(PAGE_SIZE - 1)) + PAGE_SIZE;
+            let len = (page_end.min(segment.end) - addr).min(limit - offset);
+            let data = &mut buf[..len];
+            // the page table of the process is active
+            match vm.lock().is_present(addr) {
+                true => data.copy_from_slice(unsafe {
+                    core::slice::from_raw_parts(addr as *const u8, len)
+                }),
+                false => data.iter_mut().for_each(|byte| *byte = 0),
+            }
+            inode.write_at(offset, data)?;
+            addr += len;
+            offset += len;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,301 @@
+//! User and group credentials of processes
+//!
+//! A process has a real, an effective and a saved set user id, and so for groups.
+//! Permissions are checked against the effective ones, and the supplementary groups.
+//! The user id 0, i.e. root, bypasses the checks.
+
+use alloc::vec::Vec;
+
+use bitflags::bitflags;
+use rcore_fs::vfs::{FileType, Metadata};
+
+use crate::syscall::SysError;
+
+/// Max number of supplementary groups
+pub const NGROUPS_MAX: usize = 65536;
+
+/// Set-user-ID on execution
+const S_ISUID: u16 = 0o4000;
+/// Set-group-ID on execution
+const S_ISGID: u16 = 0o2000;
+/// Restricted deletion in a directory
+const S_ISVTX: u16 = 0o1000;
+
+bitflags! {
+    /// Access to a file, as the mode of `access`
+    pub struct Access: u16 {
+        const READ = 4;
+        const WRITE = 2;
+        const EXEC = 1;
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    pub ruid: u32,
+    pub euid: u32,
+    pub suid: u32,
+    pub rgid: u32,
+    pub egid: u32,
+    pub sgid: u32,
+    /// supplementary groups
+    pub groups: Vec<u32>,
+}
+
+/// An id argument of `setresuid`, where -1 means unchanged
+fn id_arg(id: usize) -> Option<u32> {
+    match id as u32 {
+        core::u32::MAX => None,
+        id => Some(id),
+    }
+}
+
+impl Credentials {
+    /// Whether it is privileged
+    pub fn is_root(&self) -> bool {
+        self.euid == 0
+    }
+
+    /// Whether the effective or a supplementary group is `gid`
+    pub fn in_group(&self, gid: u32) -> bool {
+        self.egid == gid || self.groups.contains(&gid)
+    }
+
+    /// Whether it owns a file of `metadata`, or it is privileged
+    pub fn is_owner(&self, metadata: &Metadata) -> bool {
+        self.is_root() || self.euid == metadata.uid as u32
+    }
+
+    /// Whether it may access a file of `metadata` as `access`,
+    /// by the owner, group or other permission bits.
+    /// Root may access anything, but executing a file needs an execute bit.
+    pub fn may_access(&self, metadata: &Metadata, access: Access) -> bool {
+        let mode = metadata.mode;
+        if self.is_root() {
+            return !access.contains(Access::EXEC)
+                || metadata.type_ == FileType::Dir
+                || mode & 0o111 != 0;
+        }
+        let bits = if self.euid == metadata.uid as u32 {
+            mode >> 6
+        } else if self.in_group(metadata.gid as u32) {
+            mode >> 3
+        } else {
+            mode
+        };
+        Access::from_bits_truncate(bits & 0o7).contains(access)
+    }
+
+    /// Check whether it may access a file of `metadata` as `access`
+    pub fn check_access(&self, metadata: &Metadata, access: Access) -> Result<(), SysError> {
+        match self.may_access(metadata, access) {
+            true => Ok(()),
+            false => Err(SysError::EACCES),
+        }
+    }
+
+    /// Check whether it may remove or rename a file of `metadata`
+    /// in a directory of `dir`, which needs writing the directory.
+    /// In a sticky directory, only the owners of the file or the directory may.
+    pub fn check_delete(&self, dir: &Metadata, metadata: &Metadata) -> Result<(), SysError> {
+        self.check_access(dir, Access::WRITE | Access::EXEC)?;
+        if dir.mode & S_ISVTX != 0 && !self.is_owner(metadata) && !self.is_owner(dir) {
+            return Err(SysError::EPERM);
+        }
+        Ok(())
+    }
+
+    /// The new mode of a file of `metadata` after `chmod`, checking permissions.
+    /// Only the owner may change the mode, and the set-group-ID bit
+    /// is cleared unless it is in the group of the file.
+    pub fn chmod(&self, metadata: &Metadata, mode: u16) -> Result<u16, SysError> {
+        if !self.is_owner(metadata) {
+            return Err(SysError::EPERM);
+        }
+        let mut mode = mode & 0o7777;
+        if !self.is_root() && !self.in_group(metadata.gid as u32) {
+            mode &= !S_ISGID;
+        }
+        Ok(mode)
+    }
+
+    /// The new metadata of a file after `chown` to `uid` and `gid`,
+    /// which are -1 if unchanged, checking permissions.
+    /// Only root may change the owner, while the owner may change the group
+    /// to one of its groups. The set-user-ID and set-group-ID bits are cleared.
+    pub fn chown(&self, metadata: &Metadata, uid: usize, gid: usize) -> Result<Metadata, SysError> {
+        let mut new = metadata.clone();
+        if let Some(uid) = id_arg(uid) {
+            if !self.is_root() && uid != metadata.uid as u32 {
+                return Err(SysError::EPERM);
+            }
+            new.uid = uid as usize;
+        }
+        if let Some(gid) = id_arg(gid) {
+            if !self.is_owner(metadata) || (!self.is_root() && !self.in_group(gid)) {
+                return Err(SysError::EPERM);
+            }
+            new.gid = gid as usize;
+        }
+        if metadata.type_ != FileType::Dir {
+            new.mode &= !(S_ISUID | S_ISGID);
+        }
+        Ok(new)
+    }
+
+    /// Take the owner of the file of `metadata` as the effective ids
+    /// if it is a set-user-ID or set-group-ID program, when executing it.
+    /// The saved set ids are the effective ones anyway.
+    pub fn exec(&mut self, metadata: &Metadata) {
+        if metadata.mode & S_ISUID != 0 {
+            self.euid = metadata.uid as u32;
+        }
+        if metadata.mode & S_ISGID != 0 {
+            self.egid = metadata.gid as u32;
+        }
+        self.suid = self.euid;
+        self.sgid = self.egid;
+    }
+
+    /// `setuid`: root sets all the user ids,
+    /// while others may set the effective one to the real or saved one
+    pub fn set_uid(&mut self, uid: usize) -> Result<(), SysError> {
+        let uid = id_arg(uid).ok_or(SysError::EINVAL)?;
+        if self.is_root() {
+            self.ruid = uid;
+            self.suid = uid;
+        } else if uid != self.ruid && uid != self.suid {
+            return Err(SysError::EPERM);
+        }
+        self.euid = uid;
+        Ok(())
+    }
+
+    /// `setgid`, as `set_uid`
+    pub fn set_gid(&mut self, gid: usize) -> Result<(), SysError> {
+        let gid = id_arg(gid).ok_or(SysError::EINVAL)?;
+        if self.is_root() {
+            self.rgid = gid;
+            self.sgid = gid;
+        } else if gid != self.rgid && gid != self.sgid {
+            return Err(SysError::EPERM);
+        }
+        self.egid = gid;
+        Ok(())
+    }
+
+    /// `setreuid`: an unprivileged one may set the real id to the real or effective one,
+    /// and the effective id to the real, effective or saved one.
+    /// The saved id becomes the new effective one, if the real one is set,
+    /// or the effective one is set to other than the real one.
+    pub fn set_reuid(&mut self, ruid: usize, euid: usize) -> Result<(), SysError> {
+        let (ruid, euid) = (id_arg(ruid), id_arg(euid));
+        if !self.is_root() {
+            if ruid.map_or(false, |id| id != self.ruid && id != self.euid) {
+                return Err(SysError::EPERM);
+            }
+            if euid.map_or(false, |id| ![self.ruid, self.euid, self.suid].contains(&id)) {
+                return Err(SysError::EPERM);
+            }
+        }
+        let old_ruid = self.ruid;
+        if let Some(id) = ruid {
+            self.ruid = id;
+        }
+        if let Some(id) = euid {
+            self.euid = id;
+        }
+        if ruid.is_some() || euid.map_or(false, |id| id != old_ruid) {
+            self.suid = self.euid;
+        }
+        Ok(())
+    }
+
+    /// `setregid`, as `set_reuid`
+    pub fn set_regid(&mut self, rgid: usize, egid: usize) -> Result<(), SysError> {
+        let (rgid, egid) = (id_arg(rgid), id_arg(egid));
+        if !self.is_root() {
+            if rgid.map_or(false, |id| id != self.rgid && id != self.egid) {
+                return Err(SysError::EPERM);
+            }
+            if egid.map_or(false, |id| ![self.rgid, self.egid, self.sgid].contains(&id)) {
+                return Err(SysError::EPERM);
+            }
+        }
+        let old_rgid = self.rgid;
+        if let Some(id) = rgid {
+            self.rgid = id;
+        }
+        if let Some(id) = egid {
+            self.egid = id;
+        }
+        if rgid.is_some() || egid.map_or(false, |id| id != old_rgid) {
+            self.sgid = self.egid;
+        }
+        Ok(())
+    }
+
+    /// `setresuid`: an unprivileged one may set each id to one of the current ones
+    pub fn set_resuid(&mut self, ruid: usize, euid: usize, suid: usize) -> Result<(), SysError> {
+        let ids = [id_arg(ruid), id_arg(euid), id_arg(suid)];
+        let current = [self.ruid, self.euid, self.suid];
+        if !self.is_root()
+            && ids
+                .iter()
+                .any(|id| id.map_or(false, |id| !current.contains(&id)))
+        {
+            return Err(SysError::EPERM);
+        }
+        self.ruid = ids[0].unwrap_or(self.ruid);
+        self.euid = ids[1].unwrap_or(self.euid);
+        self.suid = ids[2].unwrap_or(self.suid);
+        Ok(())
+    }
+
+    /// `setresgid`, as `set_resuid`
+    pub fn set_resgid(&mut self, rgid: usize, egid: usize, sgid: usize) -> Result<(), SysError> {
+        let ids = [id_arg(rgid), id_arg(egid), id_arg(sgid)];
+        let current = [self.rgid, self.egid, self.sgid];
+        if !self.is_root()
+            && ids
+                .iter()
+                .any(|id| id.map_or(false, |id| !current.contains(&id)))
+        {
+            return Err(SysError::EPERM);
+        }
+        self.rgid = ids[0].unwrap_or(self.rgid);
+        self.egid = ids[1].unwrap_or(self.egid);
+        self.sgid = ids[2].unwrap_or(self.sgid);
+        Ok(())
+    }
+
+    /// `setgroups`, only by root
+    pub fn set_groups(&mut self, groups: Vec<u32>) -> Result<(), SysError> {
+        if !self.is_root() {
+            return Err(SysError::EPERM);
+        }
+        if groups.len() > NGROUPS_MAX {
+            return Err(SysError::EINVAL);
+        }
+        self.groups = groups;
+        Ok(())
+    }
+
+    /// Whether it may change the resource limits of a process of `target`,
+    /// whose user and group ids must all be its real ones, unless privileged
+    pub fn may_control(&self, target: &Credentials) -> bool {
+        let uids = [target.ruid, target.euid, target.suid];
+        let gids = [target.rgid, target.egid, target.sgid];
+        self.is_root()
+            || (uids.iter().all(|&id| id == self.ruid) && gids.iter().all(|&id| id == self.rgid))
+    }
+
+    /// The credentials to check `access` with, i.e. the real ids as the effective ones
+    pub fn real(&self) -> Self {
+        Credentials {
+            euid: self.ruid,
+            egid: self.rgid,
+            ..self.clone()
+        }
+    }
+}
@@ -0,0 +1,340 @@
+//! Futex wait queues
+//!
+//! A futex is identified by a key: a process-private one by the memory set
+//! of the process and its virtual address, and one in shared memory by its
+//! physical address, which is the same in all the processes mapping it.
+//! All the queues are protected by one lock, so a waiter can be moved
+//! between queues by requeue, and remove itself on timeout or signal.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use rcore_memory::PAGE_SIZE;
+use rcore_thread::Tid;
+
+use super::{process, processor, Process};
+use crate::memory::VmLock;
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::syscall::{SysError, SysResult};
+use crate::thread;
+use crate::timer::{self, Timer};
+
+/// Bitset of `FUTEX_WAIT` and `FUTEX_WAKE`, matching any waiter
+pub const FUTEX_BITSET_MATCH_ANY: u32 = 0xffff_ffff;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FutexKey {
+    /// by the memory set, shared by threads and `CLONE_VM`, and virtual address
+    Private(usize, usize),
+    /// by physical address
+    Shared(usize),
+}
+
+/// Key of the futex at `uaddr` of `proc`.
+/// If not `private`, futexes in shared mappings are keyed by the physical address,
+/// the page should be present, e.g. by reading the futex before.
+pub fn key(proc: &mut Process, uaddr: usize, private: bool) -> Option<FutexKey> {
+    let shared = !private
+        && proc
+            .vm()
+            .iter()
+            .any(|area| area.contains(uaddr) && area.is_shared());
+    if shared {
+        let frame = proc.vm().translate(uaddr)?;
+        Some(FutexKey::Shared(frame + uaddr % PAGE_SIZE))
+    } else {
+        let vm = &*proc.vm as *const VmLock as usize;
+        Some(FutexKey::Private(vm, uaddr))
+    }
+}
+
+struct Waiter {
+    tid: Tid,
+    bitset: u32,
+}
+
+#[derive(Default)]
+struct FutexTable {
+    queues: BTreeMap<FutexKey, VecDeque<Waiter>>,
+    /// the key of the queue each waiting thread is in
+    waiting: BTreeMap<Tid, FutexKey>,
+}
+
+lazy_static! {
+    static ref FUTEXES: Mutex<FutexTable> = Mutex::new(FutexTable::default());
+}
+
+impl FutexTable {
+    fn push(&mut self, key: FutexKey, waiter: Waiter) {
+        self.waiting.insert(waiter.tid, key);
+        self.queues
+            .entry(key)
+            .or_insert_with(VecDeque::new)
+            .push_back(waiter);
+    }
+
+    /// Remove thread `tid` from its queue, return false if it is not waiting
+    fn remove(&mut self, tid: Tid) -> bool {
+        let key = match self.waiting.remove(&tid) {
+            Some(key) => key,
+            None => return false,
+        };
+        let empty = match self.queues.get_mut(&key) {
+            Some(queue) => {
+                queue.retain(|waiter| waiter.tid != tid);
+                queue.is_empty()
+            }
+            None => false,
+        };
+        if empty {
+            self.queues.remove(&key);
+        }
+        true
+    }
+
+    /// Take at most `n` waiters from the front of queue `key`, whose bitset matches `bitset`
+    fn take(&mut self, key: FutexKey, n: usize, bitset: u32) -> Vec<Waiter> {
+        let mut taken = Vec::new();
+        let empty = match self.queues.get_mut(&key) {
+            Some(queue) => {
+                let mut i = 0;
+                while i < queue.len() && taken.len() < n {
+                    if queue[i].bitset & bitset != 0 {
+                        taken.push(queue.remove(i).unwrap());
+                    } else {
+                        i += 1;
+                    }
+                }
+                queue.is_empty()
+            }
+            None => false,
+        };
+        if empty {
+            self.queues.remove(&key);
+        }
+        for waiter in taken.iter() {
+            self.waiting.remove(&waiter.tid);
+        }
+        taken
+    }
+
+    fn wake(&mut self, key: FutexKey, n: usize, bitset: u32) -> usize {
+        let woken = self.take(key, n, bitset);
+        for waiter in woken.iter() {
+            processor().manager().wakeup(waiter.tid);
+        }
+        woken.len()
+    }
+}
+
+/// Wait on futex `key` until woken up by `wake` with a bitset matching `bitset`,
+/// or until tick `deadline` if it is not `None`.
+///
+/// `check` is called before waiting, with the wakers excluded,
+/// fail with EAGAIN if it returns false, i.e. the futex value has changed.
+/// Fail with ETIMEDOUT on timeout, or EINTR if woken up by a signal.
+pub fn wait(
+    key: FutexKey,
+    bitset: u32,
+    deadline: Option<usize>,
+    check: impl FnOnce() -> bool,
+) -> SysResult {
+    let tid = thread::current().id();
+    let mut table = FUTEXES.lock();
+    if !check() {
+        return Err(SysError::EAGAIN);
+    }
+    let timed_out = || deadline.map_or(false, |deadline| timer::now() >= deadline);
+    if timed_out() {
+        return Err(SysError::ETIMEDOUT);
+    }
+    table.push(key, Waiter { tid, bitset });
+    // sleep before the table is unlocked, so that no wake up is missed
+    processor().manager().sleep(tid, 0);
+    let _timer =
+        deadline.map(|deadline| Timer::new(deadline, 0, move || processor().manager().wakeup(tid)));
+    drop(table);
+    processor().yield_now();
+
+    // still in a queue if not woken up by `wake`
+    if !FUTEXES.lock().remove(tid) {
+        return Ok(0);
+    }
+    match timed_out() {
+        true => Err(SysError::ETIMEDOUT),
+        false => Err(SysError::EINTR),
+    }
+}
+
+/// Wake up at most `n` waiters of futex `key`, whose bitset matches `bitset`.
+/// Return the number of waiters woken up.
+pub fn wake(key: FutexKey, n: usize, bitset: u32) -> usize {
+    FUTEXES.lock().wake(key, n, bitset)
+}
+
+/// Wake up at most `n_wake` waiters of futex `from`,
+/// and move at most `n_requeue` of the others to futex `to`.
+///
+/// `check` is called before, with the wakers excluded,
+/// fail with EAGAIN if it returns false.
+/// Return the number of waiters woken up or moved.
+pub fn requeue(
+    from: FutexKey,
+    to: FutexKey,
+    n_wake: usize,
+    n_requeue: usize,
+    check: impl FnOnce() -> bool,
+) -> SysResult {
+    let mut table = FUTEXES.lock();
+    if !check() {
+        return Err(SysError::EAGAIN);
+    }
+    let woken = table.wake(from, n_wake, FUTEX_BITSET_MATCH_ANY);
+    if from == to {
+        return Ok(woken);
+    }
+    let moved = table.take(from, n_requeue, FUTEX_BITSET_MATCH_ANY);
+    let count = moved.len();
+    for waiter in moved {
+        table.push(to, waiter);
+    }
+    Ok(woken + count)
+}
+
+/// Run `op`, then wake up at most `n1` waiters of futex `key1`,
+/// and at most `n2` waiters of futex `key2` if `op` returns true.
+/// Wakers are excluded while running `op`.
+///
+/// Return the number of waiters woken up.
+pub fn wake_op(
+    key1: FutexKey,
+    key2: FutexKey,
+    n1: usize,
+    n2: usize,
+    op: impl FnOnce() -> Result<bool, SysError>,
+) -> SysResult {
+    let mut table = FUTEXES.lock();
+    let wake2 = op()?;
+    let mut woken = table.wake(key1, n1, FUTEX_BITSET_MATCH_ANY);
+    if wake2 {
+        woken += table.wake(key2, n2, FUTEX_BITSET_MATCH_ANY);
+    }
+    Ok(woken)
+}
+
+/// Forget thread `tid` if it is waiting, called when it is killed
+pub fn remove_waiter(tid: Tid) {
+    FUTEXES.lock().remove(tid);
+}
+
+/// Head of a robust futex list, registered by `set_robust_list`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct RobustListHead {
+    /// the first entry, the head itself if empty
+    list: usize,
+    /// offset from an entry to its futex word
+    futex_offset: isize,
+    /// the entry being locked or unlocked
+    list_op_pending: usize,
+}
+
+/// Read a `T` at user address `addr` of the current process
+fn read_user<T: Copy>(addr: usize) -> Option<T> {
+    process().vm().check_read_ptr(addr as *const T).ok()?;
+    Some(unsafe { (addr as *const T).read() })
+}
+
+/// Mark the futex at `uaddr` held by thread `tid` as owner died,
+/// and wake up a waiter if any. Return false if `uaddr` is invalid.
+fn owner_died(uaddr: usize, tid: Tid) -> bool {
+    const FUTEX_WAITERS: u32 = 0x8000_0000;
+    const FUTEX_OWNER_DIED: u32 = 0x4000_0000;
+    const FUTEX_TID_MASK: u32 = 0x3fff_ffff;
+
+    if uaddr % size_of::<u32>() != 0 {
+        return false;
+    }
+    if process()
+        .vm()
+        .check_write_ptr(uaddr as *mut AtomicU32)
+        .is_err()
+    {
+        return false;
+    }
+    let atomic = unsafe { &*(uaddr as *const AtomicU32) };
+    let mut value = atomic.load(Ordering::SeqCst);
+    loop {
+        if value & FUTEX_TID_MASK != tid as u32 {
+            // not held by the thread
+            return true;
+        }
+        let new = (value & FUTEX_WAITERS) | FUTEX_OWNER_DIED;
+        match atomic.compare_exchange(value, new, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) => break,
+            Err(current) => value = current,
+        }
+    }
+    if value & FUTEX_WAITERS != 0 {
+        if let Some(key) = key(&mut process(), uaddr, false) {
+            wake(key, 1, FUTEX_BITSET_MATCH_ANY);
+        }
+    }
+    true
+}
+
+/// Release the futexes in the robust list at `head` of the exiting thread `tid`,
+/// so that their waiters see the owner died instead of waiting forever.
+/// The list is in the memory of the current process.
+pub fn exit_robust_list(head: usize, tid: Tid) {
+    /// Max number of entries to walk, in case the list is circular
+    const ROBUST_LIST_LIMIT: usize = 2048;
+
+    let head_value: RobustListHead = match read_user(head) {
+        Some(value) => value,
+        None => return,
+    };
+    // the lowest bit of a pointer marks a PI futex, which is not distinguished here
+    let offset = head_value.futex_offset;
+    let pending = head_value.list_op_pending & !1;
+    let mut entry = head_value.list & !1;
+    let mut limit = ROBUST_LIST_LIMIT;
+    while entry != head && limit > 0 {
+        let next: usize = match read_user(entry) {
+            Some(next) => next & !1,
+            None => return,
+        };
+        // the pending one is handled at last
+        if entry != pending && !owner_died((entry as isize + offset) as usize, tid) {
+            return;
+        }
+        entry = next;
+        limit -= 1;
+    }
+    if pending != 0 {
+        owner_died((pending as isize + offset) as usize, tid);
+    }
+}
+
+/// Clear the tid at `clear_child_tid` of the exiting thread and wake up a waiter,
+/// which is how `pthread_join` waits for it
+pub fn exit_clear_child_tid(clear_child_tid: usize) {
+    if clear_child_tid == 0 {
+        return;
+    }
+    if process()
+        .vm()
+        .check_write_ptr(clear_child_tid as *mut u32)
+        .is_err()
+    {
+        return;
+    }
+    unsafe {
+        (clear_child_tid as *mut u32).write(0);
+    }
+    if let Some(key) = key(&mut process(), clear_child_tid, false) {
+        wake(key, 1, FUTEX_BITSET_MATCH_ANY);
+    }
+}
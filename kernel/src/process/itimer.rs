@@ -0,0 +1,190 @@
+//! Timers of processes, i.e. interval timers of `setitimer` and POSIX timers
+//!
+//! Real time is counted by kernel timers, which signal the process when they expire.
+//! The virtual and profiling timers count down ticks of CPU time instead.
+//! Values and intervals are in ticks, and a timer is disarmed if its value is 0.
+
+use alloc::sync::{Arc, Weak};
+
+use rcore_thread::Tid;
+
+use super::Process;
+use crate::signal::{send_signal_in_irq, Sigset, SIGALRM, SIGPROF, SIGVTALRM};
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::syscall::SysError;
+use crate::timer::{self, Timer};
+
+pub const ITIMER_REAL: usize = 0;
+pub const ITIMER_VIRTUAL: usize = 1;
+pub const ITIMER_PROF: usize = 2;
+
+/// A timer of real time, which sends a signal to a process when it expires
+#[derive(Default)]
+struct SignalTimer {
+    timer: Option<Timer>,
+    interval: usize,
+}
+
+impl SignalTimer {
+    fn get(&self) -> (usize, usize) {
+        let value = self.timer.as_ref().and_then(Timer::remaining).unwrap_or(0);
+        (value, self.interval)
+    }
+
+    /// Arm it to expire at tick `deadline`, then every `interval` ticks,
+    /// or disarm it if `deadline` is `None`.
+    /// Signal `sig` is sent to the process, or to the thread `tid` of it if specified.
+    /// Return the old value and interval.
+    fn set(
+        &mut self,
+        deadline: Option<usize>,
+        interval: usize,
+        proc: &Arc<Mutex<Process>>,
+        tid: Option<Tid>,
+        sig: Option<usize>,
+    ) -> (usize, usize) {
+        let old = self.get();
+        // cancel it first, so the old one never fires after
+        self.timer = None;
+        self.interval = 0;
+        if let Some(deadline) = deadline {
+            let proc = Arc::downgrade(proc);
+            self.timer = Some(Timer::new(deadline, interval, move || {
+                signal(&proc, tid, sig);
+            }));
+            self.interval = interval;
+        }
+        old
+    }
+}
+
+/// Signal `proc` on expiration in the timer interrupt, if it still exists
+fn signal(proc: &Weak<Mutex<Process>>, tid: Option<Tid>, sig: Option<usize>) {
+    if let (Some(sig), Some(proc)) = (sig, proc.upgrade()) {
+        send_signal_in_irq(&proc, tid, sig);
+    }
+}
+
+/// A timer of CPU time
+#[derive(Debug, Default, Clone, Copy)]
+struct CpuTimer {
+    value: usize,
+    interval: usize,
+}
+
+impl CpuTimer {
+    /// Count down a tick, return whether it expires
+    fn tick(&mut self) -> bool {
+        if self.value == 0 {
+            return false;
+        }
+        self.value -= 1;
+        if self.value > 0 {
+            return false;
+        }
+        self.value = self.interval;
+        true
+    }
+}
+
+/// Interval timers of a process, which are not inherited by children
+#[derive(Default)]
+pub struct IntervalTimers {
+    real: SignalTimer,
+    virt: CpuTimer,
+    prof: CpuTimer,
+}
+
+impl IntervalTimers {
+    /// The value and interval of timer `which`
+    pub fn get(&self, which: usize) -> Result<(usize, usize), SysError> {
+        match which {
+            ITIMER_REAL => Ok(self.real.get()),
+            ITIMER_VIRTUAL => Ok((self.virt.value, self.virt.interval)),
+            ITIMER_PROF => Ok((self.prof.value, self.prof.interval)),
+            _ => Err(SysError::EINVAL),
+        }
+    }
+
+    /// Arm timer `which` of process `proc` to expire after `value` ticks,
+    /// then every `interval` ticks. Return the old value and interval.
+    pub fn set(
+        &mut self,
+        which: usize,
+        value: usize,
+        interval: usize,
+        proc: &Arc<Mutex<Process>>,
+    ) -> Result<(usize, usize), SysError> {
+        let interval = match value {
+            0 => 0,
+            _ => interval,
+        };
+        let timer = match which {
+            ITIMER_REAL => {
+                let deadline = match value {
+                    0 => None,
+                    _ => Some(timer::now().saturating_add(value)),
+                };
+                return Ok(self.real.set(deadline, interval, proc, None, Some(SIGALRM)));
+            }
+            ITIMER_VIRTUAL => &mut self.virt,
+            ITIMER_PROF => &mut self.prof,
+            _ => return Err(SysError::EINVAL),
+        };
+        let old = (timer.value, timer.interval);
+        *timer = CpuTimer { value, interval };
+        Ok(old)
+    }
+
+    /// Count down the virtual and profiling timers by a tick spent in user mode,
+    /// return the signals of the expired ones.
+    /// System time is not charged for now, so the profiling timer counts as the virtual one.
+    pub fn charge_tick(&mut self) -> Sigset {
+        let mut signals = Sigset::empty();
+        if self.virt.tick() {
+            signals.add(SIGVTALRM);
+        }
+        if self.prof.tick() {
+            signals.add(SIGPROF);
+        }
+        signals
+    }
+}
+
+/// A POSIX timer of `timer_create`, deleted by `exec`
+pub struct PosixTimer {
+    /// the clock measuring it
+    pub clock: usize,
+    /// the signal to send on expiration, `None` if not notified
+    sig: Option<usize>,
+    /// the thread the signal is directed to, or the process if `None`
+    tid: Option<Tid>,
+    timer: SignalTimer,
+}
+
+impl PosixTimer {
+    pub fn new(clock: usize, sig: Option<usize>, tid: Option<Tid>) -> Self {
+        PosixTimer {
+            clock,
+            sig,
+            tid,
+            timer: SignalTimer::default(),
+        }
+    }
+
+    /// Ticks until it expires, and the interval
+    pub fn get(&self) -> (usize, usize) {
+        self.timer.get()
+    }
+
+    /// Arm it to expire at tick `deadline`, then every `interval` ticks,
+    /// or disarm it if `deadline` is `None`. Return the old value and interval.
+    pub fn set(
+        &mut self,
+        deadline: Option<usize>,
+        interval: usize,
+        proc: &Arc<Mutex<Process>>,
+    ) -> (usize, usize) {
+        self.timer.set(deadline, interval, proc, self.tid, self.sig)
+    }
+}
@@ -0,0 +1,61 @@
+//! Load averages of the system
+//!
+//! The number of active threads, i.e. running or preempted and waiting to run,
+//! is sampled every 5 seconds, and averaged exponentially over 1, 5 and 15 minutes
+//! in fixed point as Linux does.
+
+use core::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
+
+use crate::consts::USEC_PER_TICK;
+
+/// Bits of the fraction part
+const FSHIFT: usize = 11;
+const FIXED_1: usize = 1 << FSHIFT;
+/// Ticks between samples
+const LOAD_FREQ: usize = 5 * 1_000_000 / USEC_PER_TICK + 1;
+/// `FIXED_1 / exp(5sec / 1min)`, and so on for 5 and 15 minutes
+const EXP: [usize; 3] = [1884, 2014, 2037];
+
+/// Number of active threads
+static ACTIVE: AtomicIsize = AtomicIsize::new(0);
+
+/// Load averages over 1, 5 and 15 minutes, in fixed point
+static LOADS: [AtomicUsize; 3] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+
+/// Add `n` to the active threads
+pub(super) fn add_active(n: isize) {
+    ACTIVE.fetch_add(n, Ordering::Relaxed);
+}
+
+/// Sample the active threads on timer tick `tick` of CPU 0
+pub fn tick_load_avg(tick: usize) {
+    if tick % LOAD_FREQ != 0 {
+        return;
+    }
+    let active = ACTIVE.load(Ordering::Relaxed).max(0) as usize * FIXED_1;
+    for (load, &exp) in LOADS.iter().zip(EXP.iter()) {
+        let old = load.load(Ordering::Relaxed);
+        let mut new = old * exp + active * (FIXED_1 - exp);
+        if active >= old {
+            new += FIXED_1 - 1;
+        }
+        load.store(new / FIXED_1, Ordering::Relaxed);
+    }
+}
+
+/// Load averages over 1, 5 and 15 minutes, with `shift` bits of fraction part
+pub fn load_avg(shift: usize) -> [usize; 3] {
+    let mut loads = [0; 3];
+    for (avg, load) in loads.iter_mut().zip(LOADS.iter()) {
+        let load = load.load(Ordering::Relaxed);
+        *avg = match shift.checked_sub(FSHIFT) {
+            Some(bits) => load << bits,
+            None => load >> (FSHIFT - shift),
+        };
+    }
+    loads
+}
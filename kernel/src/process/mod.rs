@@ -1,3 +1,9 @@
+pub use self::coredump::dump_core;
+pub use self::cred::*;
+pub use self::itimer::*;
+pub use self::loadavg::{load_avg, tick_load_avg};
+pub use self::rlimit::*;
+pub use self::rusage::*;
 pub use self::structs::*;
 use crate::arch::cpu;
 use crate::consts::{MAX_CPU_NUM, MAX_PROCESS_NUM};
@@ -7,6 +13,13 @@ use log::*;
 pub use rcore_thread::*;
 
 mod abi;
+mod coredump;
+mod cred;
+pub mod futex;
+mod itimer;
+mod loadavg;
+mod rlimit;
+mod rusage;
 pub mod structs;
 
 pub fn init() {
@@ -20,6 +33,7 @@ pub fn init() {
         }
     }
 
+    crate::fs::init();
     crate::shell::run_user_shell();
 
     info!("process: init end");
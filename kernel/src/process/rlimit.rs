@@ -0,0 +1,131 @@
+//! Resource limits of processes
+//!
+//! Each limit has a soft one, which is enforced, and a hard one,
+//! the ceiling of the soft one. An unprivileged process may lower the hard one,
+//! but never raise it. The limits are inherited by children and across `execve`.
+
+use super::current_thread;
+use crate::consts::{USEC_PER_TICK, USER_STACK_SIZE};
+use crate::signal::{send_signal_in_irq, SIGKILL, SIGXCPU};
+use crate::syscall::SysError;
+
+pub const RLIM_INFINITY: u64 = core::u64::MAX;
+
+pub const RLIMIT_CPU: usize = 0;
+pub const RLIMIT_FSIZE: usize = 1;
+pub const RLIMIT_DATA: usize = 2;
+pub const RLIMIT_STACK: usize = 3;
+pub const RLIMIT_CORE: usize = 4;
+pub const RLIMIT_RSS: usize = 5;
+pub const RLIMIT_NPROC: usize = 6;
+pub const RLIMIT_NOFILE: usize = 7;
+pub const RLIMIT_MEMLOCK: usize = 8;
+pub const RLIMIT_AS: usize = 9;
+pub const RLIMIT_LOCKS: usize = 10;
+pub const RLIMIT_SIGPENDING: usize = 11;
+pub const RLIMIT_MSGQUEUE: usize = 12;
+pub const RLIMIT_NICE: usize = 13;
+pub const RLIMIT_RTPRIO: usize = 14;
+pub const RLIMIT_RTTIME: usize = 15;
+/// Number of resources
+pub const RLIM_NLIMITS: usize = 16;
+
+/// Max hard limit of `RLIMIT_NOFILE`
+const NR_OPEN: u64 = 1024 * 1024;
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RLimit {
+    pub cur: u64, // soft limit
+    pub max: u64, // hard limit
+}
+
+impl RLimit {
+    fn new(cur: u64, max: u64) -> Self {
+        RLimit { cur, max }
+    }
+
+    fn infinity() -> Self {
+        RLimit::new(RLIM_INFINITY, RLIM_INFINITY)
+    }
+
+    /// The soft limit, saturated to `usize`
+    pub fn cur(&self) -> usize {
+        self.cur.min(core::usize::MAX as u64) as usize
+    }
+}
+
+/// Limits of all resources of a process
+#[derive(Debug, Clone)]
+pub struct ResourceLimits([RLimit; RLIM_NLIMITS]);
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        let mut limits = [RLimit::infinity(); RLIM_NLIMITS];
+        limits[RLIMIT_STACK] = RLimit::new(USER_STACK_SIZE as u64, RLIM_INFINITY);
+        limits[RLIMIT_CORE] = RLimit::new(0, RLIM_INFINITY);
+        limits[RLIMIT_NOFILE] = RLimit::new(1024, 4096);
+        limits[RLIMIT_MEMLOCK] = RLimit::new(64 * 1024, 64 * 1024);
+        ResourceLimits(limits)
+    }
+}
+
+impl ResourceLimits {
+    /// The limit of `resource`
+    pub fn get(&self, resource: usize) -> Result<RLimit, SysError> {
+        self.0.get(resource).cloned().ok_or(SysError::EINVAL)
+    }
+
+    /// The soft limit of `resource`, which must be valid
+    pub fn cur(&self, resource: usize) -> usize {
+        self.0[resource].cur()
+    }
+
+    /// Set the limit of `resource`.
+    /// Raising the hard limit needs to be `privileged`.
+    pub fn set(&mut self, resource: usize, new: RLimit, privileged: bool) -> Result<(), SysError> {
+        let old = self.get(resource)?;
+        if new.cur > new.max {
+            return Err(SysError::EINVAL);
+        }
+        if new.max > old.max && !privileged {
+            return Err(SysError::EPERM);
+        }
+        if resource == RLIMIT_NOFILE && new.max > NR_OPEN {
+            return Err(SysError::EPERM);
+        }
+        self.0[resource] = new;
+        Ok(())
+    }
+}
+
+/// Charge a timer tick spent in user mode to the current process,
+/// along with the resource usage of the current thread,
+/// counting down its virtual and profiling interval timers.
+/// Beyond the soft limit of RLIMIT_CPU, it gets `SIGXCPU` every second,
+/// and `SIGKILL` at the hard limit.
+pub fn charge_cpu_tick() {
+    const TICKS_PER_SEC: usize = 1_000_000 / USEC_PER_TICK;
+    let thread = current_thread();
+    let usage = thread.usage.take_uncharged();
+    let mut signals = {
+        let mut proc = thread.proc.lock();
+        proc.cpu_ticks += 1;
+        proc.usage += usage;
+        let mut signals = proc.itimers.charge_tick();
+        if proc.cpu_ticks % TICKS_PER_SEC == 0 {
+            let secs = (proc.cpu_ticks / TICKS_PER_SEC) as u64;
+            let limit = proc.rlimits.0[RLIMIT_CPU];
+            if secs >= limit.max {
+                signals.add(SIGKILL);
+            } else if secs >= limit.cur {
+                signals.add(SIGXCPU);
+            }
+        }
+        signals
+    };
+    while let Some(sig) = signals.first() {
+        signals.remove(sig);
+        send_signal_in_irq(&thread.proc, None, sig);
+    }
+}
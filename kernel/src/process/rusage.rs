@@ -0,0 +1,199 @@
+//! Resource usage of threads and processes
+//!
+//! A thread splits its CPU time into user and system time on trap entry and exit,
+//! and on context switches. It charges its process on timer ticks in user mode,
+//! or when it exits. A process charges its parent when it is reaped.
+
+use core::ops::{Add, AddAssign};
+use core::time::Duration;
+
+use super::{current_thread, loadavg, processor, Process};
+use crate::arch::timer::timer_now;
+
+/// Resource usage of a thread, a process, or the reaped children of a process
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Usage {
+    /// CPU time spent in user mode
+    pub utime: Duration,
+    /// CPU time spent in kernel mode
+    pub stime: Duration,
+    /// max resident set size in bytes
+    pub maxrss: usize,
+    /// page faults handled without reading a file
+    pub minflt: usize,
+    /// page faults reading a file
+    pub majflt: usize,
+    /// context switches by blocking or yielding
+    pub nvcsw: usize,
+    /// context switches by preemption
+    pub nivcsw: usize,
+}
+
+impl Usage {
+    /// Total CPU time
+    pub fn cpu_time(&self) -> Duration {
+        self.utime + self.stime
+    }
+
+    /// The increase since `old`, an earlier reading, without the max resident set size.
+    /// CPU time read on another CPU may be a bit behind.
+    fn since(&self, old: &Usage) -> Usage {
+        Usage {
+            utime: self.utime.checked_sub(old.utime).unwrap_or_default(),
+            stime: self.stime.checked_sub(old.stime).unwrap_or_default(),
+            maxrss: 0,
+            minflt: self.minflt - old.minflt,
+            majflt: self.majflt - old.majflt,
+            nvcsw: self.nvcsw - old.nvcsw,
+            nivcsw: self.nivcsw - old.nivcsw,
+        }
+    }
+}
+
+/// The sum of usage, whose max resident set size is the larger one
+impl AddAssign for Usage {
+    fn add_assign(&mut self, other: Usage) {
+        self.utime += other.utime;
+        self.stime += other.stime;
+        self.maxrss = self.maxrss.max(other.maxrss);
+        self.minflt += other.minflt;
+        self.majflt += other.majflt;
+        self.nvcsw += other.nvcsw;
+        self.nivcsw += other.nivcsw;
+    }
+}
+
+impl Add for Usage {
+    type Output = Usage;
+
+    fn add(mut self, other: Usage) -> Usage {
+        self += other;
+        self
+    }
+}
+
+/// Resource usage of a thread, accounted as it runs
+#[derive(Debug, Default)]
+pub struct ThreadUsage {
+    /// usage until `since`
+    usage: Usage,
+    /// when it entered the current mode, or was switched to, last time
+    since: Duration,
+    /// switched away by the timer, then waiting to run
+    preempting: bool,
+    /// the scheduler of a CPU, which is never counted active
+    scheduler: bool,
+    /// the part already charged to the process
+    charged: Usage,
+}
+
+impl ThreadUsage {
+    /// Usage of the scheduler thread of a CPU
+    pub fn scheduler() -> Self {
+        let mut usage = ThreadUsage::default();
+        usage.scheduler = true;
+        usage
+    }
+
+    /// Time since `since`, when it is `now`
+    fn elapsed(&mut self, now: Duration) -> Duration {
+        let elapsed = now.checked_sub(self.since).unwrap_or_default();
+        self.since = now;
+        elapsed
+    }
+
+    /// Entered kernel mode from user mode at `now`
+    pub fn enter_kernel(&mut self, now: Duration) {
+        self.usage.utime += self.elapsed(now);
+    }
+
+    /// Returned to user mode at `now`
+    pub fn exit_kernel(&mut self, now: Duration) {
+        self.usage.stime += self.elapsed(now);
+    }
+
+    /// Switched to at `now`
+    pub fn switch_in(&mut self, now: Duration) {
+        self.since = now;
+        if !self.preempting && !self.scheduler {
+            loadavg::add_active(1);
+        }
+    }
+
+    /// Switched away at `now`, always from kernel mode
+    pub fn switch_out(&mut self, now: Duration) {
+        self.usage.stime += self.elapsed(now);
+        if self.preempting {
+            self.usage.nivcsw += 1;
+        } else {
+            self.usage.nvcsw += 1;
+            if !self.scheduler {
+                loadavg::add_active(-1);
+            }
+        }
+    }
+
+    /// Count a page fault handled, reading a file if `major`
+    pub fn count_page_fault(&mut self, major: bool) {
+        match major {
+            true => self.usage.majflt += 1,
+            false => self.usage.minflt += 1,
+        }
+    }
+
+    /// Usage until now, of the current thread
+    pub fn read(&self) -> Usage {
+        let mut usage = self.usage;
+        usage.stime += timer_now().checked_sub(self.since).unwrap_or_default();
+        usage
+    }
+
+    /// Usage of the current thread not charged to the process yet,
+    /// which is marked charged
+    pub fn take_uncharged(&mut self) -> Usage {
+        let usage = self.read();
+        let uncharged = usage.since(&self.charged);
+        self.charged = usage;
+        uncharged
+    }
+}
+
+impl Drop for ThreadUsage {
+    /// A thread killed while waiting to run is no longer active
+    fn drop(&mut self) {
+        if self.preempting && !self.scheduler {
+            loadavg::add_active(-1);
+        }
+    }
+}
+
+/// Tick the scheduler on a timer interrupt from user mode,
+/// where switching away the current thread is a preemption
+pub fn preempt_tick() {
+    let thread = current_thread();
+    thread.usage.preempting = true;
+    processor().tick();
+    thread.usage.preempting = false;
+}
+
+/// Charge the current thread to its process
+pub fn charge_usage() {
+    let thread = current_thread();
+    let usage = thread.usage.take_uncharged();
+    thread.proc.lock().usage += usage;
+}
+
+/// Usage of the current process, charging the current thread first
+pub fn process_usage() -> Usage {
+    let thread = current_thread();
+    let usage = thread.usage.take_uncharged();
+    let mut proc = thread.proc.lock();
+    proc.usage += usage;
+    proc.usage
+}
+
+/// Record the resident set size of `proc` now in its max
+pub fn update_maxrss(proc: &mut Process) {
+    let rss = proc.vm().resident_size();
+    proc.usage.maxrss = proc.usage.maxrss.max(rss);
+}
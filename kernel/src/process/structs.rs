@@ -1,11 +1,11 @@
 use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc, sync::Weak, vec::Vec};
+use bitflags::bitflags;
 use core::fmt;
 
 use core::str;
 use log::*;
 use rcore_memory::PAGE_SIZE;
 use rcore_thread::Tid;
-use spin::RwLock;
 use xmas_elf::{
     header,
     program::{Flags, SegmentData, Type},
@@ -13,12 +13,26 @@ use xmas_elf::{
 };
 
 use crate::arch::interrupt::{Context, TrapFrame};
-use crate::fs::{FileHandle, FileLike, INodeExt, OpenOptions, FOLLOW_MAX_DEPTH};
-use crate::memory::{ByFrame, GlobalFrameAlloc, KernelStack, MemoryAttr, MemorySet};
+use crate::fs::{
+    lookup_at, root_inode, FileHandle, FileLike, FileTable, INodeExt, OpenOptions, Tty, CONSOLE,
+    FOLLOW_MAX_DEPTH,
+};
+use crate::memory::{
+    ByFrame, Delay, GlobalFrameAlloc, KernelStack, MemoryAttr, MemorySet, VmGuard, VmLock,
+};
 use crate::net::SOCKETS;
-use crate::sync::{Condvar, SpinNoIrqLock as Mutex};
+use crate::signal::{
+    send_signal, SigInfo, SignalAction, SignalActionFlags, SignalStack, Sigset, SIGCHLD, SIGCONT,
+    SIGHUP, SIGNAL_COUNT, SIG_IGN,
+};
+use crate::sync::{Condvar, MutexGuard, RwLockNoIrq, Semaphore, SpinNoIrq, SpinNoIrqLock as Mutex};
+use crate::syscall::SysError;
 
 use super::abi::{self, ProcInitInfo};
+use super::{
+    update_maxrss, Credentials, IntervalTimers, PosixTimer, ResourceLimits, ThreadUsage, Usage,
+    RLIMIT_NOFILE, RLIMIT_STACK,
+};
 
 // TODO: avoid pub
 pub struct Thread {
@@ -27,9 +41,66 @@ pub struct Thread {
     /// Kernel performs futex wake when thread exits.
     /// Ref: [http://man7.org/linux/man-pages/man2/set_tid_address.2.html]
     pub clear_child_tid: usize,
+    /// Kernel writes the tid here before the thread runs, by `CLONE_CHILD_SETTID`
+    pub set_child_tid: usize,
+    /// Resume the parent blocked in `vfork` when the thread execs or exits
+    pub vfork_done: Option<VforkDone>,
+    /// Signals blocked by this thread
+    pub sig_mask: Sigset,
+    /// Signal mask to restore after delivering the signal that interrupted
+    /// a wait with a temporary mask, like `sigsuspend`
+    pub saved_sig_mask: Option<Sigset>,
+    /// The syscall id and arguments to restart after handling signals,
+    /// set when it fails with `ERESTARTSYS`
+    pub syscall_restart: Option<(usize, [usize; 6])>,
+    /// Alternate stack for signal handlers
+    pub sig_alt_stack: SignalStack,
+    /// Resource usage, charged to the process from time to time
+    pub usage: ThreadUsage,
+    /// Memory of the process, used by page faults without locking the process
+    pub vm: Arc<VmLock>,
     pub proc: Arc<Mutex<Process>>,
 }
 
+/// Notify the parent blocked in `vfork` when dropped
+pub struct VforkDone(pub Arc<Semaphore>);
+
+impl Drop for VforkDone {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
+bitflags! {
+    /// Flags of `clone`, the lowest byte is the signal sent to the parent on exit
+    pub struct CloneFlags: usize {
+        const CSIGNAL = 0xff;
+        const VM = 0x100;
+        const FS = 0x200;
+        const FILES = 0x400;
+        const SIGHAND = 0x800;
+        const PTRACE = 0x2000;
+        const VFORK = 0x4000;
+        const PARENT = 0x8000;
+        const THREAD = 0x10000;
+        const NEWNS = 0x20000;
+        const SYSVSEM = 0x40000;
+        const SETTLS = 0x80000;
+        const PARENT_SETTID = 0x100000;
+        const CHILD_CLEARTID = 0x200000;
+        const DETACHED = 0x400000;
+        const UNTRACED = 0x800000;
+        const CHILD_SETTID = 0x1000000;
+        const NEWCGROUP = 0x2000000;
+        const NEWUTS = 0x4000000;
+        const NEWIPC = 0x8000000;
+        const NEWUSER = 0x10000000;
+        const NEWPID = 0x20000000;
+        const NEWNET = 0x40000000;
+        const IO = 0x80000000;
+    }
+}
+
 /// Pid type
 /// For strong type separation
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -71,27 +142,84 @@ impl fmt::Display for Pid {
 }
 
 pub struct Process {
-    // resources
-    pub vm: MemorySet,
-    pub files: BTreeMap<usize, FileLike>,
-    pub cwd: String,
-    futexes: BTreeMap<usize, Arc<Condvar>>,
+    // resources, which can be shared with other processes by `clone`
+    pub vm: Arc<VmLock>,
+    pub files: Arc<Mutex<FileTable>>,
+    pub cwd: Arc<Mutex<String>>,
+    pub exec_path: String, // absolute path of the executable
+    pub cmdline: Vec<String>,
+    pub heap_start: usize, // the "heap" area starts here, after the highest ELF segment
+    pub heap_end: usize,   // i.e. the program break
 
     // relationship
-    pub pid: Pid, // i.e. tgid, usually the tid of first thread
+    pub pid: Pid,              // i.e. tgid, usually the tid of first thread
+    pub pgid: usize,           // process group id, i.e. the pid of the group leader
+    pub sid: usize,            // session id, i.e. the pid of the session leader
+    pub tty: Option<Arc<Tty>>, // the controlling terminal, if it still controls the session
     pub parent: Option<Arc<Mutex<Process>>>,
-    pub children: Vec<Weak<Mutex<Process>>>,
-    pub threads: Vec<Tid>, // threads in the same process
+    pub children: Vec<Arc<Mutex<Process>>>, // including zombies not waited yet
+    pub threads: Vec<Tid>,                  // threads in the same process
+    pub robust_lists: BTreeMap<Tid, usize>, // robust futex list heads of threads
+    pub exit_signal: usize,                 // sent to the parent on exit, usually SIGCHLD
+
+    // credentials
+    pub cred: Credentials, // user and group ids, checked for permissions
+    pub umask: usize,      // permission bits cleared when creating files
+
+    // resource limits
+    pub rlimits: ResourceLimits, // enforced when allocating resources
+    pub cpu_ticks: usize,        // timer ticks spent running in user mode, for RLIMIT_CPU
+    pub usage: Usage,            // resource usage charged by its threads
+    pub children_usage: Usage,   // resource usage of reaped children, including theirs
+
+    // timers
+    pub itimers: IntervalTimers,             // of `setitimer` and `alarm`
+    pub timers: BTreeMap<usize, PosixTimer>, // of `timer_create`, by their ids
 
     // for waiting child
-    pub child_exit: Arc<Condvar>, // notified when the a child process is going to terminate
-    pub child_exit_code: BTreeMap<usize, usize>, // child process store its exit code here
+    pub child_exit: Arc<Condvar>, // notified when a child process changes its state
+    pub exit_status: Option<WaitStatus>, // set when all threads exited, i.e. a zombie
+    pub stop_status: Option<WaitStatus>, // stopped or continued, until reported to the parent
+
+    // signal
+    pub sig_actions: Arc<Mutex<[SignalAction; SIGNAL_COUNT + 1]>>,
+    pub sig_pending: Sigset, // process-directed pending signals
+    pub sig_pending_threads: BTreeMap<Tid, Sigset>, // thread-directed pending signals
+    pub sig_senders: BTreeMap<(Option<Tid>, usize), SigInfo>, // of pending signals sent by users
+    pub stopped: bool,       // stopped by a signal, until continued by SIGCONT
+    pub continued: Arc<Condvar>, // notified when continued
+}
+
+/// State change of a process reported to its parent by `wait4`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitStatus {
+    /// exited with the code
+    Exited(usize),
+    /// killed by the signal, and whether the core is dumped
+    Signaled(usize, bool),
+    /// stopped by the signal
+    Stopped(usize),
+    Continued,
+}
+
+impl WaitStatus {
+    /// Encode as the status of `wait4`
+    pub fn encode(&self) -> i32 {
+        match *self {
+            WaitStatus::Exited(code) => ((code & 0xff) << 8) as i32,
+            WaitStatus::Signaled(sig, false) => sig as i32,
+            WaitStatus::Signaled(sig, true) => sig as i32 | 0x80,
+            WaitStatus::Stopped(sig) => ((sig << 8) | 0x7f) as i32,
+            WaitStatus::Continued => 0xffff,
+        }
+    }
 }
 
 /// Records the mapping between pid and Process struct.
+/// It is also read in interrupt handlers, e.g. to signal a process group on keyboard input.
 lazy_static! {
-    pub static ref PROCESSES: RwLock<BTreeMap<usize, Weak<Mutex<Process>>>> =
-        RwLock::new(BTreeMap::new());
+    pub static ref PROCESSES: RwLockNoIrq<BTreeMap<usize, Weak<Mutex<Process>>>> =
+        RwLockNoIrq::new(BTreeMap::new());
 }
 
 /// Let `rcore_thread` can switch between our `Thread`
@@ -99,6 +227,9 @@ impl rcore_thread::Context for Thread {
     unsafe fn switch_to(&mut self, target: &mut rcore_thread::Context) {
         use core::mem::transmute;
         let (target, _): (&mut Thread, *const ()) = transmute(target);
+        let now = crate::arch::timer::timer_now();
+        self.usage.switch_out(now);
+        target.usage.switch_in(now);
         self.context.switch(&mut target.context);
     }
 
@@ -110,7 +241,14 @@ impl rcore_thread::Context for Thread {
             // link to its ppid
             if let Some(parent) = &proc.parent {
                 let mut parent = parent.lock();
-                parent.children.push(Arc::downgrade(&self.proc));
+                parent.children.push(self.proc.clone());
+            } else {
+                // in a new session, which gets the console if it is free
+                proc.pgid = tid;
+                proc.sid = tid;
+                if let Some(tty) = &proc.tty {
+                    tty.set_session(tid, tid, false);
+                }
             }
         }
         // add it to threads
@@ -118,6 +256,17 @@ impl rcore_thread::Context for Thread {
         PROCESSES
             .write()
             .insert(proc.pid.get(), Arc::downgrade(&self.proc));
+        // write the tid before the thread runs
+        if self.set_child_tid != 0 {
+            let addr = self.set_child_tid;
+            let mut vm = proc.vm();
+            // the page is present, and copy-on-write is broken by the parent if forked,
+            // so no page is copied here, whose page table may be inactive
+            vm.handle_page_fault(addr);
+            unsafe {
+                vm.with(|| (addr as *mut u32).write(tid as u32));
+            }
+        }
     }
 }
 
@@ -128,7 +277,15 @@ impl Thread {
             context: Context::null(),
             kstack: KernelStack::new(),
             clear_child_tid: 0,
-            // safety: this field will never be used
+            set_child_tid: 0,
+            vfork_done: None,
+            sig_mask: Sigset::empty(),
+            saved_sig_mask: None,
+            syscall_restart: None,
+            sig_alt_stack: SignalStack::default(),
+            usage: ThreadUsage::scheduler(),
+            // safety: these fields will never be used
+            vm: core::mem::uninitialized(),
             proc: core::mem::uninitialized(),
         })
     }
@@ -137,28 +294,61 @@ impl Thread {
     pub fn new_kernel(entry: extern "C" fn(usize) -> !, arg: usize) -> Box<Thread> {
         let vm = MemorySet::new();
         let kstack = KernelStack::new();
+        let context = unsafe { Context::new_kernel_thread(entry, arg, kstack.top(), vm.token()) };
+        let vm = Arc::new(VmLock::new(vm));
         Box::new(Thread {
-            context: unsafe { Context::new_kernel_thread(entry, arg, kstack.top(), vm.token()) },
+            context,
             kstack,
             clear_child_tid: 0,
+            set_child_tid: 0,
+            vfork_done: None,
+            sig_mask: Sigset::empty(),
+            saved_sig_mask: None,
+            syscall_restart: None,
+            sig_alt_stack: SignalStack::default(),
+            usage: ThreadUsage::default(),
+            vm: vm.clone(),
             // TODO: kernel thread should not have a process
             proc: Arc::new(Mutex::new(Process {
                 vm,
-                files: BTreeMap::default(),
-                cwd: String::from("/"),
-                futexes: BTreeMap::default(),
+                files: Arc::new(Mutex::new(FileTable::default())),
+                cwd: Arc::new(Mutex::new(String::from("/"))),
+                exec_path: String::new(),
+                cmdline: Vec::new(),
+                heap_start: 0,
+                heap_end: 0,
                 pid: Pid::uninitialized(),
+                pgid: 0,
+                sid: 0,
+                tty: None,
                 parent: None,
                 children: Vec::new(),
                 threads: Vec::new(),
+                robust_lists: BTreeMap::new(),
+                exit_signal: 0,
+                cred: Credentials::default(),
+                umask: 0o022,
+                rlimits: ResourceLimits::default(),
+                cpu_ticks: 0,
+                usage: Usage::default(),
+                children_usage: Usage::default(),
+                itimers: IntervalTimers::default(),
+                timers: BTreeMap::new(),
                 child_exit: Arc::new(Condvar::new()),
-                child_exit_code: BTreeMap::new(),
+                exit_status: None,
+                stop_status: None,
+                sig_actions: Arc::new(Mutex::new([SignalAction::default(); SIGNAL_COUNT + 1])),
+                sig_pending: Sigset::empty(),
+                sig_pending_threads: BTreeMap::new(),
+                sig_senders: BTreeMap::new(),
+                stopped: false,
+                continued: Arc::new(Condvar::new()),
             })),
         })
     }
 
-    /// Make a new user process from ELF `data`
-    pub fn new_user<'a, Iter>(data: &[u8], args: Iter) -> Box<Thread>
+    /// Make a new user process from ELF `data`, with resource limits `rlimits`
+    pub fn new_user<'a, Iter>(data: &[u8], args: Iter, rlimits: &ResourceLimits) -> Box<Thread>
     where
         Iter: Iterator<Item = &'a str>,
     {
@@ -175,14 +365,14 @@ impl Thread {
         // Check interpreter
         if let Ok(loader_path) = elf.get_interpreter() {
             // assuming absolute path
-            if let Ok(inode) = crate::fs::ROOT_INODE.lookup_follow(loader_path, FOLLOW_MAX_DEPTH) {
+            if let Ok(inode) = lookup_at(&root_inode(), loader_path, FOLLOW_MAX_DEPTH, None) {
                 if let Ok(buf) = inode.read_as_vec() {
                     debug!("using loader {}", &loader_path);
                     // Elf loader should not have INTERP
                     // No infinite loop
                     let mut new_args: Vec<&str> = args.collect();
                     new_args.insert(0, loader_path);
-                    return Thread::new_user(buf.as_slice(), new_args.into_iter());
+                    return Thread::new_user(buf.as_slice(), new_args.into_iter(), rlimits);
                 } else {
                     warn!("loader specified as {} but failed to read", &loader_path);
                 }
@@ -192,13 +382,20 @@ impl Thread {
         }
 
         // Make page table
-        let mut vm = elf.make_memory_set();
+        let (mut vm, heap_start) = elf.make_memory_set();
+        crate::signal::push_trampoline(&mut vm);
+        let vdso = crate::vdso::push_vdso(&mut vm);
 
-        // User stack
+        // User stack, no larger than RLIMIT_STACK
         use crate::consts::{USER_STACK_OFFSET, USER_STACK_SIZE};
         let mut ustack_top = {
-            let ustack_buttom = USER_STACK_OFFSET;
+            let ustack_size = rlimits
+                .cur(RLIMIT_STACK)
+                .min(USER_STACK_SIZE)
+                .max(PAGE_SIZE);
+            let ustack_size = (ustack_size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
             let ustack_top = USER_STACK_OFFSET + USER_STACK_SIZE;
+            let ustack_buttom = ustack_top - ustack_size;
             vm.push(
                 ustack_buttom,
                 ustack_top,
@@ -210,8 +407,9 @@ impl Thread {
         };
 
         // Make init info
+        let args: Vec<String> = args.map(|s| String::from(s)).collect();
         let init_info = ProcInitInfo {
-            args: args.map(|s| String::from(s)).collect(),
+            args: args.clone(),
             envs: BTreeMap::new(),
             auxv: {
                 let mut map = BTreeMap::new();
@@ -221,6 +419,9 @@ impl Thread {
                 map.insert(abi::AT_PHENT, elf.header.pt2.ph_entry_size() as usize);
                 map.insert(abi::AT_PHNUM, elf.header.pt2.ph_count() as usize);
                 map.insert(abi::AT_PAGESZ, PAGE_SIZE);
+                if let Some(vdso) = vdso {
+                    map.insert(abi::AT_SYSINFO_EHDR, vdso);
+                }
                 map
             },
         };
@@ -232,7 +433,7 @@ impl Thread {
 
         let kstack = KernelStack::new();
 
-        let mut files = BTreeMap::new();
+        let mut files = FileTable::default();
         files.insert(
             0,
             FileLike::File(FileHandle::new(
@@ -268,103 +469,436 @@ impl Thread {
         );
 
         let entry_addr = elf.header.pt2.entry_point() as usize;
+        let context =
+            unsafe { Context::new_user_thread(entry_addr, ustack_top, kstack.top(), vm.token()) };
+        let vm = Arc::new(VmLock::new(vm));
 
         Box::new(Thread {
-            context: unsafe {
-                Context::new_user_thread(entry_addr, ustack_top, kstack.top(), vm.token())
-            },
+            context,
             kstack,
             clear_child_tid: 0,
+            set_child_tid: 0,
+            vfork_done: None,
+            sig_mask: Sigset::empty(),
+            saved_sig_mask: None,
+            syscall_restart: None,
+            sig_alt_stack: SignalStack::default(),
+            usage: ThreadUsage::default(),
+            vm: vm.clone(),
             proc: Arc::new(Mutex::new(Process {
                 vm,
-                files,
-                cwd: String::from("/"),
-                futexes: BTreeMap::default(),
+                files: Arc::new(Mutex::new(files)),
+                cwd: Arc::new(Mutex::new(String::from("/"))),
+                exec_path: match args.get(0) {
+                    Some(path) if path.starts_with('/') => path.clone(),
+                    Some(path) => format!("/{}", path),
+                    None => String::new(),
+                },
+                cmdline: args,
+                heap_start,
+                heap_end: heap_start,
                 pid: Pid::uninitialized(),
+                pgid: 0,
+                sid: 0,
+                tty: Some(CONSOLE.clone()),
                 parent: None,
                 children: Vec::new(),
                 threads: Vec::new(),
+                robust_lists: BTreeMap::new(),
+                exit_signal: 0,
+                cred: Credentials::default(),
+                umask: 0o022,
+                rlimits: rlimits.clone(),
+                cpu_ticks: 0,
+                usage: Usage::default(),
+                children_usage: Usage::default(),
+                itimers: IntervalTimers::default(),
+                timers: BTreeMap::new(),
                 child_exit: Arc::new(Condvar::new()),
-                child_exit_code: BTreeMap::new(),
+                exit_status: None,
+                stop_status: None,
+                sig_actions: Arc::new(Mutex::new([SignalAction::default(); SIGNAL_COUNT + 1])),
+                sig_pending: Sigset::empty(),
+                sig_pending_threads: BTreeMap::new(),
+                sig_senders: BTreeMap::new(),
+                stopped: false,
+                continued: Arc::new(Condvar::new()),
             })),
         })
     }
 
     /// Fork a new process from current one
     pub fn fork(&self, tf: &TrapFrame) -> Box<Thread> {
-        // Clone memory set, make a new page table
+        let flags = CloneFlags::from_bits_truncate(crate::signal::SIGCHLD);
+        self.new_process(tf, flags, None, None)
+    }
+
+    /// Create a new process from current one.
+    /// Resources are shared with the current process or copied as `flags`,
+    /// and the user stack and thread pointer are set if specified.
+    pub fn new_process(
+        &self,
+        tf: &TrapFrame,
+        flags: CloneFlags,
+        stack_top: Option<usize>,
+        tls: Option<usize>,
+    ) -> Box<Thread> {
         let proc = self.proc.lock();
-        let vm = proc.vm.clone();
-        let files = proc.files.clone();
-        let cwd = proc.cwd.clone();
+        let vm = if flags.contains(CloneFlags::VM) {
+            proc.vm.clone()
+        } else {
+            // Clone memory set, make a new page table
+            // Pages are shared by copy-on-write
+            Arc::new(VmLock::new(unsafe { proc.vm().clone_cow() }))
+        };
+        let files = if flags.contains(CloneFlags::FILES) {
+            proc.files.clone()
+        } else {
+            Arc::new(Mutex::new(proc.files().clone()))
+        };
+        let cwd = if flags.contains(CloneFlags::FS) {
+            proc.cwd.clone()
+        } else {
+            Arc::new(Mutex::new(proc.cwd().clone()))
+        };
+        let sig_actions = if flags.contains(CloneFlags::SIGHAND) {
+            proc.sig_actions.clone()
+        } else {
+            Arc::new(Mutex::new(*proc.sig_actions()))
+        };
+        let parent = if flags.contains(CloneFlags::PARENT) {
+            proc.parent.clone()
+        } else {
+            Some(self.proc.clone())
+        };
+        let (pgid, sid, tty) = (proc.pgid, proc.sid, proc.tty.clone());
+        let (cred, umask) = (proc.cred.clone(), proc.umask);
+        let rlimits = proc.rlimits.clone();
+        let exec_path = proc.exec_path.clone();
+        let cmdline = proc.cmdline.clone();
+        let (heap_start, heap_end) = (proc.heap_start, proc.heap_end);
         drop(proc);
-        let parent = Some(self.proc.clone());
+        let token = vm.lock().token();
         debug!("fork: finish clone MemorySet");
 
-        // MMU:   copy data to the new space
-        // NoMMU: coping data has been done in `vm.clone()`
-        for area in vm.iter() {
-            let data = Vec::<u8>::from(unsafe { area.as_slice() });
-            unsafe { vm.with(|| area.as_slice_mut().copy_from_slice(data.as_slice())) }
-        }
-
-        debug!("fork: temporary copy data!");
         let kstack = KernelStack::new();
 
         Box::new(Thread {
-            context: unsafe { Context::new_fork(tf, kstack.top(), vm.token()) },
+            context: unsafe { Context::new_clone(tf, stack_top, kstack.top(), token, tls) },
             kstack,
             clear_child_tid: 0,
+            set_child_tid: 0,
+            vfork_done: None,
+            sig_mask: self.sig_mask,
+            saved_sig_mask: None,
+            syscall_restart: None,
+            sig_alt_stack: self.sig_alt_stack,
+            usage: ThreadUsage::default(),
+            vm: vm.clone(),
             proc: Arc::new(Mutex::new(Process {
                 vm,
                 files,
                 cwd,
-                futexes: BTreeMap::default(),
+                exec_path,
+                cmdline,
+                heap_start,
+                heap_end,
                 pid: Pid::uninitialized(),
+                pgid,
+                sid,
+                tty,
                 parent,
                 children: Vec::new(),
                 threads: Vec::new(),
+                robust_lists: BTreeMap::new(),
+                exit_signal: (flags & CloneFlags::CSIGNAL).bits(),
+                cred,
+                umask,
+                rlimits,
+                cpu_ticks: 0,
+                usage: Usage::default(),
+                children_usage: Usage::default(),
+                itimers: IntervalTimers::default(),
+                timers: BTreeMap::new(),
                 child_exit: Arc::new(Condvar::new()),
-                child_exit_code: BTreeMap::new(),
+                exit_status: None,
+                stop_status: None,
+                sig_actions,
+                sig_pending: Sigset::empty(),
+                sig_pending_threads: BTreeMap::new(),
+                sig_senders: BTreeMap::new(),
+                stopped: false,
+                continued: Arc::new(Condvar::new()),
             })),
         })
     }
 
     /// Create a new thread in the same process.
+    /// The user stack and thread pointer are set if specified.
     pub fn clone(
         &self,
         tf: &TrapFrame,
-        stack_top: usize,
-        tls: usize,
-        clear_child_tid: usize,
+        stack_top: Option<usize>,
+        tls: Option<usize>,
     ) -> Box<Thread> {
         let kstack = KernelStack::new();
-        let token = self.proc.lock().vm.token();
+        let token = self.proc.lock().vm().token();
         Box::new(Thread {
             context: unsafe { Context::new_clone(tf, stack_top, kstack.top(), token, tls) },
             kstack,
-            clear_child_tid,
+            clear_child_tid: 0,
+            set_child_tid: 0,
+            vfork_done: None,
+            sig_mask: self.sig_mask,
+            saved_sig_mask: None,
+            syscall_restart: None,
+            sig_alt_stack: SignalStack::default(),
+            usage: ThreadUsage::default(),
+            vm: self.vm.clone(),
             proc: self.proc.clone(),
         })
     }
 }
 
 impl Process {
-    pub fn get_free_fd(&self) -> usize {
-        (0..).find(|i| !self.files.contains_key(i)).unwrap()
+    /// Lock the memory of the process
+    pub fn vm(&self) -> VmGuard<'_> {
+        self.vm.lock()
+    }
+    /// Lock the file descriptor table of the process
+    pub fn files(&self) -> MutexGuard<'_, FileTable, SpinNoIrq> {
+        self.files.lock()
+    }
+    /// Lock the current working directory of the process
+    pub fn cwd(&self) -> MutexGuard<'_, String, SpinNoIrq> {
+        self.cwd.lock()
     }
-    pub fn get_futex(&mut self, uaddr: usize) -> Arc<Condvar> {
-        if !self.futexes.contains_key(&uaddr) {
-            self.futexes.insert(uaddr, Arc::new(Condvar::new()));
+    /// Lock the signal actions of the process
+    pub fn sig_actions(&self) -> MutexGuard<'_, [SignalAction; SIGNAL_COUNT + 1], SpinNoIrq> {
+        self.sig_actions.lock()
+    }
+    /// The controlling terminal of its session
+    pub fn ctty(&self) -> Option<Arc<Tty>> {
+        let sid = self.sid;
+        self.tty.clone().filter(|tty| tty.session() == Some(sid))
+    }
+    /// The lowest free file descriptor, below RLIMIT_NOFILE
+    pub fn get_free_fd(&self) -> Result<usize, SysError> {
+        let files = self.files();
+        (0..self.rlimits.cur(RLIMIT_NOFILE))
+            .find(|i| !files.contains_key(i))
+            .ok_or(SysError::EMFILE)
+    }
+    /// The two lowest free file descriptors, for a pipe or a socket pair
+    pub fn get_free_fd_pair(&self) -> Result<(usize, usize), SysError> {
+        let files = self.files();
+        let mut free = (0..self.rlimits.cur(RLIMIT_NOFILE)).filter(|i| !files.contains_key(i));
+        match (free.next(), free.next()) {
+            (Some(fd0), Some(fd1)) => Ok((fd0, fd1)),
+            _ => Err(SysError::EMFILE),
+        }
+    }
+    /// Replace the program by the one loaded in `new`, made by `Thread::new_user`.
+    /// The process remains the same one to others, e.g. its parent and children.
+    pub fn exec_from(&mut self, new: &Process) {
+        // the max resident set size is kept across exec
+        update_maxrss(self);
+        self.vm = new.vm.clone();
+        self.heap_start = new.heap_start;
+        self.heap_end = new.heap_end;
+        self.robust_lists.clear();
+        // POSIX timers are deleted, while interval timers remain
+        self.timers.clear();
+        // the file table is unshared, while the working directory remains shared
+        let files = self.files().clone();
+        self.files = Arc::new(Mutex::new(files));
+        // caught signals are reset to default, ignored ones remain ignored
+        let mut actions = [SignalAction::default(); SIGNAL_COUNT + 1];
+        for (action, old) in actions.iter_mut().zip(self.sig_actions().iter()) {
+            if old.handler == SIG_IGN {
+                action.handler = SIG_IGN;
+            }
+        }
+        self.sig_actions = Arc::new(Mutex::new(actions));
+    }
+}
+
+/// Turn `proc` into a zombie with `status`, when all its threads exited.
+/// Its children are adopted by the init process, and its parent is notified.
+pub fn exit_process(proc: &Arc<Mutex<Process>>, status: WaitStatus) {
+    let (pid, pgid, sid, parent, children, exit_signal, ctty, files) = {
+        let mut inner = proc.lock();
+        if inner.exit_status.is_some() {
+            return;
+        }
+        inner.exit_status = Some(status);
+        inner.threads.clear();
+        update_maxrss(&mut inner);
+        inner.itimers = IntervalTimers::default();
+        inner.timers.clear();
+        // close files now, so that the other ends of pipes see EOF or EPIPE,
+        // unless the file table is shared with other processes.
+        // They are dropped after unlocking, as closing a pseudo-terminal
+        // master may signal processes.
+        let files =
+            core::mem::replace(&mut inner.files, Arc::new(Mutex::new(FileTable::default())));
+        let children = core::mem::replace(&mut inner.children, Vec::new());
+        // the controlling terminal is released by the session leader
+        let ctty = match inner.pid.get() == inner.sid {
+            true => inner.ctty(),
+            false => None,
+        };
+        (
+            inner.pid.get(),
+            inner.pgid,
+            inner.sid,
+            inner.parent.clone(),
+            children,
+            inner.exit_signal,
+            ctty,
+            files,
+        )
+    };
+    drop(files);
+
+    // hang up the foreground process group
+    if let Some(foreground) = ctty.and_then(|tty| tty.release(pid)) {
+        for other in process_group(foreground).iter() {
+            send_signal(other, None, SIGHUP);
+            send_signal(other, None, SIGCONT);
+        }
+    }
+
+    // process groups which may be orphaned by the exit: our own one if the parent
+    // keeps it from being orphaned, and those of the children we keep so
+    let mut groups = Vec::new();
+    if let Some(parent) = parent.as_ref() {
+        let parent = parent.lock();
+        if parent.pgid != pgid && parent.sid == sid {
+            groups.push(pgid);
+        }
+    }
+
+    // reparent orphans to init
+    let init = PROCESSES
+        .read()
+        .get(&0)
+        .and_then(|weak| weak.upgrade())
+        .filter(|init| !Arc::ptr_eq(init, proc));
+    let mut has_zombie = false;
+    for child in children.iter() {
+        let mut child = child.lock();
+        child.parent = init.clone();
+        has_zombie |= child.exit_status.is_some();
+        if child.pgid != pgid && child.sid == sid && !groups.contains(&child.pgid) {
+            groups.push(child.pgid);
+        }
+    }
+    if let Some(init) = init {
+        let mut init = init.lock();
+        init.children.extend(children);
+        if has_zombie {
+            init.child_exit.notify_all();
+        }
+    }
+
+    if let Some(parent) = parent {
+        {
+            let mut parent = parent.lock();
+            // reaped at once if the parent ignores SIGCHLD explicitly
+            let action = parent.sig_actions()[SIGCHLD];
+            if action.handler == SIG_IGN || action.flags.contains(SignalActionFlags::NOCLDWAIT) {
+                parent.children.retain(|child| !Arc::ptr_eq(child, proc));
+                remove_process(pid, proc);
+            }
+            parent.child_exit.notify_all();
+        }
+        if exit_signal != 0 {
+            send_signal(&parent, None, exit_signal);
         }
-        self.futexes.get(&uaddr).unwrap().clone()
     }
-    pub fn clone_for_exec(&mut self, other: &Self) {
-        self.files = other.files.clone();
-        self.cwd = other.cwd.clone();
-        self.pid = other.pid.clone();
-        self.parent = other.parent.clone();
-        self.threads = other.threads.clone();
+
+    for pgid in groups {
+        hang_up_orphaned_group(pgid, sid);
+    }
+}
+
+/// Whether the process group `pgid` in session `sid` is orphaned, i.e. the parent
+/// of every living member is in the same group, or out of the session
+fn is_orphaned_group(pgid: usize, sid: usize) -> bool {
+    process_group(pgid).iter().all(|member| {
+        let parent = {
+            let member = member.lock();
+            if member.exit_status.is_some() {
+                return true;
+            }
+            member.parent.clone()
+        };
+        parent.map_or(true, |parent| {
+            let parent = parent.lock();
+            parent.pgid == pgid || parent.sid != sid
+        })
+    })
+}
+
+/// Send SIGHUP and SIGCONT to the process group `pgid` in session `sid`,
+/// if it is orphaned with stopped members, which no one could continue else
+fn hang_up_orphaned_group(pgid: usize, sid: usize) {
+    let group = process_group(pgid);
+    let stopped = group.iter().any(|member| {
+        let member = member.lock();
+        member.stopped && member.exit_status.is_none()
+    });
+    if stopped && is_orphaned_group(pgid, sid) {
+        for member in group.iter() {
+            send_signal(member, None, SIGHUP);
+            send_signal(member, None, SIGCONT);
+        }
+    }
+}
+
+/// Record the stop or continue `status` of `proc` for its parent, and notify it
+pub fn report_stop_status(proc: &Arc<Mutex<Process>>, status: WaitStatus) {
+    let parent = {
+        let mut inner = proc.lock();
+        inner.stop_status = Some(status);
+        inner.parent.clone()
+    };
+    if let Some(parent) = parent {
+        let nocldstop = {
+            let parent = parent.lock();
+            parent.child_exit.notify_all();
+            let action = parent.sig_actions()[SIGCHLD];
+            action.flags.contains(SignalActionFlags::NOCLDSTOP)
+        };
+        if !nocldstop {
+            send_signal(&parent, None, SIGCHLD);
+        }
+    }
+}
+
+/// Processes in the process group `pgid`, including zombies
+pub fn process_group(pgid: usize) -> Vec<Arc<Mutex<Process>>> {
+    let procs: Vec<_> = PROCESSES
+        .read()
+        .values()
+        .filter_map(|weak| weak.upgrade())
+        .collect();
+    procs
+        .into_iter()
+        .filter(|proc| proc.lock().pgid == pgid)
+        .collect()
+}
+
+/// Forget the reaped zombie `proc` in `PROCESSES`, unless its pid is reused
+pub fn remove_process(pid: usize, proc: &Arc<Mutex<Process>>) {
+    let mut processes = PROCESSES.write();
+    let same = processes
+        .get(&pid)
+        .and_then(|weak| weak.upgrade())
+        .map_or(false, |other| Arc::ptr_eq(&other, proc));
+    if same {
+        processes.remove(&pid);
     }
 }
 
@@ -375,7 +909,9 @@ trait ToMemoryAttr {
 impl ToMemoryAttr for Flags {
     fn to_attr(&self) -> MemoryAttr {
         let mut flags = MemoryAttr::default().user();
-        // FIXME: handle readonly
+        if !self.is_write() {
+            flags = flags.readonly();
+        }
         if self.is_execute() {
             flags = flags.execute();
         }
@@ -386,7 +922,8 @@ impl ToMemoryAttr for Flags {
 /// Helper functions to process ELF file
 trait ElfExt {
     /// Generate a MemorySet according to the ELF file.
-    fn make_memory_set(&self) -> MemorySet;
+    /// Also return the start address of heap, which is after the highest segment.
+    fn make_memory_set(&self) -> (MemorySet, usize);
 
     /// Get interpreter string if it has.
     fn get_interpreter(&self) -> Result<&str, &str>;
@@ -396,9 +933,10 @@ trait ElfExt {
 }
 
 impl ElfExt for ElfFile<'_> {
-    fn make_memory_set(&self) -> MemorySet {
+    fn make_memory_set(&self) -> (MemorySet, usize) {
         debug!("creating MemorySet from ELF");
         let mut ms = MemorySet::new();
+        let mut heap_start = 0;
 
         for ph in self.program_iter() {
             if ph.get_type() != Ok(Type::Load) {
@@ -412,11 +950,12 @@ impl ElfExt for ElfFile<'_> {
             };
 
             // Get target slice
+            // The segment is writable until data is copied
             let target = {
                 ms.push(
                     virt_addr,
                     virt_addr + mem_size,
-                    ph.flags().to_attr(),
+                    MemoryAttr::default().user(),
                     ByFrame::new(GlobalFrameAlloc),
                     "",
                 );
@@ -431,8 +970,22 @@ impl ElfExt for ElfFile<'_> {
                     target[data.len()..].iter_mut().for_each(|x| *x = 0);
                 });
             }
+            // Then set the real permission
+            ms.protect(virt_addr, virt_addr + mem_size, ph.flags().to_attr())
+                .expect("failed to protect ELF segment");
+            heap_start = heap_start.max(virt_addr + mem_size);
         }
-        ms
+
+        // Empty heap, grown by brk
+        let heap_start = (heap_start + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        ms.push(
+            heap_start,
+            heap_start,
+            MemoryAttr::default().user(),
+            Delay::new(GlobalFrameAlloc),
+            "heap",
+        );
+        (ms, heap_start)
     }
 
     fn get_interpreter(&self) -> Result<&str, &str> {
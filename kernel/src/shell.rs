@@ -1,7 +1,7 @@
 //! Kernel shell
 
 use crate::drivers::CMDLINE;
-use crate::fs::{INodeExt, ROOT_INODE};
+use crate::fs::{INodeExt, CONSOLE, ROOT_INODE};
 use crate::process::*;
 use alloc::string::String;
 use alloc::vec::Vec;
@@ -10,9 +10,11 @@ use alloc::vec::Vec;
 pub fn run_user_shell() {
     if let Ok(inode) = ROOT_INODE.lookup("rust/sh") {
         let data = inode.read_as_vec().unwrap();
-        processor()
-            .manager()
-            .add(Thread::new_user(data.as_slice(), "sh".split(' ')));
+        processor().manager().add(Thread::new_user(
+            data.as_slice(),
+            "sh".split(' '),
+            &ResourceLimits::default(),
+        ));
     } else {
         processor().manager().add(Thread::new_kernel(shell, 0));
     }
@@ -23,12 +25,19 @@ pub fn run_user_shell() {
     let cmdline = CMDLINE.read();
     let inode = ROOT_INODE.lookup(&cmdline).unwrap();
     let data = inode.read_as_vec().unwrap();
-    processor()
-        .manager()
-        .add(Thread::new_user(data.as_slice(), cmdline.split(' ')));
+    processor().manager().add(Thread::new_user(
+        data.as_slice(),
+        cmdline.split(' '),
+        &ResourceLimits::default(),
+    ));
 }
 
 pub extern "C" fn shell(_arg: usize) -> ! {
+    // lines are edited here, instead of by the line discipline
+    let mut termios = CONSOLE.termios();
+    termios.make_raw();
+    CONSOLE.set_termios(termios, false);
+
     let files = ROOT_INODE.list().unwrap();
     println!("Available programs: {:?}", files);
     let mut history = Vec::new();
@@ -42,9 +51,11 @@ pub extern "C" fn shell(_arg: usize) -> ! {
         let name = cmd.trim().split(' ').next().unwrap();
         if let Ok(file) = ROOT_INODE.lookup(name) {
             let data = file.read_as_vec().unwrap();
-            let _pid = processor()
-                .manager()
-                .add(Thread::new_user(data.as_slice(), cmd.split(' ')));
+            let _pid = processor().manager().add(Thread::new_user(
+                data.as_slice(),
+                cmd.split(' '),
+                &ResourceLimits::default(),
+            ));
         // TODO: wait until process exits, or use user land shell completely
         //unsafe { thread::JoinHandle::<()>::_of(pid) }.join().unwrap();
         } else {
@@ -190,7 +201,12 @@ fn get_line(history: &mut Vec<Vec<u8>>) -> String {
 }
 
 fn get_char() -> u8 {
-    crate::fs::STDIN.pop() as u8
+    let mut c = [0u8];
+    loop {
+        if let Ok(1) = CONSOLE.read(&mut c) {
+            return c[0];
+        }
+    }
 }
 
 fn put_char(ch: u8) {
@@ -0,0 +1,855 @@
+//! POSIX signals
+//!
+//! Each process owns a table of `SignalAction`s and a set of pending signals,
+//! while each thread has its own signal mask and alternate signal stack.
+//!
+//! Signals are delivered when a thread is about to return to user mode:
+//! `handle_signal` picks a pending unblocked signal, and either performs the
+//! default action, or builds a `SignalFrame` on the user stack and redirects
+//! the `TrapFrame` to the user handler. The handler returns to a restorer,
+//! which calls `rt_sigreturn` to restore the saved `TrapFrame`.
+
+use alloc::sync::Arc;
+use bitflags::*;
+use core::mem::size_of;
+use log::*;
+use rcore_memory::PAGE_SIZE;
+
+use crate::arch::interrupt::{TrapFrame, SIGRETURN_TRAMPOLINE};
+use crate::consts::USER_STACK_OFFSET;
+use crate::memory::{ByFrame, GlobalFrameAlloc, MemoryAttr, MemorySet};
+use crate::process::*;
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::thread;
+
+pub use self::consts::*;
+
+#[cfg(not(target_arch = "mips"))]
+#[allow(dead_code)]
+mod consts {
+    pub const SIGHUP: usize = 1;
+    pub const SIGINT: usize = 2;
+    pub const SIGQUIT: usize = 3;
+    pub const SIGILL: usize = 4;
+    pub const SIGTRAP: usize = 5;
+    pub const SIGABRT: usize = 6;
+    pub const SIGBUS: usize = 7;
+    pub const SIGFPE: usize = 8;
+    pub const SIGKILL: usize = 9;
+    pub const SIGUSR1: usize = 10;
+    pub const SIGSEGV: usize = 11;
+    pub const SIGUSR2: usize = 12;
+    pub const SIGPIPE: usize = 13;
+    pub const SIGALRM: usize = 14;
+    pub const SIGTERM: usize = 15;
+    pub const SIGSTKFLT: usize = 16;
+    pub const SIGCHLD: usize = 17;
+    pub const SIGCONT: usize = 18;
+    pub const SIGSTOP: usize = 19;
+    pub const SIGTSTP: usize = 20;
+    pub const SIGTTIN: usize = 21;
+    pub const SIGTTOU: usize = 22;
+    pub const SIGURG: usize = 23;
+    pub const SIGXCPU: usize = 24;
+    pub const SIGXFSZ: usize = 25;
+    pub const SIGVTALRM: usize = 26;
+    pub const SIGPROF: usize = 27;
+    pub const SIGWINCH: usize = 28;
+    pub const SIGIO: usize = 29;
+    pub const SIGPWR: usize = 30;
+    pub const SIGSYS: usize = 31;
+
+    pub const SIG_BLOCK: usize = 0;
+    pub const SIG_UNBLOCK: usize = 1;
+    pub const SIG_SETMASK: usize = 2;
+}
+
+#[cfg(target_arch = "mips")]
+#[allow(dead_code)]
+mod consts {
+    pub const SIGHUP: usize = 1;
+    pub const SIGINT: usize = 2;
+    pub const SIGQUIT: usize = 3;
+    pub const SIGILL: usize = 4;
+    pub const SIGTRAP: usize = 5;
+    pub const SIGABRT: usize = 6;
+    pub const SIGEMT: usize = 7;
+    pub const SIGFPE: usize = 8;
+    pub const SIGKILL: usize = 9;
+    pub const SIGBUS: usize = 10;
+    pub const SIGSEGV: usize = 11;
+    pub const SIGSYS: usize = 12;
+    pub const SIGPIPE: usize = 13;
+    pub const SIGALRM: usize = 14;
+    pub const SIGTERM: usize = 15;
+    pub const SIGUSR1: usize = 16;
+    pub const SIGUSR2: usize = 17;
+    pub const SIGCHLD: usize = 18;
+    pub const SIGPWR: usize = 19;
+    pub const SIGWINCH: usize = 20;
+    pub const SIGURG: usize = 21;
+    pub const SIGIO: usize = 22;
+    pub const SIGSTOP: usize = 23;
+    pub const SIGTSTP: usize = 24;
+    pub const SIGCONT: usize = 25;
+    pub const SIGTTIN: usize = 26;
+    pub const SIGTTOU: usize = 27;
+    pub const SIGVTALRM: usize = 28;
+    pub const SIGPROF: usize = 29;
+    pub const SIGXCPU: usize = 30;
+    pub const SIGXFSZ: usize = 31;
+
+    pub const SIG_BLOCK: usize = 1;
+    pub const SIG_UNBLOCK: usize = 2;
+    pub const SIG_SETMASK: usize = 3;
+}
+
+/// The largest signal number we support
+pub const SIGNAL_COUNT: usize = 64;
+/// The first real-time signal
+pub const SIGRTMIN: usize = 32;
+
+/// Default handler
+pub const SIG_DFL: usize = 0;
+/// Ignore the signal
+pub const SIG_IGN: usize = 1;
+
+/// Size of `sigset_t` in user space
+#[cfg(not(target_arch = "mips"))]
+pub const SIGSET_SIZE: usize = 8;
+/// Size of `sigset_t` in user space
+#[cfg(target_arch = "mips")]
+pub const SIGSET_SIZE: usize = 16;
+
+/// Set of signals, bit `n - 1` stands for signal `n`
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct Sigset(u64);
+
+impl Sigset {
+    pub fn empty() -> Self {
+        Sigset(0)
+    }
+    pub fn from_bits(bits: u64) -> Self {
+        Sigset(bits)
+    }
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+    pub fn contains(&self, sig: usize) -> bool {
+        is_valid(sig) && self.0 & (1 << (sig - 1)) != 0
+    }
+    pub fn add(&mut self, sig: usize) {
+        if is_valid(sig) {
+            self.0 |= 1 << (sig - 1);
+        }
+    }
+    pub fn remove(&mut self, sig: usize) {
+        if is_valid(sig) {
+            self.0 &= !(1 << (sig - 1));
+        }
+    }
+    pub fn add_set(&mut self, other: Sigset) {
+        self.0 |= other.0;
+    }
+    pub fn remove_set(&mut self, other: Sigset) {
+        self.0 &= !other.0;
+    }
+    /// Return signals in both sets
+    pub fn intersect(&self, other: Sigset) -> Sigset {
+        Sigset(self.0 & other.0)
+    }
+    /// Return the set with `other` removed
+    pub fn without(&self, other: Sigset) -> Sigset {
+        Sigset(self.0 & !other.0)
+    }
+    /// Return the lowest signal in the set
+    pub fn first(&self) -> Option<usize> {
+        match self.0 {
+            0 => None,
+            bits => Some(bits.trailing_zeros() as usize + 1),
+        }
+    }
+    /// Signals which can not be blocked
+    pub fn unblockable() -> Sigset {
+        let mut set = Sigset::empty();
+        set.add(SIGKILL);
+        set.add(SIGSTOP);
+        set
+    }
+    /// Signals whose default action is to stop the process
+    pub fn stop_signals() -> Sigset {
+        let mut set = Sigset::empty();
+        set.add(SIGSTOP);
+        set.add(SIGTSTP);
+        set.add(SIGTTIN);
+        set.add(SIGTTOU);
+        set
+    }
+}
+
+/// Check whether `sig` is a valid signal number
+pub fn is_valid(sig: usize) -> bool {
+    sig >= 1 && sig <= SIGNAL_COUNT
+}
+
+#[cfg(not(target_arch = "mips"))]
+bitflags! {
+    pub struct SignalActionFlags: usize {
+        const NOCLDSTOP = 1;
+        const NOCLDWAIT = 2;
+        const SIGINFO = 4;
+        const RESTORER = 0x04000000;
+        const ONSTACK = 0x08000000;
+        const RESTART = 0x10000000;
+        const NODEFER = 0x40000000;
+        const RESETHAND = 0x80000000;
+    }
+}
+
+#[cfg(target_arch = "mips")]
+bitflags! {
+    pub struct SignalActionFlags: usize {
+        const NOCLDSTOP = 1;
+        const SIGINFO = 8;
+        const NOCLDWAIT = 0x10000;
+        const ONSTACK = 0x08000000;
+        const RESTART = 0x10000000;
+        const NODEFER = 0x40000000;
+        const RESETHAND = 0x80000000;
+    }
+}
+
+/// Disposition of a signal, kept in `Process`
+#[derive(Debug, Clone, Copy)]
+pub struct SignalAction {
+    pub handler: usize,
+    pub flags: SignalActionFlags,
+    /// Return address of the handler, 0 if unspecified
+    pub restorer: usize,
+    pub mask: Sigset,
+}
+
+impl Default for SignalAction {
+    fn default() -> Self {
+        SignalAction {
+            handler: SIG_DFL,
+            flags: SignalActionFlags::empty(),
+            restorer: 0,
+            mask: Sigset::empty(),
+        }
+    }
+}
+
+/// `struct sigaction` passed to `rt_sigaction`
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SigAction {
+    pub handler: usize,
+    pub flags: usize,
+    pub restorer: usize,
+    pub mask: Sigset,
+}
+
+/// `struct sigaction` passed to `rt_sigaction`
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SigAction {
+    pub handler: usize,
+    pub flags: usize,
+    pub mask: [u32; 2],
+}
+
+/// `struct sigaction` passed to `rt_sigaction`
+#[cfg(target_arch = "mips")]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SigAction {
+    pub flags: u32,
+    pub handler: usize,
+    pub mask: [u32; 4],
+}
+
+impl From<SigAction> for SignalAction {
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    fn from(act: SigAction) -> Self {
+        let flags = SignalActionFlags::from_bits_truncate(act.flags);
+        SignalAction {
+            handler: act.handler,
+            flags,
+            restorer: if flags.contains(SignalActionFlags::RESTORER) {
+                act.restorer
+            } else {
+                0
+            },
+            mask: act.mask,
+        }
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    fn from(act: SigAction) -> Self {
+        SignalAction {
+            handler: act.handler,
+            flags: SignalActionFlags::from_bits_truncate(act.flags as usize),
+            restorer: 0,
+            mask: Sigset::from_bits(act.mask[0] as u64 | (act.mask[1] as u64) << 32),
+        }
+    }
+}
+
+impl From<SignalAction> for SigAction {
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    fn from(action: SignalAction) -> Self {
+        SigAction {
+            handler: action.handler,
+            flags: action.flags.bits(),
+            restorer: action.restorer,
+            mask: action.mask,
+        }
+    }
+
+    #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+    fn from(action: SignalAction) -> Self {
+        let bits = action.mask.bits();
+        SigAction {
+            handler: action.handler,
+            flags: action.flags.bits(),
+            mask: [bits as u32, (bits >> 32) as u32],
+        }
+    }
+
+    #[cfg(target_arch = "mips")]
+    fn from(action: SignalAction) -> Self {
+        let bits = action.mask.bits();
+        SigAction {
+            flags: action.flags.bits() as u32,
+            handler: action.handler,
+            mask: [bits as u32, (bits >> 32) as u32, 0, 0],
+        }
+    }
+}
+
+/// The thread is executing on the alternate signal stack
+pub const SS_ONSTACK: i32 = 1;
+/// The alternate signal stack is disabled
+pub const SS_DISABLE: i32 = 2;
+/// Minimal size of an alternate signal stack
+pub const MINSIGSTKSZ: usize = 2048;
+
+/// `stack_t` for `sigaltstack`
+#[cfg(not(target_arch = "mips"))]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SignalStack {
+    pub sp: usize,
+    pub flags: i32,
+    pub size: usize,
+}
+
+/// `stack_t` for `sigaltstack`
+#[cfg(target_arch = "mips")]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SignalStack {
+    pub sp: usize,
+    pub size: usize,
+    pub flags: i32,
+}
+
+impl Default for SignalStack {
+    fn default() -> Self {
+        SignalStack {
+            sp: 0,
+            flags: SS_DISABLE,
+            size: 0,
+        }
+    }
+}
+
+impl SignalStack {
+    pub fn is_enabled(&self) -> bool {
+        self.flags & SS_DISABLE == 0 && self.size != 0
+    }
+    pub fn contains(&self, sp: usize) -> bool {
+        self.is_enabled() && sp > self.sp && sp <= self.sp + self.size
+    }
+}
+
+/// Signal sent by `kill`
+pub const SI_USER: i32 = 0;
+/// Signal sent by the kernel
+pub const SI_KERNEL: i32 = 0x80;
+/// Signal sent by `tkill` or `tgkill`
+pub const SI_TKILL: i32 = -6;
+
+/// Codes of `SIGCHLD`, also reported by `waitid`
+pub const CLD_EXITED: i32 = 1;
+pub const CLD_KILLED: i32 = 2;
+pub const CLD_DUMPED: i32 = 3;
+pub const CLD_STOPPED: i32 = 5;
+pub const CLD_CONTINUED: i32 = 6;
+
+/// `siginfo_t`, always 128 bytes
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SigInfo {
+    pub signo: i32,
+    #[cfg(not(target_arch = "mips"))]
+    pub errno: i32,
+    pub code: i32,
+    #[cfg(target_arch = "mips")]
+    pub errno: i32,
+    #[cfg(target_pointer_width = "64")]
+    _pad: i32,
+    /// Sender pid
+    pub pid: i32,
+    /// Sender uid
+    pub uid: u32,
+    /// Exit code or signal of the child, for `SIGCHLD`
+    pub status: i32,
+    #[cfg(target_pointer_width = "64")]
+    _rest: [u8; 100],
+    #[cfg(target_pointer_width = "32")]
+    _rest: [u8; 104],
+}
+
+impl SigInfo {
+    pub fn new(signo: usize, code: i32, pid: usize) -> Self {
+        let mut info: SigInfo = unsafe { core::mem::zeroed() };
+        info.signo = signo as i32;
+        info.code = code;
+        info.pid = pid as i32;
+        info
+    }
+}
+
+/// `ucontext_t` passed to a handler registered with `SA_SIGINFO`
+///
+/// The machine context is our own `TrapFrame`.
+#[repr(C)]
+#[derive(Clone)]
+pub struct SignalUserContext {
+    pub flags: usize,
+    pub link: usize,
+    pub stack: SignalStack,
+    pub context: TrapFrame,
+    pub sig_mask: Sigset,
+}
+
+/// Frame pushed onto the user stack when delivering a signal
+///
+/// The stack pointer points to this frame when the handler returns
+/// to the restorer, so `rt_sigreturn` can find it.
+#[repr(C)]
+pub struct SignalFrame {
+    /// Argument save area of MIPS o32 ABI
+    #[cfg(target_arch = "mips")]
+    _arg_save: [usize; 4],
+    pub info: SigInfo,
+    pub ucontext: SignalUserContext,
+}
+
+/// Address of the page holding `SIGRETURN_TRAMPOLINE`
+///
+/// It is the default return address of signal handlers,
+/// if `SA_RESTORER` is not specified.
+pub const SIGRETURN_TRAMPOLINE_ADDR: usize = USER_STACK_OFFSET - PAGE_SIZE;
+
+/// Map the sigreturn trampoline page to user space of `vm`
+pub fn push_trampoline(vm: &mut MemorySet) {
+    vm.push(
+        SIGRETURN_TRAMPOLINE_ADDR,
+        SIGRETURN_TRAMPOLINE_ADDR + PAGE_SIZE,
+        MemoryAttr::default().user().execute(),
+        ByFrame::new(GlobalFrameAlloc),
+        "signal_trampoline",
+    );
+    unsafe {
+        vm.with(|| {
+            let target = SIGRETURN_TRAMPOLINE_ADDR as *mut [u8; 8];
+            target.write(SIGRETURN_TRAMPOLINE);
+        });
+    }
+    vm.protect(
+        SIGRETURN_TRAMPOLINE_ADDR,
+        SIGRETURN_TRAMPOLINE_ADDR + PAGE_SIZE,
+        MemoryAttr::default().user().readonly().execute(),
+    )
+    .expect("failed to protect signal trampoline");
+}
+
+/// Default action of a signal
+#[derive(Debug, Eq, PartialEq)]
+pub enum DefaultAction {
+    /// Terminate the process
+    Terminate,
+    /// Terminate the process and dump core
+    CoreDump,
+    /// Ignore the signal
+    Ignore,
+    /// Stop the process
+    Stop,
+    /// Continue the process if it is stopped
+    Continue,
+}
+
+pub fn default_action(sig: usize) -> DefaultAction {
+    match sig {
+        SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV | SIGXCPU | SIGXFSZ
+        | SIGSYS => DefaultAction::CoreDump,
+        SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ignore,
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+        SIGCONT => DefaultAction::Continue,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+/// Send signal `sig` to process `proc`.
+///
+/// If `tid` is specified, the signal is directed to that thread.
+/// Threads of the process are woken up, so they can handle the signal
+/// when returning to user mode.
+pub fn send_signal(proc: &Arc<Mutex<Process>>, tid: Option<usize>, sig: usize) {
+    post_signal(proc, tid, sig, true, None);
+}
+
+/// Send signal `sig` to process `proc` as `send_signal`, by a user,
+/// e.g. `kill`, whose pid and uid are in `info`.
+pub fn send_signal_from_user(
+    proc: &Arc<Mutex<Process>>,
+    tid: Option<usize>,
+    sig: usize,
+    info: SigInfo,
+) {
+    post_signal(proc, tid, sig, true, Some(info));
+}
+
+/// Send signal `sig` to process `proc` in an interrupt handler, e.g. a timer callback.
+///
+/// The signal is only marked pending and the threads are woken up.
+/// Even SIGKILL is left for the threads to handle when returning to user mode,
+/// as killing them at once takes locks and may switch away.
+pub fn send_signal_in_irq(proc: &Arc<Mutex<Process>>, tid: Option<usize>, sig: usize) {
+    post_signal(proc, tid, sig, false, None);
+}
+
+/// Send signal `sig`, killing all threads at once if it is SIGKILL and `kill_now`.
+/// `info` is the sender of it if it is sent by a user.
+fn post_signal(
+    proc: &Arc<Mutex<Process>>,
+    tid: Option<usize>,
+    sig: usize,
+    kill_now: bool,
+    info: Option<SigInfo>,
+) {
+    let proc_arc = proc;
+    let mut proc = proc.lock();
+    info!(
+        "signal: send {} to process {} thread {:?}",
+        sig, proc.pid, tid
+    );
+    if proc.exit_status.is_some() {
+        // a zombie never handles signals
+        return;
+    }
+    if sig == SIGKILL && kill_now {
+        // SIGKILL can not be caught, kill all threads immediately
+        let threads = proc.threads.clone();
+        drop(proc);
+        let status = WaitStatus::Signaled(sig, false);
+        if threads.contains(&thread::current().id()) {
+            crate::syscall::exit_group(status);
+        }
+        for &tid in threads.iter() {
+            futex::remove_waiter(tid);
+            processor().manager().exit(tid, status.encode() as usize);
+        }
+        exit_process(proc_arc, status);
+        return;
+    }
+    // SIGCONT and stop signals cancel each other
+    let mut cancelled = Sigset::empty();
+    let mut continued = false;
+    if sig == SIGCONT {
+        cancelled = Sigset::stop_signals();
+        if proc.stopped {
+            proc.stopped = false;
+            proc.continued.notify_all();
+            continued = true;
+        }
+    } else if Sigset::stop_signals().contains(sig) {
+        cancelled.add(SIGCONT);
+    } else if sig == SIGKILL && proc.stopped {
+        // resume the threads to exit
+        proc.stopped = false;
+        proc.continued.notify_all();
+    }
+    proc.sig_pending.remove_set(cancelled);
+    for pending in proc.sig_pending_threads.values_mut() {
+        pending.remove_set(cancelled);
+    }
+    if continued {
+        drop(proc);
+        report_stop_status(proc_arc, WaitStatus::Continued);
+        proc = proc_arc.lock();
+    }
+    let action = proc.sig_actions()[sig];
+    if action.handler == SIG_IGN
+        || (action.handler == SIG_DFL && default_action(sig) == DefaultAction::Ignore)
+    {
+        // discard it now, an ignored signal is never pending
+        return;
+    }
+    match tid {
+        Some(tid) => proc.sig_pending_threads.entry(tid).or_default().add(sig),
+        None => proc.sig_pending.add(sig),
+    }
+    // a pending signal is sent once, by the last sender
+    match info {
+        Some(info) => proc.sig_senders.insert((tid, sig), info),
+        None => proc.sig_senders.remove(&(tid, sig)),
+    };
+    let threads = proc.threads.clone();
+    drop(proc);
+    for tid in threads {
+        processor().manager().wakeup(tid);
+    }
+}
+
+/// Force signal `sig` to the current thread, used for faults.
+///
+/// The signal is unblocked, and reset to default if ignored,
+/// so that the thread never returns to the faulting instruction silently.
+pub fn force_signal(sig: usize) {
+    let thread = current_thread();
+    {
+        let proc = thread.proc.lock();
+        let mut sig_actions = proc.sig_actions();
+        if sig_actions[sig].handler == SIG_IGN {
+            sig_actions[sig] = SignalAction::default();
+        }
+    }
+    thread.sig_mask.remove(sig);
+    send_signal(&thread.proc, Some(thread::current().id()), sig);
+}
+
+/// Send signal `sig` to the process with `pid`.
+pub fn send_signal_to_pid(pid: usize, sig: usize) -> bool {
+    let proc = PROCESSES.read().get(&pid).and_then(|weak| weak.upgrade());
+    match proc {
+        Some(proc) => {
+            send_signal(&proc, None, sig);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Send signal `sig` to every process in the group `pgid`.
+/// Return false if there is no such process.
+pub fn send_signal_to_pgid(pgid: usize, sig: usize) -> bool {
+    let procs = process_group(pgid);
+    for proc in procs.iter() {
+        send_signal(proc, None, sig);
+    }
+    !procs.is_empty()
+}
+
+/// Whether current thread has an unblocked pending signal,
+/// used by blocking syscalls to return `EINTR`.
+pub fn has_signal_to_handle() -> bool {
+    let thread = current_thread();
+    let mask = thread.sig_mask.without(Sigset::unblockable());
+    let proc = thread.proc.lock();
+    let thread_pending = proc
+        .sig_pending_threads
+        .get(&thread::current().id())
+        .cloned()
+        .unwrap_or_default();
+    !thread_pending.without(mask).is_empty() || !proc.sig_pending.without(mask).is_empty()
+}
+
+/// Check and handle pending signals of current thread.
+/// The thread waits here while its process is stopped.
+///
+/// Called before returning to user mode with the user `TrapFrame`.
+pub fn handle_signal(tf: &mut TrapFrame) {
+    let thread = current_thread();
+    let tid = thread::current().id();
+    // restarted unless a handler without `SA_RESTART` runs
+    let mut restart = thread.syscall_restart.take();
+    loop {
+        let mut proc = thread.proc.lock();
+        while proc.stopped {
+            let continued = proc.continued.clone();
+            proc = continued.wait(proc);
+        }
+        // thread-directed signals first
+        let mask = thread.sig_mask.without(Sigset::unblockable());
+        let (sig, action, info) = {
+            let thread_pending = proc
+                .sig_pending_threads
+                .get(&tid)
+                .cloned()
+                .unwrap_or_default()
+                .without(mask);
+            let (sig, target) = match thread_pending.first() {
+                Some(sig) => {
+                    proc.sig_pending_threads.get_mut(&tid).unwrap().remove(sig);
+                    (sig, Some(tid))
+                }
+                None => match proc.sig_pending.without(mask).first() {
+                    Some(sig) => {
+                        proc.sig_pending.remove(sig);
+                        (sig, None)
+                    }
+                    // no handler runs, so the saved mask is back now
+                    None => match thread.saved_sig_mask.take() {
+                        Some(saved) => {
+                            thread.sig_mask = saved;
+                            continue;
+                        }
+                        None => {
+                            if let Some((id, args)) = restart {
+                                tf.restart_syscall(id, &args);
+                            }
+                            return;
+                        }
+                    },
+                },
+            };
+            let info = match proc.sig_senders.remove(&(target, sig)) {
+                Some(info) => info,
+                None => SigInfo::new(sig, SI_KERNEL, 0),
+            };
+            (sig, proc.sig_actions()[sig], info)
+        };
+        info!("signal: thread {} handle signal {}", tid, sig);
+        match action.handler {
+            SIG_IGN => continue,
+            SIG_DFL => match default_action(sig) {
+                DefaultAction::Terminate => {
+                    drop(proc);
+                    crate::syscall::exit_group(WaitStatus::Signaled(sig, false));
+                }
+                DefaultAction::CoreDump => {
+                    drop(proc);
+                    let dumped = dump_core();
+                    crate::syscall::exit_group(WaitStatus::Signaled(sig, dumped));
+                }
+                DefaultAction::Ignore | DefaultAction::Continue => continue,
+                DefaultAction::Stop => {
+                    proc.stopped = true;
+                    drop(proc);
+                    report_stop_status(&thread.proc, WaitStatus::Stopped(sig));
+                    continue;
+                }
+            },
+            handler => {
+                // the handler returns to the syscall restarted, or failed with EINTR
+                if let Some((id, args)) = restart.take() {
+                    if action.flags.contains(SignalActionFlags::RESTART) {
+                        tf.restart_syscall(id, &args);
+                    }
+                }
+                if action.flags.contains(SignalActionFlags::RESETHAND) {
+                    proc.sig_actions()[sig] = SignalAction::default();
+                }
+                let pid = proc.pid.get();
+                // choose the stack
+                let user_sp = tf.get_sp();
+                let stack = thread.sig_alt_stack;
+                let mut sp = if action.flags.contains(SignalActionFlags::ONSTACK)
+                    && stack.is_enabled()
+                    && !stack.contains(user_sp)
+                {
+                    stack.sp + stack.size
+                } else {
+                    user_sp
+                };
+                // skip the red zone of x86_64 ABI
+                #[cfg(target_arch = "x86_64")]
+                {
+                    sp = sp.wrapping_sub(128);
+                }
+                sp = sp.wrapping_sub(size_of::<SignalFrame>()) & !0xf;
+                let frame = sp as *mut SignalFrame;
+                // some arch pushes return address below the frame
+                let check = proc.vm().check_write_array(
+                    sp.wrapping_sub(16) as *mut u8,
+                    size_of::<SignalFrame>() + 16,
+                );
+                if check.is_err() {
+                    error!("signal: bad user stack {:#x} for signal {}", sp, sig);
+                    drop(proc);
+                    let dumped = dump_core();
+                    crate::syscall::exit_group(WaitStatus::Signaled(SIGSEGV, dumped));
+                }
+                drop(proc);
+
+                let mut ucontext_stack = stack;
+                if stack.contains(user_sp) {
+                    ucontext_stack.flags |= SS_ONSTACK;
+                }
+                unsafe {
+                    frame.write(SignalFrame {
+                        #[cfg(target_arch = "mips")]
+                        _arg_save: [0; 4],
+                        info,
+                        ucontext: SignalUserContext {
+                            flags: 0,
+                            link: 0,
+                            stack: ucontext_stack,
+                            context: tf.clone(),
+                            // the handler returns to the saved mask, if any
+                            sig_mask: thread.saved_sig_mask.take().unwrap_or(thread.sig_mask),
+                        },
+                    });
+                }
+
+                // block signals while executing the handler
+                thread.sig_mask.add_set(action.mask);
+                if !action.flags.contains(SignalActionFlags::NODEFER) {
+                    thread.sig_mask.add(sig);
+                }
+
+                let ret_addr = match action.restorer {
+                    0 => SIGRETURN_TRAMPOLINE_ADDR,
+                    restorer => restorer,
+                };
+                let (info, ucontext) = unsafe {
+                    (
+                        &(*frame).info as *const _ as usize,
+                        &(*frame).ucontext as *const _ as usize,
+                    )
+                };
+                debug!(
+                    "signal: enter handler {:#x} for {} with sp {:#x}, from pid {}",
+                    handler, sig, sp, pid
+                );
+                tf.enter_signal_handler(handler, sp, ret_addr, [sig, info, ucontext]);
+                return;
+            }
+        }
+    }
+}
+
+/// Restore the context saved in the signal frame at the user stack.
+///
+/// Return false if the frame is invalid.
+pub fn restore_signal_frame(tf: &mut TrapFrame) -> bool {
+    let thread = current_thread();
+    let frame = tf.get_sp() as *const SignalFrame;
+    if thread.proc.lock().vm().check_read_ptr(frame).is_err() {
+        return false;
+    }
+    let ucontext = unsafe { &(*frame).ucontext };
+    thread.sig_mask = ucontext.sig_mask.without(Sigset::unblockable());
+    tf.restore_from_signal(&ucontext.context);
+    true
+}
@@ -3,10 +3,13 @@ use crate::thread;
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 #[derive(Default)]
 pub struct Condvar {
     wait_queue: SpinNoIrqLock<VecDeque<Arc<thread::Thread>>>,
+    /// number of notifications so far
+    events: AtomicUsize,
 }
 
 impl Condvar {
@@ -26,6 +29,13 @@ impl Condvar {
     }
 
     pub fn wait_any(condvars: &[&Condvar]) {
+        Condvar::wait_any_unless(condvars, || false);
+    }
+
+    /// Wait for any of `condvars` to be notified, unless `done` returns true.
+    /// It is checked after the current thread is in the wait queues,
+    /// so a notification making it true is never missed.
+    pub fn wait_any_unless(condvars: &[&Condvar], done: impl FnOnce() -> bool) {
         let token = Arc::new(thread::current());
         // Avoid racing in the same way as the function above
         let mut locks = Vec::new();
@@ -35,6 +45,12 @@ impl Condvar {
             lock.push_back(token.clone());
             locks.push(lock);
         }
+        if done() {
+            for lock in locks.iter_mut() {
+                lock.retain(|t| !Arc::ptr_eq(t, &token));
+            }
+            return;
+        }
         thread::park_action(move || {
             drop(locks);
         });
@@ -51,17 +67,29 @@ impl Condvar {
         S: MutexSupport,
     {
         let mutex = guard.mutex;
-        drop(guard);
-        self._wait();
+        // release the guard after enqueued, so no notification is missed in between
+        let lock = self.add_to_wait_queue();
+        thread::park_action(move || {
+            drop(lock);
+            drop(guard);
+        });
         mutex.lock()
     }
 
+    /// Number of notifications so far, which tells whether it is notified
+    /// since an earlier reading, even with no waiter
+    pub fn events(&self) -> usize {
+        self.events.load(Ordering::SeqCst)
+    }
+
     pub fn notify_one(&self) {
+        self.events.fetch_add(1, Ordering::SeqCst);
         if let Some(t) = self.wait_queue.lock().pop_front() {
             t.unpark();
         }
     }
     pub fn notify_all(&self) {
+        self.events.fetch_add(1, Ordering::SeqCst);
         while let Some(t) = self.wait_queue.lock().pop_front() {
             t.unpark();
         }
@@ -69,6 +97,7 @@ impl Condvar {
     /// Notify up to `n` waiters.
     /// Return the number of waiters that were woken up.
     pub fn notify_n(&self, n: usize) -> usize {
+        self.events.fetch_add(1, Ordering::SeqCst);
         let mut count = 0;
         while count < n {
             if let Some(t) = self.wait_queue.lock().pop_front() {
@@ -17,6 +17,9 @@
 //!     完全照搬`std::sync::Semaphore`，std中已经废弃。
 //!     貌似在Rust中并不常用，一般都用`Mutex`。
 //!
+//! * `rwlock`: 禁用中断读写锁。
+//!     用于也会在中断处理中获取的读写锁。
+//!
 //! * `mpsc`: 消息传递通道。
 //!     多生产者-单消费者的FIFO队列。用于在线程间传递数据。
 //!
@@ -52,10 +55,12 @@
 
 pub use self::condvar::*;
 pub use self::mutex::*;
+pub use self::rwlock::*;
 pub use self::semaphore::*;
 
 mod condvar;
 pub mod mpsc;
 mod mutex;
+mod rwlock;
 mod semaphore;
 pub mod test;
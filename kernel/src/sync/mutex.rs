@@ -135,6 +135,14 @@ impl<T: ?Sized, S: MutexSupport> Mutex<T, S> {
         self.lock.store(false, Ordering::Release);
     }
 
+    /// Access the data without locking.
+    ///
+    /// This is *extremely* unsafe unless the lock is held by the current thread,
+    /// which is not accessing the data by its guard meanwhile.
+    pub unsafe fn get_unchecked(&self) -> &mut T {
+        &mut *self.data.get()
+    }
+
     /// Tries to lock the mutex. If it is already locked, it will return None. Otherwise it returns
     /// a guard within Some.
     pub fn try_lock(&self) -> Option<MutexGuard<T, S>> {
@@ -169,6 +177,19 @@ impl<T: ?Sized + Default, S: MutexSupport> Default for Mutex<T, S> {
     }
 }
 
+impl<'a, T: ?Sized, S: MutexSupport> MutexGuard<'a, T, S> {
+    /// Unlock the mutex while calling `f`, and lock it again after.
+    ///
+    /// Used when `f` may lock it itself, e.g. reading procfs of the current process.
+    pub fn unlocked<R>(guard: &mut Self, f: impl FnOnce() -> R) -> R {
+        let mutex = guard.mutex;
+        unsafe { core::ptr::drop_in_place(guard) };
+        let ret = f();
+        unsafe { core::ptr::write(guard, mutex.lock()) };
+        ret
+    }
+}
+
 impl<'a, T: ?Sized, S: MutexSupport> Deref for MutexGuard<'a, T, S> {
     type Target = T;
     fn deref(&self) -> &T {
@@ -0,0 +1,67 @@
+//! Reader-writer lock disabling interrupts while held
+
+use super::FlagsGuard;
+use core::ops::{Deref, DerefMut};
+use spin::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Reader-writer spin lock, which disables interrupts while held as `SpinNoIrqLock`,
+/// so it can be taken in interrupt handlers without deadlock.
+pub struct RwLockNoIrq<T> {
+    lock: RwLock<T>,
+}
+
+/// Guard of a shared read access, releasing the lock then restoring interrupts
+pub struct RwLockNoIrqReadGuard<'a, T: 'a> {
+    guard: RwLockReadGuard<'a, T>,
+    _flags: FlagsGuard,
+}
+
+/// Guard of an exclusive write access, releasing the lock then restoring interrupts
+pub struct RwLockNoIrqWriteGuard<'a, T: 'a> {
+    guard: RwLockWriteGuard<'a, T>,
+    _flags: FlagsGuard,
+}
+
+impl<T> RwLockNoIrq<T> {
+    pub fn new(data: T) -> Self {
+        RwLockNoIrq {
+            lock: RwLock::new(data),
+        }
+    }
+
+    pub fn read(&self) -> RwLockNoIrqReadGuard<T> {
+        let flags = FlagsGuard::no_irq_region();
+        RwLockNoIrqReadGuard {
+            guard: self.lock.read(),
+            _flags: flags,
+        }
+    }
+
+    pub fn write(&self) -> RwLockNoIrqWriteGuard<T> {
+        let flags = FlagsGuard::no_irq_region();
+        RwLockNoIrqWriteGuard {
+            guard: self.lock.write(),
+            _flags: flags,
+        }
+    }
+}
+
+impl<'a, T> Deref for RwLockNoIrqReadGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<'a, T> Deref for RwLockNoIrqWriteGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<'a, T> DerefMut for RwLockNoIrqWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
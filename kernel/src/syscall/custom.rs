@@ -22,9 +22,9 @@ pub fn sys_map_pci_device(vendor: usize, product: usize) -> SysResult {
     let (base, len) = pci::get_bar0_mem(tag).ok_or(SysError::ENOENT)?;
 
     let mut proc = process();
-    let virt_addr = proc.vm.find_free_area(0, len);
+    let virt_addr = proc.vm().find_free_area(0, len).ok_or(SysError::ENOMEM)?;
     let attr = MemoryAttr::default().user();
-    proc.vm.push(
+    proc.vm().push(
         virt_addr,
         virt_addr + len,
         attr,
@@ -43,12 +43,12 @@ pub fn sys_map_pci_device(vendor: usize, product: usize) -> SysResult {
 /// mapped to a list of virtual addresses.
 pub fn sys_get_paddr(vaddrs: *const u64, paddrs: *mut u64, count: usize) -> SysResult {
     let mut proc = process();
-    proc.vm.check_read_array(vaddrs, count)?;
-    proc.vm.check_write_array(paddrs, count)?;
+    proc.vm().check_read_array(vaddrs, count)?;
+    proc.vm().check_write_array(paddrs, count)?;
     let vaddrs = unsafe { slice::from_raw_parts(vaddrs, count) };
     let paddrs = unsafe { slice::from_raw_parts_mut(paddrs, count) };
     for i in 0..count {
-        let paddr = proc.vm.translate(vaddrs[i] as usize).unwrap_or(0);
+        let paddr = proc.vm().translate(vaddrs[i] as usize).unwrap_or(0);
         paddrs[i] = paddr as u64;
     }
     Ok(0)
@@ -1,55 +1,99 @@
 //! Syscalls for file system
 
-use core::cell::UnsafeCell;
 use core::cmp::min;
 use core::mem::size_of;
+use core::time::Duration;
 use rcore_fs::vfs::Timespec;
 
-use crate::drivers::SOCKET_ACTIVITY;
 use crate::fs::*;
 use crate::memory::MemorySet;
-use crate::sync::Condvar;
+use crate::sync::{Condvar, MutexGuard, SpinNoIrq, SpinNoIrqLock as Mutex};
+use crate::timer::Timeout;
 
 use bitvec::prelude::{BitSlice, BitVec, LittleEndian};
 
 use super::*;
 
 pub fn sys_read(fd: usize, base: *mut u8, len: usize) -> SysResult {
-    let mut proc = process();
+    // a background process group can not read its terminal
+    let tty = process().files().get_file_like(fd)?.tty();
+    if let Some(tty) = tty {
+        tty_check_read(&tty)?;
+    }
+    let proc = process();
     if !proc.pid.is_init() {
         // we trust pid 0 process
         info!("read: fd: {}, base: {:?}, len: {:#x}", fd, base, len);
     }
-    proc.vm.check_write_array(base, len)?;
+    proc.vm().check_write_array(base, len)?;
     let slice = unsafe { slice::from_raw_parts_mut(base, len) };
-    let file_like = proc.get_file_like(fd)?;
+    let mut files = proc.files();
+    let file_like = files.get_file_like(fd)?;
+    if let Some(mut file_like) = file_like.shared_clone() {
+        drop(files);
+        drop(proc);
+        let ret = file_like.read(slice);
+        if let FileLike::File(file) = &file_like {
+            store_offset(fd, file);
+        }
+        return ret;
+    }
     let len = file_like.read(slice)?;
     Ok(len)
 }
 
 pub fn sys_write(fd: usize, base: *const u8, len: usize) -> SysResult {
-    let mut proc = process();
+    // a background process group may not write its terminal, if TOSTOP
+    let tty = process().files().get_file_like(fd)?.tty();
+    if let Some(tty) = tty {
+        tty_check_write(&tty)?;
+    }
+    let proc = process();
     if !proc.pid.is_init() {
         // we trust pid 0 process
         info!("write: fd: {}, base: {:?}, len: {:#x}", fd, base, len);
     }
-    proc.vm.check_read_array(base, len)?;
+    proc.vm().check_read_array(base, len)?;
     let slice = unsafe { slice::from_raw_parts(base, len) };
-    let file_like = proc.get_file_like(fd)?;
+    let mut files = proc.files();
+    let file_like = files.get_file_like(fd)?;
+    if let Some(mut file_like) = file_like.shared_clone() {
+        drop(files);
+        drop(proc);
+        return raise_sigpipe(file_like.write(slice));
+    }
     let len = file_like.write(slice)?;
     Ok(len)
 }
 
+/// Raise SIGPIPE on the current thread if `ret` tells a broken pipe.
+/// The process lock must not be held.
+pub(super) fn raise_sigpipe(ret: SysResult) -> SysResult {
+    if let Err(SysError::EPIPE) = ret {
+        let proc = current_thread().proc.clone();
+        let tid = thread::current().id();
+        crate::signal::send_signal(&proc, Some(tid), crate::signal::SIGPIPE);
+    }
+    ret
+}
+
 pub fn sys_pread(fd: usize, base: *mut u8, len: usize, offset: usize) -> SysResult {
     info!(
         "pread: fd: {}, base: {:?}, len: {}, offset: {}",
         fd, base, len, offset
     );
-    let mut proc = process();
-    proc.vm.check_write_array(base, len)?;
+    let proc = process();
+    proc.vm().check_write_array(base, len)?;
 
     let slice = unsafe { slice::from_raw_parts_mut(base, len) };
-    let len = proc.get_file(fd)?.read_at(offset, slice)?;
+    let mut files = proc.files();
+    let file_like = files.get_file_like(fd)?;
+    if let Some(FileLike::File(mut file)) = file_like.shared_clone() {
+        drop(files);
+        drop(proc);
+        return Ok(file.read_at(offset, slice)?);
+    }
+    let len = files.get_file(fd)?.read_at(offset, slice)?;
     Ok(len)
 }
 
@@ -58,11 +102,11 @@ pub fn sys_pwrite(fd: usize, base: *const u8, len: usize, offset: usize) -> SysR
         "pwrite: fd: {}, base: {:?}, len: {}, offset: {}",
         fd, base, len, offset
     );
-    let mut proc = process();
-    proc.vm.check_read_array(base, len)?;
+    let proc = process();
+    proc.vm().check_read_array(base, len)?;
 
     let slice = unsafe { slice::from_raw_parts(base, len) };
-    let len = proc.get_file(fd)?.write_at(offset, slice)?;
+    let len = proc.files().get_file(fd)?.write_at(offset, slice)?;
     Ok(len)
 }
 
@@ -72,24 +116,31 @@ pub fn sys_poll(ufds: *mut PollFd, nfds: usize, timeout_msecs: usize) -> SysResu
         ufds, nfds, timeout_msecs
     );
     let proc = process();
-    proc.vm.check_write_array(ufds, nfds)?;
+    proc.vm().check_write_array(ufds, nfds)?;
 
     let polls = unsafe { slice::from_raw_parts_mut(ufds, nfds) };
     for poll in polls.iter() {
-        if proc.files.get(&(poll.fd as usize)).is_none() {
+        if proc.files().get(&(poll.fd as usize)).is_none() {
             return Err(SysError::EINVAL);
         }
     }
     drop(proc);
 
-    let begin_time_ms = crate::trap::uptime_msec();
+    // negative for infinity
+    let timeout = match timeout_msecs as i32 {
+        msecs if msecs >= 0 => Some(Timeout::after(Duration::from_millis(msecs as u64))),
+        _ => None,
+    };
     loop {
         use PollEvents as PE;
         let proc = process();
+        let files = proc.files();
         let mut events = 0;
+        let mut sources = Some(Vec::new());
         for poll in polls.iter_mut() {
             poll.revents = PE::empty();
-            if let Some(file_like) = proc.files.get(&(poll.fd as usize)) {
+            if let Some(file_like) = files.get(&(poll.fd as usize)) {
+                add_wakeup_sources(&mut sources, file_like);
                 let status = file_like.poll()?;
                 if status.error {
                     poll.revents |= PE::HUP;
@@ -108,18 +159,22 @@ pub fn sys_poll(ufds: *mut PollFd, nfds: usize, timeout_msecs: usize) -> SysResu
                 events += 1;
             }
         }
+        drop(files);
         drop(proc);
 
         if events > 0 {
             return Ok(events);
         }
 
-        let current_time_ms = crate::trap::uptime_msec();
-        if timeout_msecs < (1 << 31) && current_time_ms - begin_time_ms > timeout_msecs {
+        if timeout.as_ref().map_or(false, Timeout::is_expired) {
             return Ok(0);
         }
 
-        Condvar::wait_any(&[&STDIN.pushed, &(*SOCKET_ACTIVITY)]);
+        if crate::signal::has_signal_to_handle() {
+            return Err(SysError::EINTR);
+        }
+
+        wait_for_sources(&sources, timeout.as_ref());
     }
 }
 
@@ -136,26 +191,34 @@ pub fn sys_select(
     );
 
     let proc = process();
-    let mut read_fds = FdSet::new(&proc.vm, read, nfds)?;
-    let mut write_fds = FdSet::new(&proc.vm, write, nfds)?;
-    let mut err_fds = FdSet::new(&proc.vm, err, nfds)?;
-    let timeout_msecs = if timeout as usize != 0 {
-        proc.vm.check_read_ptr(timeout)?;
-        unsafe { *timeout }.to_msec()
+    let mut read_fds = FdSet::new(&proc.vm(), read, nfds)?;
+    let mut write_fds = FdSet::new(&proc.vm(), write, nfds)?;
+    let mut err_fds = FdSet::new(&proc.vm(), err, nfds)?;
+    let timeout = if timeout as usize != 0 {
+        proc.vm().check_read_ptr(timeout)?;
+        let time = unsafe { *timeout };
+        if !time.is_valid() {
+            return Err(SysError::EINVAL);
+        }
+        Some(Timeout::after(time.to_duration()))
     } else {
         // infinity
-        1 << 31
+        None
     };
     drop(proc);
 
-    let begin_time_ms = crate::trap::uptime_msec();
     loop {
         let proc = process();
+        let files = proc.files();
         let mut events = 0;
-        for (&fd, file_like) in proc.files.iter() {
+        let mut sources = Some(Vec::new());
+        for (&fd, file_like) in files.iter() {
             if fd >= nfds {
                 continue;
             }
+            if read_fds.contains(fd) || write_fds.contains(fd) || err_fds.contains(fd) {
+                add_wakeup_sources(&mut sources, file_like);
+            }
             let status = file_like.poll()?;
             if status.error && err_fds.contains(fd) {
                 err_fds.set(fd);
@@ -170,24 +233,223 @@ pub fn sys_select(
                 events += 1;
             }
         }
+        drop(files);
         drop(proc);
 
         if events > 0 {
             return Ok(events);
         }
 
-        if timeout_msecs == 0 {
-            // no timeout, return now;
+        if timeout.as_ref().map_or(false, Timeout::is_expired) {
             return Ok(0);
         }
 
-        let current_time_ms = crate::trap::uptime_msec();
-        // infinity check
-        if timeout_msecs < (1 << 31) && current_time_ms - begin_time_ms > timeout_msecs as usize {
+        if crate::signal::has_signal_to_handle() {
+            return Err(SysError::EINTR);
+        }
+
+        wait_for_sources(&sources, timeout.as_ref());
+    }
+}
+
+/// Add the wakeup sources of `file_like` to `sources`,
+/// which becomes `None` if it has none, i.e. it can only be polled
+fn add_wakeup_sources(sources: &mut Option<Vec<Arc<Condvar>>>, file_like: &FileLike) {
+    let new = file_like.wakeup_sources();
+    if new.is_empty() {
+        *sources = None;
+    } else if let Some(sources) = sources {
+        sources.extend(new);
+    }
+}
+
+/// Wait until one of the wakeup `sources` is notified, or `timeout` expires
+fn wait_for_sources(sources: &Option<Vec<Arc<Condvar>>>, timeout: Option<&Timeout>) {
+    let sources = match sources {
+        Some(sources) => sources,
+        None => {
+            // some file can not wake us up, check again later
+            thread::yield_now();
+            return;
+        }
+    };
+    let condvars: Vec<&Condvar> = sources.iter().map(|condvar| &**condvar).collect();
+    match timeout {
+        Some(timeout) => timeout.wait_any(&condvars),
+        // nothing to wait on, check again later
+        None if condvars.is_empty() => thread::yield_now(),
+        None => Condvar::wait_any(&condvars),
+    }
+}
+
+pub fn sys_epoll_create(size: usize) -> SysResult {
+    info!("epoll_create: size: {}", size);
+    if size as i32 <= 0 {
+        return Err(SysError::EINVAL);
+    }
+    sys_epoll_create1(0)
+}
+
+pub fn sys_epoll_create1(flags: usize) -> SysResult {
+    info!("epoll_create1: flags: {:#x}", flags);
+    if flags & !EPOLL_CLOEXEC != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let proc = process();
+    let fd = proc.get_free_fd()?;
+    let epoll = Arc::new(Mutex::new(EpollInstance::new()));
+    proc.files().insert(fd, FileLike::Epoll(epoll));
+    Ok(fd)
+}
+
+pub fn sys_epoll_ctl(epfd: usize, op: usize, fd: usize, event: *const EpollEvent) -> SysResult {
+    info!(
+        "epoll_ctl: epfd: {}, op: {}, fd: {}, event: {:?}",
+        epfd, op, fd, event
+    );
+    let proc = process();
+    let event = if op == EPOLL_CTL_DEL {
+        EpollEvent {
+            events: EpollFlags::empty(),
+            data: 0,
+        }
+    } else {
+        proc.vm().check_read_ptr(event)?;
+        unsafe { *event }
+    };
+    let mut files = proc.files();
+    match files.get(&fd) {
+        None => return Err(SysError::EBADF),
+        // nested epoll is not supported
+        Some(FileLike::Epoll(_)) => return Err(SysError::EINVAL),
+        Some(_) => {}
+    }
+    if fd == epfd {
+        return Err(SysError::EINVAL);
+    }
+    match files.get_file_like(epfd)? {
+        FileLike::Epoll(epoll) => epoll.lock().control(op, fd, event)?,
+        _ => return Err(SysError::EINVAL),
+    }
+    Ok(0)
+}
+
+pub fn sys_epoll_wait(
+    epfd: usize,
+    events: *mut EpollEvent,
+    maxevents: usize,
+    timeout_msecs: usize,
+) -> SysResult {
+    sys_epoll_pwait(
+        epfd,
+        events,
+        maxevents,
+        timeout_msecs,
+        0 as *const Sigset,
+        0,
+    )
+}
+
+pub fn sys_epoll_pwait(
+    epfd: usize,
+    events: *mut EpollEvent,
+    maxevents: usize,
+    timeout_msecs: usize,
+    sigmask: *const Sigset,
+    sigsetsize: usize,
+) -> SysResult {
+    info!(
+        "epoll_pwait: epfd: {}, events: {:?}, maxevents: {}, timeout_msecs: {}, sigmask: {:?}",
+        epfd, events, maxevents, timeout_msecs as i32, sigmask
+    );
+    if maxevents as i32 <= 0 {
+        return Err(SysError::EINVAL);
+    }
+    let mask = {
+        let proc = process();
+        proc.vm().check_write_array(events, maxevents)?;
+        match proc.files().get_file_like(epfd)? {
+            FileLike::Epoll(_) => {}
+            _ => return Err(SysError::EINVAL),
+        }
+        if sigmask.is_null() {
+            None
+        } else {
+            if sigsetsize != crate::signal::SIGSET_SIZE {
+                return Err(SysError::EINVAL);
+            }
+            proc.vm().check_read_ptr(sigmask)?;
+            Some(unsafe { sigmask.read() })
+        }
+    };
+
+    // replace the signal mask while waiting
+    let thread = current_thread();
+    let old_mask = thread.sig_mask;
+    if let Some(mask) = mask {
+        thread.sig_mask = mask.without(Sigset::unblockable());
+    }
+    let ret = epoll_wait(epfd, events, maxevents, timeout_msecs as i32);
+    match ret {
+        // the signal interrupting the wait is delivered with the temporary mask,
+        // then the old one is restored
+        Err(SysError::EINTR) if mask.is_some() => thread.saved_sig_mask = Some(old_mask),
+        _ => thread.sig_mask = old_mask,
+    }
+    ret
+}
+
+/// Wait for events of the epoll instance `epfd`, negative `timeout_msecs` means infinity
+fn epoll_wait(
+    epfd: usize,
+    events: *mut EpollEvent,
+    maxevents: usize,
+    timeout_msecs: i32,
+) -> SysResult {
+    let timeout = match timeout_msecs {
+        msecs if msecs >= 0 => Some(Timeout::after(Duration::from_millis(msecs as u64))),
+        _ => None,
+    };
+    loop {
+        let proc = process();
+        let mut files = proc.files();
+        let epoll = match files.get_file_like(epfd)? {
+            FileLike::Epoll(epoll) => epoll.clone(),
+            _ => return Err(SysError::EINVAL),
+        };
+        let fds = epoll.lock().interests();
+        let mut ready = Vec::new();
+        let mut sources = Some(Vec::new());
+        for fd in fds {
+            if let Some(file_like) = files.get(&fd) {
+                let seq = file_like.event_seq();
+                let flags = match file_like.poll() {
+                    Ok(status) => EpollFlags::from_status(&status),
+                    Err(_) => EpollFlags::ERR,
+                };
+                ready.push((fd, flags, seq));
+                add_wakeup_sources(&mut sources, file_like);
+            }
+        }
+        let result = epoll.lock().collect(&ready, maxevents);
+        drop(files);
+        drop(proc);
+
+        if !result.is_empty() || timeout_msecs == 0 {
+            let events = unsafe { slice::from_raw_parts_mut(events, result.len()) };
+            events.copy_from_slice(&result);
+            return Ok(result.len());
+        }
+
+        if timeout.as_ref().map_or(false, Timeout::is_expired) {
             return Ok(0);
         }
 
-        Condvar::wait_any(&[&STDIN.pushed, &(*SOCKET_ACTIVITY)]);
+        if crate::signal::has_signal_to_handle() {
+            return Err(SysError::EINTR);
+        }
+
+        wait_for_sources(&sources, timeout.as_ref());
     }
 }
 
@@ -196,13 +458,25 @@ pub fn sys_readv(fd: usize, iov_ptr: *const IoVec, iov_count: usize) -> SysResul
         "readv: fd: {}, iov: {:?}, count: {}",
         fd, iov_ptr, iov_count
     );
-    let mut proc = process();
-    let mut iovs = IoVecs::check_and_new(iov_ptr, iov_count, &proc.vm, true)?;
+    let proc = process();
+    let mut iovs = IoVecs::check_and_new(iov_ptr, iov_count, &proc.vm(), true)?;
 
     // read all data to a buf
-    let file_like = proc.get_file_like(fd)?;
+    let mut files = proc.files();
+    let file_like = files.get_file_like(fd)?;
     let mut buf = iovs.new_buf(true);
-    let len = file_like.read(buf.as_mut_slice())?;
+    let len = match file_like.shared_clone() {
+        Some(mut file_like) => {
+            drop(files);
+            drop(proc);
+            let ret = file_like.read(buf.as_mut_slice());
+            if let FileLike::File(file) = &file_like {
+                store_offset(fd, file);
+            }
+            ret?
+        }
+        None => file_like.read(buf.as_mut_slice())?,
+    };
     // copy data to user
     iovs.write_all_from_slice(&buf[..len]);
     Ok(len)
@@ -213,13 +487,19 @@ pub fn sys_writev(fd: usize, iov_ptr: *const IoVec, iov_count: usize) -> SysResu
         "writev: fd: {}, iov: {:?}, count: {}",
         fd, iov_ptr, iov_count
     );
-    let mut proc = process();
-    let iovs = IoVecs::check_and_new(iov_ptr, iov_count, &proc.vm, false)?;
+    let proc = process();
+    let iovs = IoVecs::check_and_new(iov_ptr, iov_count, &proc.vm(), false)?;
 
     let buf = iovs.read_all_to_vec();
     let len = buf.len();
 
-    let file_like = proc.get_file_like(fd)?;
+    let mut files = proc.files();
+    let file_like = files.get_file_like(fd)?;
+    if let Some(mut file_like) = file_like.shared_clone() {
+        drop(files);
+        drop(proc);
+        return raise_sigpipe(file_like.write(buf.as_slice()));
+    }
     let len = file_like.write(buf.as_slice())?;
     Ok(len)
 }
@@ -230,44 +510,79 @@ pub fn sys_open(path: *const u8, flags: usize, mode: usize) -> SysResult {
 
 pub fn sys_openat(dir_fd: usize, path: *const u8, flags: usize, mode: usize) -> SysResult {
     let mut proc = process();
-    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
+    let path = unsafe { proc.vm().check_and_clone_cstr(path)? };
     let flags = OpenFlags::from_bits_truncate(flags);
     info!(
         "openat: dir_fd: {}, path: {:?}, flags: {:?}, mode: {:#o}",
         dir_fd as isize, path, flags, mode
     );
 
-    let inode = if flags.contains(OpenFlags::CREATE) {
+    let (inode, created) = if flags.contains(OpenFlags::CREATE) {
         let (dir_path, file_name) = split_path(&path);
         // relative to cwd
         let dir_inode = proc.lookup_inode_at(dir_fd, dir_path)?;
-        match dir_inode.find(file_name) {
+        match MutexGuard::unlocked(&mut proc, || dir_inode.find(file_name)) {
             Ok(file_inode) => {
                 if flags.contains(OpenFlags::EXCLUSIVE) {
                     return Err(SysError::EEXIST);
                 }
-                file_inode
+                (file_inode, false)
             }
             Err(FsError::EntryNotFound) => {
-                dir_inode.create(file_name, FileType::File, mode as u32)?
+                let inode = proc.create_at(&dir_inode, file_name, FileType::File, mode)?;
+                (inode, true)
             }
             Err(e) => return Err(SysError::from(e)),
         }
     } else {
-        proc.lookup_inode_at(dir_fd, &path)?
+        (proc.lookup_inode_at(dir_fd, &path)?, false)
     };
+    // a new file is opened as requested, whatever its mode is
+    if !created {
+        let metadata = inode.metadata()?;
+        if metadata.type_ == FileType::Dir && flags.writable() {
+            return Err(SysError::EISDIR);
+        }
+        let mut access = Access::empty();
+        if flags.readable() {
+            access |= Access::READ;
+        }
+        if flags.writable() || flags.contains(OpenFlags::TRUNCATE) {
+            access |= Access::WRITE;
+        }
+        proc.cred.check_access(&metadata, access)?;
+    }
+    let inode = open_device(inode, proc.ctty(), &proc.cred)?;
 
-    let fd = proc.get_free_fd();
+    let fd = proc.get_free_fd()?;
 
-    let file = FileHandle::new(inode, flags.to_options());
-    proc.files.insert(fd, FileLike::File(file));
+    let path = proc.absolute_path_at(dir_fd, &path);
+    let file = FileLike::File(FileHandle::new(inode, flags.to_options()).with_path(path));
+    let tty = file.tty();
+    proc.files().insert(fd, file);
+    drop(proc);
+    if let Some(tty) = tty {
+        if !flags.contains(OpenFlags::NOCTTY) {
+            tty_open(&tty);
+        }
+    }
     Ok(fd)
 }
 
 pub fn sys_close(fd: usize) -> SysResult {
     info!("close: fd: {:?}", fd);
-    let mut proc = process();
-    proc.files.remove(&fd).ok_or(SysError::EBADF)?;
+    let proc = process();
+    let mut files = proc.files();
+    let file_like = files.remove(&fd).ok_or(SysError::EBADF)?;
+    for file_like in files.values_mut() {
+        if let FileLike::Epoll(epoll) = file_like {
+            epoll.lock().remove(fd);
+        }
+    }
+    // closing a pseudo-terminal master may signal processes, including us
+    drop(files);
+    drop(proc);
+    drop(file_like);
     Ok(0)
 }
 
@@ -275,10 +590,11 @@ pub fn sys_access(path: *const u8, mode: usize) -> SysResult {
     sys_faccessat(AT_FDCWD, path, mode, 0)
 }
 
+/// Check whether the file at `path` is accessible as `mode`,
+/// by the real user and group ids unless `AT_EACCESS`
 pub fn sys_faccessat(dirfd: usize, path: *const u8, mode: usize, flags: usize) -> SysResult {
-    // TODO: check permissions based on uid/gid
-    let proc = process();
-    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
+    let mut proc = process();
+    let path = unsafe { proc.vm().check_and_clone_cstr(path)? };
     let flags = AtFlags::from_bits_truncate(flags);
     if !proc.pid.is_init() {
         // we trust pid 0 process
@@ -288,6 +604,16 @@ pub fn sys_faccessat(dirfd: usize, path: *const u8, mode: usize, flags: usize) -
         );
     }
     let inode = proc.lookup_inode_at(dirfd, &path)?;
+    // F_OK is 0, only checking the existence
+    let access = match mode {
+        mode if mode <= 0o7 => Access::from_bits_truncate(mode as u16),
+        _ => return Err(SysError::EINVAL),
+    };
+    let cred = match flags.contains(AtFlags::EACCESS) {
+        true => proc.cred.clone(),
+        false => proc.cred.real(),
+    };
+    cred.check_access(&inode.metadata()?, access)?;
     Ok(0)
 }
 
@@ -297,11 +623,12 @@ pub fn sys_getcwd(buf: *mut u8, len: usize) -> SysResult {
         // we trust pid 0 process
         info!("getcwd: buf: {:?}, len: {:#x}", buf, len);
     }
-    proc.vm.check_write_array(buf, len)?;
-    if proc.cwd.len() + 1 > len {
+    proc.vm().check_write_array(buf, len)?;
+    let cwd = proc.cwd();
+    if cwd.len() + 1 > len {
         return Err(SysError::ERANGE);
     }
-    unsafe { util::write_cstr(buf, &proc.cwd) }
+    unsafe { util::write_cstr(buf, &cwd) }
     Ok(buf as usize)
 }
 
@@ -312,9 +639,10 @@ pub fn sys_lstat(path: *const u8, stat_ptr: *mut Stat) -> SysResult {
 
 pub fn sys_fstat(fd: usize, stat_ptr: *mut Stat) -> SysResult {
     info!("fstat: fd: {}, stat_ptr: {:?}", fd, stat_ptr);
-    let mut proc = process();
-    proc.vm.check_write_ptr(stat_ptr)?;
-    let file = proc.get_file(fd)?;
+    let proc = process();
+    proc.vm().check_write_ptr(stat_ptr)?;
+    let mut files = proc.files();
+    let file = files.get_file(fd)?;
     let stat = Stat::from(file.metadata()?);
     unsafe {
         stat_ptr.write(stat);
@@ -323,9 +651,9 @@ pub fn sys_fstat(fd: usize, stat_ptr: *mut Stat) -> SysResult {
 }
 
 pub fn sys_fstatat(dirfd: usize, path: *const u8, stat_ptr: *mut Stat, flags: usize) -> SysResult {
-    let proc = process();
-    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
-    proc.vm.check_write_ptr(stat_ptr)?;
+    let mut proc = process();
+    let path = unsafe { proc.vm().check_and_clone_cstr(path)? };
+    proc.vm().check_write_ptr(stat_ptr)?;
     let flags = AtFlags::from_bits_truncate(flags);
     info!(
         "fstatat: dirfd: {}, path: {:?}, stat_ptr: {:?}, flags: {:?}",
@@ -349,14 +677,15 @@ pub fn sys_readlink(path: *const u8, base: *mut u8, len: usize) -> SysResult {
 }
 
 pub fn sys_readlinkat(dirfd: usize, path: *const u8, base: *mut u8, len: usize) -> SysResult {
-    let proc = process();
-    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
-    proc.vm.check_write_array(base, len)?;
+    let mut proc = process();
+    let path = unsafe { proc.vm().check_and_clone_cstr(path)? };
+    proc.vm().check_write_array(base, len)?;
     info!("readlink: path: {:?}, base: {:?}, len: {}", path, base, len);
 
     let inode = proc.lookup_inode_at(dirfd, &path)?;
     if inode.metadata()?.type_ == FileType::SymLink {
         // TODO: recursive link resolution and loop detection
+        drop(proc);
         let mut slice = unsafe { slice::from_raw_parts_mut(base, len) };
         let len = inode.read_at(0, &mut slice)?;
         Ok(len)
@@ -374,35 +703,36 @@ pub fn sys_lseek(fd: usize, offset: i64, whence: u8) -> SysResult {
     };
     info!("lseek: fd: {}, pos: {:?}", fd, pos);
 
-    let mut proc = process();
-    let file = proc.get_file(fd)?;
-    let offset = file.seek(pos)?;
+    let proc = process();
+    let offset = proc.files().get_file(fd)?.seek(pos)?;
     Ok(offset as usize)
 }
 
 pub fn sys_fsync(fd: usize) -> SysResult {
     info!("fsync: fd: {}", fd);
-    process().get_file(fd)?.sync_all()?;
+    process().files().get_file(fd)?.sync_all()?;
     Ok(0)
 }
 
 pub fn sys_fdatasync(fd: usize) -> SysResult {
     info!("fdatasync: fd: {}", fd);
-    process().get_file(fd)?.sync_data()?;
+    process().files().get_file(fd)?.sync_data()?;
     Ok(0)
 }
 
 pub fn sys_truncate(path: *const u8, len: usize) -> SysResult {
-    let proc = process();
-    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
+    let mut proc = process();
+    let path = unsafe { proc.vm().check_and_clone_cstr(path)? };
     info!("truncate: path: {:?}, len: {}", path, len);
-    proc.lookup_inode(&path)?.resize(len)?;
+    let inode = proc.lookup_inode(&path)?;
+    proc.cred.check_access(&inode.metadata()?, Access::WRITE)?;
+    inode.resize(len)?;
     Ok(0)
 }
 
 pub fn sys_ftruncate(fd: usize, len: usize) -> SysResult {
     info!("ftruncate: fd: {}, len: {}", fd, len);
-    process().get_file(fd)?.set_len(len as u64)?;
+    process().files().get_file(fd)?.set_len(len as u64)?;
     Ok(0)
 }
 
@@ -411,9 +741,24 @@ pub fn sys_getdents64(fd: usize, buf: *mut LinuxDirent64, buf_size: usize) -> Sy
         "getdents64: fd: {}, ptr: {:?}, buf_size: {}",
         fd, buf, buf_size
     );
-    let mut proc = process();
-    proc.vm.check_write_array(buf as *mut u8, buf_size)?;
-    let file = proc.get_file(fd)?;
+    let proc = process();
+    proc.vm().check_write_array(buf as *mut u8, buf_size)?;
+    let mut files = proc.files();
+    let file_like = files.get_file_like(fd)?;
+    if let Some(FileLike::File(mut file)) = file_like.shared_clone() {
+        // e.g. /proc/<pid>/fd, listed by locking the process
+        drop(files);
+        drop(proc);
+        let ret = read_dirents(&mut file, buf, buf_size);
+        store_offset(fd, &file);
+        return ret;
+    }
+    let file = files.get_file(fd)?;
+    read_dirents(file, buf, buf_size)
+}
+
+/// Read entries of the directory `file` from its offset to `buf` of `buf_size` bytes
+fn read_dirents(file: &mut FileHandle, buf: *mut LinuxDirent64, buf_size: usize) -> SysResult {
     let info = file.metadata()?;
     if info.type_ != FileType::Dir {
         return Err(SysError::ENOTDIR);
@@ -435,12 +780,17 @@ pub fn sys_getdents64(fd: usize, buf: *mut LinuxDirent64, buf_size: usize) -> Sy
 
 pub fn sys_dup2(fd1: usize, fd2: usize) -> SysResult {
     info!("dup2: from {} to {}", fd1, fd2);
-    let mut proc = process();
-    // close fd2 first if it is opened
-    proc.files.remove(&fd2);
-
-    let file_like = proc.get_file_like(fd1)?.clone();
-    proc.files.insert(fd2, file_like);
+    let proc = process();
+    if fd2 >= proc.rlimits.cur(RLIMIT_NOFILE) {
+        return Err(SysError::EBADF);
+    }
+    let mut files = proc.files();
+    let file_like = files.get_file_like(fd1)?.clone();
+    // close fd2 if it is opened, after unlocking as `sys_close`
+    let closed = files.insert(fd2, file_like);
+    drop(files);
+    drop(proc);
+    drop(closed);
     Ok(fd2)
 }
 
@@ -449,14 +799,138 @@ pub fn sys_ioctl(fd: usize, request: usize, arg1: usize, arg2: usize, arg3: usiz
         "ioctl: fd: {}, request: {}, args: {} {} {}",
         fd, request, arg1, arg2, arg3
     );
-    let mut proc = process();
-    let file_like = proc.get_file_like(fd)?;
+    // the request is an unsigned int, which may be sign extended
+    let request = request as u32 as usize;
+    let (tty, master) = {
+        let proc = process();
+        let mut files = proc.files();
+        let file_like = files.get_file_like(fd)?;
+        (file_like.tty(), file_like.pty_master())
+    };
+    if let Some(tty) = tty {
+        return tty_ioctl(&tty, request, arg1);
+    }
+    if let Some(pty) = master {
+        return pty_master_ioctl(&pty, request, arg1);
+    }
+    let proc = process();
+    let mut files = proc.files();
+    let file_like = files.get_file_like(fd)?;
     file_like.ioctl(request, arg1, arg2, arg3)
 }
 
+/// Handle ioctl `request` of terminal `tty`
+fn tty_ioctl(tty: &Arc<Tty>, request: usize, arg: usize) -> SysResult {
+    use crate::fs::ioctl::*;
+    if let Some(ret) = tty_job_ioctl(tty, request, arg) {
+        return ret;
+    }
+    match request {
+        TCGETS => {
+            let ptr = arg as *mut Termios;
+            process().vm().check_write_ptr(ptr)?;
+            unsafe {
+                ptr.write(tty.termios());
+            }
+            Ok(0)
+        }
+        TCSETS | TCSETSW | TCSETSF => {
+            // output is written at once, so there is nothing to drain
+            let ptr = arg as *const Termios;
+            process().vm().check_read_ptr(ptr)?;
+            let termios = unsafe { ptr.read() };
+            tty.set_termios(termios, request == TCSETSF);
+            Ok(0)
+        }
+        TIOCGWINSZ => {
+            let ptr = arg as *mut WinSize;
+            process().vm().check_write_ptr(ptr)?;
+            unsafe {
+                ptr.write(tty.winsize());
+            }
+            Ok(0)
+        }
+        TIOCSWINSZ => {
+            let ptr = arg as *const WinSize;
+            process().vm().check_read_ptr(ptr)?;
+            let winsize = unsafe { ptr.read() };
+            if tty.set_winsize(winsize) {
+                if let Some(pgid) = tty.foreground() {
+                    crate::signal::send_signal_to_pgid(pgid, crate::signal::SIGWINCH);
+                }
+            }
+            Ok(0)
+        }
+        FIONREAD => {
+            let ptr = arg as *mut i32;
+            process().vm().check_write_ptr(ptr)?;
+            unsafe {
+                ptr.write(tty.input_len() as i32);
+            }
+            Ok(0)
+        }
+        TCFLSH => {
+            const TCIFLUSH: usize = 0;
+            const TCOFLUSH: usize = 1;
+            const TCIOFLUSH: usize = 2;
+            match arg {
+                TCIFLUSH | TCIOFLUSH => tty.flush_input(),
+                // output is written at once
+                TCOFLUSH => {}
+                _ => return Err(SysError::EINVAL),
+            }
+            Ok(0)
+        }
+        _ => Err(SysError::ENOTTY),
+    }
+}
+
+/// Handle ioctl `request` of the master of pseudo-terminal `pty`.
+/// The modes and the window size are the ones of the slave.
+fn pty_master_ioctl(pty: &Arc<Pty>, request: usize, arg: usize) -> SysResult {
+    use crate::fs::ioctl::*;
+    match request {
+        TIOCGPTN => {
+            let ptr = arg as *mut u32;
+            process().vm().check_write_ptr(ptr)?;
+            unsafe {
+                ptr.write(pty.index() as u32);
+            }
+            Ok(0)
+        }
+        TIOCSPTLCK => {
+            let ptr = arg as *const i32;
+            process().vm().check_read_ptr(ptr)?;
+            let locked = unsafe { ptr.read() } != 0;
+            pty.set_locked(locked);
+            Ok(0)
+        }
+        TIOCGPTLCK => {
+            let ptr = arg as *mut i32;
+            process().vm().check_write_ptr(ptr)?;
+            unsafe {
+                ptr.write(pty.is_locked() as i32);
+            }
+            Ok(0)
+        }
+        FIONREAD => {
+            let ptr = arg as *mut i32;
+            process().vm().check_write_ptr(ptr)?;
+            unsafe {
+                ptr.write(pty.output_len() as i32);
+            }
+            Ok(0)
+        }
+        TCGETS | TCSETS | TCSETSW | TCSETSF | TIOCGWINSZ | TIOCSWINSZ | TCFLSH => {
+            tty_ioctl(pty.tty(), request, arg)
+        }
+        _ => Err(SysError::ENOTTY),
+    }
+}
+
 pub fn sys_chdir(path: *const u8) -> SysResult {
     let mut proc = process();
-    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
+    let path = unsafe { proc.vm().check_and_clone_cstr(path)? };
     if !proc.pid.is_init() {
         // we trust pid 0 process
         info!("chdir: path: {:?}", path);
@@ -467,12 +941,13 @@ pub fn sys_chdir(path: *const u8) -> SysResult {
     if info.type_ != FileType::Dir {
         return Err(SysError::ENOTDIR);
     }
+    proc.cred.check_access(&info, Access::EXEC)?;
 
     // BUGFIX: '..' and '.'
     if path.len() > 0 {
         let cwd = match path.as_bytes()[0] {
             b'/' => String::from("/"),
-            _ => proc.cwd.clone()
+            _ => proc.cwd().clone()
         };
         let mut cwd_vec:Vec<_> =
                    cwd.split("/")
@@ -488,13 +963,14 @@ pub fn sys_chdir(path: *const u8) -> SysResult {
                 cwd_vec.push(seg);
             }
         }
-        proc.cwd = String::from("");
+        let mut cwd = proc.cwd();
+        *cwd = String::from("");
         for seg in cwd_vec {
-            proc.cwd.push_str("/");
-            proc.cwd.push_str(seg);
+            cwd.push_str("/");
+            cwd.push_str(seg);
         }
-        if proc.cwd == "" {
-            proc.cwd = String::from("/");
+        if *cwd == "" {
+            *cwd = String::from("/");
         }
     }
     Ok(0)
@@ -511,8 +987,8 @@ pub fn sys_renameat(
     newpath: *const u8,
 ) -> SysResult {
     let mut proc = process();
-    let oldpath = unsafe { proc.vm.check_and_clone_cstr(oldpath)? };
-    let newpath = unsafe { proc.vm.check_and_clone_cstr(newpath)? };
+    let oldpath = unsafe { proc.vm().check_and_clone_cstr(oldpath)? };
+    let newpath = unsafe { proc.vm().check_and_clone_cstr(newpath)? };
     info!(
         "renameat: olddirfd: {}, oldpath: {:?}, newdirfd: {}, newpath: {:?}",
         olddirfd, oldpath, newdirfd, newpath
@@ -522,6 +998,16 @@ pub fn sys_renameat(
     let (new_dir_path, new_file_name) = split_path(&newpath);
     let old_dir_inode = proc.lookup_inode_at(olddirfd, old_dir_path)?;
     let new_dir_inode = proc.lookup_inode_at(newdirfd, new_dir_path)?;
+    let old_inode = MutexGuard::unlocked(&mut proc, || old_dir_inode.find(old_file_name))?;
+    let new_dir_info = new_dir_inode.metadata()?;
+    proc.cred
+        .check_delete(&old_dir_inode.metadata()?, &old_inode.metadata()?)?;
+    proc.cred
+        .check_access(&new_dir_info, Access::WRITE | Access::EXEC)?;
+    if let Ok(replaced) = MutexGuard::unlocked(&mut proc, || new_dir_inode.find(new_file_name)) {
+        proc.cred
+            .check_delete(&new_dir_info, &replaced.metadata()?)?;
+    }
     old_dir_inode.move_(old_file_name, &new_dir_inode, new_file_name)?;
     Ok(0)
 }
@@ -531,8 +1017,8 @@ pub fn sys_mkdir(path: *const u8, mode: usize) -> SysResult {
 }
 
 pub fn sys_mkdirat(dirfd: usize, path: *const u8, mode: usize) -> SysResult {
-    let proc = process();
-    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
+    let mut proc = process();
+    let path = unsafe { proc.vm().check_and_clone_cstr(path)? };
     // TODO: check pathname
     info!(
         "mkdirat: dirfd: {}, path: {:?}, mode: {:#o}",
@@ -541,24 +1027,26 @@ pub fn sys_mkdirat(dirfd: usize, path: *const u8, mode: usize) -> SysResult {
 
     let (dir_path, file_name) = split_path(&path);
     let inode = proc.lookup_inode_at(dirfd, dir_path)?;
-    if inode.find(file_name).is_ok() {
+    if MutexGuard::unlocked(&mut proc, || inode.find(file_name)).is_ok() {
         return Err(SysError::EEXIST);
     }
-    inode.create(file_name, FileType::Dir, mode as u32)?;
+    proc.create_at(&inode, file_name, FileType::Dir, mode)?;
     Ok(0)
 }
 
 pub fn sys_rmdir(path: *const u8) -> SysResult {
-    let proc = process();
-    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
+    let mut proc = process();
+    let path = unsafe { proc.vm().check_and_clone_cstr(path)? };
     info!("rmdir: path: {:?}", path);
 
     let (dir_path, file_name) = split_path(&path);
     let dir_inode = proc.lookup_inode(dir_path)?;
-    let file_inode = dir_inode.find(file_name)?;
-    if file_inode.metadata()?.type_ != FileType::Dir {
+    let file_inode = MutexGuard::unlocked(&mut proc, || dir_inode.find(file_name))?;
+    let metadata = file_inode.metadata()?;
+    if metadata.type_ != FileType::Dir {
         return Err(SysError::ENOTDIR);
     }
+    proc.cred.check_delete(&dir_inode.metadata()?, &metadata)?;
     dir_inode.unlink(file_name)?;
     Ok(0)
 }
@@ -574,9 +1062,9 @@ pub fn sys_linkat(
     newpath: *const u8,
     flags: usize,
 ) -> SysResult {
-    let proc = process();
-    let oldpath = unsafe { proc.vm.check_and_clone_cstr(oldpath)? };
-    let newpath = unsafe { proc.vm.check_and_clone_cstr(newpath)? };
+    let mut proc = process();
+    let oldpath = unsafe { proc.vm().check_and_clone_cstr(oldpath)? };
+    let newpath = unsafe { proc.vm().check_and_clone_cstr(newpath)? };
     let flags = AtFlags::from_bits_truncate(flags);
     info!(
         "linkat: olddirfd: {}, oldpath: {:?}, newdirfd: {}, newpath: {:?}, flags: {:?}",
@@ -586,6 +1074,8 @@ pub fn sys_linkat(
     let (new_dir_path, new_file_name) = split_path(&newpath);
     let inode = proc.lookup_inode_at(olddirfd, &oldpath)?;
     let new_dir_inode = proc.lookup_inode_at(newdirfd, new_dir_path)?;
+    proc.cred
+        .check_access(&new_dir_inode.metadata()?, Access::WRITE | Access::EXEC)?;
     new_dir_inode.link(new_file_name, &inode)?;
     Ok(0)
 }
@@ -595,8 +1085,8 @@ pub fn sys_unlink(path: *const u8) -> SysResult {
 }
 
 pub fn sys_unlinkat(dirfd: usize, path: *const u8, flags: usize) -> SysResult {
-    let proc = process();
-    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
+    let mut proc = process();
+    let path = unsafe { proc.vm().check_and_clone_cstr(path)? };
     let flags = AtFlags::from_bits_truncate(flags);
     info!(
         "unlinkat: dirfd: {}, path: {:?}, flags: {:?}",
@@ -605,23 +1095,102 @@ pub fn sys_unlinkat(dirfd: usize, path: *const u8, flags: usize) -> SysResult {
 
     let (dir_path, file_name) = split_path(&path);
     let dir_inode = proc.lookup_inode_at(dirfd, dir_path)?;
-    let file_inode = dir_inode.find(file_name)?;
-    if file_inode.metadata()?.type_ == FileType::Dir {
+    let file_inode = MutexGuard::unlocked(&mut proc, || dir_inode.find(file_name))?;
+    let metadata = file_inode.metadata()?;
+    if metadata.type_ == FileType::Dir {
         return Err(SysError::EISDIR);
     }
+    proc.cred.check_delete(&dir_inode.metadata()?, &metadata)?;
     dir_inode.unlink(file_name)?;
     Ok(0)
 }
 
+pub fn sys_chmod(path: *const u8, mode: usize) -> SysResult {
+    sys_fchmodat(AT_FDCWD, path, mode)
+}
+
+pub fn sys_fchmodat(dirfd: usize, path: *const u8, mode: usize) -> SysResult {
+    let mut proc = process();
+    let path = unsafe { proc.vm().check_and_clone_cstr(path)? };
+    info!(
+        "fchmodat: dirfd: {}, path: {:?}, mode: {:#o}",
+        dirfd as isize, path, mode
+    );
+    let inode = proc.lookup_inode_at(dirfd, &path)?;
+    drop(proc);
+    chmod(&inode, mode)
+}
+
+pub fn sys_fchmod(fd: usize, mode: usize) -> SysResult {
+    info!("fchmod: fd: {}, mode: {:#o}", fd, mode);
+    let inode = process().files().get_file(fd)?.inode();
+    chmod(&inode, mode)
+}
+
+/// Change the mode of `inode`, by its owner
+fn chmod(inode: &Arc<INode>, mode: usize) -> SysResult {
+    let mut metadata = inode.metadata()?;
+    metadata.mode = process().cred.chmod(&metadata, mode as u16)?;
+    inode.set_metadata(&metadata)?;
+    Ok(0)
+}
+
+pub fn sys_chown(path: *const u8, uid: usize, gid: usize) -> SysResult {
+    sys_fchownat(AT_FDCWD, path, uid, gid, 0)
+}
+
+pub fn sys_lchown(path: *const u8, uid: usize, gid: usize) -> SysResult {
+    warn!("lchown is partial implemented as chown");
+    sys_fchownat(AT_FDCWD, path, uid, gid, 0)
+}
+
+/// Change the owner and group of a file, where -1 means unchanged.
+/// An empty `path` refers to `dirfd` itself.
+pub fn sys_fchownat(
+    dirfd: usize,
+    path: *const u8,
+    uid: usize,
+    gid: usize,
+    flags: usize,
+) -> SysResult {
+    let mut proc = process();
+    let path = unsafe { proc.vm().check_and_clone_cstr(path)? };
+    let flags = AtFlags::from_bits_truncate(flags);
+    info!(
+        "fchownat: dirfd: {}, path: {:?}, uid: {}, gid: {}, flags: {:?}",
+        dirfd as isize, path, uid as u32 as i32, gid as u32 as i32, flags
+    );
+    let inode = proc.lookup_inode_at(dirfd, &path)?;
+    drop(proc);
+    chown(&inode, uid, gid)
+}
+
+pub fn sys_fchown(fd: usize, uid: usize, gid: usize) -> SysResult {
+    info!(
+        "fchown: fd: {}, uid: {}, gid: {}",
+        fd, uid as u32 as i32, gid as u32 as i32
+    );
+    let inode = process().files().get_file(fd)?.inode();
+    chown(&inode, uid, gid)
+}
+
+/// Change the owner and group of `inode`
+fn chown(inode: &Arc<INode>, uid: usize, gid: usize) -> SysResult {
+    let metadata = inode.metadata()?;
+    let metadata = process().cred.chown(&metadata, uid, gid)?;
+    inode.set_metadata(&metadata)?;
+    Ok(0)
+}
+
 pub fn sys_pipe(fds: *mut u32) -> SysResult {
     info!("pipe: fds: {:?}", fds);
 
-    let mut proc = process();
-    proc.vm.check_write_array(fds, 2)?;
+    let proc = process();
+    proc.vm().check_write_array(fds, 2)?;
+    let (read_fd, write_fd) = proc.get_free_fd_pair()?;
     let (read, write) = Pipe::create_pair();
 
-    let read_fd = proc.get_free_fd();
-    proc.files.insert(
+    proc.files().insert(
         read_fd,
         FileLike::File(FileHandle::new(
             Arc::new(read),
@@ -633,8 +1202,7 @@ pub fn sys_pipe(fds: *mut u32) -> SysResult {
         )),
     );
 
-    let write_fd = proc.get_free_fd();
-    proc.files.insert(
+    proc.files().insert(
         write_fd,
         FileLike::File(FileHandle::new(
             Arc::new(write),
@@ -656,8 +1224,71 @@ pub fn sys_pipe(fds: *mut u32) -> SysResult {
     Ok(0)
 }
 
+pub fn sys_mount(
+    source: *const u8,
+    target: *const u8,
+    fstype: *const u8,
+    flags: usize,
+    data: *const u8,
+) -> SysResult {
+    let mut proc = process();
+    if !proc.cred.is_root() {
+        return Err(SysError::EPERM);
+    }
+    let source = if source.is_null() {
+        String::new()
+    } else {
+        unsafe { proc.vm().check_and_clone_cstr(source)? }
+    };
+    let target = unsafe { proc.vm().check_and_clone_cstr(target)? };
+    let fstype = if fstype.is_null() {
+        String::new()
+    } else {
+        unsafe { proc.vm().check_and_clone_cstr(fstype)? }
+    };
+    let flags = MountFlags::from_bits_truncate(flags);
+    info!(
+        "mount: source: {:?}, target: {:?}, fstype: {:?}, flags: {:?}, data: {:?}",
+        source, target, fstype, flags, data
+    );
+    if flags.contains(MountFlags::REMOUNT) {
+        warn!("mount: remount is unimplemented, ignored");
+        return Ok(0);
+    }
+    if flags.contains(MountFlags::BIND) {
+        warn!("mount: bind mount is unimplemented");
+        return Err(SysError::EINVAL);
+    }
+    let target_inode = proc.lookup_inode(&target)?;
+    // pseudo file systems ignore the source
+    let source_inode = proc.lookup_inode(&source).ok();
+    let path = match target.starts_with('/') {
+        true => target.clone(),
+        false => format!("{}/{}", proc.cwd().trim_end_matches('/'), target),
+    };
+    drop(proc);
+
+    let fs = create_fs(&fstype, source_inode)?;
+    mount(fs, target_inode, &fstype, &source, &path)?;
+    Ok(0)
+}
+
+pub fn sys_umount2(target: *const u8, flags: usize) -> SysResult {
+    let mut proc = process();
+    if !proc.cred.is_root() {
+        return Err(SysError::EPERM);
+    }
+    let target = unsafe { proc.vm().check_and_clone_cstr(target)? };
+    info!("umount2: target: {:?}, flags: {:#x}", target, flags);
+    let target_inode = proc.lookup_inode(&target)?;
+    drop(proc);
+
+    umount(&target_inode)?;
+    Ok(0)
+}
+
 pub fn sys_sync() -> SysResult {
-    ROOT_INODE.fs().sync()?;
+    sync_all()?;
     Ok(0)
 }
 
@@ -667,75 +1298,70 @@ pub fn sys_sendfile(out_fd: usize, in_fd: usize, offset: *mut usize, count: usiz
         out_fd, in_fd, offset, count
     );
     let proc = process();
-    // We know it's save, pacify the borrow checker
-    let proc_cell = UnsafeCell::new(proc);
-    let proc_in = unsafe { &mut *proc_cell.get() };
-    let proc_out = unsafe { &mut *proc_cell.get() };
-    //let in_file: &mut FileHandle = unsafe { &mut *UnsafeCell::new(proc.get_file(in_fd)?).get() };
-    //let out_file: &mut FileHandle = unsafe { &mut *UnsafeCell::new(proc.get_file(out_fd)?).get() };
-    let in_file = proc_in.get_file(in_fd)?;
-    let out_file = proc_out.get_file(out_fd)?;
+    if !offset.is_null() {
+        proc.vm().check_write_ptr(offset)?;
+    }
+    // copied without the locks, as either may be a pipe or in procfs
+    let mut files = proc.files();
+    let mut in_file = files.get_file(in_fd)?.clone();
+    let mut out_file = files.get_file(out_fd)?.clone();
+    drop(files);
+    drop(proc);
     let mut buffer = [0u8; 1024];
-    if offset.is_null() {
-        // read from current file offset
-        let mut bytes_read = 0;
+    // read from specified offset and write new offset back, if not null
+    let mut read_offset = match offset.is_null() {
+        true => None,
+        false => Some(unsafe { *offset }),
+    };
+    let mut bytes_read = 0;
+    let mut copy = || -> SysResult {
         while bytes_read < count {
             let len = min(buffer.len(), count - bytes_read);
-            let read_len = in_file.read(&mut buffer[..len])?;
-            if read_len == 0 {
-                break;
-            }
-            bytes_read += read_len;
-            let mut bytes_written = 0;
-            while bytes_written < read_len {
-                let write_len = out_file.write(&buffer[bytes_written..])?;
-                if write_len == 0 {
-                    return Err(SysError::EBADF);
+            let read_len = match read_offset.as_mut() {
+                Some(read_offset) => {
+                    let read_len = in_file.read_at(*read_offset, &mut buffer[..len])?;
+                    *read_offset += read_len;
+                    read_len
                 }
-                bytes_written += write_len;
-            }
-        }
-        return Ok(bytes_read);
-    } else {
-        let proc_mem = unsafe { &mut *proc_cell.get() };
-        proc_mem.vm.check_read_ptr(offset)?;
-        let mut read_offset = unsafe { *offset };
-        // read from specified offset and write new offset back
-        let mut bytes_read = 0;
-        while bytes_read < count {
-            let len = min(buffer.len(), count - bytes_read);
-            let read_len = in_file.read_at(read_offset, &mut buffer[..len])?;
+                None => in_file.read(&mut buffer[..len])?,
+            };
             if read_len == 0 {
                 break;
             }
             bytes_read += read_len;
-            read_offset += read_len;
             let mut bytes_written = 0;
             while bytes_written < read_len {
-                let write_len = out_file.write(&buffer[bytes_written..])?;
+                let write_len = out_file.write(&buffer[bytes_written..read_len])?;
                 if write_len == 0 {
                     return Err(SysError::EBADF);
                 }
                 bytes_written += write_len;
             }
         }
+        Ok(bytes_read)
+    };
+    let ret = copy();
+    store_offset(in_fd, &in_file);
+    store_offset(out_fd, &out_file);
+    if let Some(read_offset) = read_offset {
         unsafe {
             *offset = read_offset;
         }
-        return Ok(bytes_read);
     }
+    ret
 }
 
-impl Process {
-    pub fn get_file_like(&mut self, fd: usize) -> Result<&mut FileLike, SysError> {
-        self.files.get_mut(&fd).ok_or(SysError::EBADF)
-    }
-    pub fn get_file(&mut self, fd: usize) -> Result<&mut FileHandle, SysError> {
-        match self.get_file_like(fd)? {
-            FileLike::File(file) => Ok(file),
-            _ => Err(SysError::EBADF),
+/// Store the offset of `clone`, a clone of the file `fd` used without
+/// the locks, back to the file if it is still open.
+fn store_offset(fd: usize, clone: &FileHandle) {
+    if let Ok(file) = process().files().get_file(fd) {
+        if Arc::ptr_eq(&file.inode(), &clone.inode()) {
+            file.seek(SeekFrom::Start(clone.offset())).ok();
         }
     }
+}
+
+impl MutexGuard<'static, Process, SpinNoIrq> {
     /// Lookup INode from the process.
     ///
     /// - If `path` is relative, then it is interpreted relative to the directory
@@ -747,8 +1373,10 @@ impl Process {
     /// - If `path` is absolute, then `dirfd` is ignored.
     ///
     /// - If `follow` is true, then dereference `path` if it is a symbolic link.
+    ///
+    /// The process is unlocked while looking up, which may read procfs of itself.
     pub fn lookup_inode_at(
-        &self,
+        &mut self,
         dirfd: usize,
         path: &str,
         //        follow: bool,
@@ -756,29 +1384,103 @@ impl Process {
         let follow = true;
         debug!(
             "lookup_inode_at: fd: {:?}, cwd: {:?}, path: {:?}, follow: {:?}",
-            dirfd, self.cwd, path, follow
+            dirfd,
+            *self.cwd(),
+            path,
+            follow
         );
         let follow_max_depth = if follow { FOLLOW_MAX_DEPTH } else { 0 };
-        if dirfd == AT_FDCWD {
-            Ok(ROOT_INODE
-                .lookup(&self.cwd)?
-                .lookup_follow(path, follow_max_depth)?)
+        let dir = if dirfd == AT_FDCWD {
+            None
         } else {
-            let file = match self.files.get(&dirfd).ok_or(SysError::EBADF)? {
-                FileLike::File(file) => file,
+            match self.files().get(&dirfd).ok_or(SysError::EBADF)? {
+                FileLike::File(file) => Some(file.inode()),
                 _ => return Err(SysError::EBADF),
+            }
+        };
+        let cwd = self.cwd().clone();
+        let cred = self.cred.clone();
+        MutexGuard::unlocked(self, || {
+            let dir = match dir {
+                Some(dir) => dir,
+                // the directories above the cwd need not be searchable, as in chdir
+                None => lookup_at(&root_inode(), &cwd, 0, None)?,
             };
-            Ok(file.lookup_follow(path, follow_max_depth)?)
-        }
+            lookup_at(&dir, path, follow_max_depth, Some(&cred))
+        })
     }
 
-    pub fn lookup_inode(&self, path: &str) -> Result<Arc<INode>, SysError> {
+    pub fn lookup_inode(&mut self, path: &str) -> Result<Arc<INode>, SysError> {
         self.lookup_inode_at(AT_FDCWD, path)
     }
 }
 
+impl Process {
+    /// Create `name` of `type_` in directory `dir`, which must be writable,
+    /// with `mode` masked by the umask, and owned by the process.
+    pub fn create_at(
+        &self,
+        dir: &Arc<INode>,
+        name: &str,
+        type_: FileType,
+        mode: usize,
+    ) -> Result<Arc<INode>, SysError> {
+        self.cred
+            .check_access(&dir.metadata()?, Access::WRITE | Access::EXEC)?;
+        let mode = mode & 0o7777 & !self.umask;
+        let inode = dir.create(name, type_, mode as u32)?;
+        // file systems without owners ignore it
+        if let Ok(mut metadata) = inode.metadata() {
+            metadata.uid = self.cred.euid as usize;
+            metadata.gid = self.cred.egid as usize;
+            inode.set_metadata(&metadata).ok();
+        }
+        Ok(inode)
+    }
+
+    /// Get the absolute path of `path`, resolved as in `lookup_inode_at`
+    /// but without following symlinks.
+    ///
+    /// Return an empty string if the path of `dirfd` is unknown.
+    pub fn absolute_path_at(&self, dirfd: usize, path: &str) -> String {
+        let dir = if path.starts_with('/') {
+            String::from("/")
+        } else if dirfd == AT_FDCWD {
+            self.cwd().clone()
+        } else {
+            match self.files().get(&dirfd) {
+                Some(FileLike::File(file)) if file.path() != "" => String::from(file.path()),
+                _ => return String::new(),
+            }
+        };
+        let mut segs: Vec<&str> = dir.split('/').filter(|&x| x != "").collect();
+        for seg in path.split('/') {
+            match seg {
+                "" | "." => {}
+                ".." => {
+                    segs.pop();
+                }
+                _ => segs.push(seg),
+            }
+        }
+        let mut result = String::new();
+        for seg in segs {
+            result.push('/');
+            result.push_str(seg);
+        }
+        if result == "" {
+            result.push('/');
+        }
+        result
+    }
+
+    pub fn absolute_path(&self, path: &str) -> String {
+        self.absolute_path_at(AT_FDCWD, path)
+    }
+}
+
 /// Split a `path` str to `(base_path, file_name)`
-fn split_path(path: &str) -> (&str, &str) {
+pub fn split_path(path: &str) -> (&str, &str) {
     let mut split = path.trim_end_matches('/').rsplitn(2, '/');
     let file_name = split.next().unwrap();
     let mut dir_path = split.next().unwrap_or(".");
@@ -812,6 +1514,8 @@ bitflags! {
     struct AtFlags: usize {
         const EMPTY_PATH = 0x1000;
         const SYMLINK_NOFOLLOW = 0x100;
+        /// check access by the effective ids, for `faccessat`
+        const EACCESS = 0x200;
     }
 }
 
@@ -827,6 +1531,8 @@ bitflags! {
         const CREATE = 1 << 6;
         /// error if CREATE and the file exists
         const EXCLUSIVE = 1 << 7;
+        /// do not make a terminal the controlling terminal
+        const NOCTTY = 1 << 8;
         /// truncate file upon open
         const TRUNCATE = 1 << 9;
         /// append on each write
@@ -852,6 +1558,23 @@ impl OpenFlags {
     }
 }
 
+bitflags! {
+    struct MountFlags: usize {
+        /// mount read-only
+        const RDONLY = 1;
+        /// ignore suid and sgid bits
+        const NOSUID = 2;
+        /// disallow access to device special files
+        const NODEV = 4;
+        /// disallow program execution
+        const NOEXEC = 8;
+        /// alter flags of a mounted file system
+        const REMOUNT = 32;
+        /// create a bind mount
+        const BIND = 4096;
+    }
+}
+
 #[derive(Debug)]
 #[repr(packed)] // Don't use 'C'. Or its size will align up to 8 bytes.
 pub struct LinuxDirent64 {
@@ -1,10 +1,9 @@
-use rcore_memory::memory_set::handler::{ByFrame, Delay};
+use rcore_memory::memory_set::handler::{Delay, File, Shared};
 use rcore_memory::memory_set::MemoryAttr;
-use rcore_memory::paging::PageTable;
-use rcore_memory::Page;
 use rcore_memory::PAGE_SIZE;
 
-use crate::memory::GlobalFrameAlloc;
+use crate::fs::INodeForMap;
+use crate::memory::{GlobalFrameAlloc, MemorySet};
 
 use super::*;
 
@@ -23,7 +22,35 @@ pub fn sys_mmap(
         addr, len, prot, flags, fd, offset
     );
 
-    let mut proc = process();
+    if len == 0 || offset % PAGE_SIZE != 0 || end_of(addr, len).is_none() {
+        return Err(SysError::EINVAL);
+    }
+    if !flags.contains(MmapFlags::ANONYMOUS) && offset.checked_add(len).is_none() {
+        return Err(SysError::EOVERFLOW);
+    }
+    if flags.contains(MmapFlags::FIXED) && addr % PAGE_SIZE != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let proc = process();
+    // check the file before removing any old mapping
+    let file = match flags.contains(MmapFlags::ANONYMOUS) {
+        true => None,
+        false => {
+            let mut files = proc.files();
+            let file = files.get_file(fd)?;
+            if !file.options().read
+                || (flags.contains(MmapFlags::SHARED)
+                    && prot.contains(MmapProt::WRITE)
+                    && !file.options().write)
+            {
+                return Err(SysError::EACCES);
+            }
+            let inode = file.inode();
+            let size = inode.metadata()?.size;
+            Some((inode, size))
+        }
+    };
+
     if addr == 0 {
         // although NULL can be a valid address
         // but in C, NULL is regarded as allocation failure
@@ -31,46 +58,55 @@ pub fn sys_mmap(
         addr = PAGE_SIZE;
     }
 
+    let mut vm = proc.vm();
+    // a fixed mapping replaces the old ones
+    let replaced = match flags.contains(MmapFlags::FIXED) {
+        true => (addr, addr + len),
+        false => (0, 0),
+    };
+    if vm_size_without(&vm, replaced.0, replaced.1).saturating_add(len)
+        > proc.rlimits.cur(RLIMIT_AS)
+    {
+        return Err(SysError::ENOMEM);
+    }
     if flags.contains(MmapFlags::FIXED) {
         // we have to map it to addr, so remove the old mapping first
-        proc.vm.pop_with_split(addr, addr + len);
+        vm.pop_with_split(addr, addr + len);
     } else {
-        addr = proc.vm.find_free_area(addr, len);
+        addr = vm.find_free_area(addr, len).ok_or(SysError::ENOMEM)?;
     }
 
-    if flags.contains(MmapFlags::ANONYMOUS) {
-        if flags.contains(MmapFlags::SHARED) {
-            return Err(SysError::EINVAL);
+    match file {
+        None if flags.contains(MmapFlags::SHARED) => {
+            vm.push(
+                addr,
+                addr + len,
+                prot.to_attr(),
+                Shared::new(addr, GlobalFrameAlloc),
+                "mmap_anon_shared",
+            );
         }
-        proc.vm.push(
-            addr,
-            addr + len,
-            prot.to_attr(),
-            Delay::new(GlobalFrameAlloc),
-            "mmap_anon",
-        );
-        return Ok(addr);
-    } else {
-        // only check
-        let _ = proc.get_file(fd)?;
-
-        // TODO: delay mmap file
-        proc.vm.push(
-            addr,
-            addr + len,
-            prot.to_attr(),
-            ByFrame::new(GlobalFrameAlloc),
-            "mmap_file",
-        );
-        let data = unsafe { slice::from_raw_parts_mut(addr as *mut u8, len) };
-        let file = proc.get_file(fd)?;
-        let read_len = file.read_at(offset, data)?;
-        if read_len != data.len() {
-            // use count() to consume the iterator
-            data[read_len..].iter_mut().map(|x| *x = 0).count();
+        None => {
+            vm.push(
+                addr,
+                addr + len,
+                prot.to_attr(),
+                Delay::new(GlobalFrameAlloc),
+                "mmap_anon",
+            );
+        }
+        Some((inode, size)) => {
+            // pages beyond the end of file are filled with zero, and never written back
+            let file_end = (offset + len).min(size).max(offset);
+            let inode = INodeForMap(inode);
+            let handler = match flags.contains(MmapFlags::SHARED) {
+                true => File::new_shared(inode, addr, offset, file_end, GlobalFrameAlloc),
+                false => File::new(inode, addr, offset, file_end, GlobalFrameAlloc),
+            };
+            vm.push(addr, addr + len, prot.to_attr(), handler, "mmap_file");
         }
-        return Ok(addr);
     }
+    Ok(addr)
 }
 
 pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> SysResult {
@@ -80,36 +116,88 @@ pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> SysResult {
         addr, len, prot
     );
 
-    let mut proc = process();
-    let attr = prot.to_attr();
-
-    // FIXME: properly set the attribute of the area
-    //        now some mut ptr check is fault
-    let memory_area = proc
-        .vm
+    if addr % PAGE_SIZE != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let end = end_of(addr, len).ok_or(SysError::ENOMEM)?;
+    let proc = process();
+    let mut vm = proc.vm();
+    // e.g. the vDSO, whose pages are shared with the kernel
+    if vm
         .iter()
-        .find(|area| area.is_overlap_with(addr, addr + len));
-    if memory_area.is_none() {
-        return Err(SysError::ENOMEM);
+        .any(|area| area.attr().is_fixed() && area.is_overlap_with(addr, end))
+    {
+        return Err(SysError::EACCES);
     }
-    proc.vm.edit(|pt| {
-        for page in Page::range_of(addr, addr + len) {
-            let entry = pt
-                .get_entry(page.start_address())
-                .expect("failed to get entry");
-            attr.apply(entry);
-        }
-    });
+    vm.protect(addr, end, prot.to_attr())
+        .map_err(|_| SysError::ENOMEM)?;
     Ok(0)
 }
 
+pub fn sys_msync(addr: usize, len: usize, flags: usize) -> SysResult {
+    info!(
+        "msync: addr={:#x}, size={:#x}, flags={:#x}",
+        addr, len, flags
+    );
+    if addr % PAGE_SIZE != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let end = end_of(addr, len).ok_or(SysError::ENOMEM)?;
+    // MS_ASYNC and MS_SYNC both write back synchronously
+    let proc = process();
+    proc.vm().sync(addr, end);
+    Ok(0)
+}
+
+pub fn sys_brk(addr: usize) -> SysResult {
+    info!("brk: addr={:#x}", addr);
+    let mut proc = process();
+    if addr < proc.heap_start {
+        // query the current break
+        return Ok(proc.heap_end);
+    }
+    let (heap_start, heap_end) = (proc.heap_start, proc.heap_end);
+    // the heap is limited by RLIMIT_DATA, and the whole memory by RLIMIT_AS
+    let heap_size = addr - heap_start;
+    let vm_size = vm_size_without(&proc.vm(), heap_start, heap_end).saturating_add(heap_size);
+    if heap_size > proc.rlimits.cur(RLIMIT_DATA) || vm_size > proc.rlimits.cur(RLIMIT_AS) {
+        return Ok(heap_end);
+    }
+    if proc.vm().resize(heap_start, addr).is_err() {
+        // Linux returns the old break on failure
+        return Ok(proc.heap_end);
+    }
+    proc.heap_end = addr;
+    Ok(addr)
+}
+
 pub fn sys_munmap(addr: usize, len: usize) -> SysResult {
     info!("munmap addr={:#x}, size={:#x}", addr, len);
-    let mut proc = process();
-    proc.vm.pop_with_split(addr, addr + len);
+    let end = end_of(addr, len).ok_or(SysError::EINVAL)?;
+    let proc = process();
+    proc.vm().pop_with_split(addr, end);
     Ok(0)
 }
 
+/// End of [`addr`, `addr + len`), or `None` if it wraps around, even rounded up to pages
+fn end_of(addr: usize, len: usize) -> Option<usize> {
+    addr.checked_add(len)
+        .filter(|end| end.checked_add(PAGE_SIZE - 1).is_some())
+}
+
+/// Total size of the areas in `vm`, without the part in [`start`, `end`)
+fn vm_size_without(vm: &MemorySet, start: usize, end: usize) -> usize {
+    let overlap: usize = vm
+        .iter()
+        .map(|area| {
+            area.end_addr()
+                .min(end)
+                .saturating_sub(area.start_addr().max(start))
+        })
+        .sum();
+    vm.size() - overlap
+}
+
 bitflags! {
     pub struct MmapProt: usize {
         /// Data cannot be accessed
@@ -138,12 +226,17 @@ bitflags! {
 
 impl MmapProt {
     fn to_attr(self) -> MemoryAttr {
+        if self.is_empty() {
+            // PROT_NONE: only kernel can access, so any user access faults
+            return MemoryAttr::default().readonly();
+        }
         let mut attr = MemoryAttr::default().user();
         if self.contains(MmapProt::EXEC) {
             attr = attr.execute();
         }
-        // FIXME: see sys_mprotect
-        //        if !self.contains(MmapProt::WRITE) { attr = attr.readonly(); }
+        if !self.contains(MmapProt::WRITE) {
+            attr = attr.readonly();
+        }
         attr
     }
 }
@@ -1,8 +1,10 @@
 use super::*;
 use crate::arch::cpu;
-use crate::consts::USER_STACK_SIZE;
+use crate::memory::frame_stats;
+use crate::process::futex::{self, FutexKey};
 use core::mem::size_of;
 use core::sync::atomic::{AtomicI32, Ordering};
+use rcore_memory::PAGE_SIZE;
 
 pub fn sys_arch_prctl(code: i32, addr: usize, tf: &mut TrapFrame) -> SysResult {
     const ARCH_SET_FS: i32 = 0x1002;
@@ -23,7 +25,7 @@ pub fn sys_uname(buf: *mut u8) -> SysResult {
     let offset = 65;
     let strings = ["rCore", "orz", "0.1.0", "1", "machine", "domain"];
     let proc = process();
-    proc.vm.check_write_array(buf, strings.len() * offset)?;
+    proc.vm().check_write_array(buf, strings.len() * offset)?;
 
     for i in 0..strings.len() {
         unsafe {
@@ -39,7 +41,7 @@ pub fn sys_sched_getaffinity(pid: usize, size: usize, mask: *mut u32) -> SysResu
         pid, size, mask
     );
     let proc = process();
-    proc.vm.check_write_array(mask, size / size_of::<u32>())?;
+    proc.vm().check_write_array(mask, size / size_of::<u32>())?;
 
     // we only have 4 cpu at most.
     // so just set it.
@@ -49,58 +51,125 @@ pub fn sys_sched_getaffinity(pid: usize, size: usize, mask: *mut u32) -> SysResu
     Ok(0)
 }
 
+/// Get the statistics of the system, with memory sizes in pages
 pub fn sys_sysinfo(sys_info: *mut SysInfo) -> SysResult {
-    let proc = process();
-    proc.vm.check_write_ptr(sys_info)?;
+    const SI_LOAD_SHIFT: usize = 16;
+    process().vm().check_write_ptr(sys_info)?;
 
-    let sysinfo = SysInfo::default();
-    unsafe { *sys_info = sysinfo };
+    let (frames, frames_allocated) = frame_stats();
+    let procs = PROCESSES
+        .read()
+        .values()
+        .filter(|proc| proc.upgrade().is_some())
+        .count();
+    let sysinfo = SysInfo {
+        uptime: monotonic().as_secs() as usize,
+        loads: load_avg(SI_LOAD_SHIFT),
+        totalram: frames,
+        freeram: frames - frames_allocated,
+        procs: procs as u16,
+        mem_unit: PAGE_SIZE as u32,
+        ..SysInfo::default()
+    };
+    unsafe { sys_info.write(sysinfo) };
     Ok(0)
 }
 
-pub fn sys_futex(uaddr: usize, op: u32, val: i32, timeout: *const TimeSpec) -> SysResult {
+pub fn sys_futex(
+    uaddr: usize,
+    op: u32,
+    val: i32,
+    timeout: *const TimeSpec,
+    uaddr2: usize,
+    val3: u32,
+) -> SysResult {
     info!(
-        "futex: [{}] uaddr: {:#x}, op: {:#x}, val: {}, timeout_ptr: {:?}",
+        "futex: [{}] uaddr: {:#x}, op: {:#x}, val: {}, timeout_ptr: {:?}, uaddr2: {:#x}, val3: {:#x}",
         thread::current().id(),
         uaddr,
         op,
         val,
-        timeout
+        timeout,
+        uaddr2,
+        val3
     );
-    //    if op & OP_PRIVATE == 0 {
-    //        unimplemented!("futex only support process-private");
-    //        return Err(SysError::ENOSYS);
-    //    }
-    if uaddr % size_of::<u32>() != 0 {
-        return Err(SysError::EINVAL);
-    }
-    process().vm.check_write_ptr(uaddr as *mut AtomicI32)?;
-    let atomic = unsafe { &mut *(uaddr as *mut AtomicI32) };
-    let _timeout = if timeout.is_null() {
-        None
-    } else {
-        process().vm.check_read_ptr(timeout)?;
-        Some(unsafe { *timeout })
-    };
 
     const OP_WAIT: u32 = 0;
     const OP_WAKE: u32 = 1;
+    const OP_REQUEUE: u32 = 3;
+    const OP_CMP_REQUEUE: u32 = 4;
+    const OP_WAKE_OP: u32 = 5;
+    const OP_WAIT_BITSET: u32 = 9;
+    const OP_WAKE_BITSET: u32 = 10;
     const OP_PRIVATE: u32 = 128;
+    const OP_CLOCK_REALTIME: u32 = 256;
 
-    let queue = process().get_futex(uaddr);
+    let cmd = op & !(OP_PRIVATE | OP_CLOCK_REALTIME);
+    let private = op & OP_PRIVATE != 0;
+    let (key, atomic) = futex_key(uaddr, private, false)?;
+    // the argument is `val2` instead of the timeout for some operations
+    let val2 = timeout as usize;
 
-    match op & 0xf {
-        OP_WAIT => {
-            if atomic.load(Ordering::Acquire) != val {
-                return Err(SysError::EAGAIN);
+    match cmd {
+        OP_WAIT | OP_WAIT_BITSET => {
+            let bitset = match cmd {
+                OP_WAIT => futex::FUTEX_BITSET_MATCH_ANY,
+                _ => val3,
+            };
+            if bitset == 0 {
+                return Err(SysError::EINVAL);
             }
-            // FIXME: support timeout
-            queue._wait();
-            Ok(0)
+            let deadline = if timeout.is_null() {
+                None
+            } else {
+                process().vm().check_read_ptr(timeout)?;
+                let time = unsafe { *timeout };
+                if !time.is_valid() {
+                    return Err(SysError::EINVAL);
+                }
+                Some(match cmd {
+                    // relative for FUTEX_WAIT
+                    OP_WAIT => time.deadline(),
+                    // absolute for FUTEX_WAIT_BITSET
+                    _ => {
+                        let clock = match op & OP_CLOCK_REALTIME {
+                            0 => CLOCK_MONOTONIC,
+                            _ => CLOCK_REALTIME,
+                        };
+                        clock_to_tick(clock, time.to_duration())
+                    }
+                })
+            };
+            futex::wait(key, bitset, deadline, || {
+                atomic.load(Ordering::Acquire) == val
+            })
+        }
+        OP_WAKE | OP_WAKE_BITSET => {
+            let bitset = match cmd {
+                OP_WAKE => futex::FUTEX_BITSET_MATCH_ANY,
+                _ => val3,
+            };
+            if bitset == 0 {
+                return Err(SysError::EINVAL);
+            }
+            Ok(futex::wake(key, val as usize, bitset))
+        }
+        OP_REQUEUE | OP_CMP_REQUEUE => {
+            if val < 0 || (val2 as isize) < 0 {
+                return Err(SysError::EINVAL);
+            }
+            let (key2, _) = futex_key(uaddr2, private, false)?;
+            let cmp = cmd == OP_CMP_REQUEUE;
+            futex::requeue(key, key2, val as usize, val2, || {
+                !cmp || atomic.load(Ordering::Acquire) == val3 as i32
+            })
         }
-        OP_WAKE => {
-            let woken_up_count = queue.notify_n(val as usize);
-            Ok(woken_up_count)
+        OP_WAKE_OP => {
+            let (key2, atomic2) = futex_key(uaddr2, private, true)?;
+            futex::wake_op(key, key2, val as usize, val2, || {
+                let old = futex_atomic_op(atomic2, val3)?;
+                futex_op_cmp(old, val3)
+            })
         }
         _ => {
             warn!("unsupported futex operation: {}", op);
@@ -109,8 +178,83 @@ pub fn sys_futex(uaddr: usize, op: u32, val: i32, timeout: *const TimeSpec) -> S
     }
 }
 
+/// Check the futex at `uaddr`, which is readable, and also writable if `write`,
+/// return its key and the futex value
+fn futex_key(
+    uaddr: usize,
+    private: bool,
+    write: bool,
+) -> Result<(FutexKey, &'static AtomicI32), SysError> {
+    if uaddr % size_of::<u32>() != 0 {
+        return Err(SysError::EINVAL);
+    }
+    match write {
+        true => process().vm().check_write_ptr(uaddr as *mut AtomicI32)?,
+        false => process().vm().check_read_ptr(uaddr as *const AtomicI32)?,
+    }
+    let atomic = unsafe { &*(uaddr as *const AtomicI32) };
+    // map the page by reading it, before it is translated with the process locked
+    atomic.load(Ordering::Relaxed);
+    let key = futex::key(&mut process(), uaddr, private).ok_or(SysError::EFAULT)?;
+    Ok((key, atomic))
+}
+
+/// Modify `atomic` by the operation encoded in `val3` of `FUTEX_WAKE_OP`,
+/// return the old value
+fn futex_atomic_op(atomic: &AtomicI32, val3: u32) -> Result<i32, SysError> {
+    const FUTEX_OP_SET: u32 = 0;
+    const FUTEX_OP_ADD: u32 = 1;
+    const FUTEX_OP_OR: u32 = 2;
+    const FUTEX_OP_ANDN: u32 = 3;
+    const FUTEX_OP_XOR: u32 = 4;
+    const FUTEX_OP_ARG_SHIFT: u32 = 8;
+
+    let op = (val3 >> 28) & 0xf;
+    // 12 bits signed
+    let mut oparg = (((val3 >> 12) & 0xfff) as i32) << 20 >> 20;
+    if op & FUTEX_OP_ARG_SHIFT != 0 {
+        if oparg < 0 || oparg > 31 {
+            return Err(SysError::EINVAL);
+        }
+        oparg = 1 << oparg;
+    }
+    let old = match op & !FUTEX_OP_ARG_SHIFT {
+        FUTEX_OP_SET => atomic.swap(oparg, Ordering::SeqCst),
+        FUTEX_OP_ADD => atomic.fetch_add(oparg, Ordering::SeqCst),
+        FUTEX_OP_OR => atomic.fetch_or(oparg, Ordering::SeqCst),
+        FUTEX_OP_ANDN => atomic.fetch_and(!oparg, Ordering::SeqCst),
+        FUTEX_OP_XOR => atomic.fetch_xor(oparg, Ordering::SeqCst),
+        _ => return Err(SysError::ENOSYS),
+    };
+    Ok(old)
+}
+
+/// Compare the old value `old` by the comparison encoded in `val3` of `FUTEX_WAKE_OP`
+fn futex_op_cmp(old: i32, val3: u32) -> Result<bool, SysError> {
+    const FUTEX_OP_CMP_EQ: u32 = 0;
+    const FUTEX_OP_CMP_NE: u32 = 1;
+    const FUTEX_OP_CMP_LT: u32 = 2;
+    const FUTEX_OP_CMP_LE: u32 = 3;
+    const FUTEX_OP_CMP_GT: u32 = 4;
+    const FUTEX_OP_CMP_GE: u32 = 5;
+
+    let cmparg = ((val3 & 0xfff) as i32) << 20 >> 20;
+    match (val3 >> 24) & 0xf {
+        FUTEX_OP_CMP_EQ => Ok(old == cmparg),
+        FUTEX_OP_CMP_NE => Ok(old != cmparg),
+        FUTEX_OP_CMP_LT => Ok(old < cmparg),
+        FUTEX_OP_CMP_LE => Ok(old <= cmparg),
+        FUTEX_OP_CMP_GT => Ok(old > cmparg),
+        FUTEX_OP_CMP_GE => Ok(old >= cmparg),
+        _ => Err(SysError::ENOSYS),
+    }
+}
+
 const LINUX_REBOOT_CMD_HALT: u32 = 0xcdef0123;
 pub fn sys_reboot(_magic: u32, magic2: u32, cmd: u32, _arg: *const u8) -> SysResult {
+    if !process().cred.is_root() {
+        return Err(SysError::EPERM);
+    }
     // we will skip verifying magic
     if cmd == LINUX_REBOOT_CMD_HALT {
         unsafe {
@@ -123,81 +267,105 @@ pub fn sys_reboot(_magic: u32, magic2: u32, cmd: u32, _arg: *const u8) -> SysRes
 #[repr(C)]
 #[derive(Debug, Default)]
 pub struct SysInfo {
-    uptime: u64,
-    loads: [u64; 3],
-    totalram: u64,
-    freeram: u64,
-    sharedram: u64,
-    bufferram: u64,
-    totalswap: u64,
-    freeswap: u64,
+    uptime: usize,
+    loads: [usize; 3],
+    totalram: usize,
+    freeram: usize,
+    sharedram: usize,
+    bufferram: usize,
+    totalswap: usize,
+    freeswap: usize,
     procs: u16,
-    totalhigh: u64,
-    freehigh: u64,
+    totalhigh: usize,
+    freehigh: usize,
     mem_unit: u32,
 }
 
-const RLIMIT_STACK: usize = 3;
-const RLIMIT_RSS: usize = 5;
-const RLIMIT_NOFILE: usize = 7;
-const RLIMIT_AS: usize = 9;
-
+/// Get and set the resource limits of process `pid`, or the current one if 0.
+/// The limits of another process can be changed by the same user, or root.
 pub fn sys_prlimit64(
     pid: usize,
     resource: usize,
     new_limit: *const RLimit,
     old_limit: *mut RLimit,
 ) -> SysResult {
-    let proc = process();
     info!(
         "prlimit64: pid: {}, resource: {}, new_limit: {:x?}, old_limit: {:x?}",
         pid, resource, new_limit, old_limit
     );
-    match resource {
-        RLIMIT_STACK => {
-            if !old_limit.is_null() {
-                proc.vm.check_write_ptr(old_limit)?;
-                unsafe {
-                    *old_limit = RLimit {
-                        cur: USER_STACK_SIZE as u64,
-                        max: USER_STACK_SIZE as u64,
-                    };
-                }
-            }
-            Ok(0)
-        }
-        RLIMIT_NOFILE => {
-            if !old_limit.is_null() {
-                proc.vm.check_write_ptr(old_limit)?;
-                unsafe {
-                    *old_limit = RLimit {
-                        cur: 1024,
-                        max: 1024,
-                    };
-                }
-            }
-            Ok(0)
+    let new = match new_limit.is_null() {
+        true => None,
+        false => {
+            process().vm().check_read_ptr(new_limit)?;
+            let new = unsafe { *new_limit };
+            info!("prlimit64: new: {:x?}", new);
+            Some(new)
         }
-        RLIMIT_RSS | RLIMIT_AS => {
-            if !old_limit.is_null() {
-                proc.vm.check_write_ptr(old_limit)?;
-                unsafe {
-                    // 1GB
-                    *old_limit = RLimit {
-                        cur: 1024 * 1024 * 1024,
-                        max: 1024 * 1024 * 1024,
-                    };
-                }
-            }
-            Ok(0)
+    };
+    if !old_limit.is_null() {
+        process().vm().check_write_ptr(old_limit)?;
+    }
+    let old = prlimit(pid, resource, new)?;
+    if !old_limit.is_null() {
+        unsafe {
+            *old_limit = old;
         }
-        _ => Err(SysError::ENOSYS),
     }
+    Ok(0)
+}
+
+/// Get the limit of `resource` of the current process,
+/// where the limits are `unsigned long`
+pub fn sys_getrlimit(resource: usize, limit: *mut [usize; 2]) -> SysResult {
+    info!("getrlimit: resource: {}, limit: {:?}", resource, limit);
+    process().vm().check_write_ptr(limit)?;
+    let old = prlimit(0, resource, None)?;
+    let to_usize = |value: u64| match value {
+        RLIM_INFINITY => core::usize::MAX,
+        value => value.min(core::usize::MAX as u64) as usize,
+    };
+    unsafe {
+        *limit = [to_usize(old.cur), to_usize(old.max)];
+    }
+    Ok(0)
 }
 
-#[repr(C)]
-#[derive(Debug, Default)]
-pub struct RLimit {
-    cur: u64, // soft limit
-    max: u64, // hard limit
+/// Set the limit of `resource` of the current process,
+/// where the limits are `unsigned long`
+pub fn sys_setrlimit(resource: usize, limit: *const [usize; 2]) -> SysResult {
+    info!("setrlimit: resource: {}, limit: {:?}", resource, limit);
+    process().vm().check_read_ptr(limit)?;
+    let [cur, max] = unsafe { *limit };
+    let to_u64 = |value: usize| match value {
+        core::usize::MAX => RLIM_INFINITY,
+        value => value as u64,
+    };
+    let new = RLimit {
+        cur: to_u64(cur),
+        max: to_u64(max),
+    };
+    prlimit(0, resource, Some(new))?;
+    Ok(0)
+}
+
+/// Get the limit of `resource` of process `pid`, and set it to `new` if specified
+fn prlimit(pid: usize, resource: usize, new: Option<RLimit>) -> Result<RLimit, SysError> {
+    let cred = process().cred.clone();
+    let target = match pid {
+        0 => current_thread().proc.clone(),
+        pid => PROCESSES
+            .read()
+            .get(&pid)
+            .and_then(|weak| weak.upgrade())
+            .ok_or(SysError::ESRCH)?,
+    };
+    let mut target = target.lock();
+    if pid != 0 && !cred.may_control(&target.cred) {
+        return Err(SysError::EPERM);
+    }
+    let old = target.rlimits.get(resource)?;
+    if let Some(new) = new {
+        target.rlimits.set(resource, new, cred.is_root())?;
+    }
+    Ok(old)
 }
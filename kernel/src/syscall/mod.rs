@@ -10,7 +10,9 @@ use rcore_memory::VMError;
 use crate::arch::cpu;
 use crate::arch::interrupt::TrapFrame;
 use crate::arch::syscall::*;
+use crate::fs::EpollEvent;
 use crate::process::*;
+use crate::signal::{SigAction, SignalStack, Sigset};
 use crate::sync::Condvar;
 use crate::thread;
 use crate::util;
@@ -20,7 +22,10 @@ use self::fs::*;
 use self::mem::*;
 use self::misc::*;
 pub use self::net::*;
+pub use self::proc::exit_group;
 use self::proc::*;
+use self::signal::*;
+pub use self::time::{realtime_at, TimeSpec};
 use self::time::*;
 
 mod custom;
@@ -29,6 +34,7 @@ mod mem;
 mod misc;
 mod net;
 mod proc;
+mod signal;
 mod time;
 
 /// System call dispatcher
@@ -58,18 +64,20 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
         // 10
         SYS_MPROTECT => sys_mprotect(args[0], args[1], args[2]),
         SYS_MUNMAP => sys_munmap(args[0], args[1]),
-        SYS_BRK => {
-            warn!("sys_brk is unimplemented");
-            Ok(0)
-        }
-        SYS_RT_SIGACTION => {
-            warn!("sys_sigaction is unimplemented");
-            Ok(0)
-        }
-        SYS_RT_SIGPROCMASK => {
-            warn!("sys_sigprocmask is unimplemented");
-            Ok(0)
-        }
+        SYS_BRK => sys_brk(args[0]),
+        SYS_RT_SIGACTION => sys_rt_sigaction(
+            args[0],
+            args[1] as *const SigAction,
+            args[2] as *mut SigAction,
+            args[3],
+        ),
+        SYS_RT_SIGPROCMASK => sys_rt_sigprocmask(
+            args[0],
+            args[1] as *const Sigset,
+            args[2] as *mut Sigset,
+            args[3],
+        ),
+        SYS_RT_SIGRETURN => sys_rt_sigreturn(tf),
         SYS_IOCTL => sys_ioctl(args[0], args[1], args[2], args[3], args[4]),
         SYS_PREAD64 => sys_pread(args[0], args[1] as *mut u8, args[2], args[3]),
         SYS_PWRITE64 => sys_pwrite(args[0], args[1] as *const u8, args[2], args[3]),
@@ -77,15 +85,18 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
         // 20
         SYS_WRITEV => sys_writev(args[0], args[1] as *const IoVec, args[2]),
         SYS_SCHED_YIELD => sys_yield(),
+        SYS_MSYNC => sys_msync(args[0], args[1], args[2]),
         SYS_MADVISE => {
             warn!("sys_madvise is unimplemented");
             Ok(0)
         }
-        SYS_NANOSLEEP => sys_nanosleep(args[0] as *const TimeSpec),
-        SYS_SETITIMER => {
-            warn!("sys_setitimer is unimplemented");
-            Ok(0)
-        }
+        SYS_NANOSLEEP => sys_nanosleep(args[0] as *const TimeSpec, args[1] as *mut TimeSpec),
+        SYS_GETITIMER => sys_getitimer(args[0], args[1] as *mut ITimerVal),
+        SYS_SETITIMER => sys_setitimer(
+            args[0],
+            args[1] as *const ITimerVal,
+            args[2] as *mut ITimerVal,
+        ),
         SYS_GETPID => sys_getpid(),
         // 40
         SYS_SENDFILE => sys_sendfile(args[0], args[1], args[3] as *mut usize, args[4]),
@@ -108,7 +119,7 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
             args[4] as *mut SockAddr,
             args[5] as *mut u32,
         ),
-        //        SYS_SENDMSG => sys_sendmsg(),
+        SYS_SENDMSG => sys_sendmsg(args[0], args[1] as *const MsgHdr, args[2]),
         SYS_RECVMSG => sys_recvmsg(args[0], args[1] as *mut MsgHdr, args[2]),
         SYS_SHUTDOWN => sys_shutdown(args[0], args[1]),
         SYS_BIND => sys_bind(args[0], args[1] as *const SockAddr, args[2]),
@@ -116,6 +127,7 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
         SYS_LISTEN => sys_listen(args[0], args[1]),
         SYS_GETSOCKNAME => sys_getsockname(args[0], args[1] as *mut SockAddr, args[2] as *mut u32),
         SYS_GETPEERNAME => sys_getpeername(args[0], args[1] as *mut SockAddr, args[2] as *mut u32),
+        SYS_SOCKETPAIR => sys_socketpair(args[0], args[1], args[2], args[3] as *mut u32),
         SYS_SETSOCKOPT => sys_setsockopt(args[0], args[1], args[2], args[3] as *const u8, args[4]),
         SYS_GETSOCKOPT => sys_getsockopt(
             args[0],
@@ -124,6 +136,8 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
             args[3] as *mut u8,
             args[4] as *mut u32,
         ),
+        // child_tid and newtls are in different order on x86_64
+        #[cfg(target_arch = "x86_64")]
         SYS_CLONE => sys_clone(
             args[0],
             args[1],
@@ -132,6 +146,15 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
             args[4],
             tf,
         ),
+        #[cfg(not(target_arch = "x86_64"))]
+        SYS_CLONE => sys_clone(
+            args[0],
+            args[1],
+            args[2] as *mut u32,
+            args[4] as *mut u32,
+            args[3],
+            tf,
+        ),
         SYS_EXECVE => sys_exec(
             args[0] as *const u8,
             args[1] as *const *const u8,
@@ -140,8 +163,20 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
         ),
         // 60
         SYS_EXIT => sys_exit(args[0] as usize),
-        SYS_WAIT4 => sys_wait4(args[0] as isize, args[1] as *mut i32), // TODO: wait4
-        SYS_KILL => sys_kill(args[0], args[1]),
+        SYS_WAIT4 => sys_wait4(
+            args[0] as isize,
+            args[1] as *mut i32,
+            args[2],
+            args[3] as *mut RUsage,
+        ),
+        SYS_WAITID => sys_waitid(
+            args[0],
+            args[1],
+            args[2] as *mut crate::signal::SigInfo,
+            args[3],
+            args[4] as *mut RUsage,
+        ),
+        SYS_KILL => sys_kill(args[0] as isize, args[1]),
         SYS_UNAME => sys_uname(args[0] as *mut u8),
         SYS_FCNTL => {
             warn!("sys_fcntl is unimplemented");
@@ -158,52 +193,49 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
         SYS_GETCWD => sys_getcwd(args[0] as *mut u8, args[1]),
         // 80
         SYS_CHDIR => sys_chdir(args[0] as *const u8),
-        SYS_FCHMOD => {
-            warn!("sys_fchmod is unimplemented");
-            Ok(0)
-        }
-        SYS_FCHOWN => {
-            warn!("sys_fchown is unimplemented");
-            Ok(0)
-        }
-        SYS_UMASK => {
-            warn!("sys_umask is unimplemented");
-            Ok(0o777)
-        }
+        SYS_FCHMOD => sys_fchmod(args[0], args[1]),
+        SYS_FCHOWN => sys_fchown(args[0], args[1], args[2]),
+        SYS_UMASK => sys_umask(args[0]),
         SYS_GETTIMEOFDAY => sys_gettimeofday(args[0] as *mut TimeVal, args[1] as *const u8),
-        //        SYS_GETRLIMIT => sys_getrlimit(),
+        SYS_SETTIMEOFDAY => sys_settimeofday(args[0] as *const TimeVal, args[1] as *const u8),
+        SYS_GETRLIMIT => sys_getrlimit(args[0], args[1] as *mut [usize; 2]),
         SYS_GETRUSAGE => sys_getrusage(args[0], args[1] as *mut RUsage),
         SYS_SYSINFO => sys_sysinfo(args[0] as *mut SysInfo),
-        SYS_GETUID => {
-            warn!("sys_getuid is unimplemented");
-            Ok(0)
-        }
-        SYS_GETGID => {
-            warn!("sys_getgid is unimplemented");
-            Ok(0)
-        }
-        SYS_SETUID => {
-            warn!("sys_setuid is unimplemented");
-            Ok(0)
-        }
-        SYS_GETEUID => {
-            warn!("sys_geteuid is unimplemented");
-            Ok(0)
-        }
-        SYS_GETEGID => {
-            warn!("sys_getegid is unimplemented");
-            Ok(0)
-        }
+        SYS_TIMES => sys_times(args[0] as *mut Tms),
+        SYS_GETUID => sys_getuid(),
+        SYS_GETGID => sys_getgid(),
+        SYS_SETUID => sys_setuid(args[0]),
+        SYS_SETGID => sys_setgid(args[0]),
+        SYS_GETEUID => sys_geteuid(),
+        SYS_GETEGID => sys_getegid(),
         // 110
         SYS_GETPPID => sys_getppid(),
-        SYS_SETSID => {
-            warn!("sys_setsid is unimplemented");
-            Ok(0)
-        }
-        SYS_SIGALTSTACK => {
-            warn!("sys_sigaltstack is unimplemented");
-            Ok(0)
-        }
+        SYS_SETPGID => sys_setpgid(args[0], args[1]),
+        SYS_GETPGID => sys_getpgid(args[0]),
+        SYS_SETSID => sys_setsid(),
+        SYS_SETREUID => sys_setreuid(args[0], args[1]),
+        SYS_SETREGID => sys_setregid(args[0], args[1]),
+        SYS_GETGROUPS => sys_getgroups(args[0], args[1] as *mut u32),
+        SYS_SETGROUPS => sys_setgroups(args[0], args[1] as *const u32),
+        SYS_SETRESUID => sys_setresuid(args[0], args[1], args[2]),
+        SYS_GETRESUID => sys_getresuid(
+            args[0] as *mut u32,
+            args[1] as *mut u32,
+            args[2] as *mut u32,
+        ),
+        SYS_SETRESGID => sys_setresgid(args[0], args[1], args[2]),
+        SYS_GETRESGID => sys_getresgid(
+            args[0] as *mut u32,
+            args[1] as *mut u32,
+            args[2] as *mut u32,
+        ),
+        SYS_GETSID => sys_getsid(args[0]),
+        SYS_RT_SIGPENDING => sys_rt_sigpending(args[0] as *mut Sigset, args[1]),
+        SYS_SIGALTSTACK => sys_sigaltstack(
+            args[0] as *const SignalStack,
+            args[1] as *mut SignalStack,
+            tf,
+        ),
         SYS_STATFS => {
             warn!("statfs is unimplemented");
             Err(SysError::EACCES)
@@ -213,16 +245,16 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
             Err(SysError::EACCES)
         }
         SYS_SETPRIORITY => sys_set_priority(args[0]),
-        //        SYS_SETRLIMIT => sys_setrlimit(),
+        SYS_SETRLIMIT => sys_setrlimit(args[0], args[1] as *const [usize; 2]),
         SYS_SYNC => sys_sync(),
-        SYS_MOUNT => {
-            warn!("mount is unimplemented");
-            Err(SysError::EACCES)
-        }
-        SYS_UMOUNT2 => {
-            warn!("umount2 is unimplemented");
-            Err(SysError::EACCES)
-        }
+        SYS_MOUNT => sys_mount(
+            args[0] as *const u8,
+            args[1] as *const u8,
+            args[2] as *const u8,
+            args[3],
+            args[4] as *const u8,
+        ),
+        SYS_UMOUNT2 => sys_umount2(args[0] as *const u8, args[1]),
         SYS_REBOOT => sys_reboot(
             args[0] as u32,
             args[1] as u32,
@@ -230,45 +262,82 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
             args[3] as *const u8,
         ),
         SYS_GETTID => sys_gettid(),
+        SYS_TKILL => sys_tkill(args[0], args[1]),
         SYS_FUTEX => sys_futex(
             args[0],
             args[1] as u32,
             args[2] as i32,
             args[3] as *const TimeSpec,
+            args[4],
+            args[5] as u32,
         ),
         SYS_SCHED_GETAFFINITY => sys_sched_getaffinity(args[0], args[1], args[2] as *mut u32),
         SYS_GETDENTS64 => sys_getdents64(args[0], args[1] as *mut LinuxDirent64, args[2]),
-        SYS_SET_TID_ADDRESS => {
-            warn!("sys_set_tid_address is unimplemented");
-            Ok(thread::current().id())
+        SYS_SET_TID_ADDRESS => sys_set_tid_address(args[0] as *mut u32),
+        SYS_TIMER_CREATE => {
+            sys_timer_create(args[0], args[1] as *const SigEvent, args[2] as *mut i32)
         }
+        SYS_TIMER_SETTIME => sys_timer_settime(
+            args[0],
+            args[1],
+            args[2] as *const ITimerSpec,
+            args[3] as *mut ITimerSpec,
+        ),
+        SYS_TIMER_GETTIME => sys_timer_gettime(args[0], args[1] as *mut ITimerSpec),
+        SYS_TIMER_GETOVERRUN => sys_timer_getoverrun(args[0]),
+        SYS_TIMER_DELETE => sys_timer_delete(args[0]),
+        SYS_CLOCK_SETTIME => sys_clock_settime(args[0], args[1] as *const TimeSpec),
         SYS_CLOCK_GETTIME => sys_clock_gettime(args[0], args[1] as *mut TimeSpec),
+        SYS_CLOCK_GETRES => sys_clock_getres(args[0], args[1] as *mut TimeSpec),
+        SYS_CLOCK_NANOSLEEP => sys_clock_nanosleep(
+            args[0],
+            args[1],
+            args[2] as *const TimeSpec,
+            args[3] as *mut TimeSpec,
+        ),
         SYS_EXIT_GROUP => sys_exit_group(args[0]),
+        SYS_EPOLL_CTL => sys_epoll_ctl(args[0], args[1], args[2], args[3] as *const EpollEvent),
+        SYS_TGKILL => sys_tgkill(args[0], args[1], args[2]),
         SYS_OPENAT => sys_openat(args[0], args[1] as *const u8, args[2], args[3]),
         SYS_MKDIRAT => sys_mkdirat(args[0], args[1] as *const u8, args[2]),
         //        SYS_MKNODAT => sys_mknod(),
         // 260
-        SYS_FCHOWNAT => {
-            warn!("sys_fchownat is unimplemented");
-            Ok(0)
-        }
+        SYS_FCHOWNAT => sys_fchownat(args[0], args[1] as *const u8, args[2], args[3], args[4]),
         SYS_NEWFSTATAT => sys_fstatat(args[0], args[1] as *const u8, args[2] as *mut Stat, args[3]),
         SYS_UNLINKAT => sys_unlinkat(args[0], args[1] as *const u8, args[2]),
         SYS_READLINKAT => sys_readlinkat(args[0], args[1] as *const u8, args[2] as *mut u8, args[3]),
         SYS_RENAMEAT => sys_renameat(args[0], args[1] as *const u8, args[2], args[3] as *const u8),
         SYS_LINKAT => sys_linkat(args[0], args[1] as *const u8, args[2], args[3] as *const u8, args[4]),
         SYS_SYMLINKAT => Err(SysError::EACCES),
+        SYS_FCHMODAT => sys_fchmodat(args[0], args[1] as *const u8, args[2]),
         SYS_FACCESSAT => sys_faccessat(args[0], args[1] as *const u8, args[2], args[3]),
+        SYS_SET_ROBUST_LIST => sys_set_robust_list(args[0], args[1]),
+        SYS_GET_ROBUST_LIST => {
+            sys_get_robust_list(args[0], args[1] as *mut usize, args[2] as *mut usize)
+        }
         // 280
         SYS_UTIMENSAT => {
             warn!("sys_utimensat is unimplemented");
             Ok(0)
         }
+        SYS_EPOLL_PWAIT => sys_epoll_pwait(
+            args[0],
+            args[1] as *mut EpollEvent,
+            args[2],
+            args[3],
+            args[4] as *const Sigset,
+            args[5],
+        ),
         SYS_ACCEPT4 => sys_accept(args[0], args[1] as *mut SockAddr, args[2] as *mut u32), // use accept for accept4
-        SYS_EPOLL_CREATE1 => {
-            warn!("sys_epoll_create1 is unimplemented");
-            Err(SysError::ENOSYS)
-        }
+        SYS_TIMERFD_CREATE => sys_timerfd_create(args[0], args[1]),
+        SYS_TIMERFD_SETTIME => sys_timerfd_settime(
+            args[0],
+            args[1],
+            args[2] as *const ITimerSpec,
+            args[3] as *mut ITimerSpec,
+        ),
+        SYS_TIMERFD_GETTIME => sys_timerfd_gettime(args[0], args[1] as *mut ITimerSpec),
+        SYS_EPOLL_CREATE1 => sys_epoll_create1(args[0]),
         SYS_DUP3 => sys_dup2(args[0], args[1]), // TODO: handle `flags`
         SYS_PIPE2 => sys_pipe(args[0] as *mut u32), // TODO: handle `flags`
         SYS_PRLIMIT64 => sys_prlimit64(
@@ -303,6 +372,10 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
     }
     match ret {
         Ok(code) => code as isize,
+        Err(SysError::ERESTARTSYS) => {
+            current_thread().syscall_restart = Some((id, args));
+            -(SysError::EINTR as isize)
+        }
         Err(err) => -(err as isize),
     }
 }
@@ -326,8 +399,7 @@ fn x86_64_syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> Option<Sys
         SYS_DUP2 => sys_dup2(args[0], args[1]),
         //        SYS_PAUSE => sys_pause(),
         SYS_FORK => sys_fork(tf),
-        // use fork for vfork
-        SYS_VFORK => sys_fork(tf),
+        SYS_VFORK => sys_vfork(tf),
         SYS_RENAME => sys_rename(args[0] as *const u8, args[1] as *const u8),
         SYS_MKDIR => sys_mkdir(args[0] as *const u8, args[1]),
         SYS_RMDIR => sys_rmdir(args[0] as *const u8),
@@ -335,24 +407,15 @@ fn x86_64_syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> Option<Sys
         SYS_UNLINK => sys_unlink(args[0] as *const u8),
         SYS_READLINK => sys_readlink(args[0] as *const u8, args[1] as *mut u8, args[2]),
         // 90
-        SYS_CHMOD => {
-            warn!("sys_chmod is unimplemented");
-            Ok(0)
-        }
+        SYS_CHMOD => sys_chmod(args[0] as *const u8, args[1]),
         SYS_ARCH_PRCTL => sys_arch_prctl(args[0] as i32, args[1], tf),
         SYS_TIME => sys_time(args[0] as *mut u64),
-        SYS_ALARM => {
-            warn!("sys_alarm is unimplemented");
-            Ok(0)
-        }
-        SYS_CHOWN => {
-            warn!("sys_chown is unimplemented");
-            Ok(0)
-        }
-        SYS_EPOLL_CREATE => {
-            warn!("sys_epoll_create is unimplemented");
-            Err(SysError::ENOSYS)
-        }
+        SYS_ALARM => sys_alarm(args[0]),
+        SYS_CHOWN => sys_chown(args[0] as *const u8, args[1], args[2]),
+        SYS_LCHOWN => sys_lchown(args[0] as *const u8, args[1], args[2]),
+        SYS_GETPGRP => sys_getpgrp(),
+        SYS_EPOLL_CREATE => sys_epoll_create(args[0]),
+        SYS_EPOLL_WAIT => sys_epoll_wait(args[0], args[1] as *mut EpollEvent, args[2], args[3]),
         _ => {
             return None;
         }
@@ -406,14 +469,23 @@ pub enum SysError {
     ENOLCK = 37,
     ENOSYS = 38,
     ENOTEMPTY = 39,
+    EOVERFLOW = 75,
     ENOTSOCK = 80,
+    EMSGSIZE = 90,
+    EPROTOTYPE = 91,
     ENOPROTOOPT = 92,
+    EOPNOTSUPP = 95,
     EPFNOSUPPORT = 96,
     EAFNOSUPPORT = 97,
+    EADDRINUSE = 98,
     ENOBUFS = 105,
     EISCONN = 106,
     ENOTCONN = 107,
+    ETIMEDOUT = 110,
     ECONNREFUSED = 111,
+    /// Restart the syscall after the signal interrupting it, if it is handled
+    /// with `SA_RESTART`, otherwise fail with EINTR. Never seen by users.
+    ERESTARTSYS = 512,
 }
 
 #[allow(non_snake_case)]
@@ -463,13 +535,19 @@ impl fmt::Display for SysError {
                 ENOLCK => "No record locks available",
                 ENOSYS => "Function not implemented",
                 ENOTEMPTY => "Directory not empty",
+                EOVERFLOW => "Value too large for defined data type",
                 ENOTSOCK => "Socket operation on non-socket",
+                EMSGSIZE => "Message too long",
+                EPROTOTYPE => "Protocol wrong type for socket",
                 ENOPROTOOPT => "Protocol not available",
+                EOPNOTSUPP => "Operation not supported on transport endpoint",
                 EPFNOSUPPORT => "Protocol family not supported",
                 EAFNOSUPPORT => "Address family not supported by protocol",
+                EADDRINUSE => "Address already in use",
                 ENOBUFS => "No buffer space available",
                 EISCONN => "Transport endpoint is already connected",
                 ENOTCONN => "Transport endpoint is not connected",
+                ETIMEDOUT => "Connection timed out",
                 ECONNREFUSED => "Connection refused",
                 _ => "Unknown error",
             },
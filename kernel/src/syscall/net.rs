@@ -1,12 +1,13 @@
 //! Syscalls for networking
 
-use super::fs::IoVecs;
+use super::fs::{raise_sigpipe, split_path, IoVecs};
 use super::*;
 use crate::drivers::SOCKET_ACTIVITY;
-use crate::fs::FileLike;
+use crate::fs::{FileLike, FileTable};
 use crate::net::{
-    Endpoint, LinkLevelEndpoint, NetlinkEndpoint, NetlinkSocketState, PacketSocketState,
-    RawSocketState, Socket, TcpSocketState, UdpSocketState, SOCKETS,
+    Ancillary, Endpoint, LinkLevelEndpoint, NetlinkEndpoint, NetlinkSocketState, PacketSocketState,
+    RawSocketState, Socket, TcpSocketState, UCred, UdpSocketState, UnixEndpoint, UnixSocketState,
+    UnixSocketType, SOCKETS,
 };
 use crate::sync::{MutexGuard, SpinNoIrq, SpinNoIrqLock as Mutex};
 use alloc::boxed::Box;
@@ -21,9 +22,13 @@ pub fn sys_socket(domain: usize, socket_type: usize, protocol: usize) -> SysResu
         "socket: domain: {:?}, socket_type: {:?}, protocol: {}",
         domain, socket_type, protocol
     );
-    let mut proc = process();
+    let proc = process();
     let socket: Box<dyn Socket> = match domain {
-        AddressFamily::Internet | AddressFamily::Unix => match socket_type {
+        AddressFamily::Unix => {
+            let type_ = unix_socket_type(socket_type)?;
+            Box::new(UnixSocketState::new(type_, proc.ucred()))
+        }
+        AddressFamily::Internet => match socket_type {
             SocketType::Stream => Box::new(TcpSocketState::new()),
             SocketType::Datagram => Box::new(UdpSocketState::new()),
             SocketType::Raw => Box::new(RawSocketState::new(protocol as u8)),
@@ -39,11 +44,54 @@ pub fn sys_socket(domain: usize, socket_type: usize, protocol: usize) -> SysResu
         },
         _ => return Err(SysError::EAFNOSUPPORT),
     };
-    let fd = proc.get_free_fd();
-    proc.files.insert(fd, FileLike::Socket(socket));
+    let fd = proc.get_free_fd()?;
+    proc.files().insert(fd, FileLike::Socket(socket));
     Ok(fd)
 }
 
+pub fn sys_socketpair(
+    domain: usize,
+    socket_type: usize,
+    protocol: usize,
+    sv: *mut u32,
+) -> SysResult {
+    let domain = AddressFamily::from(domain as u16);
+    let socket_type = SocketType::from(socket_type as u8 & SOCK_TYPE_MASK);
+    info!(
+        "socketpair: domain: {:?}, socket_type: {:?}, protocol: {}, sv: {:?}",
+        domain, socket_type, protocol, sv
+    );
+    let proc = process();
+    proc.vm().check_write_array(sv, 2)?;
+    let (socket0, socket1) = match domain {
+        AddressFamily::Unix => {
+            let type_ = unix_socket_type(socket_type)?;
+            UnixSocketState::new_pair(type_, proc.ucred())
+        }
+        _ => return Err(SysError::EOPNOTSUPP),
+    };
+
+    let (fd0, fd1) = proc.get_free_fd_pair()?;
+    proc.files()
+        .insert(fd0, FileLike::Socket(Box::new(socket0)));
+    proc.files()
+        .insert(fd1, FileLike::Socket(Box::new(socket1)));
+
+    unsafe {
+        *sv = fd0 as u32;
+        *(sv.add(1)) = fd1 as u32;
+    }
+    Ok(0)
+}
+
+fn unix_socket_type(socket_type: SocketType) -> Result<UnixSocketType, SysError> {
+    match socket_type {
+        SocketType::Stream => Ok(UnixSocketType::Stream),
+        SocketType::Datagram => Ok(UnixSocketType::Datagram),
+        _ => Err(SysError::EINVAL),
+    }
+}
+
 pub fn sys_setsockopt(
     fd: usize,
     level: usize,
@@ -55,10 +103,11 @@ pub fn sys_setsockopt(
         "setsockopt: fd: {}, level: {}, optname: {}",
         fd, level, optname
     );
-    let mut proc = process();
-    proc.vm.check_read_array(optval, optlen)?;
+    let proc = process();
+    proc.vm().check_read_array(optval, optlen)?;
     let data = unsafe { slice::from_raw_parts(optval, optlen) };
-    let socket = proc.get_socket(fd)?;
+    let mut files = proc.files();
+    let socket = files.get_socket(fd)?;
     socket.setsockopt(level, optname, data)
 }
 
@@ -74,11 +123,11 @@ pub fn sys_getsockopt(
         fd, level, optname, optval, optlen
     );
     let proc = process();
-    proc.vm.check_write_ptr(optlen)?;
+    proc.vm().check_write_ptr(optlen)?;
     match level {
         SOL_SOCKET => match optname {
             SO_SNDBUF => {
-                proc.vm.check_write_array(optval, 4)?;
+                proc.vm().check_write_array(optval, 4)?;
                 unsafe {
                     *(optval as *mut u32) = crate::net::TCP_SENDBUF as u32;
                     *optlen = 4;
@@ -86,7 +135,7 @@ pub fn sys_getsockopt(
                 Ok(0)
             }
             SO_RCVBUF => {
-                proc.vm.check_write_array(optval, 4)?;
+                proc.vm().check_write_array(optval, 4)?;
                 unsafe {
                     *(optval as *mut u32) = crate::net::TCP_RECVBUF as u32;
                     *optlen = 4;
@@ -111,8 +160,7 @@ pub fn sys_connect(fd: usize, addr: *const SockAddr, addr_len: usize) -> SysResu
 
     let mut proc = process();
     let endpoint = sockaddr_to_endpoint(&mut proc, addr, addr_len)?;
-    let socket = proc.get_socket(fd)?;
-    socket.connect(endpoint)?;
+    proc.files().get_socket(fd)?.connect(endpoint)?;
     Ok(0)
 }
 
@@ -120,7 +168,7 @@ pub fn sys_sendto(
     fd: usize,
     base: *const u8,
     len: usize,
-    _flags: usize,
+    flags: usize,
     addr: *const SockAddr,
     addr_len: usize,
 ) -> SysResult {
@@ -130,7 +178,7 @@ pub fn sys_sendto(
     );
 
     let mut proc = process();
-    proc.vm.check_read_array(base, len)?;
+    proc.vm().check_read_array(base, len)?;
 
     let slice = unsafe { slice::from_raw_parts(base, len) };
     let endpoint = if addr.is_null() {
@@ -140,8 +188,11 @@ pub fn sys_sendto(
         info!("sys_sendto: sending to endpoint {:?}", endpoint);
         Some(endpoint)
     };
-    let socket = proc.get_socket(fd)?;
-    socket.write(&slice, endpoint)
+    let ret = with_socket(proc, fd, |socket| socket.write(&slice, endpoint));
+    match flags & MSG_NOSIGNAL {
+        0 => raise_sigpipe(ret),
+        _ => ret,
+    }
 }
 
 pub fn sys_recvfrom(
@@ -158,13 +209,13 @@ pub fn sys_recvfrom(
     );
 
     let mut proc = process();
-    proc.vm.check_write_array(base, len)?;
+    proc.vm().check_write_array(base, len)?;
 
-    let socket = proc.get_socket(fd)?;
     let mut slice = unsafe { slice::from_raw_parts_mut(base, len) };
-    let (result, endpoint) = socket.read(&mut slice);
+    let (result, endpoint) = with_socket(proc, fd, |socket| Ok(socket.read(&mut slice)))?;
 
     if result.is_ok() && !addr.is_null() {
+        let mut proc = process();
         let sockaddr_in = SockAddr::from(endpoint);
         unsafe {
             sockaddr_in.write_to(&mut proc, addr, addr_len)?;
@@ -174,28 +225,181 @@ pub fn sys_recvfrom(
     result
 }
 
+pub fn sys_sendmsg(fd: usize, msg: *const MsgHdr, flags: usize) -> SysResult {
+    info!("sendmsg: fd: {}, msg: {:?}, flags: {}", fd, msg, flags);
+    let mut proc = process();
+    proc.vm().check_read_ptr(msg)?;
+    let hdr = unsafe { &*msg };
+    let iovs = IoVecs::check_and_new(hdr.msg_iov, hdr.msg_iovlen, &proc.vm(), false)?;
+
+    let buf = iovs.read_all_to_vec();
+    let endpoint = if hdr.msg_name.is_null() {
+        None
+    } else {
+        let len = hdr.msg_namelen as usize;
+        Some(sockaddr_to_endpoint(&mut proc, hdr.msg_name, len)?)
+    };
+    let ancillary = read_control(&mut proc, hdr)?;
+    let ret = with_socket(proc, fd, |socket| {
+        socket.write_msg(&buf, endpoint, ancillary)
+    });
+    match flags & MSG_NOSIGNAL {
+        0 => raise_sigpipe(ret),
+        _ => ret,
+    }
+}
+
 pub fn sys_recvmsg(fd: usize, msg: *mut MsgHdr, flags: usize) -> SysResult {
     info!("recvmsg: fd: {}, msg: {:?}, flags: {}", fd, msg, flags);
-    let mut proc = process();
-    proc.vm.check_read_ptr(msg)?;
+    let proc = process();
+    proc.vm().check_write_ptr(msg)?;
     let hdr = unsafe { &mut *msg };
-    let mut iovs = IoVecs::check_and_new(hdr.msg_iov, hdr.msg_iovlen, &proc.vm, true)?;
+    let mut iovs = IoVecs::check_and_new(hdr.msg_iov, hdr.msg_iovlen, &proc.vm(), true)?;
 
     let mut buf = iovs.new_buf(true);
-    let socket = proc.get_socket(fd)?;
-    let (result, endpoint) = socket.read(&mut buf);
+    let (result, endpoint, ancillary) =
+        with_socket(proc, fd, |socket| Ok(socket.read_msg(&mut buf)))?;
 
     if let Ok(len) = result {
+        let mut proc = process();
         // copy data to user
         iovs.write_all_from_slice(&buf[..len]);
         let sockaddr_in = SockAddr::from(endpoint);
         unsafe {
             sockaddr_in.write_to(&mut proc, hdr.msg_name, &mut hdr.msg_namelen as *mut u32)?;
         }
+        write_control(&mut proc, hdr, ancillary)?;
     }
     result
 }
 
+/// Parse the control messages of `hdr` into ancillary data
+fn read_control(proc: &mut Process, hdr: &MsgHdr) -> Result<Ancillary, SysError> {
+    let mut ancillary = Ancillary::default();
+    if hdr.msg_control == 0 || hdr.msg_controllen == 0 {
+        return Ok(ancillary);
+    }
+    proc.vm()
+        .check_read_array(hdr.msg_control as *const u8, hdr.msg_controllen)?;
+    let control =
+        unsafe { slice::from_raw_parts(hdr.msg_control as *const u8, hdr.msg_controllen) };
+    let mut offset = 0;
+    while offset + size_of::<CMsgHdr>() <= control.len() {
+        let cmsg = unsafe { (control[offset..].as_ptr() as *const CMsgHdr).read_unaligned() };
+        if cmsg.cmsg_len < cmsg_len(0) || offset + cmsg.cmsg_len > control.len() {
+            return Err(SysError::EINVAL);
+        }
+        let data = &control[offset + cmsg_len(0)..offset + cmsg.cmsg_len];
+        match (cmsg.cmsg_level, cmsg.cmsg_type) {
+            (SOL_SOCKET_CMSG, SCM_RIGHTS) => {
+                for fd in data.chunks_exact(4) {
+                    let fd = u32::from_ne_bytes([fd[0], fd[1], fd[2], fd[3]]) as usize;
+                    ancillary
+                        .rights
+                        .push(proc.files().get_file_like(fd)?.clone());
+                }
+            }
+            (SOL_SOCKET_CMSG, SCM_CREDENTIALS) => {
+                if data.len() < size_of::<UCred>() {
+                    return Err(SysError::EINVAL);
+                }
+                let cred = unsafe { (data.as_ptr() as *const UCred).read_unaligned() };
+                if !proc.may_pass_ucred(&cred) {
+                    return Err(SysError::EPERM);
+                }
+                ancillary.cred = Some(cred);
+            }
+            _ => return Err(SysError::EINVAL),
+        }
+        offset += cmsg_align(cmsg.cmsg_len);
+    }
+    Ok(ancillary)
+}
+
+/// Write ancillary data to the control messages of `hdr`.
+/// Passed files are installed as new fds, except those not fitting
+/// in the buffer, which are closed.
+fn write_control(proc: &mut Process, hdr: &mut MsgHdr, ancillary: Ancillary) -> SysResult {
+    let capacity = if hdr.msg_control == 0 {
+        0
+    } else {
+        hdr.msg_controllen
+    };
+    let mut control = Vec::new();
+    let mut truncated = false;
+    if let Some(cred) = ancillary.cred {
+        if control.len() + cmsg_len(size_of::<UCred>()) <= capacity {
+            let data = unsafe {
+                slice::from_raw_parts(&cred as *const UCred as *const u8, size_of::<UCred>())
+            };
+            push_cmsg(&mut control, SCM_CREDENTIALS, data);
+        } else {
+            truncated = true;
+        }
+    }
+    if !ancillary.rights.is_empty() {
+        let max_fds = capacity.saturating_sub(control.len() + cmsg_len(0)) / 4;
+        let mut fds = Vec::new();
+        for file_like in ancillary.rights {
+            if fds.len() / 4 == max_fds {
+                truncated = true;
+                break;
+            }
+            let fd = match proc.get_free_fd() {
+                Ok(fd) => fd,
+                Err(_) => {
+                    truncated = true;
+                    break;
+                }
+            };
+            proc.files().insert(fd, file_like);
+            fds.extend_from_slice(&(fd as u32).to_ne_bytes());
+        }
+        if !fds.is_empty() {
+            push_cmsg(&mut control, SCM_RIGHTS, &fds);
+        }
+    }
+    // the padding of the last message may not fit
+    control.truncate(capacity);
+    if !control.is_empty() {
+        proc.vm()
+            .check_write_array(hdr.msg_control as *mut u8, control.len())?;
+        let target =
+            unsafe { slice::from_raw_parts_mut(hdr.msg_control as *mut u8, control.len()) };
+        target.copy_from_slice(&control);
+    }
+    hdr.msg_controllen = control.len();
+    hdr.msg_flags = if truncated { MSG_CTRUNC } else { 0 };
+    Ok(0)
+}
+
+/// Append a control message of SOL_SOCKET level
+fn push_cmsg(control: &mut Vec<u8>, cmsg_type: i32, data: &[u8]) {
+    let start = control.len();
+    let cmsg = CMsgHdr {
+        cmsg_len: cmsg_len(data.len()),
+        cmsg_level: SOL_SOCKET_CMSG,
+        cmsg_type,
+    };
+    let header = unsafe {
+        slice::from_raw_parts(&cmsg as *const CMsgHdr as *const u8, size_of::<CMsgHdr>())
+    };
+    control.extend_from_slice(header);
+    control.resize(start + cmsg_len(0), 0);
+    control.extend_from_slice(data);
+    control.resize(start + cmsg_align(cmsg_len(data.len())), 0);
+}
+
+/// Round `len` up to the alignment of control messages, as CMSG_ALIGN
+fn cmsg_align(len: usize) -> usize {
+    (len + size_of::<usize>() - 1) & !(size_of::<usize>() - 1)
+}
+
+/// Length of a control message with `len` bytes of data, as CMSG_LEN
+fn cmsg_len(len: usize) -> usize {
+    cmsg_align(size_of::<CMsgHdr>()) + len
+}
+
 pub fn sys_bind(fd: usize, addr: *const SockAddr, addr_len: usize) -> SysResult {
     info!("sys_bind: fd: {} addr: {:?} len: {}", fd, addr, addr_len);
     let mut proc = process();
@@ -203,25 +407,51 @@ pub fn sys_bind(fd: usize, addr: *const SockAddr, addr_len: usize) -> SysResult
     let mut endpoint = sockaddr_to_endpoint(&mut proc, addr, addr_len)?;
     info!("sys_bind: fd: {} bind to {:?}", fd, endpoint);
 
-    let socket = proc.get_socket(fd)?;
-    socket.bind(endpoint)
+    proc.files().get_socket(fd)?;
+    let socket_file = match &endpoint {
+        Endpoint::Unix(UnixEndpoint::Path(path)) => Some(create_socket_file(&mut proc, path)?),
+        _ => None,
+    };
+    let ret = proc.files().get_socket(fd)?.bind(endpoint);
+    if let (Err(_), Some((dir, name))) = (&ret, socket_file) {
+        dir.unlink(&name).ok();
+    }
+    ret
+}
+
+/// Create the file of a unix socket to be bound to `path`
+fn create_socket_file(
+    proc: &mut MutexGuard<'static, Process, SpinNoIrq>,
+    path: &str,
+) -> Result<(Arc<INode>, String), SysError> {
+    let (dir_path, file_name) = split_path(path);
+    let dir = proc.lookup_inode(dir_path)?;
+    if dir.find(file_name).is_ok() {
+        return Err(SysError::EADDRINUSE);
+    }
+    match proc.create_at(&dir, file_name, FileType::Socket, 0o777) {
+        // the name is still usable on file systems without socket files
+        Ok(_) | Err(SysError::ENOSYS) | Err(SysError::EINVAL) => {}
+        Err(err) => return Err(err),
+    }
+    Ok((dir, String::from(file_name)))
 }
 
 pub fn sys_listen(fd: usize, backlog: usize) -> SysResult {
     info!("sys_listen: fd: {} backlog: {}", fd, backlog);
     // smoltcp tcp sockets do not support backlog
     // open multiple sockets for each connection
-    let mut proc = process();
-
-    let socket = proc.get_socket(fd)?;
+    let proc = process();
+    let mut files = proc.files();
+    let socket = files.get_socket(fd)?;
     socket.listen()
 }
 
 pub fn sys_shutdown(fd: usize, how: usize) -> SysResult {
     info!("sys_shutdown: fd: {} how: {}", fd, how);
-    let mut proc = process();
-
-    let socket = proc.get_socket(fd)?;
+    let proc = process();
+    let mut files = proc.files();
+    let socket = files.get_socket(fd)?;
     socket.shutdown()
 }
 
@@ -232,13 +462,14 @@ pub fn sys_accept(fd: usize, addr: *mut SockAddr, addr_len: *mut u32) -> SysResu
     );
     // smoltcp tcp sockets do not support backlog
     // open multiple sockets for each connection
-    let mut proc = process();
-
-    let socket = proc.get_socket(fd)?;
-    let (new_socket, remote_endpoint) = socket.accept()?;
+    let proc = process();
+    // fail before taking a connection, if no fd is left for it
+    proc.get_free_fd()?;
+    let (new_socket, remote_endpoint) = with_socket(proc, fd, |socket| socket.accept())?;
 
-    let new_fd = proc.get_free_fd();
-    proc.files.insert(new_fd, FileLike::Socket(new_socket));
+    let mut proc = process();
+    let new_fd = proc.get_free_fd()?;
+    proc.files().insert(new_fd, FileLike::Socket(new_socket));
 
     if !addr.is_null() {
         let sockaddr_in = SockAddr::from(remote_endpoint);
@@ -261,8 +492,11 @@ pub fn sys_getsockname(fd: usize, addr: *mut SockAddr, addr_len: *mut u32) -> Sy
         return Err(SysError::EINVAL);
     }
 
-    let socket = proc.get_socket(fd)?;
-    let endpoint = socket.endpoint().ok_or(SysError::EINVAL)?;
+    let endpoint = proc
+        .files()
+        .get_socket(fd)?
+        .endpoint()
+        .ok_or(SysError::EINVAL)?;
     let sockaddr_in = SockAddr::from(endpoint);
     unsafe {
         sockaddr_in.write_to(&mut proc, addr, addr_len)?;
@@ -284,8 +518,11 @@ pub fn sys_getpeername(fd: usize, addr: *mut SockAddr, addr_len: *mut u32) -> Sy
         return Err(SysError::EINVAL);
     }
 
-    let socket = proc.get_socket(fd)?;
-    let remote_endpoint = socket.remote_endpoint().ok_or(SysError::EINVAL)?;
+    let remote_endpoint = proc
+        .files()
+        .get_socket(fd)?
+        .remote_endpoint()
+        .ok_or(SysError::EINVAL)?;
     let sockaddr_in = SockAddr::from(remote_endpoint);
     unsafe {
         sockaddr_in.write_to(&mut proc, addr, addr_len)?;
@@ -293,7 +530,7 @@ pub fn sys_getpeername(fd: usize, addr: *mut SockAddr, addr_len: *mut u32) -> Sy
     Ok(0)
 }
 
-impl Process {
+impl FileTable {
     fn get_socket(&mut self, fd: usize) -> Result<&mut Box<dyn Socket>, SysError> {
         match self.get_file_like(fd)? {
             FileLike::Socket(socket) => Ok(socket),
@@ -302,6 +539,47 @@ impl Process {
     }
 }
 
+impl Process {
+    /// Credentials passed by unix sockets
+    fn ucred(&self) -> UCred {
+        UCred {
+            pid: self.pid.get() as u32,
+            uid: self.cred.euid,
+            gid: self.cred.egid,
+        }
+    }
+
+    /// Whether it may pass `ucred` by `SCM_CREDENTIALS`, whose pid must be its own,
+    /// and whose ids must be its real, effective or saved ones, unless privileged
+    fn may_pass_ucred(&self, ucred: &UCred) -> bool {
+        let cred = &self.cred;
+        cred.is_root()
+            || (ucred.pid == self.pid.get() as u32
+                && [cred.ruid, cred.euid, cred.suid].contains(&ucred.uid)
+                && [cred.rgid, cred.egid, cred.sgid].contains(&ucred.gid))
+    }
+}
+
+/// Do `f` on the socket `fd`, without holding the process lock
+/// if the socket can be shared, as it may block waiting for other processes.
+fn with_socket<T>(
+    proc: MutexGuard<'static, Process, SpinNoIrq>,
+    fd: usize,
+    f: impl FnOnce(&mut Box<dyn Socket>) -> Result<T, SysError>,
+) -> Result<T, SysError> {
+    let shared = proc.files().get_socket(fd)?.shared_clone();
+    match shared {
+        Some(mut socket) => {
+            drop(proc);
+            f(&mut socket)
+        }
+        None => {
+            let mut files = proc.files();
+            f(files.get_socket(fd)?)
+        }
+    }
+}
+
 #[repr(C)]
 pub struct SockAddrIn {
     pub sin_family: u16,
@@ -392,6 +670,20 @@ impl From<Endpoint> for SockAddr {
                     nl_groups: netlink.multicast_groups_mask,
                 },
             }
+        } else if let Endpoint::Unix(unix) = endpoint {
+            let mut addr_un = SockAddrUn {
+                sun_family: AddressFamily::Unix.into(),
+                sun_path: [0; 108],
+            };
+            let (offset, name) = match &unix {
+                UnixEndpoint::Unnamed => (0, &[][..]),
+                UnixEndpoint::Path(path) => (0, path.as_bytes()),
+                // leading NUL for the abstract namespace
+                UnixEndpoint::Abstract(name) => (1, name.as_slice()),
+            };
+            let len = min(name.len(), addr_un.sun_path.len() - 1);
+            addr_un.sun_path[offset..offset + len].copy_from_slice(&name[..len]);
+            SockAddr { addr_un }
         } else {
             unimplemented!("only ip");
         }
@@ -408,7 +700,7 @@ fn sockaddr_to_endpoint(
     if len < size_of::<u16>() {
         return Err(SysError::EINVAL);
     }
-    proc.vm.check_read_array(addr as *const u8, len)?;
+    proc.vm().check_read_array(addr as *const u8, len)?;
     unsafe {
         match AddressFamily::from((*addr).family) {
             AddressFamily::Internet => {
@@ -421,7 +713,20 @@ fn sockaddr_to_endpoint(
                 ));
                 Ok(Endpoint::Ip((addr, port).into()))
             }
-            AddressFamily::Unix => Err(SysError::EINVAL),
+            AddressFamily::Unix => {
+                let len = min(len, size_of::<SockAddrUn>()) - size_of::<u16>();
+                let path = slice::from_raw_parts((addr as *const u8).add(size_of::<u16>()), len);
+                let endpoint = match path.first() {
+                    None => UnixEndpoint::Unnamed,
+                    Some(0) => UnixEndpoint::Abstract(path[1..].to_vec()),
+                    Some(_) => {
+                        let end = path.iter().position(|&c| c == 0).unwrap_or(len);
+                        let path = str::from_utf8(&path[..end]).map_err(|_| SysError::EINVAL)?;
+                        UnixEndpoint::Path(proc.absolute_path(path))
+                    }
+                };
+                Ok(Endpoint::Unix(endpoint))
+            }
             AddressFamily::Packet => {
                 if len < size_of::<SockAddrLl>() {
                     return Err(SysError::EINVAL);
@@ -458,19 +763,28 @@ impl SockAddr {
             return Ok(0);
         }
 
-        proc.vm.check_write_ptr(addr_len)?;
+        proc.vm().check_write_ptr(addr_len)?;
         let max_addr_len = *addr_len as usize;
         let full_len = match AddressFamily::from(self.family) {
             AddressFamily::Internet => size_of::<SockAddrIn>(),
             AddressFamily::Packet => size_of::<SockAddrLl>(),
             AddressFamily::Netlink => size_of::<SockAddrNl>(),
-            AddressFamily::Unix => return Err(SysError::EINVAL),
+            AddressFamily::Unix => {
+                let path = &self.addr_un.sun_path;
+                let len = match path[0] {
+                    // the abstract name may contain NUL, except at the end
+                    0 => path.iter().rposition(|&c| c != 0).map_or(0, |i| i + 1),
+                    // the path with its NUL
+                    _ => path.iter().position(|&c| c == 0).unwrap_or(path.len()) + 1,
+                };
+                size_of::<u16>() + min(len, path.len())
+            }
             _ => return Err(SysError::EINVAL),
         };
 
         let written_len = min(max_addr_len, full_len);
         if written_len > 0 {
-            proc.vm.check_write_array(addr as *mut u8, written_len)?;
+            proc.vm().check_write_array(addr as *mut u8, written_len)?;
             let source = slice::from_raw_parts(&self as *const SockAddr as *const u8, written_len);
             let target = slice::from_raw_parts_mut(addr as *mut u8, written_len);
             target.copy_from_slice(source);
@@ -480,6 +794,13 @@ impl SockAddr {
     }
 }
 
+#[repr(C)]
+struct CMsgHdr {
+    cmsg_len: usize,
+    cmsg_level: i32,
+    cmsg_type: i32,
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct MsgHdr {
@@ -527,10 +848,17 @@ const IPPROTO_ICMP: usize = 1;
 const IPPROTO_TCP: usize = 6;
 
 const SOL_SOCKET: usize = 1;
+const SOL_SOCKET_CMSG: i32 = SOL_SOCKET as i32;
 const SO_SNDBUF: usize = 7;
 const SO_RCVBUF: usize = 8;
 const SO_LINGER: usize = 13;
 
 const TCP_CONGESTION: usize = 13;
 
+const SCM_RIGHTS: i32 = 1;
+const SCM_CREDENTIALS: i32 = 2;
+
+const MSG_CTRUNC: usize = 0x8;
+const MSG_NOSIGNAL: usize = 0x4000;
+
 const IP_HDRINCL: usize = 3;
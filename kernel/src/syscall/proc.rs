@@ -1,21 +1,26 @@
 //! Syscalls for process
 
 use super::*;
-use crate::fs::INodeExt;
+use crate::fs::{INodeExt, Tty};
+use crate::signal;
+use crate::sync::{Semaphore, SpinNoIrqLock as Mutex};
+use alloc::collections::BTreeMap;
+use core::mem::size_of;
 
 /// Fork the current process. Return the child's PID.
 pub fn sys_fork(tf: &TrapFrame) -> SysResult {
+    check_nproc()?;
     let new_thread = current_thread().fork(tf);
     let pid = processor().manager().add(new_thread);
     info!("fork: {} -> {}", thread::current().id(), pid);
     Ok(pid)
 }
 
-/// Create a new thread in the current process.
-/// The new thread's stack pointer will be set to `newsp`,
-///   and thread pointer will be set to `newtls`.
-/// The child tid will be stored at both `parent_tid` and `child_tid`.
-/// This is partially implemented for musl only.
+/// Create a new thread or process, sharing resources with the current one as `flags`.
+/// The new thread's stack pointer will be set to `newsp` if it is not 0,
+///   and thread pointer will be set to `newtls` if `CLONE_SETTLS`.
+/// The child tid will be stored at `parent_tid` and `child_tid` as the flags.
+/// Return the child's tid.
 pub fn sys_clone(
     flags: usize,
     newsp: usize,
@@ -24,99 +29,334 @@ pub fn sys_clone(
     newtls: usize,
     tf: &TrapFrame,
 ) -> SysResult {
+    let flags = CloneFlags::from_bits_truncate(flags);
     info!(
-        "clone: flags: {:#x}, newsp: {:#x}, parent_tid: {:?}, child_tid: {:?}, newtls: {:#x}",
+        "clone: flags: {:?}, newsp: {:#x}, parent_tid: {:?}, child_tid: {:?}, newtls: {:#x}",
         flags, newsp, parent_tid, child_tid, newtls
     );
-    if flags == 0x4111 {
-        warn!("sys_clone is calling sys_fork instead, ignoring other args");
-        return sys_fork(tf);
+    if (flags.contains(CloneFlags::THREAD) && !flags.contains(CloneFlags::SIGHAND))
+        || (flags.contains(CloneFlags::SIGHAND) && !flags.contains(CloneFlags::VM))
+    {
+        return Err(SysError::EINVAL);
+    }
+    if flags.contains(CloneFlags::THREAD) && !flags.contains(CloneFlags::FILES | CloneFlags::FS) {
+        // threads share the whole process
+        warn!("clone: threads not sharing files or fs are unsupported");
+        return Err(SysError::EINVAL);
+    }
+    if flags.intersects(
+        CloneFlags::NEWNS
+            | CloneFlags::NEWCGROUP
+            | CloneFlags::NEWUTS
+            | CloneFlags::NEWIPC
+            | CloneFlags::NEWUSER
+            | CloneFlags::NEWPID
+            | CloneFlags::NEWNET,
+    ) {
+        warn!("clone: namespaces are unsupported");
+        return Err(SysError::EINVAL);
     }
-    if flags != 0x7d0f00 {
-        warn!("sys_clone only support musl pthread_create");
-        return Err(SysError::ENOSYS);
+    if !flags.contains(CloneFlags::THREAD) {
+        check_nproc()?;
     }
     {
         let proc = process();
-        proc.vm.check_write_ptr(parent_tid)?;
-        proc.vm.check_write_ptr(child_tid)?;
+        if flags.contains(CloneFlags::PARENT) && proc.parent.is_none() {
+            return Err(SysError::EINVAL);
+        }
+        if flags.contains(CloneFlags::PARENT_SETTID) {
+            proc.vm().check_write_ptr(parent_tid)?;
+        }
+        if flags.intersects(CloneFlags::CHILD_SETTID | CloneFlags::CHILD_CLEARTID) {
+            proc.vm().check_write_ptr(child_tid)?;
+        }
+    }
+    let stack_top = match newsp {
+        0 => None,
+        _ => Some(newsp),
+    };
+    let tls = match flags.contains(CloneFlags::SETTLS) {
+        true => Some(newtls),
+        false => None,
+    };
+    let mut new_thread = if flags.contains(CloneFlags::THREAD) {
+        current_thread().clone(tf, stack_top, tls)
+    } else if flags.contains(CloneFlags::CHILD_SETTID) && !flags.contains(CloneFlags::VM) {
+        // make the page present before copying the memory, and copy it in the parent after,
+        // so the child tid can be written to the child's page without copying
+        let value = unsafe { child_tid.read_volatile() };
+        let new_thread = current_thread().new_process(tf, flags, stack_top, tls);
+        unsafe { child_tid.write_volatile(value) };
+        new_thread
+    } else {
+        current_thread().new_process(tf, flags, stack_top, tls)
+    };
+    if flags.contains(CloneFlags::CHILD_SETTID) {
+        new_thread.set_child_tid = child_tid as usize;
+    }
+    if flags.contains(CloneFlags::CHILD_CLEARTID) {
+        new_thread.clear_child_tid = child_tid as usize;
     }
-    let new_thread = current_thread().clone(tf, newsp, newtls, child_tid as usize);
-    // FIXME: parent pid
+    let vfork_done = if flags.contains(CloneFlags::VFORK) {
+        let done = Arc::new(Semaphore::new(0));
+        new_thread.vfork_done = Some(VforkDone(done.clone()));
+        Some(done)
+    } else {
+        None
+    };
     let tid = processor().manager().add(new_thread);
     info!("clone: {} -> {}", thread::current().id(), tid);
-    unsafe {
-        parent_tid.write(tid as u32);
-        child_tid.write(tid as u32);
+    if flags.contains(CloneFlags::PARENT_SETTID) {
+        unsafe {
+            parent_tid.write(tid as u32);
+        }
+    }
+    if let Some(done) = vfork_done {
+        // wait until the child execs or exits, as it may be using our memory and stack
+        done.acquire();
     }
     Ok(tid)
 }
 
-/// Wait for the process exit.
-/// Return the PID. Store exit code to `wstatus` if it's not null.
-pub fn sys_wait4(pid: isize, wstatus: *mut i32) -> SysResult {
-    info!("wait4: pid: {}, code: {:?}", pid, wstatus);
-    if !wstatus.is_null() {
-        process().vm.check_write_ptr(wstatus)?;
+/// Check whether the user may create one more process, within RLIMIT_NPROC,
+/// counting the processes of the same real user id
+fn check_nproc() -> Result<(), SysError> {
+    let (cred, limit) = {
+        let proc = process();
+        (proc.cred.clone(), proc.rlimits.cur(RLIMIT_NPROC))
+    };
+    if cred.is_root() {
+        return Ok(());
     }
-    #[derive(Debug)]
-    enum WaitFor {
-        AnyChild,
-        Pid(usize),
+    let procs: Vec<_> = PROCESSES
+        .read()
+        .values()
+        .filter_map(|weak| weak.upgrade())
+        .collect();
+    let count = procs
+        .iter()
+        .filter(|proc| proc.lock().cred.ruid == cred.ruid)
+        .count();
+    if count >= limit {
+        return Err(SysError::EAGAIN);
     }
-    let target = match pid {
-        -1 | 0 => WaitFor::AnyChild,
-        p if p > 0 => WaitFor::Pid(p as usize),
-        _ => unimplemented!(),
-    };
+    Ok(())
+}
+
+/// Create a child process sharing the memory, and wait until it execs or exits
+pub fn sys_vfork(tf: &TrapFrame) -> SysResult {
+    let flags =
+        CloneFlags::VM | CloneFlags::VFORK | CloneFlags::from_bits_truncate(signal::SIGCHLD);
+    sys_clone(flags.bits(), 0, 0 as *mut u32, 0 as *mut u32, 0, tf)
+}
+
+bitflags! {
+    /// Options of `wait4` and `waitid`
+    pub struct WaitOptions: usize {
+        /// return at once if no child changed its state
+        const NOHANG = 1;
+        /// report stopped children, aka `WSTOPPED`
+        const UNTRACED = 2;
+        /// report exited children, implied by `wait4`
+        const EXITED = 4;
+        /// report continued children
+        const CONTINUED = 8;
+        /// leave the child waitable, only for `waitid`
+        const NOWAIT = 0x0100_0000;
+        const NOTHREAD = 0x2000_0000;
+        const ALL = 0x4000_0000;
+        const CLONE = 0x8000_0000;
+    }
+}
+
+#[derive(Debug)]
+enum WaitFor {
+    AnyChild,
+    Pid(usize),
+    Pgid(usize),
+}
+
+/// Wait for a child matching `target` to change its state as selected by `options`.
+/// Return its pid, status and resource usage, or `None` if `NOHANG` and nothing to report.
+/// A zombie is reaped unless `NOWAIT`, whose usage is added to the children of the caller.
+/// Fail with EINTR if a signal arrives while waiting, and nothing is to report after it.
+fn wait_child(
+    target: WaitFor,
+    options: WaitOptions,
+) -> Result<Option<(usize, WaitStatus, Usage)>, SysError> {
+    let mut interrupted = false;
     loop {
         let mut proc = process();
-        // check child_exit_code
-        let find = match target {
-            WaitFor::AnyChild => proc
-                .child_exit_code
-                .iter()
-                .next()
-                .map(|(&pid, &code)| (pid, code)),
-            WaitFor::Pid(pid) => proc.child_exit_code.get(&pid).map(|&code| (pid, code)),
-        };
-        // if found, return
-        if let Some((pid, exit_code)) = find {
-            proc.child_exit_code.remove(&pid);
-            if !wstatus.is_null() {
-                unsafe {
-                    wstatus.write(exit_code as i32);
-                }
-            }
-            return Ok(pid);
-        }
-        // if not, check pid
         let children: Vec<_> = proc
             .children
             .iter()
-            .filter_map(|weak| weak.upgrade())
+            .filter(|child| {
+                let child = child.lock();
+                match target {
+                    WaitFor::AnyChild => true,
+                    WaitFor::Pid(pid) => child.pid.get() == pid,
+                    WaitFor::Pgid(pgid) => child.pgid == pgid,
+                }
+            })
+            .cloned()
             .collect();
-        let invalid = match target {
-            WaitFor::AnyChild => children.len() == 0,
-            WaitFor::Pid(pid) => children
-                .iter()
-                .find(|p| p.lock().pid.get() == pid)
-                .is_none(),
-        };
-        if invalid {
+        if children.is_empty() {
             return Err(SysError::ECHILD);
         }
+        for child in children.iter() {
+            let mut inner = child.lock();
+            let pid = inner.pid.get();
+            let usage = inner.usage + inner.children_usage;
+            if let Some(status) = inner.exit_status {
+                if !options.contains(WaitOptions::EXITED) {
+                    continue;
+                }
+                if !options.contains(WaitOptions::NOWAIT) {
+                    drop(inner);
+                    proc.children.retain(|other| !Arc::ptr_eq(other, child));
+                    proc.children_usage += usage;
+                    remove_process(pid, child);
+                }
+                return Ok(Some((pid, status, usage)));
+            }
+            let report = match inner.stop_status {
+                Some(WaitStatus::Stopped(_)) => options.contains(WaitOptions::UNTRACED),
+                Some(WaitStatus::Continued) => options.contains(WaitOptions::CONTINUED),
+                _ => false,
+            };
+            if report {
+                let status = inner.stop_status.unwrap();
+                if !options.contains(WaitOptions::NOWAIT) {
+                    inner.stop_status = None;
+                }
+                return Ok(Some((pid, status, usage)));
+            }
+        }
+        if options.contains(WaitOptions::NOHANG) {
+            return Ok(None);
+        }
+        if interrupted {
+            return Err(SysError::EINTR);
+        }
         info!(
             "wait: thread {} -> {:?}, sleep",
             thread::current().id(),
             target
         );
         let condvar = proc.child_exit.clone();
-        drop(proc); // must release lock of current process
-        condvar._wait();
+        // the lock of current process is released while waiting
+        drop(condvar.wait(proc));
+        // e.g. SIGCHLD of the child to report, so scan them again first
+        interrupted = signal::has_signal_to_handle();
     }
 }
 
+/// Wait for a child process to change its state.
+/// Return its pid, or 0 if `WNOHANG` and nothing to report.
+/// Store the encoded status to `wstatus` if it's not null.
+pub fn sys_wait4(pid: isize, wstatus: *mut i32, options: usize, rusage: *mut RUsage) -> SysResult {
+    info!(
+        "wait4: pid: {}, wstatus: {:?}, options: {:#x}, rusage: {:?}",
+        pid, wstatus, options, rusage
+    );
+    let options = WaitOptions::from_bits(options).ok_or(SysError::EINVAL)?;
+    if options.intersects(WaitOptions::EXITED | WaitOptions::NOWAIT) {
+        return Err(SysError::EINVAL);
+    }
+    {
+        let proc = process();
+        if !wstatus.is_null() {
+            proc.vm().check_write_ptr(wstatus)?;
+        }
+        if !rusage.is_null() {
+            proc.vm().check_write_ptr(rusage)?;
+        }
+    }
+    let target = match pid {
+        -1 => WaitFor::AnyChild,
+        0 => WaitFor::Pgid(process().pgid),
+        p if p > 0 => WaitFor::Pid(p as usize),
+        p => WaitFor::Pgid(-p as usize),
+    };
+    let (pid, status, usage) = match wait_child(target, options | WaitOptions::EXITED)? {
+        Some(found) => found,
+        None => return Ok(0),
+    };
+    unsafe {
+        if !wstatus.is_null() {
+            wstatus.write(status.encode());
+        }
+        if !rusage.is_null() {
+            rusage.write(RUsage::from_usage(&usage));
+        }
+    }
+    Ok(pid)
+}
+
+/// Wait for a child process to change its state, and fill `infop` about it.
+/// With `WNOHANG`, `infop` is zeroed if nothing to report.
+pub fn sys_waitid(
+    idtype: usize,
+    id: usize,
+    infop: *mut signal::SigInfo,
+    options: usize,
+    rusage: *mut RUsage,
+) -> SysResult {
+    info!(
+        "waitid: idtype: {}, id: {}, infop: {:?}, options: {:#x}",
+        idtype, id, infop, options
+    );
+    const P_ALL: usize = 0;
+    const P_PID: usize = 1;
+    const P_PGID: usize = 2;
+
+    let options = WaitOptions::from_bits(options).ok_or(SysError::EINVAL)?;
+    let events = WaitOptions::EXITED | WaitOptions::UNTRACED | WaitOptions::CONTINUED;
+    if !options.intersects(events) {
+        return Err(SysError::EINVAL);
+    }
+    {
+        let proc = process();
+        if !infop.is_null() {
+            proc.vm().check_write_ptr(infop)?;
+        }
+        if !rusage.is_null() {
+            proc.vm().check_write_ptr(rusage)?;
+        }
+    }
+    let target = match idtype {
+        P_ALL => WaitFor::AnyChild,
+        P_PID => WaitFor::Pid(id),
+        P_PGID if id == 0 => WaitFor::Pgid(process().pgid),
+        P_PGID => WaitFor::Pgid(id),
+        _ => return Err(SysError::EINVAL),
+    };
+    let (info, usage) = match wait_child(target, options)? {
+        Some((pid, status, usage)) => {
+            let (code, status) = match status {
+                WaitStatus::Exited(code) => (signal::CLD_EXITED, code as i32 & 0xff),
+                WaitStatus::Signaled(sig, false) => (signal::CLD_KILLED, sig as i32),
+                WaitStatus::Signaled(sig, true) => (signal::CLD_DUMPED, sig as i32),
+                WaitStatus::Stopped(sig) => (signal::CLD_STOPPED, sig as i32),
+                WaitStatus::Continued => (signal::CLD_CONTINUED, signal::SIGCONT as i32),
+            };
+            let mut info = signal::SigInfo::new(signal::SIGCHLD, code, pid);
+            info.status = status;
+            (info, usage)
+        }
+        None => (unsafe { core::mem::zeroed() }, Usage::default()),
+    };
+    unsafe {
+        if !infop.is_null() {
+            infop.write(info);
+        }
+        if !rusage.is_null() {
+            rusage.write(RUsage::from_usage(&usage));
+        }
+    }
+    Ok(0)
+}
+
 pub fn sys_exec(
     name: *const u8,
     argv: *const *const u8,
@@ -124,11 +364,11 @@ pub fn sys_exec(
     tf: &mut TrapFrame,
 ) -> SysResult {
     info!("exec: name: {:?}, argv: {:?} envp: {:?}", name, argv, envp);
-    let proc = process();
+    let mut proc = process();
     let _name = if name.is_null() {
         String::from("")
     } else {
-        unsafe { proc.vm.check_and_clone_cstr(name)? }
+        unsafe { proc.vm().check_and_clone_cstr(name)? }
     };
 
     if argv.is_null() {
@@ -138,9 +378,9 @@ pub fn sys_exec(
     let mut args = Vec::new();
     unsafe {
         let mut current_argv = argv as *const *const u8;
-        proc.vm.check_read_ptr(current_argv)?;
+        proc.vm().check_read_ptr(current_argv)?;
         while !(*current_argv).is_null() {
-            let arg = proc.vm.check_and_clone_cstr(*current_argv)?;
+            let arg = proc.vm().check_and_clone_cstr(*current_argv)?;
             args.push(arg);
             current_argv = current_argv.add(1);
         }
@@ -149,27 +389,67 @@ pub fn sys_exec(
     if args.is_empty() {
         return Err(SysError::EINVAL);
     }
+    // as Linux, the arguments may take up to a quarter of the stack
+    let args_size: usize = args
+        .iter()
+        .map(|arg| arg.len() + 1 + size_of::<usize>())
+        .sum();
+    if args_size > proc.rlimits.cur(RLIMIT_STACK) / 4 {
+        return Err(SysError::E2BIG);
+    }
 
-    // Read program file
+    // Read program file, which must be an executable regular file
     let path = args[0].as_str();
     let inode = proc.lookup_inode(path)?;
+    let metadata = inode.metadata()?;
+    if metadata.type_ != FileType::File {
+        return Err(SysError::EACCES);
+    }
+    proc.cred.check_access(&metadata, Access::EXEC)?;
     let buf = inode.read_as_vec()?;
 
-    // Make new Thread
+    // Make new Thread, running in the current process
     let iter = args.iter().map(|s| s.as_str());
-    let mut thread = Thread::new_user(buf.as_slice(), iter);
-    thread.proc.lock().clone_for_exec(&proc);
+    let mut thread = Thread::new_user(buf.as_slice(), iter, &proc.rlimits);
+    if thread.proc.lock().vm().size() > proc.rlimits.cur(RLIMIT_AS) {
+        return Err(SysError::ENOMEM);
+    }
+    // the other threads exit, before the memory they run in is replaced
+    let tid = thread::current().id();
+    for &other in proc.threads.iter().filter(|&&other| other != tid) {
+        futex::remove_waiter(other);
+        processor().manager().exit(other, 0);
+    }
+    proc.threads.retain(|&other| other == tid);
+    let pending = core::mem::replace(&mut proc.sig_pending_threads, BTreeMap::new());
+    proc.sig_pending_threads = pending
+        .into_iter()
+        .filter(|&(other, _)| other == tid)
+        .collect();
+    let senders = core::mem::replace(&mut proc.sig_senders, BTreeMap::new());
+    proc.sig_senders = senders
+        .into_iter()
+        .filter(|&((target, _), _)| target.map_or(true, |other| other == tid))
+        .collect();
+    proc.exec_from(&thread.proc.lock());
+    proc.cred.exec(&metadata);
+    proc.exec_path = proc.absolute_path(path);
+    proc.cmdline = args.clone();
+    thread.proc = current_thread().proc.clone();
+    // signal mask and pending signals are preserved
+    thread.sig_mask = current_thread().sig_mask;
 
     // Activate new page table
     unsafe {
-        thread.proc.lock().vm.activate();
+        proc.vm().activate();
     }
 
     // Modify the TrapFrame
     *tf = unsafe { thread.context.get_init_tf() };
 
-    // Swap Context but keep KStack
+    // Swap Context but keep KStack and resource usage
     ::core::mem::swap(&mut current_thread().kstack, &mut thread.kstack);
+    ::core::mem::swap(&mut current_thread().usage, &mut thread.usage);
     ::core::mem::swap(current_thread(), &mut *thread);
 
     Ok(0)
@@ -180,40 +460,141 @@ pub fn sys_yield() -> SysResult {
     Ok(0)
 }
 
-/// Kill the process
-pub fn sys_kill(pid: usize, sig: usize) -> SysResult {
+/// Send a signal to the process
+///
+/// If `pid` is -1, the signal is sent to every process except init and the caller.
+/// If `pid` is 0 or less than -1, it is sent to the process group of the caller or `-pid`.
+pub fn sys_kill(pid: isize, sig: usize) -> SysResult {
     info!(
         "kill: {} killed: {} with sig {}",
         thread::current().id(),
         pid,
         sig
     );
-    let current_pid = process().pid.get().clone();
-    if current_pid == pid {
-        // killing myself
-        sys_exit_group(sig);
-    } else {
-        if let Some(proc_arc) = PROCESSES.read().get(&pid).and_then(|weak| weak.upgrade()) {
-            let proc = proc_arc.lock();
-            // quit all threads
-            for tid in proc.threads.iter() {
-                processor().manager().exit(*tid, sig);
-            }
-            // notify parent and fill exit code
-            // avoid deadlock
-            let proc_parent = proc.parent.clone();
-            let pid = proc.pid.get();
-            drop(proc);
-            if let Some(parent) = proc_parent {
-                let mut parent = parent.lock();
-                parent.child_exit_code.insert(pid, sig);
-                parent.child_exit.notify_one();
-            }
-            Ok(0)
-        } else {
-            Err(SysError::EINVAL)
+    if sig != 0 && !signal::is_valid(sig) {
+        return Err(SysError::EINVAL);
+    }
+    let procs = match pid {
+        pid if pid > 0 => {
+            let proc = PROCESSES
+                .read()
+                .get(&(pid as usize))
+                .and_then(|weak| weak.upgrade())
+                .ok_or(SysError::ESRCH)?;
+            vec![proc]
         }
+        -1 => {
+            let current_pid = process().pid.get();
+            let procs: Vec<_> = PROCESSES
+                .read()
+                .iter()
+                .filter(|(&pid, _)| pid != 0 && pid != current_pid)
+                .filter_map(|(_, weak)| weak.upgrade())
+                .collect();
+            procs
+        }
+        _ => {
+            let pgid = match pid {
+                0 => process().pgid,
+                pid => -pid as usize,
+            };
+            process_group(pgid)
+        }
+    };
+    if procs.is_empty() {
+        return Err(SysError::ESRCH);
+    }
+    // only the processes permitted are signaled
+    let procs: Vec<_> = procs
+        .into_iter()
+        .filter(|proc| may_signal(proc, sig))
+        .collect();
+    if procs.is_empty() {
+        return Err(SysError::EPERM);
+    }
+    if sig != 0 {
+        let info = sender_info(sig, signal::SI_USER);
+        for proc in procs.iter() {
+            signal::send_signal_from_user(proc, None, sig, info);
+        }
+    }
+    Ok(0)
+}
+
+/// Info of signal `sig` sent by the current process, with `code`
+fn sender_info(sig: usize, code: i32) -> signal::SigInfo {
+    let proc = process();
+    let mut info = signal::SigInfo::new(sig, code, proc.pid.get());
+    info.uid = proc.cred.ruid;
+    info
+}
+
+/// Whether the current process may send `sig` to `target`.
+/// Root may signal any process, others may signal the ones of the same user,
+/// i.e. whose real or saved user id is the real or effective one of the sender,
+/// and send `SIGCONT` to the ones in the same session.
+fn may_signal(target: &Arc<Mutex<Process>>, sig: usize) -> bool {
+    let (cred, sid) = {
+        let proc = process();
+        (proc.cred.clone(), proc.sid)
+    };
+    if cred.is_root() {
+        return true;
+    }
+    let target = target.lock();
+    (sig == signal::SIGCONT && target.sid == sid)
+        || [cred.ruid, cred.euid]
+            .iter()
+            .any(|&uid| uid == target.cred.ruid || uid == target.cred.suid)
+}
+
+/// Send a signal to the thread `tid` in process `tgid`
+pub fn sys_tgkill(tgid: usize, tid: usize, sig: usize) -> SysResult {
+    info!("tgkill: tgid: {}, tid: {}, sig: {}", tgid, tid, sig);
+    let proc = PROCESSES
+        .read()
+        .get(&tgid)
+        .and_then(|weak| weak.upgrade())
+        .ok_or(SysError::ESRCH)?;
+    send_signal_to_thread(&proc, tid, sig)
+}
+
+/// Send a signal to the thread `tid`
+pub fn sys_tkill(tid: usize, sig: usize) -> SysResult {
+    info!("tkill: tid: {}, sig: {}", tid, sig);
+    let proc = find_thread_process(tid)?;
+    send_signal_to_thread(&proc, tid, sig)
+}
+
+/// The process of the thread `tid`
+fn find_thread_process(tid: usize) -> Result<Arc<Mutex<Process>>, SysError> {
+    // not locking processes with the process table locked
+    let procs: Vec<_> = PROCESSES
+        .read()
+        .values()
+        .filter_map(|weak| weak.upgrade())
+        .collect();
+    procs
+        .into_iter()
+        .find(|proc| proc.lock().threads.contains(&tid))
+        .ok_or(SysError::ESRCH)
+}
+
+fn send_signal_to_thread(proc: &Arc<Mutex<Process>>, tid: usize, sig: usize) -> SysResult {
+    if sig != 0 && !signal::is_valid(sig) {
+        return Err(SysError::EINVAL);
+    }
+    if !proc.lock().threads.contains(&tid) {
+        return Err(SysError::ESRCH);
+    }
+    if !may_signal(proc, sig) {
+        return Err(SysError::EPERM);
     }
+    if sig != 0 {
+        let info = sender_info(sig, signal::SI_TKILL);
+        signal::send_signal_from_user(proc, Some(tid), sig, info);
+    }
+    Ok(0)
 }
 
 /// Get the current process id
@@ -229,44 +610,449 @@ pub fn sys_gettid() -> SysResult {
     Ok(thread::current().id())
 }
 
-/// Get the parent process id
+/// Set the address to clear and wake up when the current thread exits.
+/// Return the tid.
+pub fn sys_set_tid_address(tidptr: *mut u32) -> SysResult {
+    info!("set_tid_address: {:?}", tidptr);
+    current_thread().clear_child_tid = tidptr as usize;
+    Ok(thread::current().id())
+}
+
+/// Register the robust futex list of the current thread
+pub fn sys_set_robust_list(head: usize, len: usize) -> SysResult {
+    info!("set_robust_list: head: {:#x}, len: {}", head, len);
+    if len != size_of::<futex::RobustListHead>() {
+        return Err(SysError::EINVAL);
+    }
+    let tid = thread::current().id();
+    process().robust_lists.insert(tid, head);
+    Ok(0)
+}
+
+/// Get the robust futex list of thread `pid`, or the current thread if it is 0
+pub fn sys_get_robust_list(pid: usize, head_ptr: *mut usize, len_ptr: *mut usize) -> SysResult {
+    info!(
+        "get_robust_list: pid: {}, head_ptr: {:?}, len_ptr: {:?}",
+        pid, head_ptr, len_ptr
+    );
+    let (tid, target) = match pid {
+        0 => (thread::current().id(), current_thread().proc.clone()),
+        tid => (tid, find_thread_process(tid)?),
+    };
+    let cred = process().cred.clone();
+    let head = {
+        let target = target.lock();
+        if pid != 0 && !cred.may_control(&target.cred) {
+            return Err(SysError::EPERM);
+        }
+        target.robust_lists.get(&tid).cloned().unwrap_or(0)
+    };
+    let proc = process();
+    proc.vm().check_write_ptr(head_ptr)?;
+    proc.vm().check_write_ptr(len_ptr)?;
+    unsafe {
+        head_ptr.write(head);
+        len_ptr.write(size_of::<futex::RobustListHead>());
+    }
+    Ok(0)
+}
+
+/// The process `pid`, or the current one if `pid` is 0
+fn find_process(pid: usize) -> Result<Arc<Mutex<Process>>, SysError> {
+    if pid == 0 {
+        return Ok(current_thread().proc.clone());
+    }
+    PROCESSES
+        .read()
+        .get(&pid)
+        .and_then(|weak| weak.upgrade())
+        .ok_or(SysError::ESRCH)
+}
+
+/// Move process `pid` into the process group `pgid` in the same session,
+/// or make it a group leader if `pgid` is its pid.
+/// It must be the current process or a child.
+pub fn sys_setpgid(pid: usize, pgid: usize) -> SysResult {
+    info!("setpgid: pid: {}, pgid: {}", pid, pgid);
+    let target = find_process(pid)?;
+    let sid = {
+        let proc = process();
+        let is_child = proc
+            .children
+            .iter()
+            .any(|child| Arc::ptr_eq(child, &target));
+        if !Arc::ptr_eq(&current_thread().proc, &target) && !is_child {
+            return Err(SysError::ESRCH);
+        }
+        proc.sid
+    };
+    let (pid, target_sid) = {
+        let target = target.lock();
+        (target.pid.get(), target.sid)
+    };
+    let pgid = if pgid == 0 { pid } else { pgid };
+    // a session leader can not move, and a child in another session can not be moved
+    if target_sid != sid || pid == sid {
+        return Err(SysError::EPERM);
+    }
+    // join an existing group in the same session
+    if pgid != pid {
+        let same_session = process_group(pgid)
+            .iter()
+            .any(|other| other.lock().sid == sid);
+        if !same_session {
+            return Err(SysError::EPERM);
+        }
+    }
+    target.lock().pgid = pgid;
+    Ok(0)
+}
+
+/// Get the process group id of process `pid`, the current one if 0
+pub fn sys_getpgid(pid: usize) -> SysResult {
+    let proc = find_process(pid)?;
+    let pgid = proc.lock().pgid;
+    Ok(pgid)
+}
+
+/// Get the process group id of the current process
+pub fn sys_getpgrp() -> SysResult {
+    Ok(process().pgid)
+}
+
+/// Start a new session and process group led by the current process,
+/// without a controlling terminal. Return the session id.
+pub fn sys_setsid() -> SysResult {
+    let pid = process().pid.get();
+    info!("setsid: {}", pid);
+    // the pid is in use by a process group
+    if !process_group(pid).is_empty() {
+        return Err(SysError::EPERM);
+    }
+    let mut proc = process();
+    proc.pgid = pid;
+    proc.sid = pid;
+    proc.tty = None;
+    Ok(pid)
+}
+
+/// Get the session id of process `pid`, the current one if 0
+pub fn sys_getsid(pid: usize) -> SysResult {
+    let proc = find_process(pid)?;
+    let sid = proc.lock().sid;
+    Ok(sid)
+}
+
+/// Handle job control ioctl `request` of terminal `tty`.
+/// Return `None` if it is not a job control request.
+pub fn tty_job_ioctl(tty: &Arc<Tty>, request: usize, arg: usize) -> Option<SysResult> {
+    use crate::fs::ioctl::*;
+    let ret = match request {
+        TIOCSCTTY => tty_set_ctty(tty, arg != 0),
+        TIOCNOTTY => tty_release_ctty(tty),
+        TIOCGPGRP | TIOCGSID => {
+            let ptr = arg as *mut i32;
+            let (ctty, sid) = {
+                let proc = process();
+                if let Err(err) = proc.vm().check_write_ptr(ptr) {
+                    return Some(Err(err.into()));
+                }
+                (proc.ctty(), proc.sid)
+            };
+            match ctty {
+                Some(ref ctty) if Arc::ptr_eq(ctty, tty) => {
+                    let id = match request {
+                        TIOCGPGRP => tty.foreground().unwrap_or(0),
+                        _ => sid,
+                    };
+                    unsafe {
+                        ptr.write(id as i32);
+                    }
+                    Ok(0)
+                }
+                _ => Err(SysError::ENOTTY),
+            }
+        }
+        TIOCSPGRP => tty_set_foreground(tty, arg as *const i32),
+        _ => return None,
+    };
+    Some(ret)
+}
+
+/// Make `tty` the controlling terminal of the session led by the current process
+fn tty_set_ctty(tty: &Arc<Tty>, steal: bool) -> SysResult {
+    let mut proc = process();
+    let pid = proc.pid.get();
+    if let Some(ctty) = proc.ctty() {
+        return match Arc::ptr_eq(&ctty, tty) {
+            true => Ok(0),
+            false => Err(SysError::EPERM),
+        };
+    }
+    if proc.sid != pid || !tty.set_session(pid, proc.pgid, steal) {
+        return Err(SysError::EPERM);
+    }
+    proc.tty = Some(tty.clone());
+    Ok(0)
+}
+
+/// Called when the current process opens terminal `tty`:
+/// a session leader without a controlling terminal acquires it,
+/// unless it controls another session
+pub fn tty_open(tty: &Arc<Tty>) {
+    tty_set_ctty(tty, false).ok();
+}
+
+/// Give up the controlling terminal `tty`.
+/// The session leader also hangs up the foreground process group.
+fn tty_release_ctty(tty: &Arc<Tty>) -> SysResult {
+    let (pid, sid) = {
+        let mut proc = process();
+        match proc.ctty() {
+            Some(ref ctty) if Arc::ptr_eq(ctty, tty) => {}
+            _ => return Err(SysError::ENOTTY),
+        }
+        proc.tty = None;
+        (proc.pid.get(), proc.sid)
+    };
+    if pid == sid {
+        if let Some(foreground) = tty.release(sid) {
+            signal::send_signal_to_pgid(foreground, signal::SIGHUP);
+            signal::send_signal_to_pgid(foreground, signal::SIGCONT);
+        }
+    }
+    Ok(0)
+}
+
+/// Put the process group at `pgid_ptr` in the foreground of the controlling terminal `tty`
+fn tty_set_foreground(tty: &Arc<Tty>, pgid_ptr: *const i32) -> SysResult {
+    let (ctty, sid, pgid) = {
+        let proc = process();
+        proc.vm().check_read_ptr(pgid_ptr)?;
+        (proc.ctty(), proc.sid, proc.pgid)
+    };
+    match ctty {
+        Some(ref ctty) if Arc::ptr_eq(ctty, tty) => {}
+        _ => return Err(SysError::ENOTTY),
+    }
+    let new_pgid = unsafe { pgid_ptr.read() };
+    if new_pgid < 0 {
+        return Err(SysError::EINVAL);
+    }
+    // a background group is stopped by SIGTTOU, unless it is ignored or blocked,
+    // and tries again when continued
+    if tty.foreground() != Some(pgid) && !signal_ignored(signal::SIGTTOU) {
+        signal::send_signal_to_pgid(pgid, signal::SIGTTOU);
+        return Err(SysError::ERESTARTSYS);
+    }
+    let new_pgid = new_pgid as usize;
+    let same_session = process_group(new_pgid)
+        .iter()
+        .any(|other| other.lock().sid == sid);
+    if !same_session {
+        return Err(SysError::EPERM);
+    }
+    tty.set_foreground(new_pgid);
+    Ok(0)
+}
+
+/// The process group of the current process,
+/// if it is in the background of its controlling terminal `tty`
+fn background_pgid(tty: &Arc<Tty>) -> Option<usize> {
+    let (ctty, pgid) = {
+        let proc = process();
+        (proc.ctty(), proc.pgid)
+    };
+    match ctty {
+        // job control only applies to the controlling terminal
+        Some(ref ctty) if Arc::ptr_eq(ctty, tty) && tty.foreground() != Some(pgid) => Some(pgid),
+        _ => None,
+    }
+}
+
+/// Check whether the current process may read its terminal `tty`.
+/// A background process group is stopped by SIGTTIN, and the read is restarted
+/// when continued, or fails with EIO if SIGTTIN is ignored or blocked.
+pub fn tty_check_read(tty: &Arc<Tty>) -> SysResult {
+    let pgid = match background_pgid(tty) {
+        Some(pgid) => pgid,
+        None => return Ok(0),
+    };
+    if signal_ignored(signal::SIGTTIN) {
+        return Err(SysError::EIO);
+    }
+    signal::send_signal_to_pgid(pgid, signal::SIGTTIN);
+    Err(SysError::ERESTARTSYS)
+}
+
+/// Check whether the current process may write its terminal `tty`.
+/// With `TOSTOP`, a background process group is stopped by SIGTTOU,
+/// and the write is restarted when continued, unless SIGTTOU is ignored or blocked.
+pub fn tty_check_write(tty: &Arc<Tty>) -> SysResult {
+    if !tty.tostop() {
+        return Ok(0);
+    }
+    match background_pgid(tty) {
+        Some(pgid) if !signal_ignored(signal::SIGTTOU) => {
+            signal::send_signal_to_pgid(pgid, signal::SIGTTOU);
+            Err(SysError::ERESTARTSYS)
+        }
+        _ => Ok(0),
+    }
+}
+
+/// Whether `sig` is ignored or blocked by the current thread
+fn signal_ignored(sig: usize) -> bool {
+    let handler = process().sig_actions()[sig].handler;
+    handler == signal::SIG_IGN || current_thread().sig_mask.contains(sig)
+}
+
+/// Get the parent process id, 0 if it is the init process
 pub fn sys_getppid() -> SysResult {
-    Ok(process().parent.as_ref().unwrap().lock().pid.get())
+    let parent = process().parent.clone();
+    Ok(parent.map_or(0, |parent| parent.lock().pid.get()))
+}
+
+pub fn sys_getuid() -> SysResult {
+    Ok(process().cred.ruid as usize)
+}
+
+pub fn sys_geteuid() -> SysResult {
+    Ok(process().cred.euid as usize)
+}
+
+pub fn sys_getgid() -> SysResult {
+    Ok(process().cred.rgid as usize)
+}
+
+pub fn sys_getegid() -> SysResult {
+    Ok(process().cred.egid as usize)
+}
+
+pub fn sys_setuid(uid: usize) -> SysResult {
+    info!("setuid: {}", uid);
+    process().cred.set_uid(uid)?;
+    Ok(0)
+}
+
+pub fn sys_setgid(gid: usize) -> SysResult {
+    info!("setgid: {}", gid);
+    process().cred.set_gid(gid)?;
+    Ok(0)
+}
+
+pub fn sys_setreuid(ruid: usize, euid: usize) -> SysResult {
+    info!("setreuid: {} {}", ruid as u32 as i32, euid as u32 as i32);
+    process().cred.set_reuid(ruid, euid)?;
+    Ok(0)
+}
+
+pub fn sys_setregid(rgid: usize, egid: usize) -> SysResult {
+    info!("setregid: {} {}", rgid as u32 as i32, egid as u32 as i32);
+    process().cred.set_regid(rgid, egid)?;
+    Ok(0)
+}
+
+pub fn sys_setresuid(ruid: usize, euid: usize, suid: usize) -> SysResult {
+    info!(
+        "setresuid: {} {} {}",
+        ruid as u32 as i32, euid as u32 as i32, suid as u32 as i32
+    );
+    process().cred.set_resuid(ruid, euid, suid)?;
+    Ok(0)
+}
+
+pub fn sys_setresgid(rgid: usize, egid: usize, sgid: usize) -> SysResult {
+    info!(
+        "setresgid: {} {} {}",
+        rgid as u32 as i32, egid as u32 as i32, sgid as u32 as i32
+    );
+    process().cred.set_resgid(rgid, egid, sgid)?;
+    Ok(0)
+}
+
+/// Write the real, effective and saved ids to the pointers
+fn write_ids(ptrs: [*mut u32; 3], ids: [u32; 3]) -> SysResult {
+    let proc = process();
+    for &ptr in ptrs.iter() {
+        proc.vm().check_write_ptr(ptr)?;
+    }
+    for (&ptr, &id) in ptrs.iter().zip(ids.iter()) {
+        unsafe {
+            ptr.write(id);
+        }
+    }
+    Ok(0)
+}
+
+pub fn sys_getresuid(ruid: *mut u32, euid: *mut u32, suid: *mut u32) -> SysResult {
+    let cred = process().cred.clone();
+    write_ids([ruid, euid, suid], [cred.ruid, cred.euid, cred.suid])
+}
+
+pub fn sys_getresgid(rgid: *mut u32, egid: *mut u32, sgid: *mut u32) -> SysResult {
+    let cred = process().cred.clone();
+    write_ids([rgid, egid, sgid], [cred.rgid, cred.egid, cred.sgid])
+}
+
+/// Get the supplementary groups, or the number of them if `size` is 0
+pub fn sys_getgroups(size: usize, list: *mut u32) -> SysResult {
+    let proc = process();
+    let groups = &proc.cred.groups;
+    if size == 0 {
+        return Ok(groups.len());
+    }
+    if size < groups.len() {
+        return Err(SysError::EINVAL);
+    }
+    proc.vm().check_write_array(list, groups.len())?;
+    let list = unsafe { slice::from_raw_parts_mut(list, groups.len()) };
+    list.copy_from_slice(groups);
+    Ok(groups.len())
+}
+
+pub fn sys_setgroups(size: usize, list: *const u32) -> SysResult {
+    info!("setgroups: size: {}, list: {:?}", size, list);
+    let mut proc = process();
+    if size > NGROUPS_MAX {
+        return Err(SysError::EINVAL);
+    }
+    proc.vm().check_read_array(list, size)?;
+    let groups = unsafe { slice::from_raw_parts(list, size) }.to_vec();
+    proc.cred.set_groups(groups)?;
+    Ok(0)
+}
+
+/// Set the file mode creation mask, return the previous one
+pub fn sys_umask(mask: usize) -> SysResult {
+    info!("umask: {:#o}", mask);
+    let mut proc = process();
+    Ok(core::mem::replace(&mut proc.umask, mask & 0o777))
 }
 
 /// Exit the current thread
 pub fn sys_exit(exit_code: usize) -> ! {
     let tid = thread::current().id();
     info!("exit: {}, code: {}", tid, exit_code);
+
+    // release robust futexes, then clear the tid and wake up the joiner
+    // ref: http://man7.org/linux/man-pages/man2/set_tid_address.2.html
+    let robust_list = process().robust_lists.remove(&tid);
+    if let Some(head) = robust_list {
+        futex::exit_robust_list(head, tid);
+    }
+    futex::exit_clear_child_tid(current_thread().clear_child_tid);
+    // resume the parent if created by vfork
+    current_thread().vfork_done.take();
+
+    charge_usage();
     let mut proc = process();
     proc.threads.retain(|&id| id != tid);
-
-    // for last thread,
-    // notify parent and fill exit code
-    // avoid deadlock
     let exit = proc.threads.len() == 0;
-    let proc_parent = proc.parent.clone();
-    let pid = proc.pid.get();
     drop(proc);
+    // the last thread turns the process into a zombie
     if exit {
-        if let Some(parent) = proc_parent {
-            let mut parent = parent.lock();
-            parent.child_exit_code.insert(pid, exit_code);
-            parent.child_exit.notify_one();
-        }
-    }
-
-    // perform futex wake 1
-    // ref: http://man7.org/linux/man-pages/man2/set_tid_address.2.html
-    // FIXME: do it in all possible ways a thread can exit
-    //        it has memory access so we can't move it to Thread::drop?
-    let clear_child_tid = current_thread().clear_child_tid;
-    if clear_child_tid != 0 {
-        unsafe {
-            (clear_child_tid as *mut u32).write(0);
-        }
-        let queue = process().get_futex(clear_child_tid);
-        queue.notify_one();
+        exit_process(&current_thread().proc, WaitStatus::Exited(exit_code));
     }
 
     processor().manager().exit(tid, exit_code as usize);
@@ -276,38 +1062,37 @@ pub fn sys_exit(exit_code: usize) -> ! {
 
 /// Exit the current thread group (i.e. process)
 pub fn sys_exit_group(exit_code: usize) -> ! {
-    let proc = process();
-    info!("exit_group: {}, code: {}", proc.pid, exit_code);
+    exit_group(WaitStatus::Exited(exit_code))
+}
+
+/// Exit all threads of the current process, which becomes a zombie with `status`
+pub fn exit_group(status: WaitStatus) -> ! {
+    charge_usage();
+    let mut proc = process();
+    info!("exit_group: {}, status: {:?}", proc.pid, status);
 
     // quit all threads
     for tid in proc.threads.iter() {
-        processor().manager().exit(*tid, exit_code);
+        futex::remove_waiter(*tid);
+        processor().manager().exit(*tid, status.encode() as usize);
     }
-
-    // notify parent and fill exit code
-    // avoid deadlock
-    let proc_parent = proc.parent.clone();
-    let pid = proc.pid.get();
+    let robust_lists = core::mem::replace(&mut proc.robust_lists, BTreeMap::new());
     drop(proc);
-    if let Some(parent) = proc_parent {
-        let mut parent = parent.lock();
-        parent.child_exit_code.insert(pid, exit_code);
-        parent.child_exit.notify_one();
+    // resume the parent if created by vfork
+    current_thread().vfork_done.take();
+
+    // release robust futexes of all threads, which may be shared with other processes.
+    // clear_child_tid is not cleared, as the memory is going away.
+    for (tid, head) in robust_lists {
+        futex::exit_robust_list(head, tid);
     }
 
+    exit_process(&current_thread().proc, status);
+
     processor().yield_now();
     unreachable!();
 }
 
-pub fn sys_nanosleep(req: *const TimeSpec) -> SysResult {
-    process().vm.check_read_ptr(req)?;
-    let time = unsafe { req.read() };
-    info!("nanosleep: time: {:#?}", time);
-    // TODO: handle spurious wakeup
-    thread::sleep(time.to_duration());
-    Ok(0)
-}
-
 pub fn sys_set_priority(priority: usize) -> SysResult {
     let pid = thread::current().id();
     processor().manager().set_priority(pid, priority as u8);
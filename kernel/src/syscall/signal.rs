@@ -0,0 +1,188 @@
+//! Syscalls for signal
+
+use super::*;
+use crate::signal::*;
+
+pub fn sys_rt_sigaction(
+    signum: usize,
+    act: *const SigAction,
+    oldact: *mut SigAction,
+    sigsetsize: usize,
+) -> SysResult {
+    info!(
+        "rt_sigaction: signum: {}, act: {:?}, oldact: {:?}, sigsetsize: {}",
+        signum, act, oldact, sigsetsize
+    );
+    if !is_valid(signum) || sigsetsize != SIGSET_SIZE {
+        return Err(SysError::EINVAL);
+    }
+    let mut proc = process();
+    if !oldact.is_null() {
+        proc.vm().check_write_ptr(oldact)?;
+    }
+    let new_action = if act.is_null() {
+        None
+    } else {
+        if signum == SIGKILL || signum == SIGSTOP {
+            return Err(SysError::EINVAL);
+        }
+        proc.vm().check_read_ptr(act)?;
+        let mut action = SignalAction::from(unsafe { act.read() });
+        action.mask = action.mask.without(Sigset::unblockable());
+        Some(action)
+    };
+    if !oldact.is_null() {
+        unsafe {
+            oldact.write(SigAction::from(proc.sig_actions()[signum]));
+        }
+    }
+    if let Some(action) = new_action {
+        proc.sig_actions()[signum] = action;
+        // discard pending signals which are ignored now
+        if action.handler == SIG_IGN
+            || (action.handler == SIG_DFL && default_action(signum) == DefaultAction::Ignore)
+        {
+            proc.sig_pending.remove(signum);
+            for pending in proc.sig_pending_threads.values_mut() {
+                pending.remove(signum);
+            }
+        }
+    }
+    Ok(0)
+}
+
+pub fn sys_rt_sigprocmask(
+    how: usize,
+    set: *const Sigset,
+    oldset: *mut Sigset,
+    sigsetsize: usize,
+) -> SysResult {
+    info!(
+        "rt_sigprocmask: how: {}, set: {:?}, oldset: {:?}, sigsetsize: {}",
+        how, set, oldset, sigsetsize
+    );
+    if sigsetsize != SIGSET_SIZE {
+        return Err(SysError::EINVAL);
+    }
+    let new_set = {
+        let proc = process();
+        if !oldset.is_null() {
+            proc.vm()
+                .check_write_array(oldset as *mut u8, SIGSET_SIZE)?;
+        }
+        if set.is_null() {
+            None
+        } else {
+            proc.vm().check_read_ptr(set)?;
+            Some(unsafe { set.read() })
+        }
+    };
+    let thread = current_thread();
+    let old_mask = thread.sig_mask;
+    if let Some(set) = new_set {
+        let mut mask = old_mask;
+        match how {
+            SIG_BLOCK => mask.add_set(set),
+            SIG_UNBLOCK => mask.remove_set(set),
+            SIG_SETMASK => mask = set,
+            _ => return Err(SysError::EINVAL),
+        }
+        thread.sig_mask = mask.without(Sigset::unblockable());
+    }
+    if !oldset.is_null() {
+        unsafe {
+            write_sigset(oldset, old_mask);
+        }
+    }
+    Ok(0)
+}
+
+pub fn sys_rt_sigpending(set: *mut Sigset, sigsetsize: usize) -> SysResult {
+    info!("rt_sigpending: set: {:?}, sigsetsize: {}", set, sigsetsize);
+    if sigsetsize > SIGSET_SIZE {
+        return Err(SysError::EINVAL);
+    }
+    let tid = thread::current().id();
+    let proc = process();
+    proc.vm().check_write_array(set as *mut u8, SIGSET_SIZE)?;
+    let mut pending = proc.sig_pending;
+    if let Some(&thread_pending) = proc.sig_pending_threads.get(&tid) {
+        pending.add_set(thread_pending);
+    }
+    // only blocked signals can stay pending
+    let blocked = pending.intersect(current_thread().sig_mask);
+    unsafe {
+        write_sigset(set, blocked);
+    }
+    Ok(0)
+}
+
+pub fn sys_sigaltstack(
+    ss: *const SignalStack,
+    old_ss: *mut SignalStack,
+    tf: &TrapFrame,
+) -> SysResult {
+    info!("sigaltstack: ss: {:?}, old_ss: {:?}", ss, old_ss);
+    let new_stack = {
+        let proc = process();
+        if !old_ss.is_null() {
+            proc.vm().check_write_ptr(old_ss)?;
+        }
+        if ss.is_null() {
+            None
+        } else {
+            proc.vm().check_read_ptr(ss)?;
+            Some(unsafe { ss.read() })
+        }
+    };
+    let thread = current_thread();
+    let old_stack = thread.sig_alt_stack;
+    let on_stack = old_stack.contains(tf.get_sp());
+    if let Some(mut stack) = new_stack {
+        if on_stack {
+            return Err(SysError::EPERM);
+        }
+        match stack.flags {
+            // SS_ONSTACK is accepted for compatibility
+            0 | SS_ONSTACK => {
+                if stack.size < MINSIGSTKSZ {
+                    return Err(SysError::ENOMEM);
+                }
+                stack.flags = 0;
+            }
+            SS_DISABLE => {
+                stack = SignalStack::default();
+            }
+            _ => return Err(SysError::EINVAL),
+        }
+        thread.sig_alt_stack = stack;
+    }
+    if !old_ss.is_null() {
+        let mut old_stack = old_stack;
+        if on_stack {
+            old_stack.flags |= SS_ONSTACK;
+        }
+        unsafe {
+            old_ss.write(old_stack);
+        }
+    }
+    Ok(0)
+}
+
+/// Return from signal handler, the TrapFrame is restored from the signal frame
+pub fn sys_rt_sigreturn(tf: &mut TrapFrame) -> SysResult {
+    info!("rt_sigreturn: sp: {:#x}", tf.get_sp());
+    if !restore_signal_frame(tf) {
+        error!("rt_sigreturn: bad signal frame");
+        let dumped = dump_core();
+        exit_group(WaitStatus::Signaled(SIGSEGV, dumped));
+    }
+    Ok(0)
+}
+
+/// Write `set` to user space, and clear the remaining bytes of `sigset_t`
+unsafe fn write_sigset(ptr: *mut Sigset, set: Sigset) {
+    let bytes = ptr as *mut u8;
+    bytes.write_bytes(0, SIGSET_SIZE);
+    ptr.write_unaligned(set);
+}
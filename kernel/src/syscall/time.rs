@@ -1,15 +1,13 @@
 //! Syscalls for time
 
 use super::*;
-use crate::consts::USEC_PER_TICK;
+use crate::fs::{FileLike, TimerFd};
+use crate::signal::{has_signal_to_handle, SIGALRM};
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::timer::{self, Timeout};
 use core::time::Duration;
 use lazy_static::lazy_static;
-
-/// should be initialized together
-lazy_static! {
-    pub static ref EPOCH_BASE: u64 = crate::arch::timer::read_epoch();
-    pub static ref TICK_BASE: u64 = unsafe { crate::trap::TICK as u64 };
-}
+use rcore_fs::vfs::Timespec;
 
 // 1ms msec
 // 1us usec
@@ -19,18 +17,98 @@ const USEC_PER_SEC: u64 = 1_000_000;
 const MSEC_PER_SEC: u64 = 1_000;
 const USEC_PER_MSEC: u64 = 1_000;
 const NSEC_PER_USEC: u64 = 1_000;
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+pub const CLOCK_REALTIME: usize = 0;
+pub const CLOCK_MONOTONIC: usize = 1;
+pub const CLOCK_PROCESS_CPUTIME_ID: usize = 2;
+pub const CLOCK_THREAD_CPUTIME_ID: usize = 3;
+pub const CLOCK_MONOTONIC_RAW: usize = 4;
+pub const CLOCK_REALTIME_COARSE: usize = 5;
+pub const CLOCK_MONOTONIC_COARSE: usize = 6;
+pub const CLOCK_BOOTTIME: usize = 7;
+
+/// Interpret `value` as absolute, for `clock_nanosleep` and `timer_settime`
+const TIMER_ABSTIME: usize = 1;
+
+/// The realtime clock reads the realtime at a monotonic time,
+/// plus the monotonic time elapsed since then
+struct RealtimeBase {
+    realtime: Duration,
+    monotonic: Duration,
+}
+
+lazy_static! {
+    /// Read from the RTC at first, then reset when the realtime clock is set
+    static ref REALTIME_BASE: Mutex<RealtimeBase> = Mutex::new(RealtimeBase {
+        realtime: Duration::from_secs(crate::arch::timer::read_epoch()),
+        monotonic: monotonic(),
+    });
+}
+
+/// Time since boot by the cycle counter, which is never set.
+/// It is the boot time as well, as the system never suspends.
+pub fn monotonic() -> Duration {
+    crate::arch::timer::timer_now()
+}
 
-/// Get time since epoch in usec
-fn get_epoch_usec() -> u64 {
-    let tick_base = *TICK_BASE;
-    let epoch_base = *EPOCH_BASE;
-    let tick = unsafe { crate::trap::TICK as u64 };
+/// Time since epoch
+pub fn realtime() -> Duration {
+    realtime_at(monotonic())
+}
+
+/// Time since epoch at the `monotonic` time
+pub fn realtime_at(monotonic: Duration) -> Duration {
+    let base = REALTIME_BASE.lock();
+    let elapsed = monotonic.checked_sub(base.monotonic);
+    base.realtime + elapsed.unwrap_or_default()
+}
+
+/// Set the realtime clock to `time` since epoch, along with the RTC and the vDSO
+fn set_realtime(time: Duration) {
+    *REALTIME_BASE.lock() = RealtimeBase {
+        realtime: time,
+        monotonic: monotonic(),
+    };
+    crate::arch::timer::write_epoch(time.as_secs());
+    crate::vdso::update();
+}
 
-    (tick - tick_base) * USEC_PER_TICK as u64 + epoch_base * USEC_PER_SEC
+/// Read `clock`
+fn clock_now(clock: usize) -> Result<Duration, SysError> {
+    match clock {
+        CLOCK_REALTIME | CLOCK_REALTIME_COARSE => Ok(realtime()),
+        CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_MONOTONIC_COARSE | CLOCK_BOOTTIME => {
+            Ok(monotonic())
+        }
+        CLOCK_PROCESS_CPUTIME_ID => Ok(process_usage().cpu_time()),
+        CLOCK_THREAD_CPUTIME_ID => Ok(current_thread().usage.read().cpu_time()),
+        // CPU clocks of other processes and threads are not supported
+        _ => Err(SysError::EINVAL),
+    }
+}
+
+/// The tick when `clock` reaches `time`, or now if it has passed.
+/// `clock` is one that can measure timers.
+pub fn clock_to_tick(clock: usize, time: Duration) -> usize {
+    let now = match clock {
+        CLOCK_REALTIME => realtime(),
+        _ => monotonic(),
+    };
+    let ticks = timer::duration_to_ticks(time.checked_sub(now).unwrap_or_default());
+    timer::now().saturating_add(ticks)
+}
+
+/// Check `clock` can measure timers and sleeps
+fn check_timer_clock(clock: usize) -> Result<(), SysError> {
+    match clock {
+        CLOCK_REALTIME | CLOCK_MONOTONIC | CLOCK_BOOTTIME => Ok(()),
+        _ => Err(SysError::EINVAL),
+    }
 }
 
 #[repr(C)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone)]
 pub struct TimeVal {
     sec: u64,
     usec: u64,
@@ -41,17 +119,37 @@ impl TimeVal {
         self.sec * MSEC_PER_SEC + self.usec / USEC_PER_MSEC
     }
 
-    pub fn get_epoch() -> Self {
-        let usec = get_epoch_usec();
+    pub fn to_duration(&self) -> Duration {
+        Duration::new(self.sec, (self.usec * NSEC_PER_USEC) as u32)
+    }
+
+    /// Whether the microseconds are in range
+    pub fn is_valid(&self) -> bool {
+        self.usec < USEC_PER_SEC
+    }
+
+    fn to_ticks(&self) -> usize {
+        timer::duration_to_ticks(self.to_duration())
+    }
+
+    fn from_ticks(ticks: usize) -> Self {
+        TimeVal::from_duration(timer::ticks_to_duration(ticks))
+    }
+
+    pub fn from_duration(time: Duration) -> Self {
         TimeVal {
-            sec: usec / USEC_PER_SEC,
-            usec: usec % USEC_PER_SEC,
+            sec: time.as_secs(),
+            usec: time.subsec_micros() as u64,
         }
     }
+
+    pub fn get_epoch() -> Self {
+        TimeVal::from_duration(realtime())
+    }
 }
 
 #[repr(C)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone)]
 pub struct TimeSpec {
     sec: u64,
     nsec: u64,
@@ -62,11 +160,41 @@ impl TimeSpec {
         Duration::new(self.sec, self.nsec as u32)
     }
 
-    pub fn get_epoch() -> Self {
-        let usec = get_epoch_usec();
+    /// Whether the nanoseconds are in range
+    pub fn is_valid(&self) -> bool {
+        self.nsec < NSEC_PER_SEC
+    }
+
+    fn to_ticks(&self) -> usize {
+        timer::duration_to_ticks(self.to_duration())
+    }
+
+    fn from_ticks(ticks: usize) -> Self {
+        TimeSpec::from_duration(timer::ticks_to_duration(ticks))
+    }
+
+    pub fn from_duration(time: Duration) -> Self {
         TimeSpec {
-            sec: usec / USEC_PER_SEC,
-            nsec: usec % USEC_PER_SEC * NSEC_PER_USEC,
+            sec: time.as_secs(),
+            nsec: time.subsec_nanos() as u64,
+        }
+    }
+
+    /// The tick when it passes from now
+    pub fn deadline(&self) -> usize {
+        timer::now().saturating_add(self.to_ticks())
+    }
+
+    pub fn get_epoch() -> Self {
+        TimeSpec::from_duration(realtime())
+    }
+}
+
+impl From<TimeSpec> for Timespec {
+    fn from(time: TimeSpec) -> Self {
+        Timespec {
+            sec: time.sec as _,
+            nsec: time.nsec as _,
         }
     }
 }
@@ -78,7 +206,7 @@ pub fn sys_gettimeofday(tv: *mut TimeVal, tz: *const u8) -> SysResult {
     }
 
     let proc = process();
-    proc.vm.check_write_ptr(tv)?;
+    proc.vm().check_write_ptr(tv)?;
 
     let timeval = TimeVal::get_epoch();
     unsafe {
@@ -87,24 +215,78 @@ pub fn sys_gettimeofday(tv: *mut TimeVal, tz: *const u8) -> SysResult {
     Ok(0)
 }
 
+/// Set the realtime clock, only by root
+pub fn sys_settimeofday(tv: *const TimeVal, tz: *const u8) -> SysResult {
+    info!("settimeofday: tv: {:?}, tz: {:?}", tv, tz);
+    if tz as usize != 0 {
+        return Err(SysError::EINVAL);
+    }
+
+    let proc = process();
+    proc.vm().check_read_ptr(tv)?;
+    let timeval = unsafe { tv.read() };
+    if !timeval.is_valid() {
+        return Err(SysError::EINVAL);
+    }
+    if !proc.cred.is_root() {
+        return Err(SysError::EPERM);
+    }
+    drop(proc);
+    set_realtime(timeval.to_duration());
+    Ok(0)
+}
+
 pub fn sys_clock_gettime(clock: usize, ts: *mut TimeSpec) -> SysResult {
     info!("clock_gettime: clock: {:?}, ts: {:?}", clock, ts);
 
+    let time = clock_now(clock)?;
     let proc = process();
-    proc.vm.check_write_ptr(ts)?;
+    proc.vm().check_write_ptr(ts)?;
 
-    let timespec = TimeSpec::get_epoch();
+    let timespec = TimeSpec::from_duration(time);
     unsafe {
         *ts = timespec;
     }
     Ok(0)
 }
 
+/// Only the realtime clock can be set, by root
+pub fn sys_clock_settime(clock: usize, ts: *const TimeSpec) -> SysResult {
+    info!("clock_settime: clock: {:?}, ts: {:?}", clock, ts);
+    if clock != CLOCK_REALTIME {
+        return Err(SysError::EINVAL);
+    }
+
+    let proc = process();
+    proc.vm().check_read_ptr(ts)?;
+    let timespec = unsafe { ts.read() };
+    if !timespec.is_valid() {
+        return Err(SysError::EINVAL);
+    }
+    if !proc.cred.is_root() {
+        return Err(SysError::EPERM);
+    }
+    drop(proc);
+    set_realtime(timespec.to_duration());
+    Ok(0)
+}
+
+/// All clocks are read in nanoseconds
+pub fn sys_clock_getres(clock: usize, res: *mut TimeSpec) -> SysResult {
+    info!("clock_getres: clock: {:?}, res: {:?}", clock, res);
+    clock_now(clock)?;
+    if !res.is_null() {
+        process().vm().check_write_ptr(res)?;
+        unsafe { res.write(TimeSpec { sec: 0, nsec: 1 }) };
+    }
+    Ok(0)
+}
+
 pub fn sys_time(time: *mut u64) -> SysResult {
-    let sec = get_epoch_usec() / USEC_PER_SEC;
+    let sec = realtime().as_secs();
     if time as usize != 0 {
         let proc = process();
-        proc.vm.check_write_ptr(time)?;
+        proc.vm().check_write_ptr(time)?;
         unsafe {
             time.write(sec as u64);
         }
@@ -112,32 +294,421 @@ pub fn sys_time(time: *mut u64) -> SysResult {
     Ok(sec as usize)
 }
 
-// ignore other fields for now
+/// Resource usage of `getrusage` and `wait4`, where the unmaintained fields are zero
 #[repr(C)]
+#[derive(Debug, Default)]
 pub struct RUsage {
     utime: TimeVal,
     stime: TimeVal,
+    maxrss: usize, // in kilobytes
+    ixrss: usize,
+    idrss: usize,
+    isrss: usize,
+    minflt: usize,
+    majflt: usize,
+    nswap: usize,
+    inblock: usize,
+    oublock: usize,
+    msgsnd: usize,
+    msgrcv: usize,
+    nsignals: usize,
+    nvcsw: usize,
+    nivcsw: usize,
 }
 
+impl RUsage {
+    pub fn from_usage(usage: &Usage) -> Self {
+        RUsage {
+            utime: TimeVal::from_duration(usage.utime),
+            stime: TimeVal::from_duration(usage.stime),
+            maxrss: usage.maxrss / 1024,
+            minflt: usage.minflt,
+            majflt: usage.majflt,
+            nvcsw: usage.nvcsw,
+            nivcsw: usage.nivcsw,
+            ..RUsage::default()
+        }
+    }
+}
+
+/// Get the resource usage of the current process, its reaped children,
+/// or the current thread
 pub fn sys_getrusage(who: usize, rusage: *mut RUsage) -> SysResult {
     info!("getrusage: who: {}, rusage: {:?}", who, rusage);
+    const RUSAGE_SELF: isize = 0;
+    const RUSAGE_CHILDREN: isize = -1;
+    const RUSAGE_THREAD: isize = 1;
+
+    process().vm().check_write_ptr(rusage)?;
+    let who = who as isize;
+    let mut usage = match who {
+        RUSAGE_SELF => process_usage(),
+        RUSAGE_CHILDREN => process().children_usage,
+        RUSAGE_THREAD => current_thread().usage.read(),
+        _ => return Err(SysError::EINVAL),
+    };
+    // the max resident set size of a thread is of its process
+    if who != RUSAGE_CHILDREN {
+        let mut proc = process();
+        update_maxrss(&mut proc);
+        usage.maxrss = proc.usage.maxrss;
+    }
+    unsafe { rusage.write(RUsage::from_usage(&usage)) };
+    Ok(0)
+}
+
+/// CPU time of a process and its reaped children, in clock ticks
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct Tms {
+    utime: usize,
+    stime: usize,
+    cutime: usize,
+    cstime: usize,
+}
+
+/// Clock ticks per second of `times`, i.e. `sysconf(_SC_CLK_TCK)`
+const USER_HZ: u64 = 100;
+
+/// `time` in clock ticks of `times`
+fn clock_ticks(time: Duration) -> usize {
+    let ticks = time.as_secs() * USER_HZ + time.subsec_nanos() as u64 * USER_HZ / NSEC_PER_SEC;
+    ticks as usize
+}
+
+/// Get the CPU time of the current process and its reaped children to `buf` if not null.
+/// Return the clock ticks since boot.
+pub fn sys_times(buf: *mut Tms) -> SysResult {
+    info!("times: buf: {:?}", buf);
+    if !buf.is_null() {
+        process().vm().check_write_ptr(buf)?;
+        let usage = process_usage();
+        let children = process().children_usage;
+        let tms = Tms {
+            utime: clock_ticks(usage.utime),
+            stime: clock_ticks(usage.stime),
+            cutime: clock_ticks(children.utime),
+            cstime: clock_ticks(children.stime),
+        };
+        unsafe { buf.write(tms) };
+    }
+    Ok(clock_ticks(monotonic()))
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ITimerVal {
+    interval: TimeVal,
+    value: TimeVal,
+}
+
+impl ITimerVal {
+    fn from_ticks((value, interval): (usize, usize)) -> Self {
+        ITimerVal {
+            interval: TimeVal::from_ticks(interval),
+            value: TimeVal::from_ticks(value),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ITimerSpec {
+    interval: TimeSpec,
+    value: TimeSpec,
+}
+
+impl ITimerSpec {
+    fn from_ticks((value, interval): (usize, usize)) -> Self {
+        ITimerSpec {
+            interval: TimeSpec::from_ticks(interval),
+            value: TimeSpec::from_ticks(value),
+        }
+    }
+
+    /// The tick to expire at, of the value relative to now,
+    /// or absolute by `clock`. `None` if it is zero, i.e. disarmed.
+    fn deadline(&self, clock: usize, absolute: bool) -> Option<usize> {
+        if self.value.sec == 0 && self.value.nsec == 0 {
+            return None;
+        }
+        Some(match absolute {
+            true => clock_to_tick(clock, self.value.to_duration()),
+            false => self.value.deadline(),
+        })
+    }
+}
+
+/// Read a valid `ITimerSpec` from user space
+fn read_itimerspec(ptr: *const ITimerSpec) -> Result<ITimerSpec, SysError> {
+    process().vm().check_read_ptr(ptr)?;
+    let spec = unsafe { ptr.read() };
+    if !spec.value.is_valid() || !spec.interval.is_valid() {
+        return Err(SysError::EINVAL);
+    }
+    Ok(spec)
+}
+
+/// Write the value and interval of a timer to user space, unless `ptr` is null
+fn write_itimerspec(ptr: *mut ITimerSpec, timer: (usize, usize)) -> Result<(), SysError> {
+    if !ptr.is_null() {
+        process().vm().check_write_ptr(ptr)?;
+        unsafe { ptr.write(ITimerSpec::from_ticks(timer)) };
+    }
+    Ok(())
+}
+
+pub fn sys_getitimer(which: usize, value: *mut ITimerVal) -> SysResult {
+    info!("getitimer: which: {}, value: {:?}", which, value);
     let proc = process();
-    proc.vm.check_write_ptr(rusage)?;
-
-    let tick_base = *TICK_BASE;
-    let tick = unsafe { crate::trap::TICK as u64 };
-
-    let usec = (tick - tick_base) * USEC_PER_TICK as u64;
-    let new_rusage = RUsage {
-        utime: TimeVal {
-            sec: usec / USEC_PER_SEC,
-            usec: usec % USEC_PER_SEC,
-        },
-        stime: TimeVal {
-            sec: usec / USEC_PER_SEC,
-            usec: usec % USEC_PER_SEC,
-        },
+    proc.vm().check_write_ptr(value)?;
+    let timer = proc.itimers.get(which)?;
+    unsafe { value.write(ITimerVal::from_ticks(timer)) };
+    Ok(0)
+}
+
+pub fn sys_setitimer(which: usize, new: *const ITimerVal, old: *mut ITimerVal) -> SysResult {
+    info!(
+        "setitimer: which: {}, new: {:?}, old: {:?}",
+        which, new, old
+    );
+    let thread = current_thread();
+    let mut proc = thread.proc.lock();
+    proc.vm().check_read_ptr(new)?;
+    let new = unsafe { new.read() };
+    if !new.value.is_valid() || !new.interval.is_valid() {
+        return Err(SysError::EINVAL);
+    }
+    if !old.is_null() {
+        proc.vm().check_write_ptr(old)?;
+    }
+    let (value, interval) = (new.value.to_ticks(), new.interval.to_ticks());
+    let timer = proc.itimers.set(which, value, interval, &thread.proc)?;
+    if !old.is_null() {
+        unsafe { old.write(ITimerVal::from_ticks(timer)) };
+    }
+    Ok(0)
+}
+
+/// Send `SIGALRM` after `seconds`, or cancel it if 0.
+/// Return the seconds remaining of the previous one.
+pub fn sys_alarm(seconds: usize) -> SysResult {
+    info!("alarm: seconds: {}", seconds);
+    let thread = current_thread();
+    let ticks = timer::duration_to_ticks(Duration::from_secs(seconds as u64));
+    let (old, _) = thread
+        .proc
+        .lock()
+        .itimers
+        .set(ITIMER_REAL, ticks, 0, &thread.proc)?;
+    // rounded to seconds, but a pending one is never 0
+    let old = timer::ticks_to_duration(old);
+    let secs = old.as_secs() + (old.subsec_micros() >= 500_000) as u64;
+    match secs {
+        0 if old > Duration::default() => Ok(1),
+        secs => Ok(secs as usize),
+    }
+}
+
+/// Sleep until `timeout` expires, fail with EINTR if a signal is to be handled
+fn sleep(timeout: &Timeout) -> SysResult {
+    while !timeout.is_expired() {
+        if has_signal_to_handle() {
+            return Err(SysError::EINTR);
+        }
+        timeout.wait_any(&[]);
+    }
+    Ok(0)
+}
+
+pub fn sys_nanosleep(req: *const TimeSpec, rem: *mut TimeSpec) -> SysResult {
+    sys_clock_nanosleep(CLOCK_MONOTONIC, 0, req, rem)
+}
+
+/// Sleep for `req`, or until `clock` reaches `req` if `TIMER_ABSTIME`.
+/// The time remaining is written to `rem` if interrupted by a signal.
+pub fn sys_clock_nanosleep(
+    clock: usize,
+    flags: usize,
+    req: *const TimeSpec,
+    rem: *mut TimeSpec,
+) -> SysResult {
+    info!(
+        "clock_nanosleep: clock: {}, flags: {:#x}, req: {:?}, rem: {:?}",
+        clock, flags, req, rem
+    );
+    check_timer_clock(clock)?;
+    process().vm().check_read_ptr(req)?;
+    let time = unsafe { req.read() };
+    if !time.is_valid() {
+        return Err(SysError::EINVAL);
+    }
+    let absolute = flags & TIMER_ABSTIME != 0;
+    let timeout = match absolute {
+        true => Timeout::at(clock_to_tick(clock, time.to_duration())),
+        false => Timeout::at(time.deadline()),
     };
-    unsafe { *rusage = new_rusage };
+    let ret = sleep(&timeout);
+    if ret.is_err() && !absolute && !rem.is_null() {
+        process().vm().check_write_ptr(rem)?;
+        unsafe { rem.write(TimeSpec::from_ticks(timeout.remaining())) };
+    }
+    ret
+}
+
+/// `struct sigevent`, without the fields of `SIGEV_THREAD` done by libc
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct SigEvent {
+    value: usize,
+    signo: i32,
+    notify: i32,
+    tid: i32,
+}
+
+const SIGEV_SIGNAL: i32 = 0;
+const SIGEV_NONE: i32 = 1;
+const SIGEV_THREAD_ID: i32 = 4;
+
+/// Create a POSIX timer notified as `event`, or by `SIGALRM` if it is null.
+/// The id of the timer is written to `timer_id`.
+pub fn sys_timer_create(clock: usize, event: *const SigEvent, timer_id: *mut i32) -> SysResult {
+    info!(
+        "timer_create: clock: {}, event: {:?}, timer_id: {:?}",
+        clock, event, timer_id
+    );
+    check_timer_clock(clock)?;
+    let mut proc = process();
+    let (sig, tid) = if event.is_null() {
+        (Some(SIGALRM), None)
+    } else {
+        proc.vm().check_read_ptr(event)?;
+        let event = unsafe { event.read() };
+        let sig = event.signo as usize;
+        match event.notify {
+            SIGEV_NONE => (None, None),
+            SIGEV_SIGNAL if crate::signal::is_valid(sig) => (Some(sig), None),
+            SIGEV_THREAD_ID if crate::signal::is_valid(sig) => {
+                let tid = event.tid as usize;
+                if !proc.threads.contains(&tid) {
+                    return Err(SysError::EINVAL);
+                }
+                (Some(sig), Some(tid))
+            }
+            _ => return Err(SysError::EINVAL),
+        }
+    };
+    proc.vm().check_write_ptr(timer_id)?;
+    let id = (0..).find(|id| !proc.timers.contains_key(id)).unwrap();
+    proc.timers.insert(id, PosixTimer::new(clock, sig, tid));
+    unsafe { timer_id.write(id as i32) };
+    Ok(0)
+}
+
+pub fn sys_timer_settime(
+    timer_id: usize,
+    flags: usize,
+    new: *const ITimerSpec,
+    old: *mut ITimerSpec,
+) -> SysResult {
+    info!(
+        "timer_settime: timer_id: {}, flags: {:#x}, new: {:?}, old: {:?}",
+        timer_id, flags, new, old
+    );
+    let new = read_itimerspec(new)?;
+    let thread = current_thread();
+    let mut proc = thread.proc.lock();
+    let timer = proc.timers.get_mut(&timer_id).ok_or(SysError::EINVAL)?;
+    let deadline = new.deadline(timer.clock, flags & TIMER_ABSTIME != 0);
+    let old_timer = timer.set(deadline, new.interval.to_ticks(), &thread.proc);
+    drop(proc);
+    write_itimerspec(old, old_timer)?;
+    Ok(0)
+}
+
+pub fn sys_timer_gettime(timer_id: usize, curr: *mut ITimerSpec) -> SysResult {
+    info!("timer_gettime: timer_id: {}, curr: {:?}", timer_id, curr);
+    let timer = process()
+        .timers
+        .get(&timer_id)
+        .ok_or(SysError::EINVAL)?
+        .get();
+    process().vm().check_write_ptr(curr)?;
+    unsafe { curr.write(ITimerSpec::from_ticks(timer)) };
+    Ok(0)
+}
+
+/// Signals are not queued, so expirations while one is pending are not counted
+pub fn sys_timer_getoverrun(timer_id: usize) -> SysResult {
+    info!("timer_getoverrun: timer_id: {}", timer_id);
+    match process().timers.contains_key(&timer_id) {
+        true => Ok(0),
+        false => Err(SysError::EINVAL),
+    }
+}
+
+pub fn sys_timer_delete(timer_id: usize) -> SysResult {
+    info!("timer_delete: timer_id: {}", timer_id);
+    let timer = process().timers.remove(&timer_id);
+    timer.map(|_| 0).ok_or(SysError::EINVAL)
+}
+
+#[cfg(target_arch = "mips")]
+const TFD_NONBLOCK: usize = 0x80;
+#[cfg(not(target_arch = "mips"))]
+const TFD_NONBLOCK: usize = 0o4000;
+const TFD_CLOEXEC: usize = 0o2000000;
+
+const TFD_TIMER_ABSTIME: usize = 1;
+/// Cancel reads when the realtime clock is set, which is not supported yet
+const TFD_TIMER_CANCEL_ON_SET: usize = 2;
+
+pub fn sys_timerfd_create(clock: usize, flags: usize) -> SysResult {
+    info!("timerfd_create: clock: {}, flags: {:#x}", clock, flags);
+    check_timer_clock(clock)?;
+    if flags & !(TFD_NONBLOCK | TFD_CLOEXEC) != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let proc = process();
+    let fd = proc.get_free_fd()?;
+    let timer = TimerFd::new(clock, flags & TFD_NONBLOCK != 0);
+    proc.files().insert(fd, FileLike::TimerFd(timer));
+    Ok(fd)
+}
+
+/// The timer of timer file `fd`
+fn timer_fd(fd: usize) -> Result<TimerFd, SysError> {
+    match process().files().get_file_like(fd)? {
+        FileLike::TimerFd(timer) => Ok(timer.clone()),
+        _ => Err(SysError::EINVAL),
+    }
+}
+
+pub fn sys_timerfd_settime(
+    fd: usize,
+    flags: usize,
+    new: *const ITimerSpec,
+    old: *mut ITimerSpec,
+) -> SysResult {
+    info!(
+        "timerfd_settime: fd: {}, flags: {:#x}, new: {:?}, old: {:?}",
+        fd, flags, new, old
+    );
+    if flags & !(TFD_TIMER_ABSTIME | TFD_TIMER_CANCEL_ON_SET) != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let timer = timer_fd(fd)?;
+    let new = read_itimerspec(new)?;
+    let deadline = new.deadline(timer.clock, flags & TFD_TIMER_ABSTIME != 0);
+    let old_timer = timer.set(deadline, new.interval.to_ticks());
+    write_itimerspec(old, old_timer)?;
+    Ok(0)
+}
+
+pub fn sys_timerfd_gettime(fd: usize, curr: *mut ITimerSpec) -> SysResult {
+    info!("timerfd_gettime: fd: {}, curr: {:?}", fd, curr);
+    let timer = timer_fd(fd)?.get();
+    process().vm().check_write_ptr(curr)?;
+    unsafe { curr.write(ITimerSpec::from_ticks(timer)) };
     Ok(0)
 }
@@ -0,0 +1,162 @@
+//! Kernel timers on a queue driven by the timer interrupt
+//!
+//! Deadlines are ticks of `trap::TICK`. When a timer expires, its callback is
+//! called in the interrupt context on CPU 0, so it must be short and never block.
+//! A periodic timer is rearmed by its period, and a timer is cancelled when dropped.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
+use core::time::Duration;
+
+use crate::consts::USEC_PER_TICK;
+use crate::sync::{Condvar, SpinNoIrqLock as Mutex};
+
+type Callback = Arc<dyn Fn() + Send + Sync>;
+
+struct Entry {
+    deadline: usize,
+    period: usize,
+    callback: Callback,
+}
+
+#[derive(Default)]
+struct TimerQueue {
+    next_id: usize,
+    /// (deadline, id) of pending timers, in the order of expiration
+    queue: BTreeSet<(usize, usize)>,
+    timers: BTreeMap<usize, Entry>,
+}
+
+lazy_static! {
+    static ref TIMERS: Mutex<TimerQueue> = Mutex::new(TimerQueue::default());
+}
+
+/// The current tick
+pub fn now() -> usize {
+    unsafe { crate::trap::TICK }
+}
+
+/// Round up `time` to ticks, saturated
+pub fn duration_to_ticks(time: Duration) -> usize {
+    let usec = time.as_micros().min(core::usize::MAX as u128) as usize;
+    usec / USEC_PER_TICK + (usec % USEC_PER_TICK != 0) as usize
+}
+
+pub fn ticks_to_duration(ticks: usize) -> Duration {
+    Duration::from_micros((ticks as u64).saturating_mul(USEC_PER_TICK as u64))
+}
+
+/// A timer in the queue, cancelled when dropped.
+///
+/// The callback may still be running on CPU 0 when it is cancelled.
+pub struct Timer(usize);
+
+impl Timer {
+    /// Start a timer expiring at tick `deadline`,
+    /// then every `period` ticks unless it is 0
+    pub fn new(
+        deadline: usize,
+        period: usize,
+        callback: impl Fn() + Send + Sync + 'static,
+    ) -> Self {
+        let mut timers = TIMERS.lock();
+        let id = timers.next_id;
+        timers.next_id += 1;
+        timers.queue.insert((deadline, id));
+        let entry = Entry {
+            deadline,
+            period,
+            callback: Arc::new(callback),
+        };
+        timers.timers.insert(id, entry);
+        Timer(id)
+    }
+
+    /// Ticks until it expires next, or `None` if it is one-shot and has expired
+    pub fn remaining(&self) -> Option<usize> {
+        let timers = TIMERS.lock();
+        let entry = timers.timers.get(&self.0)?;
+        Some(entry.deadline.saturating_sub(now()))
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        // the callback is dropped after unlocking
+        let _entry = {
+            let mut timers = TIMERS.lock();
+            let entry = timers.timers.remove(&self.0);
+            if let Some(entry) = &entry {
+                timers.queue.remove(&(entry.deadline, self.0));
+            }
+            entry
+        };
+    }
+}
+
+/// Call back the timers expired by now, on each tick of CPU 0
+pub fn tick() {
+    let now = now();
+    loop {
+        let callback = {
+            let mut guard = TIMERS.lock();
+            let timers = &mut *guard;
+            let (deadline, id) = match timers.queue.iter().next() {
+                Some(&(deadline, id)) if deadline <= now => (deadline, id),
+                _ => return,
+            };
+            timers.queue.remove(&(deadline, id));
+            let entry = timers.timers.get_mut(&id).unwrap();
+            let callback = entry.callback.clone();
+            if entry.period == 0 {
+                timers.timers.remove(&id);
+            } else {
+                entry.deadline = entry.deadline.saturating_add(entry.period);
+                timers.queue.insert((entry.deadline, id));
+            }
+            callback
+        };
+        callback();
+    }
+}
+
+/// A deadline of waiting, which notifies a condvar when it passes
+pub struct Timeout {
+    deadline: usize,
+    expired: Arc<Condvar>,
+    _timer: Timer,
+}
+
+impl Timeout {
+    /// Time out at tick `deadline`
+    pub fn at(deadline: usize) -> Self {
+        let expired = Arc::new(Condvar::new());
+        let condvar = expired.clone();
+        Timeout {
+            deadline,
+            expired,
+            _timer: Timer::new(deadline, 0, move || condvar.notify_all()),
+        }
+    }
+
+    /// Time out after `time`
+    pub fn after(time: Duration) -> Self {
+        Timeout::at(now().saturating_add(duration_to_ticks(time)))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        now() >= self.deadline
+    }
+
+    /// Ticks until it expires
+    pub fn remaining(&self) -> usize {
+        self.deadline.saturating_sub(now())
+    }
+
+    /// Wait until any of `condvars` is notified, or it expires
+    pub fn wait_any(&self, condvars: &[&Condvar]) {
+        let mut condvars = condvars.to_vec();
+        condvars.push(&self.expired);
+        Condvar::wait_any_unless(&condvars, || self.is_expired());
+    }
+}
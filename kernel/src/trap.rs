@@ -1,21 +1,46 @@
 use crate::arch::cpu;
 use crate::arch::interrupt::TrapFrame;
+use crate::arch::timer::timer_now;
 use crate::process::*;
 use log::*;
 
 pub static mut TICK: usize = 0;
 
 pub fn uptime_msec() -> usize {
-    unsafe { crate::trap::TICK / crate::consts::USEC_PER_TICK / 1000 }
+    unsafe { crate::trap::TICK * crate::consts::USEC_PER_TICK / 1000 }
 }
 
-pub fn timer() {
+pub fn timer(tf: &TrapFrame) {
     if cpu::id() == 0 {
         unsafe {
             TICK += 1;
         }
+        crate::timer::tick();
+        crate::vdso::update();
+        tick_load_avg(unsafe { TICK });
+    }
+    if tf.is_user() {
+        charge_cpu_tick();
+        preempt_tick();
+    } else {
+        processor().tick();
+    }
+}
+
+/// Enter the kernel by a trap from `tf`,
+/// charging the time since returning to user mode as user time
+pub fn enter(tf: &TrapFrame) {
+    if tf.is_user() {
+        current_thread().usage.enter_kernel(timer_now());
+    }
+}
+
+/// Leave the kernel to `tf`,
+/// charging the time since entering the kernel as system time
+pub fn exit(tf: &TrapFrame) {
+    if tf.is_user() {
+        current_thread().usage.exit_kernel(timer_now());
     }
-    processor().tick();
 }
 
 pub fn error(tf: &TrapFrame) -> ! {
@@ -28,11 +53,23 @@ pub fn error(tf: &TrapFrame) -> ! {
     unreachable!();
 }
 
-pub fn serial(c: char) {
-    if c == '\r' {
-        // in linux, we use '\n' instead
-        crate::fs::STDIN.push('\n');
+/// Handle a page fault which can not be fixed up.
+///
+/// A fault from user mode raises `SIGSEGV` to the current thread,
+/// while a fault from kernel mode is fatal.
+pub fn segfault(tf: &TrapFrame) {
+    if tf.is_user() {
+        crate::signal::force_signal(crate::signal::SIGSEGV);
     } else {
-        crate::fs::STDIN.push(c);
+        error(tf);
+    }
+}
+
+/// Input `c` from the serial port or keyboard to the console
+pub fn serial(c: char) {
+    let mut buf = [0u8; 4];
+    for &byte in c.encode_utf8(&mut buf).as_bytes() {
+        // the input is lost if no one reads it
+        let _ = crate::fs::CONSOLE.receive(byte);
     }
 }
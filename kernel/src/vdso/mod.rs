@@ -0,0 +1,19 @@
+//! The vDSO, a shared object mapped to every user program
+//!
+//! It reads the clocks from a data page shared by all processes,
+//! which is updated on every timer tick, so reading time needs no syscall.
+
+#[cfg(not(any(target_arch = "aarch64", target_arch = "mips")))]
+pub use self::time::{push_vdso, update};
+
+#[cfg(not(any(target_arch = "aarch64", target_arch = "mips")))]
+mod time;
+
+/// The vDSO is not provided on this architecture yet
+#[cfg(any(target_arch = "aarch64", target_arch = "mips"))]
+pub fn push_vdso(_vm: &mut crate::memory::MemorySet) -> Option<usize> {
+    None
+}
+
+#[cfg(any(target_arch = "aarch64", target_arch = "mips"))]
+pub fn update() {}
@@ -0,0 +1,119 @@
+//! The vDSO and its data page, with the clocks read by it
+//!
+//! The image is built from `vdso.c` by the Makefile, and linked in by `build.rs`.
+//! The data page is mapped one page below it, where it expects `VdsoData`.
+
+use core::ptr::write_volatile;
+use core::sync::atomic::{fence, Ordering};
+use lazy_static::lazy_static;
+use rcore_memory::paging::{Entry, PageTable};
+use rcore_memory::PAGE_SIZE;
+
+use crate::arch::timer::{cycle_frequency, cycle_to_duration, get_cycle};
+use crate::memory::{active_table, Linear, MemoryAttr, MemorySet};
+use crate::signal::SIGRETURN_TRAMPOLINE_ADDR;
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::syscall::realtime_at;
+
+global_asm!(include_str!("vdso.gen.s"));
+
+extern "C" {
+    fn _vdso_start();
+    fn _vdso_end();
+}
+
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// The clocks at a cycle, read by the vDSO.
+/// Keep it the same as `struct vdso_data` of `vdso.c`.
+#[repr(C)]
+struct VdsoData {
+    /// odd while it is being updated
+    seq: u32,
+    /// the time of `delta` cycles is `delta * mult >> shift` nanoseconds
+    shift: u32,
+    mult: u64,
+    /// the cycle when the clocks below are read
+    cycle: u64,
+    monotonic_sec: u64,
+    monotonic_nsec: u64,
+    realtime_sec: u64,
+    realtime_nsec: u64,
+}
+
+/// It takes a whole page, so nothing else is exposed to user space
+#[repr(C, align(4096))]
+struct DataPage(VdsoData);
+
+static mut DATA_PAGE: DataPage = DataPage(VdsoData {
+    seq: 0,
+    shift: 0,
+    mult: 0,
+    cycle: 0,
+    monotonic_sec: 0,
+    monotonic_nsec: 0,
+    realtime_sec: 0,
+    realtime_nsec: 0,
+});
+
+lazy_static! {
+    /// Updates of the data page, which is read without locking by the vDSO
+    static ref DATA: Mutex<&'static mut VdsoData> = Mutex::new(unsafe { &mut DATA_PAGE.0 });
+}
+
+/// `mult` and `shift` to convert cycles to nanoseconds, with `mult` in 32 bits
+fn cycle_scale() -> (u64, u32) {
+    let freq = cycle_frequency();
+    let mut shift = 32;
+    while (NSEC_PER_SEC << shift) / freq >= 1 << 32 {
+        shift -= 1;
+    }
+    ((NSEC_PER_SEC << shift) / freq, shift)
+}
+
+/// Update the data page to the clocks now,
+/// on every tick and when the realtime clock is set
+pub fn update() {
+    let mut data = DATA.lock();
+    let cycle = get_cycle();
+    let monotonic = cycle_to_duration(cycle);
+    let realtime = realtime_at(monotonic);
+    let (mult, shift) = cycle_scale();
+    let seq = data.seq;
+    unsafe { write_volatile(&mut data.seq, seq.wrapping_add(1)) };
+    fence(Ordering::Release);
+    data.shift = shift;
+    data.mult = mult;
+    data.cycle = cycle;
+    data.monotonic_sec = monotonic.as_secs();
+    data.monotonic_nsec = monotonic.subsec_nanos() as u64;
+    data.realtime_sec = realtime.as_secs();
+    data.realtime_nsec = realtime.subsec_nanos() as u64;
+    fence(Ordering::Release);
+    unsafe { write_volatile(&mut data.seq, seq.wrapping_add(2)) };
+}
+
+/// Map the vDSO and its data page to user space of `vm`, below the sigreturn trampoline.
+/// Return the address of its ELF header, for `AT_SYSINFO_EHDR`.
+pub fn push_vdso(vm: &mut MemorySet) -> Option<usize> {
+    let (start, end) = (_vdso_start as usize, _vdso_end as usize);
+    let addr = SIGRETURN_TRAMPOLINE_ADDR - (end - start);
+    let data = unsafe { &DATA_PAGE as *const DataPage as usize };
+    // shared by all processes, so never made writable by mprotect
+    let attr = MemoryAttr::default().user().readonly().fixed();
+    push_page(vm, addr - PAGE_SIZE, data, attr, "vdso_data");
+    for offset in (0..end - start).step_by(PAGE_SIZE) {
+        push_page(vm, addr + offset, start + offset, attr.execute(), "vdso");
+    }
+    Some(addr)
+}
+
+/// Map page `addr` of `vm` to the frame of kernel page `page`
+fn push_page(vm: &mut MemorySet, addr: usize, page: usize, attr: MemoryAttr, name: &'static str) {
+    let target = active_table()
+        .get_entry(page)
+        .expect("failed to get entry")
+        .target();
+    let offset = target as isize - addr as isize;
+    vm.push(addr, addr + PAGE_SIZE, attr, Linear::new(offset), name);
+}
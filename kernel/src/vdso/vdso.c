@@ -0,0 +1,189 @@
+/*
+ * vDSO of rCore
+ *
+ * Time functions reading the clocks from the data page mapped right below,
+ * which is updated by the kernel on every timer tick.
+ * They fall back to syscalls if the clock is not there.
+ */
+
+#include <stdint.h>
+
+#define NSEC_PER_SEC 1000000000ULL
+
+#define CLOCK_REALTIME 0
+#define CLOCK_MONOTONIC 1
+#define CLOCK_MONOTONIC_RAW 4
+#define CLOCK_REALTIME_COARSE 5
+#define CLOCK_MONOTONIC_COARSE 6
+#define CLOCK_BOOTTIME 7
+
+#if defined(__x86_64__)
+#define SYS_GETTIMEOFDAY 96
+#define SYS_TIME 201
+#define SYS_CLOCK_GETTIME 228
+#elif defined(__riscv)
+#define SYS_GETTIMEOFDAY 169
+#define SYS_CLOCK_GETTIME 113
+#else
+#error "vDSO is not supported on this architecture"
+#endif
+
+struct timespec {
+    long tv_sec;
+    long tv_nsec;
+};
+
+struct timeval {
+    long tv_sec;
+    long tv_usec;
+};
+
+/* `VdsoData` of the kernel */
+struct vdso_data {
+    /* odd while it is being updated */
+    uint32_t seq;
+    /* the time of `delta` cycles is `delta * mult >> shift` nanoseconds */
+    uint32_t shift;
+    uint64_t mult;
+    /* the cycle when the clocks below are read */
+    uint64_t cycle;
+    uint64_t monotonic_sec;
+    uint64_t monotonic_nsec;
+    uint64_t realtime_sec;
+    uint64_t realtime_nsec;
+};
+
+/* defined by the linker script, one page below the ELF header */
+extern const struct vdso_data _vdso_data __attribute__((visibility("hidden")));
+
+static long syscall2(long n, long a0, long a1)
+{
+#if defined(__x86_64__)
+    long ret;
+    __asm__ volatile("syscall"
+                     : "=a"(ret)
+                     : "a"(n), "D"(a0), "S"(a1)
+                     : "rcx", "r11", "memory");
+    return ret;
+#elif defined(__riscv)
+    register long a7 __asm__("a7") = n;
+    register long r0 __asm__("a0") = a0;
+    register long r1 __asm__("a1") = a1;
+    __asm__ volatile("ecall" : "+r"(r0) : "r"(a7), "r"(r1) : "memory");
+    return r0;
+#endif
+}
+
+static inline uint64_t get_cycle(void)
+{
+#if defined(__x86_64__)
+    uint32_t lo, hi;
+    __asm__ volatile("rdtsc" : "=a"(lo), "=d"(hi));
+    return ((uint64_t)hi << 32) | lo;
+#elif __riscv_xlen == 64
+    uint64_t time;
+    __asm__ volatile("rdtime %0" : "=r"(time));
+    return time;
+#else
+    uint32_t lo, hi, tmp;
+    do {
+        __asm__ volatile("rdtimeh %0" : "=r"(hi));
+        __asm__ volatile("rdtime %0" : "=r"(lo));
+        __asm__ volatile("rdtimeh %0" : "=r"(tmp));
+    } while (hi != tmp);
+    return ((uint64_t)hi << 32) | lo;
+#endif
+}
+
+/* Nanoseconds of `delta` cycles, without overflowing */
+static inline uint64_t cycles_to_nsec(const struct vdso_data *data, uint64_t delta)
+{
+    uint64_t mask = ((uint64_t)1 << data->shift) - 1;
+    return (delta >> data->shift) * data->mult + (((delta & mask) * data->mult) >> data->shift);
+}
+
+/* Read the realtime clock if `realtime`, otherwise the monotonic one.
+ * Return -1 if the kernel has not filled the data page. */
+static int read_clock(int realtime, struct timespec *ts)
+{
+    const volatile struct vdso_data *data = &_vdso_data;
+    uint32_t seq;
+    uint64_t sec, nsec, cycle;
+    do {
+        seq = data->seq;
+        __atomic_thread_fence(__ATOMIC_ACQUIRE);
+        if (data->mult == 0) {
+            return -1;
+        }
+        cycle = get_cycle();
+        /* the counter of another CPU may be a bit behind */
+        nsec = cycle > data->cycle ? cycles_to_nsec((const struct vdso_data *)data, cycle - data->cycle) : 0;
+        if (realtime) {
+            sec = data->realtime_sec;
+            nsec += data->realtime_nsec;
+        } else {
+            sec = data->monotonic_sec;
+            nsec += data->monotonic_nsec;
+        }
+        __atomic_thread_fence(__ATOMIC_ACQUIRE);
+    } while ((seq & 1) || seq != data->seq);
+    /* normally less than a tick passed, so avoid division */
+    while (nsec >= NSEC_PER_SEC) {
+        __asm__("" : "+r"(nsec));
+        nsec -= NSEC_PER_SEC;
+        sec++;
+    }
+    ts->tv_sec = sec;
+    ts->tv_nsec = nsec;
+    return 0;
+}
+
+int __vdso_clock_gettime(long clock, struct timespec *ts)
+{
+    switch (clock) {
+    case CLOCK_REALTIME:
+    case CLOCK_REALTIME_COARSE:
+        if (read_clock(1, ts) == 0) {
+            return 0;
+        }
+        break;
+    case CLOCK_MONOTONIC:
+    case CLOCK_MONOTONIC_RAW:
+    case CLOCK_MONOTONIC_COARSE:
+    case CLOCK_BOOTTIME:
+        if (read_clock(0, ts) == 0) {
+            return 0;
+        }
+        break;
+    }
+    return syscall2(SYS_CLOCK_GETTIME, clock, (long)ts);
+}
+
+int __vdso_gettimeofday(struct timeval *tv, void *tz)
+{
+    struct timespec ts;
+    if (tz != 0 || read_clock(1, &ts) != 0) {
+        return syscall2(SYS_GETTIMEOFDAY, (long)tv, (long)tz);
+    }
+    if (tv != 0) {
+        tv->tv_sec = ts.tv_sec;
+        tv->tv_usec = ts.tv_nsec / 1000;
+    }
+    return 0;
+}
+
+long __vdso_time(long *t)
+{
+    struct timespec ts;
+    if (read_clock(1, &ts) != 0) {
+#ifdef SYS_TIME
+        return syscall2(SYS_TIME, (long)t, 0);
+#else
+        syscall2(SYS_CLOCK_GETTIME, CLOCK_REALTIME, (long)&ts);
+#endif
+    }
+    if (t != 0) {
+        *t = ts.tv_sec;
+    }
+    return ts.tv_sec;
+}